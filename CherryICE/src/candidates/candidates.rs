@@ -1,31 +1,142 @@
-use std::net:SocketAddr;
+use std::net::SocketAddr;
 
-pub enum CandidateType{
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateType {
     Host,
     ServerReflexive,
     PeerReflexive,
     Relayed,
 }
-//Host : 
-// - Loopback is avoided 
+//Host :
+// - Loopback is avoided
 // - And a bunch of IPv6 restrictions
-// ServerReflexive : 
+// ServerReflexive :
 // A Binding response will provide the agent with only a
 // server-reflexive candidate (also obtained from the mapped address).
 // The base of the server-reflexive candidate is the host candidate from
 // which the Allocate or Binding request was sent.
-// 0.0.0.0 is not a loopback address, but it's a non-routable meta-address that can be used to indicate a non-applicable target. 127.0.0.1 is the address used for loopback traffic. 
-
-pub trait CandidateTrait{
-    fn fetch_info(ip_port : SocketAddr) -> Option<Self> where Self: Sized; //Ip and port to be provided by orchestrator, this
-                                             //will be of the host
-    //fn start_stun_server(&self, output_channel: ...) -> server_handle
-    //fn start_media server(&self) -> media_handle
-    fn punch_nat_hole(&self) -> bool { return true; } //Not used for every candidate type
+// 0.0.0.0 is not a loopback address, but it's a non-routable meta-address that can be used to indicate a non-applicable target. 127.0.0.1 is the address used for loopback traffic.
+// Relayed :
+// The base of a relayed candidate is the candidate itself (RFC 8445 section 5.1.1.2), its
+// related address the server reflexive address the TURN server saw.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateTransport {
+    Udp,
+    Tcp,
+}
+
+/// A transport address that a peer can try to reach us on (RFC 8445 section 5.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub candidate_type: CandidateType,
+    pub transport: CandidateTransport,
+    pub address: SocketAddr,
+    /// Address checks for this candidate are sent from
+    pub base: SocketAddr,
+    /// Host address of reflexive candidates, mapped address of relayed ones, `None` for host
+    /// candidates
+    pub related_address: Option<SocketAddr>,
+    /// 1 for RTP, 2 for RTCP
+    pub component_id: u16,
+    /// STUN or TURN server the candidate was learnt from
+    pub server: Option<SocketAddr>,
+    /// Same for candidates of the same type, base IP and STUN/TURN server
+    pub foundation: String,
+    pub priority: u32,
+}
+
+impl Candidate {
+    //Foundation and priority are left for the agent, they depend on the other candidates
+    pub fn new(
+        candidate_type: CandidateType,
+        transport: CandidateTransport,
+        address: SocketAddr,
+        base: SocketAddr,
+        related_address: Option<SocketAddr>,
+        component_id: u16,
+    ) -> Self {
+        Candidate {
+            candidate_type,
+            transport,
+            address,
+            base,
+            related_address,
+            component_id,
+            server: None,
+            foundation: String::new(),
+            priority: 0,
+        }
+    }
+
+    /// UDP host candidate of a socket bound to `address`, its own base
+    pub fn new_host(address: SocketAddr, component_id: u16) -> Self {
+        return Self::new(
+            CandidateType::Host,
+            CandidateTransport::Udp,
+            address,
+            address,
+            None,
+            component_id,
+        );
+    }
+
+    /// UDP server reflexive candidate seen by a STUN server, `base` being the host candidate
+    /// the Binding request was sent from
+    pub fn new_server_reflexive(address: SocketAddr, base: SocketAddr, component_id: u16) -> Self {
+        return Self::new(
+            CandidateType::ServerReflexive,
+            CandidateTransport::Udp,
+            address,
+            base,
+            Some(base),
+            component_id,
+        );
+    }
+
+    /// UDP relayed candidate of a TURN allocation, `mapped_address` being the server
+    /// reflexive address the TURN server saw
+    pub fn new_relayed(
+        address: SocketAddr,
+        mapped_address: Option<SocketAddr>,
+        component_id: u16,
+    ) -> Self {
+        return Self::new(
+            CandidateType::Relayed,
+            CandidateTransport::Udp,
+            address,
+            address,
+            mapped_address,
+            component_id,
+        );
+    }
 }
 
-//We support only server reflexive Candidates for now
-pub struct Candidate<T: CandidateTrait>{
-    candidate_type : CandidateType,
-    info : T
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_candidate_bases() {
+        let host: SocketAddr = "192.168.1.2:50000".parse().unwrap();
+        let mapped: SocketAddr = "203.0.113.7:61000".parse().unwrap();
+        let relayed: SocketAddr = "198.51.100.1:49170".parse().unwrap();
+
+        let host_candidate = Candidate::new_host(host, 1);
+        assert_eq!(host_candidate.base, host);
+        assert_eq!(host_candidate.related_address, None);
+
+        let server_reflexive = Candidate::new_server_reflexive(mapped, host, 1);
+        assert_eq!(
+            server_reflexive.candidate_type,
+            CandidateType::ServerReflexive
+        );
+        assert_eq!(server_reflexive.base, host);
+        assert_eq!(server_reflexive.related_address, Some(host));
+
+        let relayed_candidate = Candidate::new_relayed(relayed, Some(mapped), 2);
+        assert_eq!(relayed_candidate.base, relayed);
+        assert_eq!(relayed_candidate.related_address, Some(mapped));
+        assert_eq!(relayed_candidate.component_id, 2);
+    }
 }
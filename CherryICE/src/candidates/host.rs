@@ -0,0 +1,372 @@
+use super::candidates::Candidate;
+use crate::CherrySTUN::turnPeerPolicy::IpCidr;
+use log::{debug, info, warn};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
+
+// Host (RFC 8445 section 5.1.1.1) :
+// - Loopback is avoided, as are interfaces that are down
+// - Link-local addresses are avoided, they need a scope and rarely reach peers
+// - IPv4-compatible, IPv4-mapped and site-local IPv6 addresses MUST NOT be used
+// - Deprecated IPv6 addresses are on their way out, privacy addresses replace them
+
+//IFA_F_DEPRECATED of the flags in /proc/net/if_inet6
+const IF_INET6_DEPRECATED: u32 = 0x20;
+
+/// Which interfaces and addresses host candidates are gathered on
+#[derive(Debug, Clone, Default)]
+pub struct HostGatherer {
+    /// Ports sockets are bound to, any free port when `None`
+    pub port_range: Option<RangeInclusive<u16>>,
+    /// Interface names to gather on, all of them when empty
+    pub include_interfaces: Vec<String>,
+    pub exclude_interfaces: Vec<String>,
+    /// Addresses to gather on, all of them when empty
+    pub include_addresses: Vec<IpCidr>,
+    pub exclude_addresses: Vec<IpCidr>,
+}
+
+/// A host candidate with the socket bound to it
+#[derive(Debug)]
+pub struct HostCandidate {
+    pub candidate: Candidate,
+    pub socket: UdpSocket,
+    /// Name of the network interface of the address
+    pub interface: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub interface: String,
+    pub address: IpAddr,
+    pub up: bool,
+    pub loopback: bool,
+}
+
+impl HostGatherer {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Set `port_range` field, builder pattern.
+    pub fn set_port_range(&mut self, port_range: Option<RangeInclusive<u16>>) -> &mut Self {
+        self.port_range = port_range;
+        self
+    }
+
+    /// Add an interface name to gather on, builder pattern.
+    pub fn add_include_interface(&mut self, interface: &str) -> &mut Self {
+        self.include_interfaces.push(interface.to_string());
+        self
+    }
+
+    /// Add an interface name not to gather on, builder pattern.
+    pub fn add_exclude_interface(&mut self, interface: &str) -> &mut Self {
+        self.exclude_interfaces.push(interface.to_string());
+        self
+    }
+
+    /// Add a prefix to gather on, builder pattern.
+    pub fn add_include_address(&mut self, cidr: IpCidr) -> &mut Self {
+        self.include_addresses.push(cidr);
+        self
+    }
+
+    /// Add a prefix not to gather on, builder pattern.
+    pub fn add_exclude_address(&mut self, cidr: IpCidr) -> &mut Self {
+        self.exclude_addresses.push(cidr);
+        self
+    }
+
+    /// Binds a UDP socket on every usable address of the host. Addresses no port of
+    /// `port_range` is free on are skipped.
+    pub fn gather(&self, component_id: u16) -> std::io::Result<Vec<HostCandidate>> {
+        let interface_addresses = match interface_addresses() {
+            Ok(interface_addresses) => interface_addresses,
+            Err(e) => return Err(e),
+        };
+        let deprecated = deprecated_ipv6_addresses();
+        let mut host_candidates = Vec::new();
+        for interface_address in interface_addresses {
+            if !self.is_allowed(&interface_address, &deprecated) {
+                debug!("Skipping {:?}", interface_address);
+                continue;
+            }
+            let socket = match self.bind(interface_address.address) {
+                Some(socket) => socket,
+                None => continue,
+            };
+            let address = match socket.local_addr() {
+                Ok(address) => address,
+                Err(e) => {
+                    warn!("Error reading address of host socket: {}", e);
+                    continue;
+                }
+            };
+            info!(
+                "Host candidate {:?} on {}",
+                address, interface_address.interface
+            );
+            host_candidates.push(HostCandidate {
+                candidate: Candidate::new_host(address, component_id),
+                socket,
+                interface: interface_address.interface,
+            });
+        }
+        return Ok(host_candidates);
+    }
+
+    fn is_allowed(&self, interface_address: &InterfaceAddress, deprecated: &[Ipv6Addr]) -> bool {
+        let address = interface_address.address;
+        if !interface_address.up || interface_address.loopback || !is_usable_address(address) {
+            return false;
+        }
+        match address {
+            IpAddr::V6(ipv6) if deprecated.contains(&ipv6) => return false,
+            _ => {}
+        }
+        if (!self.include_interfaces.is_empty()
+            && !self
+                .include_interfaces
+                .contains(&interface_address.interface))
+            || self
+                .exclude_interfaces
+                .contains(&interface_address.interface)
+        {
+            return false;
+        }
+        if (!self.include_addresses.is_empty()
+            && !self.include_addresses.iter().any(|x| x.contains(address)))
+            || self.exclude_addresses.iter().any(|x| x.contains(address))
+        {
+            return false;
+        }
+        return true;
+    }
+
+    //First free port of `port_range`, from a random one on
+    fn bind(&self, ip: IpAddr) -> Option<UdpSocket> {
+        let port_range = match &self.port_range {
+            Some(port_range) => port_range,
+            None => match UdpSocket::bind(SocketAddr::new(ip, 0)) {
+                Ok(socket) => return Some(socket),
+                Err(e) => {
+                    warn!("Error binding {:?}: {}", ip, e);
+                    return None;
+                }
+            },
+        };
+        if port_range.is_empty() {
+            return None;
+        }
+        let first = *port_range.start() as u32;
+        let count = *port_range.end() as u32 - first + 1;
+        let offset = rand::random::<u32>() % count;
+        for index in 0..count {
+            let port = (first + (offset + index) % count) as u16;
+            match UdpSocket::bind(SocketAddr::new(ip, port)) {
+                Ok(socket) => return Some(socket),
+                Err(_) => continue,
+            }
+        }
+        warn!("No free port in {:?} on {:?}", port_range, ip);
+        return None;
+    }
+}
+
+//Addresses RFC 8445 lets be host candidates, whatever the interface
+pub fn is_usable_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(ipv4) => {
+            return !(ipv4.is_unspecified()
+                || ipv4.is_loopback()
+                || ipv4.is_link_local()
+                || ipv4.is_multicast()
+                || ipv4.is_broadcast())
+        }
+        IpAddr::V6(ipv6) => {
+            let segments = ipv6.segments();
+            let is_ipv4_compatible = segments[..6] == [0; 6] && !ipv6.is_loopback();
+            let is_site_local = segments[0] & 0xffc0 == 0xfec0;
+            return !(ipv6.is_unspecified()
+                || ipv6.is_loopback()
+                || ipv6.is_unicast_link_local()
+                || ipv6.is_multicast()
+                || is_ipv4_compatible
+                || ipv6.to_ipv4_mapped().is_some()
+                || is_site_local);
+        }
+    }
+}
+
+/// Addresses of the network interfaces of the host, from getifaddrs
+#[cfg(unix)]
+pub fn interface_addresses() -> std::io::Result<Vec<InterfaceAddress>> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    //SAFETY: the list is freed below and not used after
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut interface_addresses = Vec::new();
+    let mut current = ifaddrs;
+    while !current.is_null() {
+        //SAFETY: entries of the list are valid until freeifaddrs
+        let ifaddr = unsafe { &*current };
+        current = ifaddr.ifa_next;
+        if ifaddr.ifa_addr.is_null() {
+            continue;
+        }
+        let address = match unsafe { (*ifaddr.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let sockaddr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sockaddr.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sockaddr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sockaddr.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        let interface = unsafe { std::ffi::CStr::from_ptr(ifaddr.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        let flags = ifaddr.ifa_flags as libc::c_int;
+        interface_addresses.push(InterfaceAddress {
+            interface,
+            address,
+            up: flags & libc::IFF_UP != 0,
+            loopback: flags & libc::IFF_LOOPBACK != 0,
+        });
+    }
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    return Ok(interface_addresses);
+}
+
+#[cfg(not(unix))]
+pub fn interface_addresses() -> std::io::Result<Vec<InterfaceAddress>> {
+    return Err(std::io::ErrorKind::Unsupported.into());
+}
+
+//getifaddrs has no address flags, Linux lists them in /proc/net/if_inet6
+fn deprecated_ipv6_addresses() -> Vec<Ipv6Addr> {
+    match std::fs::read_to_string("/proc/net/if_inet6") {
+        Ok(if_inet6) => return parse_deprecated_ipv6_addresses(&if_inet6),
+        Err(_) => return Vec::new(),
+    }
+}
+
+//Lines are the address, interface index, prefix length, scope, flags and interface name, all
+//but the name in hex
+fn parse_deprecated_ipv6_addresses(if_inet6: &str) -> Vec<Ipv6Addr> {
+    let mut deprecated = Vec::new();
+    for line in if_inet6.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6 {
+            continue;
+        }
+        let (address, flags) = match (
+            u128::from_str_radix(fields[0], 16),
+            u32::from_str_radix(fields[4], 16),
+        ) {
+            (Ok(address), Ok(flags)) => (Ipv6Addr::from(address), flags),
+            _ => continue,
+        };
+        if flags & IF_INET6_DEPRECATED != 0 {
+            deprecated.push(address);
+        }
+    }
+    return deprecated;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_usable_addresses() {
+        for usable in ["192.168.1.2", "203.0.113.7", "2001:db8::1", "fd00::2"] {
+            assert!(is_usable_address(usable.parse().unwrap()), "{}", usable);
+        }
+        for unusable in [
+            "127.0.0.1",
+            "169.254.1.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "::192.168.1.2",
+            "::ffff:192.168.1.2",
+            "fec0::1",
+        ] {
+            assert!(
+                !is_usable_address(unusable.parse().unwrap()),
+                "{}",
+                unusable
+            );
+        }
+    }
+
+    #[test]
+    fn test_deprecated_ipv6_addresses() {
+        let if_inet6 = "00000000000000000000000000000001 01 80 10 80       lo\n\
+                        20010db8000000000000000000000001 02 40 00 a0     eth0\n\
+                        20010db8000000000000000000000002 02 40 00 00     eth0\n";
+        assert_eq!(
+            parse_deprecated_ipv6_addresses(if_inet6),
+            vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let eth0 = InterfaceAddress {
+            interface: "eth0".to_string(),
+            address: "192.168.1.2".parse().unwrap(),
+            up: true,
+            loopback: false,
+        };
+        let docker0 = InterfaceAddress {
+            interface: "docker0".to_string(),
+            address: "172.17.0.1".parse().unwrap(),
+            up: true,
+            loopback: false,
+        };
+        let mut gatherer = HostGatherer::new();
+        assert!(gatherer.is_allowed(&eth0, &[]));
+        assert!(gatherer.is_allowed(&docker0, &[]));
+        assert!(!gatherer.is_allowed(
+            &InterfaceAddress {
+                up: false,
+                ..eth0.clone()
+            },
+            &[]
+        ));
+
+        gatherer.add_exclude_interface("docker0");
+        assert!(!gatherer.is_allowed(&docker0, &[]));
+        gatherer
+            .add_include_address("192.168.0.0/16".parse().unwrap())
+            .add_exclude_address("192.168.1.2".parse().unwrap());
+        assert!(!gatherer.is_allowed(&eth0, &[]));
+        let eth1 = InterfaceAddress {
+            interface: "eth1".to_string(),
+            address: "192.168.2.2".parse().unwrap(),
+            ..eth0.clone()
+        };
+        assert!(gatherer.is_allowed(&eth1, &[]));
+        gatherer.add_include_interface("eth0");
+        assert!(!gatherer.is_allowed(&eth1, &[]));
+    }
+
+    #[test]
+    fn test_gather_in_port_range() {
+        let mut gatherer = HostGatherer::new();
+        gatherer.set_port_range(Some(40000..=40999));
+        for host_candidate in gatherer.gather(1).unwrap() {
+            let address = host_candidate.candidate.address;
+            assert!(is_usable_address(address.ip()));
+            assert!((40000..=40999).contains(&address.port()));
+            assert_eq!(host_candidate.socket.local_addr().unwrap(), address);
+            assert_eq!(host_candidate.candidate.base, address);
+        }
+    }
+}
@@ -1,2 +1,6 @@
 pub mod candidates;
+pub mod host;
+pub mod priority;
+pub mod relayed;
+pub mod sdp;
 pub mod server_reflexive;
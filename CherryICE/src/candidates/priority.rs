@@ -0,0 +1,235 @@
+use super::candidates::{Candidate, CandidateType};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+
+//Recommended type preferences (RFC 8445 section 5.1.2.2)
+pub const HOST_TYPE_PREFERENCE: u8 = 126;
+pub const PEER_REFLEXIVE_TYPE_PREFERENCE: u8 = 110;
+pub const SERVER_REFLEXIVE_TYPE_PREFERENCE: u8 = 100;
+pub const RELAYED_TYPE_PREFERENCE: u8 = 0;
+pub const MAX_TYPE_PREFERENCE: u8 = 126;
+const MAX_LOCAL_PREFERENCE: u16 = 65535;
+
+// Priority (RFC 8445 section 5.1.2.1) :
+// priority = (2^24)*(type preference) + (2^8)*(local preference) + (2^0)*(256 - component ID)
+// Local preference (RFC 8421 section 4) :
+// Interfaces are ordered by preference, the addresses of each interface interleaving IPv6
+// and IPv4 so that neither family is starved when the other one is broken. IPv6 goes
+// first. Every base IP gets its own local preference, so candidates of the same type and
+// component keep distinct priorities.
+pub struct CandidatePrioritizer {
+    host_preference: u8,
+    peer_reflexive_preference: u8,
+    server_reflexive_preference: u8,
+    relayed_preference: u8,
+    //Most preferred first, interfaces not in here come after in the order they are given
+    interfaces: Vec<String>,
+}
+
+impl Default for CandidatePrioritizer {
+    fn default() -> Self {
+        CandidatePrioritizer {
+            host_preference: HOST_TYPE_PREFERENCE,
+            peer_reflexive_preference: PEER_REFLEXIVE_TYPE_PREFERENCE,
+            server_reflexive_preference: SERVER_REFLEXIVE_TYPE_PREFERENCE,
+            relayed_preference: RELAYED_TYPE_PREFERENCE,
+            interfaces: Vec::new(),
+        }
+    }
+}
+
+impl CandidatePrioritizer {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Set type preference of `candidate_type`, at most `MAX_TYPE_PREFERENCE`, builder pattern.
+    pub fn set_type_preference(
+        &mut self,
+        candidate_type: CandidateType,
+        preference: u8,
+    ) -> &mut Self {
+        let preference = preference.min(MAX_TYPE_PREFERENCE);
+        match candidate_type {
+            CandidateType::Host => self.host_preference = preference,
+            CandidateType::PeerReflexive => self.peer_reflexive_preference = preference,
+            CandidateType::ServerReflexive => self.server_reflexive_preference = preference,
+            CandidateType::Relayed => self.relayed_preference = preference,
+        }
+        return self;
+    }
+
+    /// Add interface after the ones added before (most preferred first), builder pattern.
+    pub fn add_interface(&mut self, interface: &str) -> &mut Self {
+        self.interfaces.push(interface.to_string());
+        return self;
+    }
+
+    pub fn type_preference(&self, candidate_type: CandidateType) -> u8 {
+        return match candidate_type {
+            CandidateType::Host => self.host_preference,
+            CandidateType::PeerReflexive => self.peer_reflexive_preference,
+            CandidateType::ServerReflexive => self.server_reflexive_preference,
+            CandidateType::Relayed => self.relayed_preference,
+        };
+    }
+
+    /// Local preference of each of the `(interface, ip)` addresses, per RFC 8421
+    pub fn local_preferences(&self, addresses: &[(String, IpAddr)]) -> HashMap<IpAddr, u16> {
+        let mut interfaces: Vec<&str> = self
+            .interfaces
+            .iter()
+            .map(|x| x.as_str())
+            .filter(|x| addresses.iter().any(|(interface, _)| interface == x))
+            .collect();
+        for (interface, _) in addresses {
+            if !interfaces.contains(&interface.as_str()) {
+                interfaces.push(interface);
+            }
+        }
+
+        let mut local_preferences = HashMap::new();
+        let mut next_preference = MAX_LOCAL_PREFERENCE;
+        for interface in interfaces {
+            let of_interface = |ipv6: bool| {
+                addresses
+                    .iter()
+                    .filter(|(x, ip)| x == interface && ip.is_ipv6() == ipv6)
+                    .map(|(_, ip)| *ip)
+                    .collect::<Vec<IpAddr>>()
+            };
+            let ipv6 = of_interface(true);
+            let ipv4 = of_interface(false);
+            for i in 0..ipv6.len().max(ipv4.len()) {
+                for ip in [ipv6.get(i), ipv4.get(i)].into_iter().flatten() {
+                    if local_preferences.contains_key(ip) {
+                        continue;
+                    }
+                    local_preferences.insert(*ip, next_preference);
+                    next_preference = next_preference.saturating_sub(1);
+                }
+            }
+        }
+        return local_preferences;
+    }
+
+    /// Sets priority and foundation of `candidates`. `addresses` are the `(interface, ip)`
+    /// of the host candidates, bases that are not one of them (relayed candidates) are
+    /// treated as being on an interface of their own, least preferred.
+    pub fn prioritize(&self, candidates: &mut [Candidate], addresses: &[(String, IpAddr)]) {
+        let mut addresses = addresses.to_vec();
+        for candidate in candidates.iter() {
+            let ip = candidate.base.ip();
+            if !addresses.iter().any(|(_, x)| *x == ip) {
+                addresses.push((String::new(), ip));
+            }
+        }
+        let local_preferences = self.local_preferences(&addresses);
+        for candidate in candidates.iter_mut() {
+            let local_preference = match local_preferences.get(&candidate.base.ip()) {
+                Some(local_preference) => *local_preference,
+                None => 0,
+            };
+            candidate.priority = compute_priority(
+                self.type_preference(candidate.candidate_type),
+                local_preference,
+                candidate.component_id,
+            );
+            candidate.foundation = compute_foundation(candidate);
+        }
+    }
+}
+
+pub fn compute_priority(type_preference: u8, local_preference: u16, component_id: u16) -> u32 {
+    let component_preference = 256 - component_id.clamp(1, 256) as u32;
+    return ((type_preference as u32) << 24)
+        + ((local_preference as u32) << 8)
+        + component_preference;
+}
+
+/// Same for candidates of the same type, base IP, STUN/TURN server IP and transport (RFC
+/// 8445 section 5.1.1.3), otherwise different
+pub fn compute_foundation(candidate: &Candidate) -> String {
+    let mut hasher = DefaultHasher::new();
+    candidate.candidate_type.hash(&mut hasher);
+    candidate.base.ip().hash(&mut hasher);
+    candidate.server.map(|x| x.ip()).hash(&mut hasher);
+    candidate.transport.hash(&mut hasher);
+    return (hasher.finish() as u32).to_string();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_compute_priority() {
+        assert_eq!(compute_priority(126, 65535, 1), 2130706431);
+        assert_eq!(compute_priority(0, 0, 2), 254);
+        assert_eq!(compute_priority(100, 1, 1), (100 << 24) + 256 + 255);
+    }
+
+    #[test]
+    fn test_local_preferences() {
+        let wifi_v4: IpAddr = "192.168.1.2".parse().unwrap();
+        let wifi_v4_2: IpAddr = "192.168.1.3".parse().unwrap();
+        let wifi_v6: IpAddr = "2001:db8::2".parse().unwrap();
+        let eth_v4: IpAddr = "10.0.0.2".parse().unwrap();
+        let eth_v6: IpAddr = "2001:db8:1::2".parse().unwrap();
+        let addresses = vec![
+            ("wlan0".to_string(), wifi_v4),
+            ("wlan0".to_string(), wifi_v4_2),
+            ("wlan0".to_string(), wifi_v6),
+            ("eth0".to_string(), eth_v4),
+            ("eth0".to_string(), eth_v6),
+        ];
+
+        let mut prioritizer = CandidatePrioritizer::new();
+        prioritizer.add_interface("eth0");
+        let local_preferences = prioritizer.local_preferences(&addresses);
+        assert_eq!(local_preferences[&eth_v6], 65535);
+        assert_eq!(local_preferences[&eth_v4], 65534);
+        assert_eq!(local_preferences[&wifi_v6], 65533);
+        assert_eq!(local_preferences[&wifi_v4], 65532);
+        assert_eq!(local_preferences[&wifi_v4_2], 65531);
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let host: SocketAddr = "192.168.1.2:50000".parse().unwrap();
+        let host_2: SocketAddr = "192.168.1.2:50002".parse().unwrap();
+        let mapped: SocketAddr = "203.0.113.7:61000".parse().unwrap();
+        let relayed: SocketAddr = "198.51.100.1:49170".parse().unwrap();
+        let stun_server: SocketAddr = "198.51.100.2:3478".parse().unwrap();
+
+        let mut server_reflexive = Candidate::new_server_reflexive(mapped, host, 1);
+        server_reflexive.server = Some(stun_server);
+        let mut server_reflexive_2 = Candidate::new_server_reflexive(mapped, host_2, 2);
+        server_reflexive_2.server = Some(stun_server);
+        let mut relayed_candidate = Candidate::new_relayed(relayed, Some(mapped), 1);
+        relayed_candidate.server = Some(stun_server);
+        let mut candidates = vec![
+            Candidate::new_host(host, 1),
+            Candidate::new_host(host_2, 2),
+            server_reflexive,
+            server_reflexive_2,
+            relayed_candidate,
+        ];
+        let mut prioritizer = CandidatePrioritizer::new();
+        prioritizer.set_type_preference(CandidateType::Relayed, 5);
+        prioritizer.prioritize(&mut candidates, &[("eth0".to_string(), host.ip())]);
+
+        assert_eq!(candidates[0].priority, compute_priority(126, 65535, 1));
+        assert_eq!(candidates[1].priority, compute_priority(126, 65535, 2));
+        assert_eq!(candidates[2].priority, compute_priority(100, 65535, 1));
+        assert_eq!(candidates[4].priority, compute_priority(5, 65534, 1));
+        //Components of the same base share foundations, types do not
+        assert_eq!(candidates[0].foundation, candidates[1].foundation);
+        assert_eq!(candidates[2].foundation, candidates[3].foundation);
+        assert_ne!(candidates[0].foundation, candidates[2].foundation);
+        assert_ne!(candidates[2].foundation, candidates[4].foundation);
+    }
+}
@@ -0,0 +1,64 @@
+use super::candidates::Candidate;
+use crate::CherrySTUN::stunAttributes::TURN_ADDRESS_FAMILY_IPV6;
+use crate::CherrySTUN::turnClient::{TurnAllocation, TurnClient};
+use log::{error, info, warn};
+use std::net::UdpSocket;
+
+// A dual-stack TURN server gives an IPv4 and an IPv6 relayed address in the same allocation
+// (RFC 8656 ADDITIONAL-ADDRESS-FAMILY), we make a candidate of each.
+pub struct RelayedCandidates {
+    pub candidates: Vec<Candidate>,
+    /// Keeps the relayed addresses, they are released once it is dropped
+    pub allocation: TurnAllocation,
+}
+
+impl RelayedCandidates {
+    //`host` is the socket of the host candidate the allocation is made from, it is used by
+    //the allocation from now on
+    pub fn fetch_info(
+        turn_client: &mut TurnClient,
+        host: UdpSocket,
+        component_id: u16,
+    ) -> Option<Self> {
+        let host_addr = match host.local_addr() {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("{:?}", e);
+                return None;
+            }
+        };
+        //IPv6 can only be asked for in addition to IPv4
+        if host_addr.is_ipv4() {
+            turn_client.set_additional_address_family(Some(TURN_ADDRESS_FAMILY_IPV6));
+        }
+        let allocation = match turn_client.allocate(host) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                error!("{:?}", e);
+                return None;
+            }
+        };
+        let mut relayed_addrs = vec![allocation.relayed_address()];
+        match allocation.additional_relayed_address() {
+            Some(addr) => relayed_addrs.push(addr),
+            None if host_addr.is_ipv4() => {
+                warn!("TURN server did not give an IPv6 relayed address")
+            }
+            None => {}
+        }
+        info!("Relayed addresses: {:?}", relayed_addrs);
+        let candidates = relayed_addrs
+            .into_iter()
+            .map(|addr| {
+                let mut candidate =
+                    Candidate::new_relayed(addr, allocation.mapped_address(), component_id);
+                candidate.server = Some(allocation.server_address());
+                return candidate;
+            })
+            .collect();
+        return Some(RelayedCandidates {
+            candidates,
+            allocation,
+        });
+    }
+}
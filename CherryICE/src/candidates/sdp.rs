@@ -0,0 +1,472 @@
+use super::candidates::{Candidate, CandidateTransport, CandidateType};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+// a=candidate: line (RFC 8839 section 5.1) :
+// candidate-attribute   = "candidate" ":" foundation SP component-id SP
+//                         transport SP
+//                         priority SP
+//                         connection-address SP     ;from RFC 4566
+//                         port         ;port from RFC 4566
+//                         SP cand-type
+//                         [SP rel-addr]
+//                         [SP rel-port]
+//                         *(SP cand-extension)
+// foundation            = 1*32ice-char
+// component-id          = 1*3DIGIT
+// transport             = "UDP" / transport-extension
+// priority              = 1*10DIGIT
+// cand-type             = "typ" SP candidate-types
+// candidate-types       = "host" / "srflx" / "prflx" / "relay" / token
+// rel-addr              = "raddr" SP connection-address
+// rel-port              = "rport" SP port
+// cand-extension        = extension-att-name SP extension-att-value
+// ice-char              = ALPHA / DIGIT / "+" / "/"
+// tcptype (RFC 6544 section 4.5) is one of the extensions, "tcptype" SP ("active" /
+// "passive" / "so"). The connection address can be an FQDN, browsers give mDNS names
+// (RFC 8839 section 5.1, draft-ietf-mmusic-mdns-ice-candidates) in place of host IPs.
+// Candidate types other than the four of RFC 8445 (the `token` of candidate-types) are
+// rejected: no agent defines one, and a candidate of unknown type can't be prioritized nor
+// paired.
+
+const CANDIDATE_ATTRIBUTE_PREFIX: &str = "candidate:";
+const SDP_ATTRIBUTE_PREFIX: &str = "a=";
+const MAX_FOUNDATION_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TcpType {
+    Active,
+    Passive,
+    SimultaneousOpen,
+}
+
+/// A candidate as written in SDP, kept as written so that it gives back the same line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateAttribute {
+    pub foundation: String,
+    pub component_id: u16,
+    /// "UDP", "udp", "TCP"... case is kept
+    pub transport: String,
+    pub priority: u32,
+    /// IP address or FQDN
+    pub connection_address: String,
+    pub port: u16,
+    pub candidate_type: CandidateType,
+    pub related_address: Option<String>,
+    pub related_port: Option<u16>,
+    pub tcp_type: Option<TcpType>,
+    /// Other extensions (generation, ufrag, network-id...) in order
+    pub extensions: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateAttributeError {
+    pub message: String,
+}
+
+impl fmt::Display for CandidateAttributeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+impl std::error::Error for CandidateAttributeError {}
+
+fn attribute_error(message: String) -> CandidateAttributeError {
+    return CandidateAttributeError { message };
+}
+
+impl TcpType {
+    fn as_str(&self) -> &'static str {
+        return match self {
+            TcpType::Active => "active",
+            TcpType::Passive => "passive",
+            TcpType::SimultaneousOpen => "so",
+        };
+    }
+}
+
+impl FromStr for TcpType {
+    type Err = CandidateAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "active" => Ok(TcpType::Active),
+            "passive" => Ok(TcpType::Passive),
+            "so" => Ok(TcpType::SimultaneousOpen),
+            _ => Err(attribute_error(format!("Unknown tcptype: {}", s))),
+        };
+    }
+}
+
+fn candidate_type_str(candidate_type: CandidateType) -> &'static str {
+    return match candidate_type {
+        CandidateType::Host => "host",
+        CandidateType::ServerReflexive => "srflx",
+        CandidateType::PeerReflexive => "prflx",
+        CandidateType::Relayed => "relay",
+    };
+}
+
+//Errors on `token` types, see above
+fn parse_candidate_type(s: &str) -> Result<CandidateType, CandidateAttributeError> {
+    return match s {
+        "host" => Ok(CandidateType::Host),
+        "srflx" => Ok(CandidateType::ServerReflexive),
+        "prflx" => Ok(CandidateType::PeerReflexive),
+        "relay" => Ok(CandidateType::Relayed),
+        _ => Err(attribute_error(format!("Unknown candidate type: {}", s))),
+    };
+}
+
+fn parse_number<T: FromStr>(
+    field: &str,
+    s: &str,
+    max_digits: usize,
+) -> Result<T, CandidateAttributeError> {
+    if s.is_empty() || s.len() > max_digits || !s.bytes().all(|x| x.is_ascii_digit()) {
+        return Err(attribute_error(format!("Invalid {}: {}", field, s)));
+    }
+    return match s.parse::<T>() {
+        Ok(number) => Ok(number),
+        Err(_) => Err(attribute_error(format!("Invalid {}: {}", field, s))),
+    };
+}
+
+impl CandidateAttribute {
+    /// Transport of the candidate, `None` for transports other than UDP and TCP
+    pub fn transport(&self) -> Option<CandidateTransport> {
+        if self.transport.eq_ignore_ascii_case("udp") {
+            return Some(CandidateTransport::Udp);
+        }
+        if self.transport.eq_ignore_ascii_case("tcp") {
+            return Some(CandidateTransport::Tcp);
+        }
+        return None;
+    }
+
+    /// Remote candidate to check against. Fails for unknown transports and for FQDN
+    /// addresses, which have to be resolved first. Its base is not known, it is the
+    /// candidate itself.
+    pub fn to_candidate(&self) -> Result<Candidate, CandidateAttributeError> {
+        let transport = match self.transport() {
+            Some(transport) => transport,
+            None => {
+                return Err(attribute_error(format!(
+                    "Unsupported transport: {}",
+                    self.transport
+                )))
+            }
+        };
+        let ip = match self.connection_address.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => {
+                return Err(attribute_error(format!(
+                    "Connection address is not an IP: {}",
+                    self.connection_address
+                )))
+            }
+        };
+        let address = SocketAddr::new(ip, self.port);
+        let related_address = match (&self.related_address, self.related_port) {
+            (Some(related_address), Some(related_port)) => {
+                match related_address.parse::<IpAddr>() {
+                    Ok(related_ip) => Some(SocketAddr::new(related_ip, related_port)),
+                    Err(_) => None,
+                }
+            }
+            _ => None,
+        };
+        let mut candidate = Candidate::new(
+            self.candidate_type,
+            transport,
+            address,
+            address,
+            related_address,
+            self.component_id,
+        );
+        candidate.foundation = self.foundation.clone();
+        candidate.priority = self.priority;
+        return Ok(candidate);
+    }
+}
+
+impl From<&Candidate> for CandidateAttribute {
+    fn from(candidate: &Candidate) -> Self {
+        let transport = match candidate.transport {
+            CandidateTransport::Udp => "UDP",
+            CandidateTransport::Tcp => "TCP",
+        };
+        CandidateAttribute {
+            foundation: candidate.foundation.clone(),
+            component_id: candidate.component_id,
+            transport: transport.to_string(),
+            priority: candidate.priority,
+            connection_address: candidate.address.ip().to_string(),
+            port: candidate.address.port(),
+            candidate_type: candidate.candidate_type,
+            related_address: candidate.related_address.map(|x| x.ip().to_string()),
+            related_port: candidate.related_address.map(|x| x.port()),
+            tcp_type: None,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for CandidateAttribute {
+    // Without the "a=", as in the candidate of RTCIceCandidate
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = format!(
+            "{}{} {} {} {} {} {} typ {}",
+            CANDIDATE_ATTRIBUTE_PREFIX,
+            self.foundation,
+            self.component_id,
+            self.transport,
+            self.priority,
+            self.connection_address,
+            self.port,
+            candidate_type_str(self.candidate_type)
+        );
+        match &self.related_address {
+            Some(related_address) => line += &format!(" raddr {}", related_address),
+            None => {}
+        }
+        match self.related_port {
+            Some(related_port) => line += &format!(" rport {}", related_port),
+            None => {}
+        }
+        match self.tcp_type {
+            Some(tcp_type) => line += &format!(" tcptype {}", tcp_type.as_str()),
+            None => {}
+        }
+        for (name, value) in &self.extensions {
+            line += &format!(" {} {}", name, value);
+        }
+        return write!(f, "{}", line);
+    }
+}
+
+impl FromStr for CandidateAttribute {
+    type Err = CandidateAttributeError;
+
+    // Takes "a=candidate:..." as well as "candidate:..."
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix(SDP_ATTRIBUTE_PREFIX).unwrap_or(s);
+        let s = match s.strip_prefix(CANDIDATE_ATTRIBUTE_PREFIX) {
+            Some(s) => s,
+            None => return Err(attribute_error(format!("Not a candidate attribute: {}", s))),
+        };
+        let tokens: Vec<&str> = s.split_ascii_whitespace().collect();
+        if tokens.len() < 8 {
+            return Err(attribute_error(format!("Missing candidate fields: {}", s)));
+        }
+
+        let foundation = tokens[0];
+        if foundation.len() > MAX_FOUNDATION_LENGTH
+            || !foundation
+                .bytes()
+                .all(|x| x.is_ascii_alphanumeric() || x == b'+' || x == b'/')
+        {
+            return Err(attribute_error(format!(
+                "Invalid foundation: {}",
+                foundation
+            )));
+        }
+        let component_id: u16 = match parse_number("component-id", tokens[1], 3) {
+            Ok(component_id) => component_id,
+            Err(e) => return Err(e),
+        };
+        if !(1..=256).contains(&component_id) {
+            return Err(attribute_error(format!(
+                "Component ID out of range: {}",
+                component_id
+            )));
+        }
+        let transport = tokens[2];
+        let priority: u32 = match parse_number("priority", tokens[3], 10) {
+            Ok(priority) => priority,
+            Err(e) => return Err(e),
+        };
+        let connection_address = tokens[4];
+        let port: u16 = match parse_number("port", tokens[5], 5) {
+            Ok(port) => port,
+            Err(e) => return Err(e),
+        };
+        if tokens[6] != "typ" {
+            return Err(attribute_error(format!("Expected typ, got: {}", tokens[6])));
+        }
+        let candidate_type = match parse_candidate_type(tokens[7]) {
+            Ok(candidate_type) => candidate_type,
+            Err(e) => return Err(e),
+        };
+
+        let mut attribute = CandidateAttribute {
+            foundation: foundation.to_string(),
+            component_id,
+            transport: transport.to_string(),
+            priority,
+            connection_address: connection_address.to_string(),
+            port,
+            candidate_type,
+            related_address: None,
+            related_port: None,
+            tcp_type: None,
+            extensions: Vec::new(),
+        };
+        let rest = &tokens[8..];
+        if !rest.len().is_multiple_of(2) {
+            return Err(attribute_error(format!(
+                "Extension without a value: {}",
+                rest[rest.len() - 1]
+            )));
+        }
+        //raddr, rport and tcptype are only taken in that order and before the other
+        //extensions, anything else would not be written back the same
+        for pair in rest.chunks(2) {
+            let (name, value) = (pair[0], pair[1]);
+            let in_order = |later_set: bool| later_set || !attribute.extensions.is_empty();
+            match name {
+                "raddr"
+                    if !in_order(
+                        attribute.related_address.is_some()
+                            || attribute.related_port.is_some()
+                            || attribute.tcp_type.is_some(),
+                    ) =>
+                {
+                    attribute.related_address = Some(value.to_string());
+                }
+                "rport"
+                    if !in_order(
+                        attribute.related_port.is_some() || attribute.tcp_type.is_some(),
+                    ) =>
+                {
+                    attribute.related_port = match parse_number("rport", value, 5) {
+                        Ok(related_port) => Some(related_port),
+                        Err(e) => return Err(e),
+                    };
+                }
+                "tcptype" if !in_order(attribute.tcp_type.is_some()) => {
+                    attribute.tcp_type = match value.parse() {
+                        Ok(tcp_type) => Some(tcp_type),
+                        Err(e) => return Err(e),
+                    };
+                }
+                _ => attribute
+                    .extensions
+                    .push((name.to_string(), value.to_string())),
+            }
+        }
+        return Ok(attribute);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //Gathered by Chrome 129 and Firefox 131
+    const BROWSER_CANDIDATES: [&str; 9] = [
+        "candidate:3098175849 1 udp 2122260223 192.168.1.2 54400 typ host generation 0 ufrag EsAw network-id 1 network-cost 10",
+        "candidate:2999745851 1 udp 2122262783 2001:db8::2 56143 typ host generation 0 ufrag EsAw network-id 2 network-cost 10",
+        "candidate:842163049 1 udp 1677729535 203.0.113.7 61000 typ srflx raddr 192.168.1.2 rport 54400 generation 0 ufrag EsAw network-id 1 network-cost 10",
+        "candidate:1467250027 1 tcp 1518280447 192.168.1.2 9 typ host tcptype active generation 0 ufrag EsAw network-id 1 network-cost 10",
+        "candidate:2157334355 1 udp 2113937151 4b1cd8a2-1d2b-4c6f-8f1e-3b5c1a2d9e07.local 54400 typ host generation 0 ufrag EsAw network-cost 999",
+        "candidate:0 1 UDP 2122252543 192.168.1.2 49203 typ host",
+        "candidate:1 1 UDP 1686052863 203.0.113.7 49203 typ srflx raddr 192.168.1.2 rport 49203",
+        "candidate:2 1 TCP 2105524479 192.168.1.2 9 typ host tcptype active",
+        "candidate:4 2 UDP 8331262 198.51.100.1 52914 typ relay raddr 203.0.113.7 rport 49204",
+    ];
+
+    #[test]
+    fn test_browser_candidates_round_trip() {
+        for line in BROWSER_CANDIDATES {
+            let attribute: CandidateAttribute = line.parse().unwrap();
+            assert_eq!(attribute.to_string(), line);
+            let sdp_line = format!("a={}\r\n", line);
+            assert_eq!(sdp_line.parse::<CandidateAttribute>().unwrap(), attribute);
+        }
+
+        let server_reflexive: CandidateAttribute = BROWSER_CANDIDATES[2].parse().unwrap();
+        assert_eq!(
+            server_reflexive.candidate_type,
+            CandidateType::ServerReflexive
+        );
+        assert_eq!(
+            server_reflexive.related_address.as_deref(),
+            Some("192.168.1.2")
+        );
+        assert_eq!(server_reflexive.related_port, Some(54400));
+        assert_eq!(
+            server_reflexive.extensions[1],
+            ("ufrag".to_string(), "EsAw".to_string())
+        );
+        let tcp: CandidateAttribute = BROWSER_CANDIDATES[7].parse().unwrap();
+        assert_eq!(tcp.tcp_type, Some(TcpType::Active));
+        assert_eq!(tcp.transport(), Some(CandidateTransport::Tcp));
+    }
+
+    #[test]
+    fn test_to_candidate() {
+        let relayed = BROWSER_CANDIDATES[8]
+            .parse::<CandidateAttribute>()
+            .unwrap()
+            .to_candidate()
+            .unwrap();
+        assert_eq!(relayed.candidate_type, CandidateType::Relayed);
+        assert_eq!(relayed.address, "198.51.100.1:52914".parse().unwrap());
+        assert_eq!(
+            relayed.related_address,
+            Some("203.0.113.7:49204".parse().unwrap())
+        );
+        assert_eq!(relayed.component_id, 2);
+        assert_eq!(relayed.priority, 8331262);
+        assert_eq!(relayed.foundation, "4");
+
+        let ipv6 = BROWSER_CANDIDATES[1]
+            .parse::<CandidateAttribute>()
+            .unwrap()
+            .to_candidate()
+            .unwrap();
+        assert_eq!(ipv6.address, "[2001:db8::2]:56143".parse().unwrap());
+        //mDNS names have to be resolved first
+        let mdns: CandidateAttribute = BROWSER_CANDIDATES[4].parse().unwrap();
+        assert!(mdns.to_candidate().is_err());
+
+        let mut candidate = Candidate::new_server_reflexive(
+            "203.0.113.7:61000".parse().unwrap(),
+            "[2001:db8::2]:50000".parse().unwrap(),
+            1,
+        );
+        candidate.foundation = "842163049".to_string();
+        candidate.priority = 1677729535;
+        let attribute = CandidateAttribute::from(&candidate);
+        assert_eq!(
+            attribute.to_string(),
+            "candidate:842163049 1 UDP 1677729535 203.0.113.7 61000 typ srflx raddr 2001:db8::2 rport 50000"
+        );
+        let mut parsed = attribute.to_candidate().unwrap();
+        parsed.base = candidate.base;
+        assert_eq!(parsed, candidate);
+    }
+
+    #[test]
+    fn test_invalid_candidates() {
+        let invalid = [
+            "candidate:1 1 UDP 2122252543 192.168.1.2 49203",
+            "candidate:1 1 UDP 2122252543 192.168.1.2 49203 type host",
+            "candidate:1 1 UDP 2122252543 192.168.1.2 49203 typ unicorn",
+            "candidate:1 0 UDP 2122252543 192.168.1.2 49203 typ host",
+            "candidate:1 1 UDP 21222525430 192.168.1.2 49203 typ host",
+            "candidate:1 1 UDP 2122252543 192.168.1.2 65536 typ host",
+            "candidate:1- 1 UDP 2122252543 192.168.1.2 49203 typ host",
+            "candidate:1 1 UDP 2122252543 192.168.1.2 49203 typ host generation",
+            "candidate:1 1 TCP 2122252543 192.168.1.2 9 typ host tcptype unicorn",
+            "a=rtcp-mux",
+        ];
+        for line in invalid {
+            assert!(line.parse::<CandidateAttribute>().is_err(), "{}", line);
+        }
+    }
+}
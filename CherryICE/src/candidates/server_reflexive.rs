@@ -1,53 +1,290 @@
-use super::candidates::CandidateTrait;
-use crate::CherrySTUN::stunClient;
-use log::{error, info, warn};
-use std::net::{SocketAddr, ToSocketAddrs};
+use super::candidates::Candidate;
+use super::host::HostCandidate;
+use crate::CherrySTUN::stun::STUN;
+use crate::CherrySTUN::stunAttributes::STUNAttributesContent;
+use crate::CherrySTUN::stunContext::STUNContext;
+use crate::CherrySTUN::stunDecode::STUNDecode;
+use crate::CherrySTUN::stunEncode::STUNEncode;
+use crate::CherrySTUN::stunHeader::{STUNMessageClass, STUNMessageMethod};
+use log::{debug, info, warn};
+use std::io::Cursor;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
 
+//Binding requests without a response are sent again after this, doubled every time (RFC 8489
+//section 6.2.1)
+const SERVER_REFLEXIVE_INITIAL_RTO: Duration = Duration::from_millis(500);
+const SERVER_REFLEXIVE_RECEIVE_BUFFER_SIZE: usize = 1500;
+
+// ServerReflexive :
+// A Binding response will provide the agent with only a
+// server-reflexive candidate (also obtained from the mapped address).
+// The base of the server-reflexive candidate is the host candidate from
+// which the Allocate or Binding request was sent.
 pub struct ServerReflexiveCandidate {
-    host_addr: SocketAddr,
-    server_reflexive_addr: SocketAddr,
-    stun_turn_server: SocketAddr,
+    pub candidate: Candidate,
+    /// STUN server that saw the address, the first configured one when several did
+    pub stun_server: SocketAddr,
 }
 
-impl CandidateTrait for ServerReflexiveCandidate {
-    fn fetch_info(ip_port: SocketAddr) -> Option<Self> {
-        let stun_server = String::from("stunserver2025.stunprotocol.org:3748");
-        match stunClient::StunClient::get_server_reflexive_address_custom_stun_server(
-            ip_port.port() as u32,
-            stun_server,
-        ) {
-            Ok(addr) => {
-                info!("Server reflexive address/public: {:?}", addr);
-                warn!("Note: NAT hole to actual peers have not been made...should be done after ICE exchange.");
-                warn!("And ICE exchange is done through a singnaling server.");
-                warn!("This is just a test to see if the server reflexive address can be fetched.");
-                warn!("This is not a complete ICE implementation.");
-                let stun_server_sock = addr
-                    .to_socket_addrs()
-                    .unwrap()
-                    .filter(|x| x.is_ipv4())
-                    .next()
-                    .unwrap();
-                Some(ServerReflexiveCandidate {
-                    host_addr: ip_port,
-                    server_reflexive_addr: addr,
-                    stun_turn_server: stun_server_sock,
-                })
+//A Binding request in flight, `None` mapped address until answered
+struct ServerReflexiveQuery {
+    stun_server: SocketAddr,
+    request_bin: Vec<u8>,
+    transaction_id: [u8; 12],
+    mapped_address: Option<SocketAddr>,
+    done: bool,
+}
+
+impl ServerReflexiveCandidate {
+    /// Asks every STUN server of the family of `host` at once, from the socket of `host` so
+    /// that the mappings are of the host candidate. Servers that see the same address give
+    /// one candidate, none when it is the host address itself (RFC 8445 section 5.1.3).
+    /// Servers that did not answer within `timeout` are left out.
+    pub fn fetch_info(
+        host: &HostCandidate,
+        stun_servers: &[SocketAddr],
+        timeout: Duration,
+    ) -> Vec<Self> {
+        let base = host.candidate.base;
+        let mut queries: Vec<ServerReflexiveQuery> = stun_servers
+            .iter()
+            .filter(|x| x.is_ipv4() == base.is_ipv4())
+            .filter_map(|x| Self::new_query(*x))
+            .collect();
+        if queries.is_empty() {
+            return Vec::new();
+        }
+        let old_read_timeout = match host.socket.read_timeout() {
+            Ok(read_timeout) => read_timeout,
+            Err(e) => {
+                warn!("Error reading timeout of {:?}: {}", base, e);
+                return Vec::new();
             }
+        };
+        Self::transact(&host.socket, &mut queries, timeout);
+        match host.socket.set_read_timeout(old_read_timeout) {
+            Ok(()) => {}
+            Err(e) => warn!("Error resetting timeout of {:?}: {}", base, e),
+        }
+
+        let mut candidates: Vec<Self> = Vec::new();
+        for query in queries {
+            let mapped_address = match query.mapped_address {
+                Some(mapped_address) => mapped_address,
+                None => continue,
+            };
+            if mapped_address == base {
+                debug!("{:?} saw the base {:?}, no NAT", query.stun_server, base);
+                continue;
+            }
+            if candidates
+                .iter()
+                .any(|x| x.candidate.address == mapped_address)
+            {
+                debug!("{:?} also saw {:?}", query.stun_server, mapped_address);
+                continue;
+            }
+            info!(
+                "Server reflexive address of {:?}: {:?} (from {:?})",
+                base, mapped_address, query.stun_server
+            );
+            let mut candidate =
+                Candidate::new_server_reflexive(mapped_address, base, host.candidate.component_id);
+            candidate.server = Some(query.stun_server);
+            candidates.push(ServerReflexiveCandidate {
+                candidate,
+                stun_server: query.stun_server,
+            });
+        }
+        return candidates;
+    }
+
+    fn new_query(stun_server: SocketAddr) -> Option<ServerReflexiveQuery> {
+        let request =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        let transaction_id = request.header.transaction_id;
+        let mut request_bin = Vec::new();
+        match request.encode(
+            &mut Cursor::new(&mut request_bin),
+            &Some(&STUNContext::new()),
+        ) {
+            Ok(_) => {}
             Err(e) => {
-                error!("{:?}", e);
-                None
+                warn!("Error encoding Binding request: {:?}", e);
+                return None;
             }
         }
+        return Some(ServerReflexiveQuery {
+            stun_server,
+            request_bin,
+            transaction_id,
+            mapped_address: None,
+            done: false,
+        });
+    }
+
+    //Sends all of the requests and reads the responses as they come, until each server
+    //answered or `timeout` passed
+    fn transact(socket: &UdpSocket, queries: &mut [ServerReflexiveQuery], timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut next_send = Instant::now();
+        let mut rto = SERVER_REFLEXIVE_INITIAL_RTO;
+        let mut buf = vec![0; SERVER_REFLEXIVE_RECEIVE_BUFFER_SIZE];
+        while queries.iter().any(|x| !x.done) {
+            let now = Instant::now();
+            if now >= deadline {
+                for query in queries.iter().filter(|x| !x.done) {
+                    info!("No Binding response from {:?}", query.stun_server);
+                }
+                return;
+            }
+            if now >= next_send {
+                for query in queries.iter().filter(|x| !x.done) {
+                    match socket.send_to(&query.request_bin, query.stun_server) {
+                        Ok(_) => {}
+                        Err(e) => warn!("Error sending to {:?}: {}", query.stun_server, e),
+                    }
+                }
+                next_send = now + rto;
+                rto *= 2;
+            }
+            match socket.set_read_timeout(Some(next_send.min(deadline) - now)) {
+                Ok(()) => {}
+                Err(e) => {
+                    warn!("Error setting read timeout: {}", e);
+                    return;
+                }
+            }
+            let (len, source) = match socket.recv_from(&mut buf) {
+                Ok(x) => x,
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Error receiving Binding responses: {}", e);
+                    return;
+                }
+            };
+            let response = match STUN::decode(
+                &mut Cursor::new(&buf[..len]),
+                &mut Some(&mut STUNContext::new()),
+            ) {
+                Ok(response) => response,
+                Err(e) => {
+                    debug!("Ignoring datagram from {:?}: {:?}", source, e);
+                    continue;
+                }
+            };
+            let query = match queries.iter_mut().find(|x| {
+                !x.done
+                    && x.stun_server == source
+                    && x.transaction_id == response.header.transaction_id
+            }) {
+                Some(query) => query,
+                None => {
+                    debug!("Ignoring unexpected message from {:?}", source);
+                    continue;
+                }
+            };
+            query.done = true;
+            if response.header.message_class != STUNMessageClass::ResponseSuccess {
+                warn!("Binding request to {:?} failed", source);
+                continue;
+            }
+            query.mapped_address = response.body.attributes.iter().find_map(|x| match x.value {
+                STUNAttributesContent::XORMappedAddress { address } => Some(address),
+                STUNAttributesContent::MappedAddress { address } => Some(address),
+                _ => None,
+            });
+        }
     }
-    fn punch_nat_hole(&self) -> bool {
-        // match stunClient::StunClient::NATHolePunching(
-        //     8081,
-        //     SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 2003),
-        // ) {
-        //     Ok(()) => {}
-        //     Err(e) => error!("{:?}", e),
-        // }
-        todo!()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CherrySTUN::stunAttributes::STUNAttributeType;
+    use crate::CherrySTUN::stunServer::StunServer;
+
+    //Answers the first Binding request with `mapped_address`, as a server behind a NAT of the
+    //client would
+    fn spawn_nat_server(mapped_address: SocketAddr) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = vec![0; SERVER_REFLEXIVE_RECEIVE_BUFFER_SIZE];
+            let (len, source) = socket.recv_from(&mut buf).unwrap();
+            let request = STUN::decode(
+                &mut Cursor::new(&buf[..len]),
+                &mut Some(&mut STUNContext::new()),
+            )
+            .unwrap();
+            let mut response = STUN::new_default(
+                STUNMessageClass::ResponseSuccess,
+                STUNMessageMethod::Binding,
+                Some(request.header.transaction_id),
+            );
+            response.body.add_new_attribute(
+                STUNAttributesContent::new_xor_mapped_address(mapped_address),
+                STUNAttributeType::XORMappedAddress,
+                0,
+            );
+            let mut response_bin = Vec::new();
+            response
+                .encode(
+                    &mut Cursor::new(&mut response_bin),
+                    &Some(&STUNContext::new()),
+                )
+                .unwrap();
+            socket.send_to(&response_bin, source).unwrap();
+        });
+        return address;
+    }
+
+    #[test]
+    fn test_fetch_info_from_host_socket() {
+        //Sees the host address itself on loopback, nothing to add to the host candidate
+        let local = StunServer::new()
+            .spawn("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        let mapped_address: SocketAddr = "203.0.113.7:61000".parse().unwrap();
+        let first = spawn_nat_server(mapped_address);
+        let second = spawn_nat_server(mapped_address);
+        //Nothing listens there, it is given up on
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        silent.set_nonblocking(true).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let host = HostCandidate {
+            candidate: Candidate::new_host(address, 1),
+            socket,
+            interface: "lo".to_string(),
+        };
+        let stun_servers = [
+            silent.local_addr().unwrap(),
+            local.local_addr,
+            first,
+            second,
+            "[::1]:3478".parse().unwrap(),
+        ];
+        let candidates =
+            ServerReflexiveCandidate::fetch_info(&host, &stun_servers, Duration::from_millis(1800));
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].candidate.address, mapped_address);
+        assert_eq!(candidates[0].candidate.base, address);
+        assert_eq!(candidates[0].candidate.related_address, Some(address));
+        assert_eq!(candidates[0].stun_server, first);
+
+        //Sent at 0, 500 and 1500 ms
+        let mut buf = [0; SERVER_REFLEXIVE_RECEIVE_BUFFER_SIZE];
+        let mut transmissions = 0;
+        while silent.recv_from(&mut buf).is_ok() {
+            transmissions += 1;
+        }
+        assert_eq!(transmissions, 3);
     }
 }
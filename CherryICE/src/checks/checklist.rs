@@ -0,0 +1,352 @@
+use crate::candidates::candidates::{Candidate, CandidateType};
+use log::debug;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+//RFC 8445 section 6.1.2.5 suggests 100 as the limit
+pub const DEFAULT_MAX_CANDIDATE_PAIRS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidatePairState {
+    /// Not checked until a pair of the same foundation succeeded
+    Frozen,
+    /// To be checked
+    Waiting,
+    InProgress,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidatePair {
+    pub local: Candidate,
+    pub remote: Candidate,
+    pub priority: u64,
+    pub state: CandidatePairState,
+}
+
+impl CandidatePair {
+    pub fn new(local: Candidate, remote: Candidate, controlling: bool) -> Self {
+        let priority = match controlling {
+            true => pair_priority(local.priority, remote.priority),
+            false => pair_priority(remote.priority, local.priority),
+        };
+        return CandidatePair {
+            local,
+            remote,
+            priority,
+            state: CandidatePairState::Frozen,
+        };
+    }
+
+    /// Pairs of the same foundation are likely to behave the same, one is checked first
+    pub fn foundation(&self) -> (&str, &str) {
+        return (&self.local.foundation, &self.remote.foundation);
+    }
+
+    //Checks of the pair are sent from the local base to the remote candidate (RFC 8445
+    //section 6.1.2.4)
+    fn is_redundant_with(&self, other: &CandidatePair) -> bool {
+        return self.local.base == other.local.base
+            && self.local.transport == other.local.transport
+            && self.remote.address == other.remote.address
+            && self.remote.component_id == other.remote.component_id;
+    }
+}
+
+/// priority = 2^32*MIN(G,D) + 2*MAX(G,D) + (G>D?1:0), G being the priority of the candidate
+/// of the controlling agent and D the one of the controlled agent (RFC 8445 section
+/// 6.1.2.3)
+pub fn pair_priority(controlling: u32, controlled: u32) -> u64 {
+    let (g, d) = (controlling as u64, controlled as u64);
+    //Candidate priorities are below 2^31, remote ones may not be
+    return (g.min(d) << 32).saturating_add(2 * g.max(d) + (g > d) as u64);
+}
+
+// Checklist (RFC 8445 section 6.1.2) :
+// Local and remote candidates of the same component and address family are paired, the
+// pairs sorted by decreasing priority. Server reflexive local candidates are replaced by
+// their base, we can't send from a server reflexive address, making their pairs redundant
+// with the ones of the host candidate, which are kept. The lowest priority pairs go past
+// `max_pairs`.
+pub struct CheckList {
+    pairs: Vec<CandidatePair>,
+    max_pairs: usize,
+    controlling: bool,
+}
+
+impl Default for CheckList {
+    fn default() -> Self {
+        CheckList {
+            pairs: Vec::new(),
+            max_pairs: DEFAULT_MAX_CANDIDATE_PAIRS,
+            controlling: false,
+        }
+    }
+}
+
+impl CheckList {
+    pub fn new(controlling: bool) -> Self {
+        return CheckList {
+            controlling,
+            ..Default::default()
+        };
+    }
+
+    /// Set `max_pairs` field, builder pattern.
+    pub fn set_max_pairs(&mut self, max_pairs: usize) -> &mut Self {
+        self.max_pairs = max_pairs;
+        self.truncate();
+        return self;
+    }
+
+    /// Switching roles (on a role conflict) changes pair priorities, the pairs are sorted again
+    pub fn set_controlling(&mut self, controlling: bool) -> &mut Self {
+        self.controlling = controlling;
+        for pair in self.pairs.iter_mut() {
+            pair.priority = match controlling {
+                true => pair_priority(pair.local.priority, pair.remote.priority),
+                false => pair_priority(pair.remote.priority, pair.local.priority),
+            };
+        }
+        self.sort();
+        return self;
+    }
+
+    pub fn controlling(&self) -> bool {
+        return self.controlling;
+    }
+
+    /// Highest priority first
+    pub fn pairs(&self) -> &[CandidatePair] {
+        return &self.pairs;
+    }
+
+    pub fn pairs_mut(&mut self) -> &mut [CandidatePair] {
+        return &mut self.pairs;
+    }
+
+    /// Forms the checklist from all of the local and remote candidates so far, replacing
+    /// the pairs there were and setting initial states
+    pub fn form(&mut self, locals: &[Candidate], remotes: &[Candidate]) {
+        self.pairs.clear();
+        for local in locals {
+            for remote in remotes {
+                match self.new_pair(local, remote, locals) {
+                    Some(pair) => {
+                        self.insert_pair(pair);
+                    }
+                    None => {}
+                }
+            }
+        }
+        self.set_initial_states();
+        debug!("Formed checklist of {} pairs", self.pairs.len());
+    }
+
+    /// Adds the pair of a candidate learnt after the checklist was formed (trickled or peer
+    /// reflexive), in the state the caller sets. Returns false when it was redundant or past
+    /// `max_pairs`.
+    pub fn add_pair(&mut self, pair: CandidatePair) -> bool {
+        return self.insert_pair(pair);
+    }
+
+    /// Pair of `local` and `remote` per RFC 8445 section 6.1.2.2, `None` when they can't be
+    /// paired. `locals` are searched for the base of server reflexive candidates.
+    pub fn new_pair(
+        &self,
+        local: &Candidate,
+        remote: &Candidate,
+        locals: &[Candidate],
+    ) -> Option<CandidatePair> {
+        if local.component_id != remote.component_id
+            || local.transport != remote.transport
+            || local.address.is_ipv4() != remote.address.is_ipv4()
+        {
+            return None;
+        }
+        let local = match local.candidate_type {
+            CandidateType::ServerReflexive => match locals
+                .iter()
+                .find(|x| x.candidate_type == CandidateType::Host && x.address == local.base)
+            {
+                Some(host) => host,
+                None => local,
+            },
+            _ => local,
+        };
+        return Some(CandidatePair::new(
+            local.clone(),
+            remote.clone(),
+            self.controlling,
+        ));
+    }
+
+    //Keeps the order and `max_pairs`, a pair redundant with a higher priority one is dropped
+    //and one with a lower priority replaced
+    fn insert_pair(&mut self, pair: CandidatePair) -> bool {
+        match self.pairs.iter().position(|x| x.is_redundant_with(&pair)) {
+            Some(i) if self.pairs[i].priority >= pair.priority => return false,
+            Some(i) => {
+                self.pairs.remove(i);
+            }
+            None => {}
+        }
+        let position = self.pairs.partition_point(|x| x.priority >= pair.priority);
+        self.pairs.insert(position, pair);
+        self.truncate();
+        return position < self.pairs.len();
+    }
+
+    fn sort(&mut self) {
+        self.pairs.sort_by_key(|x| Reverse(x.priority));
+    }
+
+    fn truncate(&mut self) {
+        if self.pairs.len() > self.max_pairs {
+            debug!(
+                "Dropping {} lowest priority pairs",
+                self.pairs.len() - self.max_pairs
+            );
+            self.pairs.truncate(self.max_pairs);
+        }
+    }
+
+    //All frozen but the pair of each foundation with the lowest component ID, the highest
+    //priority one among them (RFC 8445 section 6.1.2.6)
+    fn set_initial_states(&mut self) {
+        let mut first_of_foundation: HashMap<(&str, &str), usize> = HashMap::new();
+        for (i, pair) in self.pairs.iter().enumerate() {
+            match first_of_foundation.get(&pair.foundation()) {
+                Some(j) if self.pairs[*j].local.component_id <= pair.local.component_id => {}
+                _ => {
+                    first_of_foundation.insert(pair.foundation(), i);
+                }
+            }
+        }
+        let waiting: Vec<usize> = first_of_foundation.into_values().collect();
+        for (i, pair) in self.pairs.iter_mut().enumerate() {
+            pair.state = match waiting.contains(&i) {
+                true => CandidatePairState::Waiting,
+                false => CandidatePairState::Frozen,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::candidates::priority::CandidatePrioritizer;
+    use std::net::SocketAddr;
+
+    fn addr(s: &str) -> SocketAddr {
+        return s.parse().unwrap();
+    }
+
+    fn local_candidates() -> Vec<Candidate> {
+        let host = addr("192.168.1.2:50000");
+        let host_rtcp = addr("192.168.1.2:50001");
+        let host_v6 = addr("[2001:db8::2]:50000");
+        let mut server_reflexive =
+            Candidate::new_server_reflexive(addr("203.0.113.7:61000"), host, 1);
+        server_reflexive.server = Some(addr("198.51.100.2:3478"));
+        let mut candidates = vec![
+            Candidate::new_host(host, 1),
+            Candidate::new_host(host_rtcp, 2),
+            Candidate::new_host(host_v6, 1),
+            server_reflexive,
+        ];
+        CandidatePrioritizer::new().prioritize(
+            &mut candidates,
+            &[
+                ("eth0".to_string(), host.ip()),
+                ("eth0".to_string(), host_v6.ip()),
+            ],
+        );
+        return candidates;
+    }
+
+    fn remote_candidates() -> Vec<Candidate> {
+        let mut candidates = vec![
+            Candidate::new_host(addr("10.0.0.5:40000"), 1),
+            Candidate::new_host(addr("10.0.0.5:40001"), 2),
+            Candidate::new_relayed(addr("198.51.100.9:49170"), None, 1),
+        ];
+        candidates[2].server = Some(addr("198.51.100.9:3478"));
+        CandidatePrioritizer::new().prioritize(&mut candidates, &[]);
+        return candidates;
+    }
+
+    #[test]
+    fn test_pair_priority() {
+        assert_eq!(pair_priority(1, 2), (1 << 32) + 4);
+        assert_eq!(pair_priority(2, 1), (1 << 32) + 4 + 1);
+        assert_eq!(
+            pair_priority(2130706431, 1694498815),
+            (1694498815 << 32) + 2 * 2130706431 + 1
+        );
+        assert_eq!(pair_priority(u32::MAX, u32::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_form_checklist() {
+        let locals = local_candidates();
+        let remotes = remote_candidates();
+        let mut checklist = CheckList::new(true);
+        checklist.form(&locals, &remotes);
+
+        //IPv6 host has no remote of its family, server reflexive is pruned
+        let pairs = checklist.pairs();
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs
+            .iter()
+            .all(|x| x.local.candidate_type == CandidateType::Host));
+        assert!(pairs.windows(2).all(|x| x[0].priority >= x[1].priority));
+        assert_eq!(
+            pairs[0].priority,
+            pair_priority(locals[0].priority, remotes[0].priority)
+        );
+        //Host-host pairs of both components share a foundation, the RTP one goes first
+        assert_eq!(pairs[0].remote.address, remotes[0].address);
+        assert_eq!(pairs[0].state, CandidatePairState::Waiting);
+        assert_eq!(pairs[1].remote.address, remotes[1].address);
+        assert_eq!(pairs[1].state, CandidatePairState::Frozen);
+        assert_eq!(pairs[2].remote.address, remotes[2].address);
+        assert_eq!(pairs[2].state, CandidatePairState::Waiting);
+
+        checklist.set_controlling(false);
+        assert_eq!(
+            checklist.pairs()[0].priority,
+            pair_priority(remotes[0].priority, locals[0].priority)
+        );
+    }
+
+    #[test]
+    fn test_max_pairs() {
+        let mut checklist = CheckList::new(false);
+        checklist.set_max_pairs(2);
+        checklist.form(&local_candidates(), &remote_candidates());
+        let states: Vec<CandidatePairState> = checklist.pairs().iter().map(|x| x.state).collect();
+        assert_eq!(
+            states,
+            vec![CandidatePairState::Waiting, CandidatePairState::Frozen]
+        );
+
+        let locals = local_candidates();
+        let mut remote = Candidate::new_host(addr("10.0.0.6:40000"), 1);
+        remote.priority = 2130706431;
+        let pair = checklist.new_pair(&locals[3], &remote, &locals).unwrap();
+        assert_eq!(pair.local, locals[0]);
+        assert!(checklist.add_pair(pair));
+        assert_eq!(checklist.pairs().len(), 2);
+        assert_eq!(checklist.pairs()[1].remote.address, remote.address);
+        let redundant = checklist.new_pair(&locals[0], &remote, &locals).unwrap();
+        assert!(!checklist.add_pair(redundant));
+        let mut lowest = Candidate::new_host(addr("10.0.0.7:40000"), 1);
+        lowest.priority = 1;
+        let pair = checklist.new_pair(&locals[0], &lowest, &locals).unwrap();
+        assert!(!checklist.add_pair(pair));
+        assert_eq!(checklist.pairs().len(), 2);
+    }
+}
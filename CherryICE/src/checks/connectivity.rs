@@ -0,0 +1,1140 @@
+use super::checklist::{CandidatePair, CandidatePairState, CheckList};
+use crate::candidates::candidates::{Candidate, CandidateType};
+use crate::candidates::priority::{compute_foundation, compute_priority, CandidatePrioritizer};
+use crate::CherrySTUN::stun::STUN;
+use crate::CherrySTUN::stunAttributes::{STUNAttributesContent, STUNAuthType, STUNErrorCode};
+use crate::CherrySTUN::stunContext::STUNContext;
+use crate::CherrySTUN::stunDecode::STUNDecode;
+use crate::CherrySTUN::stunEncode::STUNEncode;
+use crate::CherrySTUN::stunHeader::{STUNMessageClass, STUNMessageMethod};
+use log::{debug, info, warn};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+//RFC 8445 section 14.2 recommends 50ms between checks
+pub const DEFAULT_TA: Duration = Duration::from_millis(50);
+//Initial retransmission timeout of a check, doubled on every retransmission (RFC 8489)
+pub const DEFAULT_CHECK_RTO: Duration = Duration::from_millis(500);
+pub const DEFAULT_CHECK_MAX_TRANSMISSIONS: u32 = 7;
+
+/// Username fragment and password of one agent, exchanged in the SDP (ice-ufrag, ice-pwd)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceCredentials {
+    pub ufrag: String,
+    pub password: String,
+}
+
+/// A datagram for the caller to send from the socket of `base`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transmit {
+    pub base: SocketAddr,
+    pub destination: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+//An outstanding check of the pair (local_base, remote)
+struct CheckTransaction {
+    local_base: SocketAddr,
+    remote: SocketAddr,
+    request_bin: Vec<u8>,
+    transmissions: u32,
+    rto: Duration,
+    next_retransmit: Instant,
+    //Replaced by a triggered check: not sent again, but a response is still taken until
+    //`next_retransmit` (RFC 8445 section 7.3.1.4)
+    cancelled: bool,
+}
+
+// Connectivity checks (RFC 8445 section 7) :
+// Every Ta one check goes out, a triggered one if any, otherwise the highest priority
+// Waiting pair, otherwise a Frozen pair whose foundation has nothing Waiting or
+// In-Progress. Checks are Binding requests with USERNAME "remote ufrag:local ufrag",
+// signed with the password of the peer, answered with a XOR-MAPPED-ADDRESS signed with
+// our own password. A peer's check queues a triggered check of the pair it came on.
+// A success response makes the pair Succeeded and unfreezes the pairs of its foundation,
+// the valid pair being the one of the local candidate the XOR-MAPPED-ADDRESS is.
+// Peer reflexive candidates (RFC 8445 sections 7.2.5.3.1 and 7.3.1.3) are learnt from
+// checks coming from an address that is not a remote candidate, and from mapped addresses
+// that are not local candidates. They get the priority of the PRIORITY attribute.
+//
+// Nothing is sent or received here, the caller moves `Transmit`s and incoming datagrams
+// between this and the sockets of the host candidates, like `StunServer::handle_message`.
+pub struct ConnectivityChecks {
+    checklist: CheckList,
+    locals: Vec<Candidate>,
+    remotes: Vec<Candidate>,
+    local_credentials: IceCredentials,
+    remote_credentials: IceCredentials,
+    tie_breaker: u64,
+    prioritizer: CandidatePrioritizer,
+    ta: Duration,
+    rto: Duration,
+    max_transmissions: u32,
+    next_check: Option<Instant>,
+    //(local base, remote address) of the pairs to check next
+    triggered: VecDeque<(SocketAddr, SocketAddr)>,
+    transactions: HashMap<[u8; 12], CheckTransaction>,
+    valid_pairs: Vec<CandidatePair>,
+}
+
+impl ConnectivityChecks {
+    /// `checklist` is formed from `locals`, which are looked up for the mapped addresses of
+    /// responses
+    pub fn new(
+        checklist: CheckList,
+        locals: Vec<Candidate>,
+        local_credentials: IceCredentials,
+        remote_credentials: IceCredentials,
+    ) -> Self {
+        let mut remotes: Vec<Candidate> = Vec::new();
+        for pair in checklist.pairs() {
+            if !remotes.contains(&pair.remote) {
+                remotes.push(pair.remote.clone());
+            }
+        }
+        ConnectivityChecks {
+            checklist,
+            locals,
+            remotes,
+            local_credentials,
+            remote_credentials,
+            tie_breaker: rand::thread_rng().gen(),
+            prioritizer: CandidatePrioritizer::new(),
+            ta: DEFAULT_TA,
+            rto: DEFAULT_CHECK_RTO,
+            max_transmissions: DEFAULT_CHECK_MAX_TRANSMISSIONS,
+            next_check: None,
+            triggered: VecDeque::new(),
+            transactions: HashMap::new(),
+            valid_pairs: Vec::new(),
+        }
+    }
+
+    /// Set `ta` field, builder pattern.
+    pub fn set_ta(&mut self, ta: Duration) -> &mut Self {
+        self.ta = ta;
+        self
+    }
+
+    /// Set `rto` field, builder pattern.
+    pub fn set_rto(&mut self, rto: Duration) -> &mut Self {
+        self.rto = rto;
+        self
+    }
+
+    /// Set `max_transmissions` field, builder pattern.
+    pub fn set_max_transmissions(&mut self, max_transmissions: u32) -> &mut Self {
+        self.max_transmissions = max_transmissions;
+        self
+    }
+
+    /// Set `tie_breaker` field, builder pattern.
+    pub fn set_tie_breaker(&mut self, tie_breaker: u64) -> &mut Self {
+        self.tie_breaker = tie_breaker;
+        self
+    }
+
+    /// Set `prioritizer` field, used for the PRIORITY of checks, builder pattern.
+    pub fn set_prioritizer(&mut self, prioritizer: CandidatePrioritizer) -> &mut Self {
+        self.prioritizer = prioritizer;
+        self
+    }
+
+    pub fn checklist(&self) -> &CheckList {
+        return &self.checklist;
+    }
+
+    pub fn controlling(&self) -> bool {
+        return self.checklist.controlling();
+    }
+
+    /// Local candidates, with the peer reflexive ones learnt so far
+    pub fn local_candidates(&self) -> &[Candidate] {
+        return &self.locals;
+    }
+
+    /// Remote candidates of the checklist, with the peer reflexive ones learnt so far
+    pub fn remote_candidates(&self) -> &[Candidate] {
+        return &self.remotes;
+    }
+
+    /// Pairs checks succeeded on, by the local candidate the peer saw
+    pub fn valid_pairs(&self) -> &[CandidatePair] {
+        return &self.valid_pairs;
+    }
+
+    /// When `poll` has something to send next, `None` when waiting for the peer only
+    pub fn next_timeout(&self) -> Option<Instant> {
+        let next_retransmit = self
+            .transactions
+            .values()
+            .filter(|x| !x.cancelled)
+            .map(|x| x.next_retransmit)
+            .min();
+        let pairs = self.checklist.pairs();
+        //Same pairs as `next_pair` picks from, triggered ones are Waiting
+        let has_checks = pairs.iter().any(|x| can_check(pairs, x));
+        let next_check = match has_checks {
+            true => Some(self.next_check.unwrap_or_else(Instant::now)),
+            false => None,
+        };
+        return match (next_retransmit, next_check) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// Next datagram to send, if any is due at `now`. Called until it returns `None`.
+    pub fn poll(&mut self, now: Instant) -> Option<Transmit> {
+        match self.poll_retransmit(now) {
+            Some(transmit) => return Some(transmit),
+            None => {}
+        }
+        match self.next_check {
+            Some(next_check) if now < next_check => return None,
+            _ => {}
+        }
+        let (local_base, remote) = match self.next_pair() {
+            Some(key) => key,
+            None => return None,
+        };
+        self.next_check = Some(now + self.ta);
+        return self.send_check(local_base, remote, now);
+    }
+
+    /// Handles a datagram `source` sent to the socket of `base`. Returns the response to send
+    /// back for requests.
+    pub fn on_message(
+        &mut self,
+        base: SocketAddr,
+        source: SocketAddr,
+        data: &[u8],
+    ) -> Option<Transmit> {
+        let mut context = STUNContext::new();
+        context.defer_integrity_check = true;
+        let message = match STUN::decode(&mut Cursor::new(data), &mut Some(&mut context)) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring undecodable packet from {:?}: {:?}", source, e);
+                return None;
+            }
+        };
+        if message.header.message_method != STUNMessageMethod::Binding {
+            return None;
+        }
+        match message.header.message_class {
+            STUNMessageClass::Request => {
+                return self.handle_request(base, source, message, context);
+            }
+            STUNMessageClass::ResponseSuccess | STUNMessageClass::ResponseError => {
+                self.handle_response(base, source, message, context);
+                return None;
+            }
+            STUNMessageClass::Indication => return None,
+        }
+    }
+
+    fn poll_retransmit(&mut self, now: Instant) -> Option<Transmit> {
+        self.transactions
+            .retain(|_, x| !x.cancelled || x.next_retransmit > now);
+        let transaction_id = match self
+            .transactions
+            .iter()
+            .filter(|(_, x)| !x.cancelled && x.next_retransmit <= now)
+            .min_by_key(|(_, x)| x.next_retransmit)
+        {
+            Some((transaction_id, _)) => *transaction_id,
+            None => return None,
+        };
+        let transaction = match self.transactions.get_mut(&transaction_id) {
+            Some(transaction) => transaction,
+            None => return None,
+        };
+        if transaction.transmissions >= self.max_transmissions {
+            let (local_base, remote) = (transaction.local_base, transaction.remote);
+            self.transactions.remove(&transaction_id);
+            info!("Check {:?} -> {:?} timed out", local_base, remote);
+            self.set_pair_state(local_base, remote, CandidatePairState::Failed);
+            return self.poll_retransmit(now);
+        }
+        transaction.transmissions += 1;
+        transaction.rto *= 2;
+        transaction.next_retransmit = now + transaction.rto;
+        return Some(Transmit {
+            base: transaction.local_base,
+            destination: transaction.remote,
+            data: transaction.request_bin.clone(),
+        });
+    }
+
+    //Triggered checks first, then Waiting pairs, then unfreezing (RFC 8445 section 6.1.4.2)
+    fn next_pair(&mut self) -> Option<(SocketAddr, SocketAddr)> {
+        while let Some((local_base, remote)) = self.triggered.pop_front() {
+            match self.pair_mut(local_base, remote) {
+                Some(pair) if pair.state == CandidatePairState::Waiting => {
+                    return Some((local_base, remote))
+                }
+                _ => {}
+            }
+        }
+        let pairs = self.checklist.pairs();
+        match pairs
+            .iter()
+            .find(|x| x.state == CandidatePairState::Waiting)
+        {
+            Some(pair) => return Some((pair.local.base, pair.remote.address)),
+            None => {}
+        }
+        let pair = match pairs.iter().find(|x| can_check(pairs, x)) {
+            Some(pair) => (pair.local.base, pair.remote.address),
+            None => return None,
+        };
+        return Some(pair);
+    }
+
+    fn pair_mut(
+        &mut self,
+        local_base: SocketAddr,
+        remote: SocketAddr,
+    ) -> Option<&mut CandidatePair> {
+        return self
+            .checklist
+            .pairs_mut()
+            .iter_mut()
+            .find(|x| x.local.base == local_base && x.remote.address == remote);
+    }
+
+    fn set_pair_state(
+        &mut self,
+        local_base: SocketAddr,
+        remote: SocketAddr,
+        state: CandidatePairState,
+    ) {
+        match self.pair_mut(local_base, remote) {
+            Some(pair) => pair.state = state,
+            None => {}
+        }
+    }
+
+    //Queues a triggered check of the pair, cancelling a check of it still in progress (RFC
+    //8445 section 7.3.1.4). The response to that one may still come and is taken.
+    fn trigger_check(&mut self, local_base: SocketAddr, remote: SocketAddr) {
+        let pair = match self.pair_mut(local_base, remote) {
+            Some(pair) => pair,
+            None => return,
+        };
+        if pair.state == CandidatePairState::Succeeded {
+            return;
+        }
+        pair.state = CandidatePairState::Waiting;
+        for transaction in self.transactions.values_mut() {
+            if transaction.local_base == local_base && transaction.remote == remote {
+                //One more RTO for the response to come
+                transaction.cancelled = true;
+                transaction.next_retransmit += transaction.rto;
+            }
+        }
+        if !self.triggered.contains(&(local_base, remote)) {
+            self.triggered.push_back((local_base, remote));
+        }
+    }
+
+    //Priority of the peer reflexive candidate the check could discover
+    fn peer_reflexive_priority(&self, local: &Candidate) -> u32 {
+        return compute_priority(
+            self.prioritizer
+                .type_preference(CandidateType::PeerReflexive),
+            ((local.priority >> 8) & 0xFFFF) as u16,
+            local.component_id,
+        );
+    }
+
+    fn send_check(
+        &mut self,
+        local_base: SocketAddr,
+        remote: SocketAddr,
+        now: Instant,
+    ) -> Option<Transmit> {
+        let local = match self.pair_mut(local_base, remote) {
+            Some(pair) => {
+                pair.state = CandidatePairState::InProgress;
+                pair.local.clone()
+            }
+            None => return None,
+        };
+        let mut request =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        let role = match self.controlling() {
+            true => STUNAttributesContent::new_ice_controlling(self.tie_breaker),
+            false => STUNAttributesContent::new_ice_controlled(self.tie_breaker),
+        };
+        for attribute in [
+            STUNAttributesContent::new_username(self.check_username()),
+            STUNAttributesContent::new_priority(self.peer_reflexive_priority(&local)),
+            role,
+            STUNAttributesContent::MessageIntegrity {
+                authType: STUNAuthType::ShortTerm,
+            },
+            STUNAttributesContent::Fingerprint,
+        ] {
+            let attribute_type = attribute.attribute_type();
+            request.body.add_new_attribute(attribute, attribute_type, 0);
+        }
+        let mut context = STUNContext::new();
+        context.username = Some(self.check_username());
+        context.password = Some(self.remote_credentials.password.clone());
+        let mut request_bin = Vec::new();
+        match request.encode(&mut Cursor::new(&mut request_bin), &Some(&context)) {
+            Ok(()) => {}
+            Err(e) => {
+                warn!("Error encoding check: {:?}", e);
+                self.set_pair_state(local_base, remote, CandidatePairState::Failed);
+                return None;
+            }
+        }
+        debug!("Checking {:?} -> {:?}", local_base, remote);
+        self.transactions.insert(
+            request.header.transaction_id,
+            CheckTransaction {
+                local_base,
+                remote,
+                request_bin: request_bin.clone(),
+                transmissions: 1,
+                rto: self.rto,
+                next_retransmit: now + self.rto,
+                cancelled: false,
+            },
+        );
+        return Some(Transmit {
+            base: local_base,
+            destination: remote,
+            data: request_bin,
+        });
+    }
+
+    //USERNAME of our checks, the peer's checks come with the ufrags the other way round
+    fn check_username(&self) -> String {
+        return format!(
+            "{}:{}",
+            self.remote_credentials.ufrag, self.local_credentials.ufrag
+        );
+    }
+
+    fn handle_request(
+        &mut self,
+        base: SocketAddr,
+        source: SocketAddr,
+        request: STUN,
+        context: STUNContext,
+    ) -> Option<Transmit> {
+        let transaction_id = request.header.transaction_id;
+        let expected_username = format!(
+            "{}:{}",
+            self.local_credentials.ufrag, self.remote_credentials.ufrag
+        );
+        let integrity = match (&context.username, &context.received_integrity) {
+            (Some(_), Some(integrity)) => integrity,
+            _ => {
+                debug!("Check from {:?} without credentials", source);
+                return self.error_response(
+                    base,
+                    source,
+                    transaction_id,
+                    STUNErrorCode::BadRequest,
+                );
+            }
+        };
+        let key =
+            match STUNAttributesContent::short_term_key(self.local_credentials.password.clone()) {
+                Ok(key) => key,
+                Err(e) => {
+                    warn!("Error preparing short-term key: {:?}", e);
+                    return None;
+                }
+            };
+        if context.username.as_deref() != Some(expected_username.as_str())
+            || !integrity.verify(&key)
+        {
+            debug!("Check from {:?} with wrong credentials", source);
+            return self.error_response(base, source, transaction_id, STUNErrorCode::Unauthorized);
+        }
+
+        //Role conflicts (RFC 8445 section 7.3.1.1), the larger tiebreaker gets controlling
+        let mut remote_controlling = None;
+        for attribute in &request.body.attributes {
+            match attribute.value {
+                STUNAttributesContent::IceControlling { tie_breaker } => {
+                    remote_controlling = Some((true, tie_breaker))
+                }
+                STUNAttributesContent::IceControlled { tie_breaker } => {
+                    remote_controlling = Some((false, tie_breaker))
+                }
+                _ => {}
+            }
+        }
+        match remote_controlling {
+            Some((remote_controlling, tie_breaker)) if remote_controlling == self.controlling() => {
+                let keep_role = match remote_controlling {
+                    true => self.tie_breaker >= tie_breaker,
+                    false => self.tie_breaker < tie_breaker,
+                };
+                if keep_role {
+                    info!("Role conflict with {:?}, keeping our role", source);
+                    return self.error_response(
+                        base,
+                        source,
+                        transaction_id,
+                        STUNErrorCode::RoleConflict,
+                    );
+                }
+                info!("Role conflict with {:?}, switching role", source);
+                let controlling = !self.controlling();
+                self.checklist.set_controlling(controlling);
+            }
+            _ => {}
+        }
+
+        let priority = request.body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::Priority { priority } => Some(priority),
+            _ => None,
+        });
+        match priority {
+            Some(priority) => self.add_remote_pair(base, source, priority),
+            None => {
+                debug!("Check from {:?} without PRIORITY", source);
+                return self.error_response(
+                    base,
+                    source,
+                    transaction_id,
+                    STUNErrorCode::BadRequest,
+                );
+            }
+        }
+        self.trigger_check(base, source);
+        return self.success_response(base, source, transaction_id);
+    }
+
+    //Pairs the local candidate of `base` with the source of a check when there is no pair of
+    //them yet, learning a peer reflexive remote candidate if the source is not a remote
+    //candidate (RFC 8445 section 7.3.1.3)
+    fn add_remote_pair(&mut self, base: SocketAddr, source: SocketAddr, priority: u32) {
+        if self
+            .checklist
+            .pairs()
+            .iter()
+            .any(|x| x.local.base == base && x.remote.address == source)
+        {
+            return;
+        }
+        let local = match self.locals.iter().find(|x| x.address == base) {
+            Some(local) => local.clone(),
+            None => {
+                debug!("Check to {:?}, which is not a local candidate", base);
+                return;
+            }
+        };
+        let remote = match self
+            .remotes
+            .iter()
+            .find(|x| x.address == source && x.component_id == local.component_id)
+        {
+            Some(remote) => remote.clone(),
+            None => {
+                let mut remote = Candidate::new(
+                    CandidateType::PeerReflexive,
+                    local.transport,
+                    source,
+                    source,
+                    None,
+                    local.component_id,
+                );
+                remote.priority = priority;
+                remote.foundation = compute_foundation(&remote);
+                info!("Learnt peer reflexive remote candidate {:?}", source);
+                self.remotes.push(remote.clone());
+                remote
+            }
+        };
+        let mut pair = CandidatePair::new(local, remote, self.controlling());
+        pair.state = CandidatePairState::Waiting;
+        if !self.checklist.add_pair(pair) {
+            debug!("No room for the pair {:?} -> {:?}", base, source);
+        }
+    }
+
+    fn success_response(
+        &self,
+        base: SocketAddr,
+        source: SocketAddr,
+        transaction_id: [u8; 12],
+    ) -> Option<Transmit> {
+        return self.response(
+            base,
+            source,
+            transaction_id,
+            STUNMessageClass::ResponseSuccess,
+            STUNAttributesContent::new_xor_mapped_address(source),
+            true,
+        );
+    }
+
+    fn error_response(
+        &self,
+        base: SocketAddr,
+        source: SocketAddr,
+        transaction_id: [u8; 12],
+        code: STUNErrorCode,
+    ) -> Option<Transmit> {
+        return self.response(
+            base,
+            source,
+            transaction_id,
+            STUNMessageClass::ResponseError,
+            STUNAttributesContent::new_error_code(code),
+            code == STUNErrorCode::RoleConflict,
+        );
+    }
+
+    //Signed with our password, as the peer checks with it. 400 and 401 are not, the peer
+    //did not authenticate (RFC 8489 section 9.1.3)
+    fn response(
+        &self,
+        base: SocketAddr,
+        source: SocketAddr,
+        transaction_id: [u8; 12],
+        class: STUNMessageClass,
+        attribute: STUNAttributesContent,
+        signed: bool,
+    ) -> Option<Transmit> {
+        let mut response =
+            STUN::new_default(class, STUNMessageMethod::Binding, Some(transaction_id));
+        let mut attributes = vec![attribute];
+        if signed {
+            attributes.push(STUNAttributesContent::MessageIntegrity {
+                authType: STUNAuthType::ShortTerm,
+            });
+        }
+        attributes.push(STUNAttributesContent::Fingerprint);
+        for attribute in attributes {
+            let attribute_type = attribute.attribute_type();
+            response
+                .body
+                .add_new_attribute(attribute, attribute_type, 0);
+        }
+        let mut context = STUNContext::new();
+        context.password = Some(self.local_credentials.password.clone());
+        let mut response_bin = Vec::new();
+        match response.encode(&mut Cursor::new(&mut response_bin), &Some(&context)) {
+            Ok(()) => {}
+            Err(e) => {
+                warn!("Error encoding response: {:?}", e);
+                return None;
+            }
+        }
+        return Some(Transmit {
+            base,
+            destination: source,
+            data: response_bin,
+        });
+    }
+
+    fn handle_response(
+        &mut self,
+        base: SocketAddr,
+        source: SocketAddr,
+        response: STUN,
+        context: STUNContext,
+    ) {
+        let transaction_id = response.header.transaction_id;
+        if !self.transactions.contains_key(&transaction_id) {
+            return;
+        }
+        let key =
+            match STUNAttributesContent::short_term_key(self.remote_credentials.password.clone()) {
+                Ok(key) => key,
+                Err(_) => return,
+            };
+        match &context.received_integrity {
+            Some(integrity) if integrity.verify(&key) => {}
+            _ => {
+                debug!("Ignoring unauthenticated response from {:?}", source);
+                return;
+            }
+        }
+        let transaction = match self.transactions.remove(&transaction_id) {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        let (local_base, remote) = (transaction.local_base, transaction.remote);
+        //The triggered check that replaced it decides about failures
+        if transaction.cancelled
+            && (response.header.message_class != STUNMessageClass::ResponseSuccess
+                || source != remote
+                || base != local_base)
+        {
+            return;
+        }
+
+        //Responses have to come back the way the check went (RFC 8445 section 7.2.5.2.1)
+        if source != remote || base != local_base {
+            info!(
+                "Check {:?} -> {:?} answered from {:?} to {:?}",
+                local_base, remote, source, base
+            );
+            self.set_pair_state(local_base, remote, CandidatePairState::Failed);
+            return;
+        }
+
+        if response.header.message_class == STUNMessageClass::ResponseError {
+            let code = response.body.attributes.iter().find_map(|x| match x.value {
+                STUNAttributesContent::ErrorCode { code, .. } => Some(code),
+                _ => None,
+            });
+            if code == Some(STUNErrorCode::RoleConflict as u16) {
+                info!("Role conflict reported by {:?}, switching role", remote);
+                let controlling = !self.controlling();
+                self.checklist.set_controlling(controlling);
+                self.trigger_check(local_base, remote);
+                return;
+            }
+            info!("Check {:?} -> {:?} failed: {:?}", local_base, remote, code);
+            self.set_pair_state(local_base, remote, CandidatePairState::Failed);
+            return;
+        }
+
+        let mapped_address = response.body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::XORMappedAddress { address } => Some(address),
+            _ => None,
+        });
+        let mapped_address = match mapped_address {
+            Some(mapped_address) => mapped_address,
+            None => {
+                info!("Response from {:?} without XOR-MAPPED-ADDRESS", remote);
+                self.set_pair_state(local_base, remote, CandidatePairState::Failed);
+                return;
+            }
+        };
+        let pair = match self.pair_mut(local_base, remote) {
+            Some(pair) => {
+                pair.state = CandidatePairState::Succeeded;
+                pair.clone()
+            }
+            None => return,
+        };
+        info!(
+            "Check {:?} -> {:?} succeeded, seen as {:?}",
+            local_base, remote, mapped_address
+        );
+        self.unfreeze_foundation(&pair);
+        self.add_valid_pair(&pair, mapped_address);
+    }
+
+    //Pairs of a foundation that worked are likely to work too (RFC 8445 section 7.2.5.3.3)
+    fn unfreeze_foundation(&mut self, succeeded: &CandidatePair) {
+        for pair in self.checklist.pairs_mut() {
+            if pair.state == CandidatePairState::Frozen
+                && pair.foundation() == succeeded.foundation()
+            {
+                pair.state = CandidatePairState::Waiting;
+            }
+        }
+    }
+
+    //The valid pair is made of the local candidate the mapped address is, which is not the
+    //checked one when it went through a NAT (RFC 8445 section 7.2.5.3.2). A mapped address
+    //that is no local candidate is a peer reflexive one, with the PRIORITY of the check.
+    fn add_valid_pair(&mut self, pair: &CandidatePair, mapped_address: SocketAddr) {
+        let local = match self
+            .locals
+            .iter()
+            .find(|x| x.address == mapped_address && x.base == pair.local.base)
+        {
+            Some(local) => local.clone(),
+            None => {
+                let mut local = Candidate::new(
+                    CandidateType::PeerReflexive,
+                    pair.local.transport,
+                    mapped_address,
+                    pair.local.base,
+                    Some(pair.local.base),
+                    pair.local.component_id,
+                );
+                local.priority = self.peer_reflexive_priority(&pair.local);
+                local.foundation = compute_foundation(&local);
+                info!(
+                    "Learnt peer reflexive local candidate {:?} of {:?}",
+                    mapped_address, pair.local.base
+                );
+                self.locals.push(local.clone());
+                local
+            }
+        };
+        let mut valid_pair = CandidatePair::new(local, pair.remote.clone(), self.controlling());
+        valid_pair.state = CandidatePairState::Succeeded;
+        if !self.valid_pairs.iter().any(|x| {
+            x.local.address == valid_pair.local.address
+                && x.remote.address == valid_pair.remote.address
+        }) {
+            self.valid_pairs.push(valid_pair);
+            self.valid_pairs
+                .sort_by_key(|x| std::cmp::Reverse(x.priority));
+        }
+    }
+}
+
+//Waiting pairs, and Frozen ones whose foundation has nothing Waiting or In-Progress
+fn can_check(pairs: &[CandidatePair], pair: &CandidatePair) -> bool {
+    return match pair.state {
+        CandidatePairState::Waiting => true,
+        CandidatePairState::Frozen => !pairs.iter().any(|x| {
+            x.foundation() == pair.foundation()
+                && (x.state == CandidatePairState::Waiting
+                    || x.state == CandidatePairState::InProgress)
+        }),
+        _ => false,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn credentials(ufrag: &str) -> IceCredentials {
+        return IceCredentials {
+            ufrag: ufrag.to_string(),
+            password: ufrag.to_string() + "-password-0123456789",
+        };
+    }
+
+    fn host_candidates(addresses: &[&str]) -> Vec<Candidate> {
+        let mut candidates: Vec<Candidate> = addresses
+            .iter()
+            .map(|x| Candidate::new_host(x.parse().unwrap(), 1))
+            .collect();
+        let interfaces: Vec<(String, std::net::IpAddr)> = candidates
+            .iter()
+            .map(|x| ("eth0".to_string(), x.address.ip()))
+            .collect();
+        CandidatePrioritizer::new().prioritize(&mut candidates, &interfaces);
+        return candidates;
+    }
+
+    fn agent(
+        controlling: bool,
+        locals: &[Candidate],
+        remotes: &[Candidate],
+        local_ufrag: &str,
+        remote_ufrag: &str,
+    ) -> ConnectivityChecks {
+        let mut checklist = CheckList::new(controlling);
+        checklist.form(locals, remotes);
+        return ConnectivityChecks::new(
+            checklist,
+            locals.to_vec(),
+            credentials(local_ufrag),
+            credentials(remote_ufrag),
+        );
+    }
+
+    //Delivers everything both agents have to send until they are done, as if on one network
+    fn run(a: &mut ConnectivityChecks, b: &mut ConnectivityChecks, start: Instant) -> Instant {
+        return run_behind_nat(a, b, start, None);
+    }
+
+    //`nat` maps the (internal, external) address of a, b only sees and reaches the external
+    //one
+    fn run_behind_nat(
+        a: &mut ConnectivityChecks,
+        b: &mut ConnectivityChecks,
+        start: Instant,
+        nat: Option<(SocketAddr, SocketAddr)>,
+    ) -> Instant {
+        let mut now = start;
+        for _ in 0..1000 {
+            let mut in_flight: Vec<(bool, Transmit)> = Vec::new();
+            while let Some(transmit) = a.poll(now) {
+                in_flight.push((true, transmit));
+            }
+            while let Some(transmit) = b.poll(now) {
+                in_flight.push((false, transmit));
+            }
+            while let Some((from_a, mut transmit)) = in_flight.pop() {
+                match nat {
+                    Some((internal, external)) if from_a && transmit.base == internal => {
+                        transmit.base = external
+                    }
+                    Some((internal, external)) if !from_a => {
+                        if transmit.destination != external {
+                            continue;
+                        }
+                        transmit.destination = internal;
+                    }
+                    _ => {}
+                }
+                let receiver = match from_a {
+                    true => &mut *b,
+                    false => &mut *a,
+                };
+                match receiver.on_message(transmit.destination, transmit.base, &transmit.data) {
+                    Some(response) => in_flight.push((!from_a, response)),
+                    None => {}
+                }
+            }
+            now += Duration::from_millis(10);
+        }
+        return now;
+    }
+
+    #[test]
+    fn test_checks_succeed() {
+        let a_locals = host_candidates(&["10.0.0.1:1000", "10.0.1.1:1001"]);
+        let b_locals = host_candidates(&["10.0.0.2:2000"]);
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        let mut b = agent(false, &b_locals, &a_locals, "bbbb", "aaaa");
+        let start = Instant::now();
+
+        //Paced by Ta
+        let first = a.poll(start).unwrap();
+        assert_eq!(first.destination, b_locals[0].address);
+        assert!(a.poll(start).is_none());
+        assert!(a.poll(start + DEFAULT_TA).is_some());
+
+        run(&mut a, &mut b, start + DEFAULT_TA);
+        assert!(a
+            .checklist()
+            .pairs()
+            .iter()
+            .all(|x| x.state == CandidatePairState::Succeeded));
+        assert!(b
+            .checklist()
+            .pairs()
+            .iter()
+            .all(|x| x.state == CandidatePairState::Succeeded));
+        assert_eq!(a.valid_pairs().len(), 2);
+        assert_eq!(b.valid_pairs().len(), 2);
+        assert_eq!(
+            a.valid_pairs()[0].priority,
+            a.checklist().pairs()[0].priority
+        );
+        assert!(a.controlling() && !b.controlling());
+    }
+
+    #[test]
+    fn test_next_timeout_with_frozen_pairs() {
+        //RTP and RTCP pairs share a foundation, the RTCP one stays Frozen while the RTP one
+        //is In-Progress
+        let mut a_locals = vec![
+            Candidate::new_host("10.0.0.1:1000".parse().unwrap(), 1),
+            Candidate::new_host("10.0.0.1:1001".parse().unwrap(), 2),
+        ];
+        let mut b_locals = vec![
+            Candidate::new_host("10.0.0.2:2000".parse().unwrap(), 1),
+            Candidate::new_host("10.0.0.2:2001".parse().unwrap(), 2),
+        ];
+        let prioritizer = CandidatePrioritizer::new();
+        prioritizer.prioritize(&mut a_locals, &[]);
+        prioritizer.prioritize(&mut b_locals, &[]);
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        a.set_max_transmissions(3);
+
+        //b never answers, every wakeup but the last one (the RTCP check timing out) sends
+        let mut now = Instant::now();
+        let mut wakeups = 0;
+        let mut sent = 0;
+        while let Some(timeout) = a.next_timeout() {
+            assert!(wakeups < 20);
+            now = now.max(timeout);
+            wakeups += 1;
+            while a.poll(now).is_some() {
+                sent += 1;
+            }
+        }
+        assert_eq!(sent, 6);
+        assert_eq!(wakeups, 7);
+        assert!(a
+            .checklist()
+            .pairs()
+            .iter()
+            .all(|x| x.state == CandidatePairState::Failed));
+    }
+
+    #[test]
+    fn test_triggered_check() {
+        let a_locals = host_candidates(&["10.0.0.1:1000"]);
+        let b_locals = host_candidates(&["10.0.0.2:2000"]);
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        let mut b = agent(false, &b_locals, &a_locals, "bbbb", "aaaa");
+        let now = Instant::now();
+
+        let check = a.poll(now).unwrap();
+        //b has not sent anything yet, the check of a queues a check the other way
+        b.next_check = Some(now + Duration::from_secs(60));
+        let response = b
+            .on_message(check.destination, check.base, &check.data)
+            .unwrap();
+        assert_eq!(response.destination, check.base);
+        assert_eq!(b.checklist().pairs()[0].state, CandidatePairState::Waiting);
+        assert_eq!(b.triggered.len(), 1);
+
+        assert!(a
+            .on_message(response.destination, response.base, &response.data)
+            .is_none());
+        assert_eq!(a.valid_pairs().len(), 1);
+    }
+
+    #[test]
+    fn test_crossing_checks() {
+        let a_locals = host_candidates(&["10.0.0.1:1000"]);
+        let b_locals = host_candidates(&["10.0.0.2:2000"]);
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        let mut b = agent(false, &b_locals, &a_locals, "bbbb", "aaaa");
+        let now = Instant::now();
+
+        let a_check = a.poll(now).unwrap();
+        let b_check = b.poll(now).unwrap();
+        //The check of b cancels the one of a, which is not sent again
+        let b_response = a
+            .on_message(b_check.destination, b_check.base, &b_check.data)
+            .unwrap();
+        assert_eq!(a.triggered.len(), 1);
+        assert!(a.poll_retransmit(now + DEFAULT_CHECK_RTO).is_none());
+        assert!(a.next_timeout().unwrap() <= now + DEFAULT_TA);
+
+        //Its response is still taken, no need to wait for the triggered check
+        let a_response = b
+            .on_message(a_check.destination, a_check.base, &a_check.data)
+            .unwrap();
+        assert!(a
+            .on_message(a_response.destination, a_response.base, &a_response.data)
+            .is_none());
+        assert_eq!(a.valid_pairs().len(), 1);
+        assert_eq!(
+            a.checklist().pairs()[0].state,
+            CandidatePairState::Succeeded
+        );
+        assert!(b
+            .on_message(b_response.destination, b_response.base, &b_response.data)
+            .is_none());
+        assert_eq!(b.valid_pairs().len(), 1);
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let a_locals = host_candidates(&["10.0.0.1:1000"]);
+        let b_locals = host_candidates(&["10.0.0.2:2000"]);
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        let mut b = agent(false, &b_locals, &a_locals, "bbbb", "aaaa");
+        b.local_credentials.password = "not-what-a-was-told".to_string();
+        a.set_max_transmissions(2);
+        let now = run(&mut a, &mut b, Instant::now());
+        assert!(a.valid_pairs().is_empty());
+        assert_eq!(a.checklist().pairs()[0].state, CandidatePairState::Failed);
+        assert!(a.next_timeout().is_none() || a.next_timeout().unwrap() > now);
+    }
+
+    #[test]
+    fn test_role_conflict() {
+        let a_locals = host_candidates(&["10.0.0.1:1000"]);
+        let b_locals = host_candidates(&["10.0.0.2:2000"]);
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        let mut b = agent(true, &b_locals, &a_locals, "bbbb", "aaaa");
+        a.set_tie_breaker(1);
+        b.set_tie_breaker(2);
+        run(&mut a, &mut b, Instant::now());
+        assert!(!a.controlling());
+        assert!(b.controlling());
+        assert_eq!(a.valid_pairs().len(), 1);
+        assert_eq!(b.valid_pairs().len(), 1);
+    }
+
+    #[test]
+    fn test_peer_reflexive_candidates() {
+        let a_locals = host_candidates(&["10.0.0.1:1000"]);
+        let b_locals = host_candidates(&["192.0.2.2:2000"]);
+        let internal = a_locals[0].address;
+        let external: SocketAddr = "198.51.100.1:5000".parse().unwrap();
+        let mut a = agent(true, &a_locals, &b_locals, "aaaa", "bbbb");
+        let mut b = agent(false, &b_locals, &a_locals, "bbbb", "aaaa");
+        b.set_max_transmissions(3);
+        run_behind_nat(&mut a, &mut b, Instant::now(), Some((internal, external)));
+
+        //b learns the NAT binding of a from its check
+        let remote = b
+            .remote_candidates()
+            .iter()
+            .find(|x| x.address == external)
+            .unwrap();
+        assert_eq!(remote.candidate_type, CandidateType::PeerReflexive);
+        assert_eq!(remote.priority, compute_priority(110, 65535, 1));
+        let pair = b
+            .checklist()
+            .pairs()
+            .iter()
+            .find(|x| x.remote.address == external)
+            .unwrap();
+        assert_eq!(pair.state, CandidatePairState::Succeeded);
+        assert_eq!(b.valid_pairs().len(), 1);
+        assert_eq!(b.valid_pairs()[0].remote.address, external);
+        //The pair of the host candidate of a never works
+        assert!(b
+            .checklist()
+            .pairs()
+            .iter()
+            .any(|x| x.remote.address == internal && x.state == CandidatePairState::Failed));
+
+        //a learns it from the response
+        let local = a
+            .local_candidates()
+            .iter()
+            .find(|x| x.address == external)
+            .unwrap();
+        assert_eq!(local.candidate_type, CandidateType::PeerReflexive);
+        assert_eq!(local.base, internal);
+        assert_eq!(local.priority, remote.priority);
+        assert_eq!(a.valid_pairs().len(), 1);
+        assert_eq!(a.valid_pairs()[0].local.address, external);
+        assert_eq!(a.valid_pairs()[0].remote.address, b_locals[0].address);
+    }
+
+    #[test]
+    fn test_check_without_priority() {
+        let a_locals = host_candidates(&["10.0.0.1:1000"]);
+        let b_locals = host_candidates(&["10.0.0.2:2000"]);
+        let mut b = agent(false, &b_locals, &a_locals, "bbbb", "aaaa");
+        let mut request =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        for attribute in [
+            STUNAttributesContent::new_username("bbbb:aaaa".to_string()),
+            STUNAttributesContent::MessageIntegrity {
+                authType: STUNAuthType::ShortTerm,
+            },
+        ] {
+            let attribute_type = attribute.attribute_type();
+            request.body.add_new_attribute(attribute, attribute_type, 0);
+        }
+        let mut context = STUNContext::new();
+        context.username = Some("bbbb:aaaa".to_string());
+        context.password = Some(credentials("bbbb").password);
+        let mut request_bin = Vec::new();
+        request
+            .encode(&mut Cursor::new(&mut request_bin), &Some(&context))
+            .unwrap();
+        let source: SocketAddr = "10.0.0.9:9000".parse().unwrap();
+        let response = b
+            .on_message(b_locals[0].address, source, &request_bin)
+            .unwrap();
+        let response = STUN::decode(
+            &mut Cursor::new(&response.data[..]),
+            &mut Some(&mut STUNContext::new()),
+        )
+        .unwrap();
+        assert_eq!(
+            response.header.message_class,
+            STUNMessageClass::ResponseError
+        );
+        assert!(b.remote_candidates().iter().all(|x| x.address != source));
+    }
+}
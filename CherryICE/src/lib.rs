@@ -1,17 +1,4 @@
+#![allow(non_snake_case)]
 pub mod candidates;
+pub mod checks;
 extern crate CherrySTUN;
-
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
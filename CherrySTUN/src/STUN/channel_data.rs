@@ -0,0 +1,132 @@
+//ChannelData messages (RFC 8656 section 12.4) carry application data between a TURN client and
+//server once a channel is bound, with a 4 byte header instead of a STUN message around it.
+//
+//     0                   1                   2                   3
+//     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//    |         Channel Number        |            Length             |
+//    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//    |                                                               |
+//    /                       Application Data                        /
+//    /                                                               /
+//    |                                                               |
+//    |                               +-------------------------------+
+//    |                               |
+//    +-------------------------------+
+//
+//Over TCP and TLS the message is padded to a multiple of 4 bytes, over UDP padding is optional.
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+
+pub const TURN_CHANNEL_NUMBER_MIN: u16 = 0x4000;
+pub const TURN_CHANNEL_NUMBER_MAX: u16 = 0x4FFF;
+pub const TURN_CHANNEL_DATA_HEADER_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelData {
+    pub channel_number: u16,
+    pub data: Vec<u8>,
+}
+
+impl ChannelData {
+    pub fn new(channel_number: u16, data: Vec<u8>) -> Self {
+        ChannelData {
+            channel_number,
+            data,
+        }
+    }
+
+    /// True for numbers that can be bound to a peer
+    pub fn is_valid_channel_number(channel_number: u16) -> bool {
+        return (TURN_CHANNEL_NUMBER_MIN..=TURN_CHANNEL_NUMBER_MAX).contains(&channel_number);
+    }
+
+    /// Header and data, padded to a multiple of 4 bytes when `padded` (stream transports)
+    pub fn encode(&self, padded: bool) -> Result<Vec<u8>, STUNError> {
+        if self.data.len() > u16::MAX as usize {
+            return Err(STUNError {
+                step: STUNStep::STUNEncode,
+                error_type: STUNErrorType::InvalidMessageBinLength,
+                message: "ChannelData can not carry more than 65535 bytes".to_string(),
+            });
+        }
+        let mut bin = Vec::with_capacity(TURN_CHANNEL_DATA_HEADER_LENGTH + self.data.len() + 3);
+        bin.extend_from_slice(&self.channel_number.to_be_bytes());
+        bin.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+        bin.extend_from_slice(&self.data);
+        if padded {
+            while bin.len() % 4 != 0 {
+                bin.push(0);
+            }
+        }
+        return Ok(bin);
+    }
+
+    /// Length of the whole message starting with `header_bin`, including the padding when
+    /// `padded`. Used to cut messages out of a stream.
+    pub fn frame_length(header_bin: &[u8], padded: bool) -> Option<usize> {
+        if header_bin.len() < TURN_CHANNEL_DATA_HEADER_LENGTH {
+            return None;
+        }
+        let data_length = u16::from_be_bytes([header_bin[2], header_bin[3]]) as usize;
+        let length = TURN_CHANNEL_DATA_HEADER_LENGTH + data_length;
+        if padded {
+            return Some(length.div_ceil(4) * 4);
+        }
+        return Some(length);
+    }
+
+    /// Anything after the application data (padding) is ignored
+    pub fn decode(bin: &[u8]) -> Result<Self, STUNError> {
+        let length = match Self::frame_length(bin, false) {
+            Some(length) => length,
+            None => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::WrongSizeError,
+                    message: "ChannelData shorter than its header".to_string(),
+                })
+            }
+        };
+        let channel_number = u16::from_be_bytes([bin[0], bin[1]]);
+        if !Self::is_valid_channel_number(channel_number) {
+            return Err(STUNError {
+                step: STUNStep::STUNDecode,
+                error_type: STUNErrorType::InvalidOrUnsupportedAttribute,
+                message: format!("Invalid channel number {:#06x}", channel_number),
+            });
+        }
+        if bin.len() < length {
+            return Err(STUNError {
+                step: STUNStep::STUNDecode,
+                error_type: STUNErrorType::InvalidMessageBinLength,
+                message: "ChannelData shorter than its length field".to_string(),
+            });
+        }
+        return Ok(ChannelData {
+            channel_number,
+            data: bin[TURN_CHANNEL_DATA_HEADER_LENGTH..length].to_vec(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channel_data_framing() {
+        let message = ChannelData::new(0x4001, vec![1, 2, 3, 4, 5]);
+        let bin = message.encode(false).unwrap();
+        assert_eq!(bin, [0x40, 0x01, 0x00, 0x05, 1, 2, 3, 4, 5]);
+        assert_eq!(ChannelData::decode(&bin).unwrap(), message);
+
+        let padded_bin = message.encode(true).unwrap();
+        assert_eq!(padded_bin.len(), 12);
+        assert_eq!(ChannelData::frame_length(&padded_bin, true), Some(12));
+        assert_eq!(ChannelData::frame_length(&padded_bin, false), Some(9));
+        assert_eq!(ChannelData::decode(&padded_bin).unwrap(), message);
+
+        assert!(ChannelData::decode(&bin[..7]).is_err());
+        assert!(ChannelData::decode(&[0x50, 0x00, 0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//Tells apart the protocols sharing one socket, using the first byte as in RFC 7983:
+//
+//                 +----------------+
+//                 |        [0..3] -+--> forward to STUN
+//                 |                |
+//                 |      [16..19] -+--> forward to ZRTP
+//                 |                |
+//     packet -->  |      [20..63] -+--> forward to DTLS
+//                 |                |
+//                 |     [64..127] -+--> forward to TURN Channel
+//                 |                |
+//                 |    [128..191] -+--> forward to RTP/RTCP
+//                 +----------------+
+//
+//RFC 7983 narrows TURN channels to [64..79], the whole 0x4000-0x7FFF range RFC 8656 sets aside
+//for them is taken here.
+use super::channel_data::TURN_CHANNEL_DATA_HEADER_LENGTH;
+use crate::STUNHeader::header::{STUN_5389_MAGIC_NUMBER_U32, STUN_HEADER_ENDING_POSITION};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum STUNPacketType {
+    Stun,
+    ChannelData, //TURN ChannelData, see `ChannelData`
+    Dtls,
+    Rtp, //RTP and RTCP
+    Unknown,
+}
+
+/// Guesses what `packet_bin` is. STUN is only reported for packets with a full header and
+/// the magic cookie.
+pub fn classify_packet(packet_bin: &[u8]) -> STUNPacketType {
+    let first_byte = match packet_bin.first() {
+        Some(byte) => *byte,
+        None => return STUNPacketType::Unknown,
+    };
+    match first_byte {
+        0..=3 => {
+            if packet_bin.len() >= STUN_HEADER_ENDING_POSITION as usize
+                && packet_bin[4..8] == STUN_5389_MAGIC_NUMBER_U32.to_be_bytes()
+            {
+                return STUNPacketType::Stun;
+            }
+            return STUNPacketType::Unknown;
+        }
+        20..=63 => return STUNPacketType::Dtls,
+        64..=127 => {
+            if packet_bin.len() >= TURN_CHANNEL_DATA_HEADER_LENGTH {
+                return STUNPacketType::ChannelData;
+            }
+            return STUNPacketType::Unknown;
+        }
+        128..=191 => return STUNPacketType::Rtp,
+        _ => return STUNPacketType::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TestFixtures::fixtures::STUN_REQUEST_BINDING_HEADER_BINARY;
+
+    #[test]
+    fn test_classify_packet() {
+        assert_eq!(
+            classify_packet(&STUN_REQUEST_BINDING_HEADER_BINARY),
+            STUNPacketType::Stun
+        );
+        //Too short, or without the magic cookie
+        assert_eq!(
+            classify_packet(&STUN_REQUEST_BINDING_HEADER_BINARY[..12]),
+            STUNPacketType::Unknown
+        );
+        assert_eq!(classify_packet(&[0x00; 20]), STUNPacketType::Unknown);
+        //DTLS handshake record, RTP version 2
+        assert_eq!(classify_packet(&[22, 0xfe, 0xfd]), STUNPacketType::Dtls);
+        assert_eq!(classify_packet(&[0x80, 0x60]), STUNPacketType::Rtp);
+        //ChannelData over the whole channel range
+        assert_eq!(
+            classify_packet(&[0x40, 0x00, 0x00, 0x00]),
+            STUNPacketType::ChannelData
+        );
+        assert_eq!(
+            classify_packet(&[0x7f, 0xff, 0x00, 0x00]),
+            STUNPacketType::ChannelData
+        );
+        assert_eq!(classify_packet(&[0x40, 0x00]), STUNPacketType::Unknown);
+        assert_eq!(classify_packet(&[]), STUNPacketType::Unknown);
+    }
+}
@@ -1,3 +1,6 @@
 pub mod decode;
 pub mod encode;
 pub mod stun;
+pub mod classifier;
+pub mod channel_data;
+pub mod stream;
@@ -0,0 +1,132 @@
+//Framing of STUN and ChannelData messages on stream transports (TCP and TLS). STUN messages are
+//cut at the length in their header (RFC 8489 section 6.2.2), ChannelData messages at their
+//length rounded up to 4 bytes, as they are padded on streams (RFC 8656 section 12.5).
+use super::channel_data::ChannelData;
+use crate::STUNHeader::header::STUN_HEADER_ENDING_POSITION;
+use std::io::{self, Read};
+
+//Both headers give the length of what follows in bytes 2 and 3
+const STUN_STREAM_LENGTH_PREFIX: usize = 4;
+const STUN_STREAM_READ_CHUNK: usize = 4096;
+
+/// Length of the message at the start of `bin`, `None` until its length field was received
+pub fn stream_frame_length(bin: &[u8]) -> Option<usize> {
+    if bin.len() < STUN_STREAM_LENGTH_PREFIX {
+        return None;
+    }
+    match bin[0] {
+        64..=127 => return ChannelData::frame_length(bin, true),
+        _ => {
+            let message_length = u16::from_be_bytes([bin[2], bin[3]]) as usize;
+            return Some(STUN_HEADER_ENDING_POSITION as usize + message_length);
+        }
+    }
+}
+
+/// Reads exactly one message from `stream`, nothing past it. For streams that carry something
+/// else once the message was answered, like TURN data connections.
+pub fn read_frame_exact<R: Read>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let mut frame = vec![0; STUN_STREAM_LENGTH_PREFIX];
+    match stream.read_exact(&mut frame) {
+        Ok(()) => {}
+        Err(e) => return Err(e),
+    }
+    //Always some, the prefix was read
+    let length = stream_frame_length(&frame).unwrap_or(STUN_STREAM_LENGTH_PREFIX);
+    frame.resize(length, 0);
+    match stream.read_exact(&mut frame[STUN_STREAM_LENGTH_PREFIX..]) {
+        Ok(()) => return Ok(frame),
+        Err(e) => return Err(e),
+    }
+}
+
+/// Cuts messages out of a stream, keeping partial ones across reads
+pub struct StunStreamReader {
+    buffer: Vec<u8>,
+}
+
+impl StunStreamReader {
+    pub fn new() -> Self {
+        StunStreamReader { buffer: Vec::new() }
+    }
+
+    /// Next complete message, reading from `stream` when needed. `None` when a read timed out
+    /// first, what was received so far is kept for the next call. A closed stream ends in
+    /// `UnexpectedEof`.
+    pub fn read_frame<R: Read>(&mut self, stream: &mut R) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match stream_frame_length(&self.buffer) {
+                Some(length) if self.buffer.len() >= length => {
+                    return Ok(Some(self.buffer.drain(..length).collect()))
+                }
+                _ => {}
+            }
+            let mut chunk = [0; STUN_STREAM_READ_CHUNK];
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Stream closed",
+                    ))
+                }
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// What was read past the last message
+    pub fn into_remaining(self) -> Vec<u8> {
+        return self.buffer;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_stream_framing() {
+        let mut stream_bin = vec![
+            0x00, 0x01, 0x00, 0x04, //Binding request, 4 byte body
+            0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87,
+            0xdf, 0xae, 0x80, 0x22, 0x00, 0x00,
+        ];
+        let channel_data = ChannelData::new(0x4000, vec![1, 2, 3])
+            .encode(true)
+            .unwrap();
+        stream_bin.extend_from_slice(&channel_data);
+        stream_bin.extend_from_slice(&[0x00, 0x01, 0x00]);
+        assert_eq!(stream_frame_length(&stream_bin), Some(24));
+        assert_eq!(stream_frame_length(&channel_data), Some(8));
+        assert_eq!(stream_frame_length(&[0x40, 0x00]), None);
+
+        let mut reader = StunStreamReader::new();
+        let mut stream = Cursor::new(stream_bin.clone());
+        assert_eq!(
+            reader.read_frame(&mut stream).unwrap().unwrap(),
+            stream_bin[..24]
+        );
+        assert_eq!(
+            reader.read_frame(&mut stream).unwrap().unwrap(),
+            channel_data
+        );
+        //A partial message and then the end of the stream
+        assert_eq!(
+            reader.read_frame(&mut stream).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(reader.into_remaining(), [0x00, 0x01, 0x00]);
+
+        let mut stream = Cursor::new(stream_bin.clone());
+        assert_eq!(read_frame_exact(&mut stream).unwrap(), stream_bin[..24]);
+        assert_eq!(stream.position(), 24);
+    }
+}
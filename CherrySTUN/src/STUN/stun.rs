@@ -1,6 +1,7 @@
 use crate::STUNBody::body::STUNBody;
 use crate::STUNHeader::header::STUNHeader;
 use crate::STUNHeader::header::{STUNMessageClass, STUNMessageMethod};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 #[derive(Debug, Clone)]
 pub struct STUN {
@@ -9,20 +10,44 @@ pub struct STUN {
     _private: (),                 //To protect direct building of this struct
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum STUNNatMappingType{
     EndpointIndependent,
     AddressDependant,
     PortDependant
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum STUNNatFilteringType{
     EndpointIndependentFiltering,
     AddressDependantFiltering,
     AddressAndPortDependantFiltering
 }
 
+//Values are the same as the family byte in MAPPED-ADDRESS and friends
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord, FromPrimitive)]
+pub enum STUNAddressFamily {
+    IPv4 = 0x01,
+    IPv6 = 0x02,
+}
+
+impl STUNAddressFamily {
+    pub fn of(address: &SocketAddr) -> Self {
+        match address {
+            SocketAddr::V4(_) => return Self::IPv4,
+            SocketAddr::V6(_) => return Self::IPv6,
+        }
+    }
+
+    ///Wildcard address of this family, used when binding local sockets
+    pub fn unspecified_address(&self, port: u16) -> SocketAddr {
+        match self {
+            Self::IPv4 => return SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+            Self::IPv6 => return SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+        }
+    }
+}
+
 impl STUN {
     pub fn new(header: STUNHeader, body: STUNBody) -> Self {
         Self {
@@ -0,0 +1,80 @@
+/*
+ * This attribute is used by clients to request the allocation of an
+ * IPv4 and IPv6 address type from a server.  The ADDITIONAL-ADDRESS-
+ * FAMILY attribute MAY be present in the Allocate request.  The
+ * attribute value of 0x02 (IPv6 address) is the only valid value in
+ * Allocate request.
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |    Family     |            Reserved                           |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *
+ * (RFC 8656 section 18.12)
+ */
+
+use super::attributes::STUNAttributesContent;
+#[cfg(test)]
+use super::attributes::TURN_ADDRESS_FAMILY_IPV6;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_additional_address_family(family: u8) -> Self {
+        Self::AdditionalAddressFamily { family }
+    }
+
+    pub fn encode_additional_address_family(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::AdditionalAddressFamily { family } => return Ok(vec![*family, 0, 0, 0]),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for AdditionalAddressFamily on non AdditionalAddressFamily type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_additional_address_family(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => {
+                return Ok(Self::AdditionalAddressFamily {
+                    family: (bin >> 24) as u8,
+                })
+            }
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading additional address family. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_additional_address_family_encode_decode() {
+        let additional_address_family =
+            STUNAttributesContent::new_additional_address_family(TURN_ADDRESS_FAMILY_IPV6);
+        let bin = additional_address_family
+            .encode_additional_address_family()
+            .unwrap();
+        assert_eq!(bin, [2, 0, 0, 0]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_additional_address_family(&mut cursor).unwrap(),
+            additional_address_family
+        );
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * This attribute is used by servers to signal the reason for not
+ * allocating the requested address family.  The value portion of this
+ * attribute is variable length with the following format:
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |  Family       |    Reserved             |Class|     Number    |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |      Reason Phrase (variable)                                ..
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *
+ * Family:  There are two values defined for this field and specified
+ *    in Section 14.1 of [RFC8489]: 0x01 for IPv4 addresses and 0x02
+ *    for IPv6 addresses.
+ *
+ * Class, Number and Reason Phrase are as in the ERROR-CODE attribute.
+ * (RFC 8656 section 18.13)
+ */
+
+#[cfg(test)]
+use super::attributes::TURN_ADDRESS_FAMILY_IPV6;
+use super::attributes::{STUNAttributesContent, STUNErrorCode};
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    ///Address error code attribute with the RFC reason phrase
+    pub fn new_address_error_code(family: u8, code: STUNErrorCode) -> Self {
+        Self::AddressErrorCode {
+            family,
+            code: code as u16,
+            reason: code.reason_phrase().to_string(),
+        }
+    }
+
+    ///returns the non padded bin, use the `add_padding_to_attr_bin` to add the required padding
+    pub fn encode_address_error_code(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::AddressErrorCode {
+                family,
+                code,
+                reason,
+            } => {
+                if *code < 300 || *code > 699 {
+                    return Err(STUNError {
+                        step: STUNStep::STUNEncode,
+                        error_type: STUNErrorType::AttributeStructureMismatch,
+                        message: "Error code must be between 300 and 699, found: ".to_string()
+                            + code.to_string().as_str(),
+                    });
+                }
+                let mut bin = vec![*family, 0, (code / 100) as u8, (code % 100) as u8];
+                bin.extend_from_slice(reason.as_bytes());
+                return Ok(bin);
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message:
+                        "Called encode function for AddressErrorCode on non AddressErrorCode type"
+                            .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_address_error_code(
+        cursor: &mut Cursor<&[u8]>,
+        length: u16,
+    ) -> Result<Self, STUNError> {
+        if length < 4 {
+            return Err(STUNError {
+                step: STUNStep::STUNDecode,
+                error_type: STUNErrorType::AttributeStructureMismatch,
+                message: "Address error code attribute shorter than 4 bytes".to_string(),
+            });
+        }
+        let family_class_number = match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => bin,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading family, class and number of address error code. "
+                        .to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        let family = (family_class_number >> 24) as u8;
+        let code =
+            (((family_class_number >> 8) & 0b111) * 100 + (family_class_number & 0xff)) as u16;
+        let reason_bin = match Self::read_padded_attr_bin(cursor, length - 4) {
+            Ok(bin) => bin,
+            Err(e) => return Err(e),
+        };
+        let reason = match String::from_utf8(reason_bin) {
+            Ok(str) => str,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::UTF8DecodeError,
+                    message: "Error decoding reason phrase to string utf8. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        return Ok(Self::AddressErrorCode {
+            family,
+            code,
+            reason,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_address_error_code_encode_decode() {
+        let address_error = STUNAttributesContent::new_address_error_code(
+            TURN_ADDRESS_FAMILY_IPV6,
+            STUNErrorCode::AddressFamilyNotSupported,
+        );
+        let mut bin = address_error.encode_address_error_code().unwrap();
+        assert_eq!(bin[..4], [0x02, 0x00, 0x04, 0x28]);
+        let length = bin.len() as u16;
+        STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_address_error_code(&mut cursor, length).unwrap(),
+            address_error
+        );
+        assert_eq!(cursor.position(), bin.len() as u64);
+    }
+}
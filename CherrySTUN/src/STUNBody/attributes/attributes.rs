@@ -3,19 +3,104 @@ use std::net::SocketAddr;
 #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, FromPrimitive)]
 pub enum STUNAttributeType {
     MappedAddress = 0x0001, //Done
+    ChangeRequest = 0x0003, //Done
     Username = 0x0006,      //Done
     MessageIntegrity = 0x0008, //Done
-    ErrorCode = 0x0009,
-    UnknownAttributes = 0x000A, //[TODO]
+    ErrorCode = 0x0009, //Done
+    UnknownAttributes = 0x000A, //Done
+    ChannelNumber = 0x000C,     //Done
+    Lifetime = 0x000D,          //Done
     Realm = 0x0014,            //Done
+    XORPeerAddress = 0x0012,   //Done
+    Data = 0x0013,             //Done
     Nonce = 0x0015,            //Done
+    XORRelayedAddress = 0x0016, //Done
+    RequestedTransport = 0x0019, //Done
+    RequestedAddressFamily = 0x0017, //Done
     XORMappedAddress = 0x0020, //Done
-    Fingerprint = 0x8028, //[TODO]
-    Software = 0x8022, //[TODO]
+    ConnectionId = 0x002A,     //Done
+    EvenPort = 0x0018,         //Done
+    DontFragment = 0x001A,     //Done
+    ReservationToken = 0x0022, //Done
+    Padding = 0x0026,          //Done
+    ResponsePort = 0x0027,     //Done
+    Fingerprint = 0x8028, //Done
+    AdditionalAddressFamily = 0x8000, //Done
+    AddressErrorCode = 0x8001, //Done
+    Priority = 0x0024, //Done
+    UseCandidate = 0x0025, //Done
+    IceControlled = 0x8029, //Done
+    IceControlling = 0x802A, //Done
+    Software = 0x8022, //Done
     AlternateServer = 0x8023, //[TODO]
-    OtherAddress= 0x802C, //Used for testing NAT behvaviour. (Couldnt find it documented anywhere)
+    ResponseOrigin = 0x802B, //Done
+    OtherAddress= 0x802C, //Used for testing NAT behvaviour (RFC 5780)
 }
 
+impl STUNAttributeType {
+    ///Attributes in 0x0000-0x7FFF must be understood by the receiver, unknown ones
+    ///are answered with a 420
+    pub fn is_comprehension_required(attribute_type: u16) -> bool {
+        return attribute_type < 0x8000;
+    }
+}
+
+//Error codes (ERROR-CODE attribute) used across STUN usages
+#[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
+pub enum STUNErrorCode {
+    TryAlternate = 300,
+    BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
+    UnknownAttribute = 420,
+    AllocationMismatch = 437,
+    StaleNonce = 438,
+    AddressFamilyNotSupported = 440,
+    WrongCredentials = 441,
+    UnsupportedTransportProtocol = 442,
+    PeerAddressFamilyMismatch = 443,
+    ConnectionAlreadyExists = 446,
+    ConnectionTimeoutOrFailure = 447,
+    AllocationQuotaReached = 486,
+    RoleConflict = 487,
+    ServerError = 500,
+    InsufficientCapacity = 508,
+}
+
+impl STUNErrorCode {
+    ///Reason phrases as suggested by the RFCs
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            Self::TryAlternate => return "Try Alternate",
+            Self::BadRequest => return "Bad Request",
+            Self::Unauthorized => return "Unauthorized",
+            Self::Forbidden => return "Forbidden",
+            Self::UnknownAttribute => return "Unknown Attribute",
+            Self::AllocationMismatch => return "Allocation Mismatch",
+            Self::StaleNonce => return "Stale Nonce",
+            Self::AddressFamilyNotSupported => return "Address Family not Supported",
+            Self::WrongCredentials => return "Wrong Credentials",
+            Self::UnsupportedTransportProtocol => return "Unsupported Transport Protocol",
+            Self::PeerAddressFamilyMismatch => return "Peer Address Family Mismatch",
+            Self::ConnectionAlreadyExists => return "Connection Already Exists",
+            Self::ConnectionTimeoutOrFailure => return "Connection Timeout or Failure",
+            Self::AllocationQuotaReached => return "Allocation Quota Reached",
+            Self::RoleConflict => return "Role Conflict",
+            Self::ServerError => return "Server Error",
+            Self::InsufficientCapacity => return "Insufficient Capacity",
+        }
+    }
+}
+
+//Protocol numbers of REQUESTED-TRANSPORT (RFC 8656), as in the IP header
+pub const TURN_TRANSPORT_TCP: u8 = 6;
+pub const TURN_TRANSPORT_UDP: u8 = 17;
+
+//Families of REQUESTED-ADDRESS-FAMILY and ADDITIONAL-ADDRESS-FAMILY (RFC 8656), as in
+//XOR-MAPPED-ADDRESS
+pub const TURN_ADDRESS_FAMILY_IPV4: u8 = 0x01;
+pub const TURN_ADDRESS_FAMILY_IPV6: u8 = 0x02;
+
 //To track type of authentication
 #[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
 pub enum STUNAuthType {
@@ -42,6 +127,36 @@ pub enum STUNAttributesContent {
     MessageIntegrity { authType: STUNAuthType }, //its used to check validity/fill in the Message Integrity for new messages
                                                  //But the encode/decode function compulsorily needs the STUNContext to be
                                                  //provided
+    Software { software: String },
+    ErrorCode { code: u16, reason: String },
+    UnknownAttributes { attributes: Vec<u16> },
+    //RFC 5780 NAT behaviour discovery attributes
+    ChangeRequest { change_ip: bool, change_port: bool },
+    ResponsePort { port: u16 },
+    ResponseOrigin { address: SocketAddr },
+    Padding { length: u16 }, //value of the padding does not matter, only its length is kept
+    //Computed/validated during encode/decode, has to be the last attribute
+    Fingerprint,
+    //RFC 8656 TURN attributes
+    XORRelayedAddress { address: SocketAddr }, //Same obfuscation as XORMappedAddress
+    Lifetime { lifetime: u32 },                //In seconds
+    RequestedTransport { protocol: u8 },
+    XORPeerAddress { address: SocketAddr }, //Same obfuscation as XORMappedAddress
+    Data { data: Vec<u8> },
+    ChannelNumber { channel_number: u16 },
+    ConnectionId { connection_id: u32 },
+    EvenPort { reserve: bool }, //Asks to reserve the next port as well
+    DontFragment,
+    ReservationToken { token: u64 },
+    RequestedAddressFamily { family: u8 },
+    AdditionalAddressFamily { family: u8 },
+    //Why the family of ADDITIONAL-ADDRESS-FAMILY was not allocated
+    AddressErrorCode { family: u8, code: u16, reason: String },
+    //RFC 8445 ICE attributes
+    Priority { priority: u32 }, //Of the peer reflexive candidate the check could discover
+    UseCandidate,
+    IceControlled { tie_breaker: u64 },
+    IceControlling { tie_breaker: u64 },
 }
 
 impl STUNAttributesContent {
@@ -62,6 +177,57 @@ impl STUNAttributesContent {
             STUNAttributesContent::OtherAddress { .. } => {
                 return STUNAttributeType::OtherAddress
             }
+            STUNAttributesContent::Software { .. } => return STUNAttributeType::Software,
+            STUNAttributesContent::ErrorCode { .. } => return STUNAttributeType::ErrorCode,
+            STUNAttributesContent::UnknownAttributes { .. } => {
+                return STUNAttributeType::UnknownAttributes
+            }
+            STUNAttributesContent::ChangeRequest { .. } => {
+                return STUNAttributeType::ChangeRequest
+            }
+            STUNAttributesContent::ResponsePort { .. } => return STUNAttributeType::ResponsePort,
+            STUNAttributesContent::ResponseOrigin { .. } => {
+                return STUNAttributeType::ResponseOrigin
+            }
+            STUNAttributesContent::Padding { .. } => return STUNAttributeType::Padding,
+            STUNAttributesContent::Fingerprint => return STUNAttributeType::Fingerprint,
+            STUNAttributesContent::XORRelayedAddress { .. } => {
+                return STUNAttributeType::XORRelayedAddress
+            }
+            STUNAttributesContent::Lifetime { .. } => return STUNAttributeType::Lifetime,
+            STUNAttributesContent::RequestedTransport { .. } => {
+                return STUNAttributeType::RequestedTransport
+            }
+            STUNAttributesContent::XORPeerAddress { .. } => {
+                return STUNAttributeType::XORPeerAddress
+            }
+            STUNAttributesContent::Data { .. } => return STUNAttributeType::Data,
+            STUNAttributesContent::ChannelNumber { .. } => {
+                return STUNAttributeType::ChannelNumber
+            }
+            STUNAttributesContent::ConnectionId { .. } => return STUNAttributeType::ConnectionId,
+            STUNAttributesContent::EvenPort { .. } => return STUNAttributeType::EvenPort,
+            STUNAttributesContent::DontFragment => return STUNAttributeType::DontFragment,
+            STUNAttributesContent::ReservationToken { .. } => {
+                return STUNAttributeType::ReservationToken
+            }
+            STUNAttributesContent::RequestedAddressFamily { .. } => {
+                return STUNAttributeType::RequestedAddressFamily
+            }
+            STUNAttributesContent::AdditionalAddressFamily { .. } => {
+                return STUNAttributeType::AdditionalAddressFamily
+            }
+            STUNAttributesContent::AddressErrorCode { .. } => {
+                return STUNAttributeType::AddressErrorCode
+            }
+            STUNAttributesContent::Priority { .. } => return STUNAttributeType::Priority,
+            STUNAttributesContent::UseCandidate => return STUNAttributeType::UseCandidate,
+            STUNAttributesContent::IceControlled { .. } => {
+                return STUNAttributeType::IceControlled
+            }
+            STUNAttributesContent::IceControlling { .. } => {
+                return STUNAttributeType::IceControlling
+            }
         };
     }
 }
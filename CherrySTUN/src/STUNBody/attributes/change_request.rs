@@ -0,0 +1,105 @@
+/*
+ * The CHANGE-REQUEST attribute contains two flags to control the IP
+ * address and port that the server uses to send the response.  These
+ * flags are called the "change IP" and "change port" flags.  The
+ * CHANGE-REQUEST attribute is allowed only in the Binding Request.  The
+ * "change IP" and "change port" flags are useful for determining the
+ * current filtering behavior of a NAT.  They instruct the server to
+ * send the Binding Responses from the alternate source IP address
+ * and/or alternate port.
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 A B 0|
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *
+ * A: This is the "change IP" flag.
+ * B: This is the "change port" flag.
+ * (RFC 5780 section 7.2)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+pub const STUN_CHANGE_REQUEST_CHANGE_IP: u32 = 0b0100;
+pub const STUN_CHANGE_REQUEST_CHANGE_PORT: u32 = 0b0010;
+
+impl STUNAttributesContent {
+    pub fn new_change_request(change_ip: bool, change_port: bool) -> Self {
+        Self::ChangeRequest {
+            change_ip,
+            change_port,
+        }
+    }
+
+    pub fn encode_change_request(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::ChangeRequest {
+                change_ip,
+                change_port,
+            } => {
+                let mut flags: u32 = 0;
+                if *change_ip {
+                    flags |= STUN_CHANGE_REQUEST_CHANGE_IP;
+                }
+                if *change_port {
+                    flags |= STUN_CHANGE_REQUEST_CHANGE_PORT;
+                }
+                return Ok(flags.to_be_bytes().to_vec());
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for ChangeRequest on non ChangeRequest type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_change_request(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        let flags = match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => bin,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading change request flags. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        return Ok(Self::ChangeRequest {
+            change_ip: flags & STUN_CHANGE_REQUEST_CHANGE_IP != 0,
+            change_port: flags & STUN_CHANGE_REQUEST_CHANGE_PORT != 0,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_change_request_encode_decode() {
+        let change_request = STUNAttributesContent::new_change_request(true, true);
+        let bin = change_request.encode_change_request().unwrap();
+        assert_eq!(bin, [0x00, 0x00, 0x00, 0x06]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_change_request(&mut cursor).unwrap(),
+            change_request
+        );
+
+        let mut cursor = Cursor::new(&[0x00, 0x00, 0x00, 0x02][..]);
+        assert_eq!(
+            STUNAttributesContent::decode_change_request(&mut cursor).unwrap(),
+            STUNAttributesContent::new_change_request(false, true)
+        );
+        return;
+    }
+}
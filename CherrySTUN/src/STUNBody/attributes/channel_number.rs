@@ -0,0 +1,77 @@
+/*
+ * The CHANNEL-NUMBER attribute contains the number of the channel.  The
+ * value portion of this attribute is 4 bytes long and consists of a
+ * 16-bit unsigned integer followed by a two-octet RFFU (Reserved For
+ * Future Use) field, which MUST be set to 0 on transmission and MUST be
+ * ignored on reception.
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |        Channel Number         |         RFFU = 0              |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ * (RFC 8656 section 18.1)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_channel_number(channel_number: u16) -> Self {
+        Self::ChannelNumber { channel_number }
+    }
+
+    pub fn encode_channel_number(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::ChannelNumber { channel_number } => {
+                let mut bin = channel_number.to_be_bytes().to_vec();
+                bin.extend_from_slice(&[0, 0]);
+                return Ok(bin);
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for ChannelNumber on non ChannelNumber type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_channel_number(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => {
+                return Ok(Self::ChannelNumber {
+                    channel_number: (bin >> 16) as u16,
+                })
+            }
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading channel number. ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channel_number_encode_decode() {
+        let channel_number = STUNAttributesContent::new_channel_number(0x4001);
+        let bin = channel_number.encode_channel_number().unwrap();
+        assert_eq!(bin, [0x40, 0x01, 0, 0]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_channel_number(&mut cursor).unwrap(),
+            channel_number
+        );
+    }
+}
@@ -0,0 +1,62 @@
+/*
+ * The CONNECTION-ID attribute uniquely identifies a peer data
+ * connection.  It is a 32-bit unsigned integral value.
+ * (RFC 6062 section 6.2.1)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_connection_id(connection_id: u32) -> Self {
+        Self::ConnectionId { connection_id }
+    }
+
+    pub fn encode_connection_id(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::ConnectionId { connection_id } => {
+                return Ok(connection_id.to_be_bytes().to_vec())
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for ConnectionId on non ConnectionId type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_connection_id(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(connection_id) => return Ok(Self::ConnectionId { connection_id }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading connection id. ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_connection_id_encode_decode() {
+        let connection_id = STUNAttributesContent::new_connection_id(0xdeadbeef);
+        let bin = connection_id.encode_connection_id().unwrap();
+        assert_eq!(bin, [0xde, 0xad, 0xbe, 0xef]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_connection_id(&mut cursor).unwrap(),
+            connection_id
+        );
+    }
+}
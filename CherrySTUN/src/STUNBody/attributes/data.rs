@@ -0,0 +1,63 @@
+/*
+ * The DATA attribute is present in all Send indications.  If the
+ * ICMP attribute is not present in a Data indication, it contains a
+ * DATA attribute.  The value portion of this attribute is variable
+ * length and consists of the application data (that is, the data that
+ * would immediately follow the UDP header if the data was sent directly
+ * between the client and the peer).  The application data is equivalent
+ * to the "UDP user data" and does not include the "surplus area"
+ * defined in Section 4 of [UDP-OPT].  If the length of this attribute
+ * is not a multiple of 4, then padding must be added after this
+ * attribute.
+ * (RFC 8656 section 18.4)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_data(data: Vec<u8>) -> Self {
+        Self::Data { data }
+    }
+
+    ///returns the non padded bin, use the `add_padding_to_attr_bin` to add the required padding
+    pub fn encode_data(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::Data { data } => return Ok(data.clone()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for Data on non Data type".to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_data(cursor: &mut Cursor<&[u8]>, length: u16) -> Result<Self, STUNError> {
+        match Self::read_padded_attr_bin(cursor, length) {
+            Ok(data) => return Ok(Self::Data { data }),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_data_encode_decode() {
+        let data = STUNAttributesContent::new_data(vec![1, 2, 3, 4, 5]);
+        let mut bin = data.encode_data().unwrap();
+        STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+        assert_eq!(bin, [1, 2, 3, 4, 5, 0, 0, 0]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_data(&mut cursor, 5).unwrap(),
+            data
+        );
+        assert_eq!(cursor.position(), 8);
+    }
+}
@@ -0,0 +1,60 @@
+/*
+ * This attribute is used by the client to request that the server set
+ * the DF (Don't Fragment) bit in the IP header when relaying the
+ * application data onward to the peer and for determining the server
+ * capability in Allocate requests.  This attribute has no value part,
+ * and thus, the attribute length field is 0.
+ * (RFC 8656 section 18.8)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_dont_fragment() -> Self {
+        Self::DontFragment
+    }
+
+    pub fn encode_dont_fragment(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::DontFragment => return Ok(Vec::new()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for DontFragment on non DontFragment type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    //Whatever a sender put in the value is skipped
+    pub fn decode_dont_fragment(
+        cursor: &mut Cursor<&[u8]>,
+        length: u16,
+    ) -> Result<Self, STUNError> {
+        match Self::read_padded_attr_bin(cursor, length) {
+            Ok(_) => return Ok(Self::DontFragment),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dont_fragment_encode_decode() {
+        let dont_fragment = STUNAttributesContent::new_dont_fragment();
+        assert!(dont_fragment.encode_dont_fragment().unwrap().is_empty());
+        let bin: [u8; 0] = [];
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_dont_fragment(&mut cursor, 0).unwrap(),
+            dont_fragment
+        );
+    }
+}
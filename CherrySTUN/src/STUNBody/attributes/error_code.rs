@@ -0,0 +1,161 @@
+/*
+ * The ERROR-CODE attribute is used in error response messages.  It
+ * contains a numeric error code value in the range of 300 to 699 plus a
+ * textual reason phrase encoded in UTF-8 [RFC3629].
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |           Reserved, should be 0         |Class|     Number    |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |      Reason Phrase (variable)                                ..
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *
+ * The Class represents the hundreds digit of the error code.  The value
+ * MUST be between 3 and 6.  The Number represents the binary encoding of
+ * the error code modulo 100, and its value MUST be between 0 and 99.
+ */
+
+use super::attributes::{STUNAttributesContent, STUNErrorCode};
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Write};
+
+impl STUNAttributesContent {
+    ///Error code attribute with the RFC reason phrase
+    pub fn new_error_code(code: STUNErrorCode) -> Self {
+        Self::ErrorCode {
+            code: code as u16,
+            reason: code.reason_phrase().to_string(),
+        }
+    }
+
+    ///returns the non padded error code bin, use the `add_padding_to_attr_bin` to add the
+    ///required padding
+    pub fn encode_error_code(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::ErrorCode { code, reason } => {
+                if *code < 300 || *code > 699 {
+                    return Err(STUNError {
+                        step: STUNStep::STUNEncode,
+                        error_type: STUNErrorType::AttributeStructureMismatch,
+                        message: "Error code must be between 300 and 699, found: ".to_string()
+                            + code.to_string().as_str(),
+                    });
+                }
+                let bin: Vec<u8> = Vec::new();
+                let mut write_cursor = Cursor::new(bin);
+                match write_cursor.write_u16::<NetworkEndian>(0) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(STUNError {
+                            step: STUNStep::STUNEncode,
+                            error_type: STUNErrorType::WriteError,
+                            message: e.to_string() + "Error writing reserved bits of error code.",
+                        })
+                    }
+                }
+                match write_cursor.write_all(&[(code / 100) as u8, (code % 100) as u8]) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(STUNError {
+                            step: STUNStep::STUNEncode,
+                            error_type: STUNErrorType::WriteError,
+                            message: e.to_string() + "Error writing class/number of error code.",
+                        })
+                    }
+                }
+                match write_cursor.write_all(reason.as_bytes()) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(STUNError {
+                            step: STUNStep::STUNEncode,
+                            error_type: STUNErrorType::WriteError,
+                            message: e.to_string() + "Error writing reason phrase of error code.",
+                        })
+                    }
+                }
+                return Ok(write_cursor.get_ref().to_vec());
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for ErrorCode on non ErrorCode type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_error_code(cursor: &mut Cursor<&[u8]>, length: u16) -> Result<Self, STUNError> {
+        if length < 4 {
+            return Err(STUNError {
+                step: STUNStep::STUNDecode,
+                error_type: STUNErrorType::AttributeStructureMismatch,
+                message: "Error code attribute shorter than 4 bytes".to_string(),
+            });
+        }
+        let class_number = match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => bin,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading class and number of error code. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        let code = (((class_number >> 8) & 0b111) * 100 + (class_number & 0xff)) as u16;
+        let reason_bin = match Self::read_padded_attr_bin(cursor, length - 4) {
+            Ok(bin) => bin,
+            Err(e) => return Err(e),
+        };
+        let reason = match String::from_utf8(reason_bin) {
+            Ok(str) => str,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::UTF8DecodeError,
+                    message: "Error decoding reason phrase to string utf8. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        return Ok(Self::ErrorCode { code, reason });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_code_encode_decode() {
+        let error_attr = STUNAttributesContent::new_error_code(STUNErrorCode::UnknownAttribute);
+        let mut bin = error_attr.encode_error_code().unwrap();
+        assert_eq!(bin[..4], [0x00, 0x00, 0x04, 0x14]);
+        assert_eq!(bin.len(), 4 + "Unknown Attribute".len());
+        let length = bin.len() as u16;
+        STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+        let mut cursor = Cursor::new(&bin[..]);
+        match STUNAttributesContent::decode_error_code(&mut cursor, length) {
+            Ok(attr) => {
+                assert_eq!(attr, error_attr);
+                assert_eq!(cursor.position(), bin.len() as u64);
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+                panic!("Unexpected error...");
+            }
+        }
+
+        let invalid_attr = STUNAttributesContent::ErrorCode {
+            code: 200,
+            reason: "OK".to_string(),
+        };
+        assert!(invalid_attr.encode_error_code().is_err());
+        return;
+    }
+}
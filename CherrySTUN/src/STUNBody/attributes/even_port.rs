@@ -0,0 +1,95 @@
+/*
+ * This attribute allows the client to request that the port in the
+ * relayed transport address be even and (optionally) that the server
+ * reserve the next-higher port number.  The value portion of this
+ * attribute is 1 byte long.
+ *
+ *     0
+ *     0 1 2 3 4 5 6 7
+ *    +-+-+-+-+-+-+-+-+
+ *    |R|    RFFU     |
+ *    +-+-+-+-+-+-+-+-+
+ *
+ * R: If 1, the server is requested to reserve the next-higher port
+ *    number (on the same IP address) for a subsequent allocation.  If
+ *    0, no such reservation is requested.
+ *
+ * The other 7 bits of the attribute's value must be set to zero on
+ * transmission and ignored on reception.
+ * (RFC 8656 section 18.6)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+
+const EVEN_PORT_RESERVE_BIT: u8 = 0x80;
+
+impl STUNAttributesContent {
+    pub fn new_even_port(reserve: bool) -> Self {
+        Self::EvenPort { reserve }
+    }
+
+    ///returns the non padded bin, use the `add_padding_to_attr_bin` to add the required padding
+    pub fn encode_even_port(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::EvenPort { reserve } => match reserve {
+                true => return Ok(vec![EVEN_PORT_RESERVE_BIT]),
+                false => return Ok(vec![0]),
+            },
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for EvenPort on non EvenPort type".to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_even_port(cursor: &mut Cursor<&[u8]>, length: u16) -> Result<Self, STUNError> {
+        let bin = match Self::read_padded_attr_bin(cursor, length) {
+            Ok(bin) => bin,
+            Err(e) => return Err(e),
+        };
+        match bin.first() {
+            Some(flags) => {
+                return Ok(Self::EvenPort {
+                    reserve: flags & EVEN_PORT_RESERVE_BIT != 0,
+                })
+            }
+            None => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::AttributeStructureMismatch,
+                    message: "Empty EVEN-PORT attribute".to_string(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_even_port_encode_decode() {
+        let even_port = STUNAttributesContent::new_even_port(true);
+        let mut bin = even_port.encode_even_port().unwrap();
+        STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+        assert_eq!(bin, [0x80, 0, 0, 0]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_even_port(&mut cursor, 1).unwrap(),
+            even_port
+        );
+        assert_eq!(cursor.position(), 4);
+        //RFFU bits are ignored
+        let bin = [0x7F, 0, 0, 0];
+        assert_eq!(
+            STUNAttributesContent::decode_even_port(&mut Cursor::new(&bin[..]), 1).unwrap(),
+            STUNAttributesContent::new_even_port(false)
+        );
+    }
+}
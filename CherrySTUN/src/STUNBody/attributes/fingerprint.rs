@@ -0,0 +1,76 @@
+/*
+ * The FINGERPRINT attribute MAY be present in all STUN messages.
+ *
+ * The value of the attribute is computed as the CRC-32 of the STUN
+ * message up to (but excluding) the FINGERPRINT attribute itself,
+ * XOR'ed with the 32-bit value 0x5354554e.  (The XOR operation ensures
+ * that the FINGERPRINT test will not report a false positive on a
+ * packet containing a CRC-32 generated by an application protocol.)
+ * The 32-bit CRC is the one defined in ITU V.42, which has a generator
+ * polynomial of x^32 + x^26 + x^23 + x^22 + x^16 + x^12 + x^11 + x^10 +
+ * x^8 + x^7 + x^5 + x^4 + x^2 + x + 1.
+ *
+ * When present, the FINGERPRINT attribute MUST be the last attribute in
+ * the message and thus will appear after MESSAGE-INTEGRITY and
+ * MESSAGE-INTEGRITY-SHA256.
+ *
+ * Like MESSAGE-INTEGRITY, the length field in the header has to include the
+ * FINGERPRINT attribute (8 bytes) before the CRC is computed.
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+pub const STUN_FINGERPRINT_XOR: u32 = 0x5354554e;
+
+impl STUNAttributesContent {
+    pub fn new_fingerprint() -> Self {
+        Self::Fingerprint
+    }
+
+    ///`message_bin` is the message up to the FINGERPRINT attribute, with the length in the
+    ///header already including it
+    pub fn compute_fingerprint(message_bin: &[u8]) -> u32 {
+        return Self::crc32(message_bin) ^ STUN_FINGERPRINT_XOR;
+    }
+
+    pub fn decode_fingerprint(cursor: &mut Cursor<&[u8]>) -> Result<u32, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(fingerprint) => return Ok(fingerprint),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading fingerprint. ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+
+    //Bitwise CRC-32 (ISO-HDLC, reflected 0xEDB88320), messages are small enough to not need a
+    //table
+    fn crc32(bin: &[u8]) -> u32 {
+        let mut crc: u32 = 0xFFFFFFFF;
+        for byte in bin.iter() {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (!(crc & 1)).wrapping_add(1);
+                crc = (crc >> 1) ^ (0xEDB88320 & mask);
+            }
+        }
+        return !crc;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(STUNAttributesContent::crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(STUNAttributesContent::crc32(b""), 0);
+    }
+}
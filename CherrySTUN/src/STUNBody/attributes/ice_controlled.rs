@@ -0,0 +1,68 @@
+/*
+ * The ICE-CONTROLLED attribute is present in a Binding request.  The
+ * attribute indicates that the client believes it is currently in the
+ * controlled role.  The content of the attribute is a 64-bit unsigned
+ * integer in network byte order, which contains a random number.  The
+ * number is used for solving role conflicts, when it is referred to as
+ * the "tiebreaker value".  An ICE agent MUST use the same number for
+ * all Binding requests, for all streams, within an ICE session, unless
+ * it has received a 487 response, in which case it MUST change the
+ * number.
+ * (RFC 8445 section 16.1)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_ice_controlled(tie_breaker: u64) -> Self {
+        Self::IceControlled { tie_breaker }
+    }
+
+    pub fn encode_ice_controlled(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::IceControlled { tie_breaker } => return Ok(tie_breaker.to_be_bytes().to_vec()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for IceControlled on non IceControlled type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_ice_controlled(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u64::<NetworkEndian>() {
+            Ok(tie_breaker) => return Ok(Self::IceControlled { tie_breaker }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading ICE-CONTROLLED tiebreaker. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ice_controlled_encode_decode() {
+        let ice_controlled = STUNAttributesContent::new_ice_controlled(0x0102030405060708);
+        let bin = ice_controlled.encode_ice_controlled().unwrap();
+        assert_eq!(bin, [1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_ice_controlled(&mut cursor).unwrap(),
+            ice_controlled
+        );
+    }
+}
@@ -0,0 +1,65 @@
+/*
+ * The ICE-CONTROLLING attribute is present in a Binding request.  The
+ * attribute indicates that the client believes it is currently in the
+ * controlling role.  The content of the attribute is a 64-bit unsigned
+ * integer in network byte order, which contains a random number.  As
+ * for the ICE-CONTROLLED attribute, the number is used for solving role
+ * conflicts.
+ * (RFC 8445 section 16.1)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_ice_controlling(tie_breaker: u64) -> Self {
+        Self::IceControlling { tie_breaker }
+    }
+
+    pub fn encode_ice_controlling(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::IceControlling { tie_breaker } => return Ok(tie_breaker.to_be_bytes().to_vec()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for IceControlling on non IceControlling type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_ice_controlling(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u64::<NetworkEndian>() {
+            Ok(tie_breaker) => return Ok(Self::IceControlling { tie_breaker }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading ICE-CONTROLLING tiebreaker. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ice_controlling_encode_decode() {
+        let ice_controlling = STUNAttributesContent::new_ice_controlling(u64::MAX - 1);
+        let bin = ice_controlling.encode_ice_controlling().unwrap();
+        assert_eq!(bin, [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_ice_controlling(&mut cursor).unwrap(),
+            ice_controlling
+        );
+    }
+}
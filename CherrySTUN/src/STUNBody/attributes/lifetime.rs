@@ -0,0 +1,48 @@
+/*
+ * The LIFETIME attribute represents the duration for which the server
+ * will maintain an allocation in the absence of a refresh.  The TURN
+ * client can include the LIFETIME attribute with the desired lifetime
+ * in Allocate and Refresh requests.  The value portion of this
+ * attribute is 4 bytes long and consists of a 32-bit unsigned integral
+ * value representing the number of seconds remaining until expiration.
+ * (RFC 8656 section 18.2)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_lifetime(lifetime: u32) -> Self {
+        Self::Lifetime { lifetime }
+    }
+
+    pub fn encode_lifetime(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::Lifetime { lifetime } => {
+                return Ok(lifetime.to_be_bytes().to_vec());
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for Lifetime on non Lifetime type".to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_lifetime(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(lifetime) => return Ok(Self::Lifetime { lifetime }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading lifetime. ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
@@ -71,14 +71,23 @@ impl STUNAttributesContent {
                         }
                     }
                     STUNAuthType::ShortTerm => {
-                        //[TODO]
-                        return Err(STUNError {
-                            step: STUNStep::STUNEncode,
-                            error_type: STUNErrorType::AttributeTypeMismatch,
-                            message:
-                                "Called encode function for Mapped address on non Mapped address type"
-                                .to_string(),
-                        });
+                        let hmac_key = match Self::get_short_term_hmac_key(encode_context) {
+                            Ok(bin) => bin,
+                            Err(e) => return Err(e),
+                        };
+                        let hmac_digest: [u8; hmac_sha1::SHA1_DIGEST_BYTES] =
+                            hmac_sha1::hmac_sha1(hmac_key.as_slice(), message_bin);
+                        match message_integrity_bin.write_all(&hmac_digest) {
+                            Ok(()) => {}
+                            Err(e) => {
+                                return Err(STUNError {
+                                    step: STUNStep::STUNEncode,
+                                    error_type: STUNErrorType::WriteError,
+                                    message: "Error writing hmac to bin rep. ".to_string()
+                                        + e.to_string().as_str(),
+                                })
+                            }
+                        }
                     }
                 }
 
@@ -96,9 +105,36 @@ impl STUNAttributesContent {
         }
     }
 
+    ///Long-term credential key, MD5(username ":" realm ":" SASLprep(password)). Servers can
+    ///store this instead of the password and hand it out through `STUNContext::integrity_key`
+    pub fn long_term_key(
+        username: String,
+        realm: String,
+        password: String,
+    ) -> Result<Vec<u8>, STUNError> {
+        let mut context = STUNContext::new();
+        context.username = Some(username);
+        context.realm = Some(realm);
+        context.password = Some(password);
+        return Self::get_hmac_key(&Some(&context));
+    }
+
+    ///Short-term credential key, SASLprep(password)
+    pub fn short_term_key(password: String) -> Result<Vec<u8>, STUNError> {
+        let mut context = STUNContext::new();
+        context.password = Some(password);
+        return Self::get_short_term_hmac_key(&Some(&context));
+    }
+
     fn get_hmac_key(encode_context: &Option<&STUNContext>) -> Result<Vec<u8>, STUNError> {
         let mut hmac_key_input: String = String::new();
         match encode_context {
+            Some(STUNContext {
+                integrity_key: Some(key),
+                ..
+            }) => {
+                return Ok(key.clone());
+            }
             Some(context) => {
                 match &context.username {
                     Some(username) => hmac_key_input.push_str(&username),
@@ -162,6 +198,36 @@ impl STUNAttributesContent {
         log::debug!("{:?}", digest);
         return Ok(digest.to_vec());
     }
+    //Short-term key is SASLprep(password), username and realm play no part
+    fn get_short_term_hmac_key(
+        encode_context: &Option<&STUNContext>,
+    ) -> Result<Vec<u8>, STUNError> {
+        match encode_context {
+            Some(STUNContext {
+                integrity_key: Some(key),
+                ..
+            }) => {
+                return Ok(key.clone());
+            }
+            Some(STUNContext {
+                password: Some(password),
+                ..
+            }) => match Self::sasl(password.to_string()) {
+                Ok(pass) => return Ok(pass.into_bytes()),
+                Err(e) => return Err(e),
+            },
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::RequiredContextMissingError,
+                    message:
+                        "Required context (password) to compute short-term MessageIntegrity is missing."
+                            .to_string(),
+                });
+            }
+        }
+    }
+
     pub fn extract_hmac(cursor: &mut Cursor<&[u8]>) -> Result<Vec<u8>, STUNError> {
         let mut hmac_bin = vec![0; 20 as usize];
         match cursor.read_exact(hmac_bin.as_mut_slice()) {
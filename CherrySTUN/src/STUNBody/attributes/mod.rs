@@ -10,3 +10,28 @@ mod username;
 mod utils;
 mod xor_mapped_address;
 mod alternate_address;
+mod error_code;
+mod software;
+mod unknown_attributes;
+mod change_request;
+mod other_address;
+mod padding;
+mod response_port;
+mod fingerprint;
+mod requested_transport;
+mod lifetime;
+mod xor_relayed_address;
+mod xor_peer_address;
+mod data;
+mod channel_number;
+mod connection_id;
+mod dont_fragment;
+mod even_port;
+mod reservation_token;
+mod additional_address_family;
+mod address_error_code;
+mod requested_address_family;
+mod priority;
+mod use_candidate;
+mod ice_controlled;
+mod ice_controlling;
@@ -0,0 +1,56 @@
+/*
+ * OTHER-ADDRESS and RESPONSE-ORIGIN (RFC 5780 section 7.3 and 7.4) use the same encoding as
+ * MAPPED-ADDRESS.
+ *
+ * The OTHER-ADDRESS attribute is used in Binding Responses.  It informs
+ * the client of the source IP address and port that would be used if
+ * the client requested the "change IP" and "change port" behavior.
+ *
+ * The RESPONSE-ORIGIN attribute is inserted by the server and indicates
+ * the source IP address and port the response was sent from.
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+use std::net::SocketAddr;
+
+impl STUNAttributesContent {
+    pub fn new_other_address(address: SocketAddr) -> Self {
+        Self::OtherAddress { address }
+    }
+
+    pub fn new_response_origin(address: SocketAddr) -> Self {
+        Self::ResponseOrigin { address }
+    }
+
+    pub fn encode_other_address(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::OtherAddress { address } | Self::ResponseOrigin { address } => {
+                return Self::new_mapped_address(*address).encode_mapped_address();
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for OtherAddress on non OtherAddress type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_response_origin(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match Self::decode_mapped_address(cursor) {
+            Ok(Self::MappedAddress { address }) => return Ok(Self::ResponseOrigin { address }),
+            Ok(_) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::InternalError,
+                    message: "Mapped address decode returned a different attribute".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+/*
+ * The PADDING attribute allows for the entire message to be padded to
+ * force the STUN message to be divided into IP fragments.  PADDING
+ * consists entirely of a free-form string, the value of which does not
+ * matter.  PADDING can be used in either Binding Requests or Binding
+ * Responses.
+ * (RFC 5780 section 7.6)
+ *
+ * As the value does not matter, only the length of it is kept in memory.
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_padding(length: u16) -> Self {
+        Self::Padding { length }
+    }
+
+    ///returns the non padded bin, use the `add_padding_to_attr_bin` to add the required padding
+    pub fn encode_padding(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::Padding { length } => {
+                return Ok(vec![0; *length as usize]);
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for Padding on non Padding type".to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_padding(cursor: &mut Cursor<&[u8]>, length: u16) -> Result<Self, STUNError> {
+        match Self::read_padded_attr_bin(cursor, length) {
+            Ok(_) => return Ok(Self::Padding { length }),
+            Err(e) => return Err(e),
+        }
+    }
+}
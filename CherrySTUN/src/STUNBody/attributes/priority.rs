@@ -0,0 +1,61 @@
+/*
+ * The PRIORITY attribute indicates the priority that is to be
+ * associated with a peer-reflexive candidate, if one will be discovered
+ * by this check.  It is a 32-bit unsigned integer and has an attribute
+ * value of 0x0024.
+ * (RFC 8445 section 16.1)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_priority(priority: u32) -> Self {
+        Self::Priority { priority }
+    }
+
+    pub fn encode_priority(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::Priority { priority } => return Ok(priority.to_be_bytes().to_vec()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for Priority on non Priority type".to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_priority(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(priority) => return Ok(Self::Priority { priority }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading priority. ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_priority_encode_decode() {
+        let priority = STUNAttributesContent::new_priority(0x6E0001FF);
+        let bin = priority.encode_priority().unwrap();
+        assert_eq!(bin, [0x6E, 0x00, 0x01, 0xFF]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_priority(&mut cursor).unwrap(),
+            priority
+        );
+    }
+}
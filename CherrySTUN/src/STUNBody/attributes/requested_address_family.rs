@@ -0,0 +1,81 @@
+/*
+ * This attribute is used in Allocate and Refresh requests to specify
+ * the address type requested by the client.  The value of this
+ * attribute is 4 bytes.
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |    Family     |            Reserved                           |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *
+ * Family:  There are two values defined for this field and specified
+ *    in Section 14.1 of [RFC8489]: 0x01 for IPv4 addresses and 0x02
+ *    for IPv6 addresses.
+ * (RFC 8656 section 18.10)
+ */
+
+use super::attributes::STUNAttributesContent;
+#[cfg(test)]
+use super::attributes::TURN_ADDRESS_FAMILY_IPV6;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_requested_address_family(family: u8) -> Self {
+        Self::RequestedAddressFamily { family }
+    }
+
+    pub fn encode_requested_address_family(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::RequestedAddressFamily { family } => return Ok(vec![*family, 0, 0, 0]),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for RequestedAddressFamily on non RequestedAddressFamily type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_requested_address_family(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => {
+                return Ok(Self::RequestedAddressFamily {
+                    family: (bin >> 24) as u8,
+                })
+            }
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading requested address family. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_requested_address_family_encode_decode() {
+        let requested_address_family =
+            STUNAttributesContent::new_requested_address_family(TURN_ADDRESS_FAMILY_IPV6);
+        let bin = requested_address_family
+            .encode_requested_address_family()
+            .unwrap();
+        assert_eq!(bin, [2, 0, 0, 0]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_requested_address_family(&mut cursor).unwrap(),
+            requested_address_family
+        );
+    }
+}
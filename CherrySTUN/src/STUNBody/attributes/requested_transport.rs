@@ -0,0 +1,79 @@
+/*
+ * This attribute is used by the client to request a specific transport
+ * protocol for the allocated transport address.
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |    Protocol   |                    RFFU                       |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *
+ * The Protocol field specifies the desired protocol.  The codepoints
+ * used in this field are taken from those allowed in the Protocol field
+ * in the IPv4 header and the NextHeader field in the IPv6 header.
+ * (RFC 8656 section 18.11)
+ */
+
+use super::attributes::STUNAttributesContent;
+#[cfg(test)]
+use super::attributes::TURN_TRANSPORT_UDP;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_requested_transport(protocol: u8) -> Self {
+        Self::RequestedTransport { protocol }
+    }
+
+    pub fn encode_requested_transport(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::RequestedTransport { protocol } => {
+                return Ok(vec![*protocol, 0, 0, 0]);
+            }
+            _ => return Err(STUNError {
+                step: STUNStep::STUNEncode,
+                error_type: STUNErrorType::AttributeTypeMismatch,
+                message:
+                    "Called encode function for RequestedTransport on non RequestedTransport type"
+                        .to_string(),
+            }),
+        }
+    }
+
+    pub fn decode_requested_transport(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u32::<NetworkEndian>() {
+            Ok(bin) => {
+                return Ok(Self::RequestedTransport {
+                    protocol: (bin >> 24) as u8,
+                })
+            }
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading requested transport. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_requested_transport_encode_decode() {
+        let requested_transport =
+            STUNAttributesContent::new_requested_transport(TURN_TRANSPORT_UDP);
+        let bin = requested_transport.encode_requested_transport().unwrap();
+        assert_eq!(bin, [17, 0, 0, 0]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_requested_transport(&mut cursor).unwrap(),
+            requested_transport
+        );
+    }
+}
@@ -0,0 +1,68 @@
+/*
+ * The RESERVATION-TOKEN attribute contains a token that uniquely
+ * identifies a relayed transport address being held in reserve by the
+ * server.  The server includes this attribute in a success response to
+ * tell the client about the token, and the client includes this
+ * attribute in a subsequent Allocate request to request the server use
+ * that relayed transport address for the allocation.
+ *
+ * The attribute value is 8 bytes and contains the token value.
+ * (RFC 8656 section 18.9)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_reservation_token(token: u64) -> Self {
+        Self::ReservationToken { token }
+    }
+
+    pub fn encode_reservation_token(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::ReservationToken { token } => return Ok(token.to_be_bytes().to_vec()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message:
+                        "Called encode function for ReservationToken on non ReservationToken type"
+                            .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_reservation_token(cursor: &mut Cursor<&[u8]>) -> Result<Self, STUNError> {
+        match cursor.read_u64::<NetworkEndian>() {
+            Ok(token) => return Ok(Self::ReservationToken { token }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading reservation token. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reservation_token_encode_decode() {
+        let reservation_token = STUNAttributesContent::new_reservation_token(0x0102030405060708);
+        let bin = reservation_token.encode_reservation_token().unwrap();
+        assert_eq!(bin, [1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_reservation_token(&mut cursor).unwrap(),
+            reservation_token
+        );
+    }
+}
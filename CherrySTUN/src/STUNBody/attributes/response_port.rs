@@ -0,0 +1,82 @@
+/*
+ * The RESPONSE-PORT attribute contains a port.  The RESPONSE-PORT
+ * attribute can be present in the Binding Request and indicates which
+ * port the Binding Response will be sent to.  For servers which support
+ * the RESPONSE-PORT attribute, the Binding Response MUST be transmitted
+ * to the source IP address of the Binding Request and the port contained
+ * in RESPONSE-PORT.  It is used in tests such as Section 4.6.  When not
+ * present, the server sends the Binding Response to the source IP
+ * address and port of the Binding Request.  The server MUST NOT process
+ * a request containing a RESPONSE-PORT and a PADDING attribute.  The
+ * RESPONSE-PORT attribute is a 16-bit unsigned integer in network byte
+ * order followed by 2 bytes of padding.
+ * (RFC 5780 section 7.5)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_response_port(port: u16) -> Self {
+        Self::ResponsePort { port }
+    }
+
+    ///returns the non padded bin, use the `add_padding_to_attr_bin` to add the required padding
+    pub fn encode_response_port(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::ResponsePort { port } => {
+                return Ok(port.to_be_bytes().to_vec());
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for ResponsePort on non ResponsePort type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_response_port(
+        cursor: &mut Cursor<&[u8]>,
+        length: u16,
+    ) -> Result<Self, STUNError> {
+        let bin = match Self::read_padded_attr_bin(cursor, length) {
+            Ok(bin) => bin,
+            Err(e) => return Err(e),
+        };
+        match (&bin[..]).read_u16::<NetworkEndian>() {
+            Ok(port) => return Ok(Self::ResponsePort { port }),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading response port. ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_response_port_encode_decode() {
+        let response_port = STUNAttributesContent::new_response_port(32853);
+        let mut bin = response_port.encode_response_port().unwrap();
+        assert_eq!(bin, [0x80, 0x55]);
+        STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_response_port(&mut cursor, 2).unwrap(),
+            response_port
+        );
+        assert_eq!(cursor.position(), 4);
+        return;
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * The SOFTWARE attribute contains a textual description of the software
+ * being used by the agent sending the message.  It is used by clients
+ * and servers.  Its value SHOULD include manufacturer and version
+ * number.  The attribute has no impact on operation of the protocol and
+ * serves only as a tool for diagnostic and debugging purposes.  The
+ * value of SOFTWARE is variable length.  It MUST be a UTF-8-encoded
+ * sequence of fewer than 128 characters (which can be as long as 509
+ * when encoding them and as long as 763 bytes when decoding them).
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+
+pub const STUN_SOFTWARE_MAX_CHARS: usize = 128;
+
+impl STUNAttributesContent {
+    pub fn new_software(software: String) -> Self {
+        Self::Software { software }
+    }
+
+    ///returns the non padded software bin, use the `add_padding_to_attr_bin` to add the
+    ///required padding
+    pub fn encode_software(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::Software { software } => {
+                if software.chars().count() >= STUN_SOFTWARE_MAX_CHARS {
+                    return Err(STUNError {
+                        step: STUNStep::STUNEncode,
+                        error_type: STUNErrorType::AttributeStructureMismatch,
+                        message: "SOFTWARE must be fewer than 128 characters".to_string(),
+                    });
+                }
+                return Ok(software.clone().into_bytes());
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for Software on non Software type".to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_software(cursor: &mut Cursor<&[u8]>, length: u16) -> Result<Self, STUNError> {
+        let software_bin = match Self::read_padded_attr_bin(cursor, length) {
+            Ok(bin) => bin,
+            Err(e) => return Err(e),
+        };
+        let software = match String::from_utf8(software_bin) {
+            Ok(str) => str,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::UTF8DecodeError,
+                    message: "Error decoding software to string utf8. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        return Ok(Self::Software { software });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TestFixtures::fixtures::*;
+
+    #[test]
+    fn test_software_encode_decode() {
+        let software_attr = STUNAttributesContent::new_software("STUN test client".to_string());
+        match software_attr.encode_software() {
+            Ok(mut bin) => {
+                STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+                assert_eq!(bin, STUN_REQUEST_BODY_BIN[4..20]);
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+                panic!("Unexpected error...");
+            }
+        }
+
+        //"test vector" with 1 byte of padding
+        let mut cursor = Cursor::new(&STUN_IPV4_XOR_MAPPED_RESPONSE_BODY_BIN[4..16]);
+        match STUNAttributesContent::decode_software(&mut cursor, 11) {
+            Ok(attr) => {
+                assert_eq!(
+                    attr,
+                    STUNAttributesContent::Software {
+                        software: "test vector".to_string()
+                    }
+                );
+                assert_eq!(cursor.position(), 12);
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+                panic!("Unexpected error...");
+            }
+        }
+
+        let long_software = STUNAttributesContent::new_software("a".repeat(128));
+        assert!(long_software.encode_software().is_err());
+        return;
+    }
+}
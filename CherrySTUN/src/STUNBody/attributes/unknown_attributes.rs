@@ -0,0 +1,102 @@
+/*
+ * The UNKNOWN-ATTRIBUTES attribute is present only in an error response
+ * when the response code in the ERROR-CODE attribute is 420 (Unknown
+ * Attribute).
+ *
+ * The attribute contains a list of 16-bit values, each of which
+ * represents an attribute type that was not understood by the server.
+ *
+ *     0                   1                   2                   3
+ *     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |      Attribute 1 Type         |       Attribute 2 Type        |
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ *    |      Attribute 3 Type         |       Attribute 4 Type    ...
+ *    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_unknown_attributes(attributes: Vec<u16>) -> Self {
+        Self::UnknownAttributes { attributes }
+    }
+
+    ///returns the non padded bin, use the `add_padding_to_attr_bin` to add the required padding
+    pub fn encode_unknown_attributes(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::UnknownAttributes { attributes } => {
+                let bin: Vec<u8> = Vec::new();
+                let mut write_cursor = Cursor::new(bin);
+                for attribute_type in attributes.iter() {
+                    match write_cursor.write_u16::<NetworkEndian>(*attribute_type) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Err(STUNError {
+                                step: STUNStep::STUNEncode,
+                                error_type: STUNErrorType::WriteError,
+                                message: e.to_string()
+                                    + "Error writing attribute type to unknown attributes.",
+                            })
+                        }
+                    }
+                }
+                return Ok(write_cursor.get_ref().to_vec());
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message:
+                        "Called encode function for UnknownAttributes on non UnknownAttributes type"
+                            .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_unknown_attributes(
+        cursor: &mut Cursor<&[u8]>,
+        length: u16,
+    ) -> Result<Self, STUNError> {
+        let bin = match Self::read_padded_attr_bin(cursor, length) {
+            Ok(bin) => bin,
+            Err(e) => return Err(e),
+        };
+        let mut attributes = Vec::new();
+        let mut attr_cursor = Cursor::new(&bin[..]);
+        while let Ok(attribute_type) = attr_cursor.read_u16::<NetworkEndian>() {
+            attributes.push(attribute_type);
+        }
+        return Ok(Self::UnknownAttributes { attributes });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unknown_attributes_encode_decode() {
+        let unknown_attr =
+            STUNAttributesContent::new_unknown_attributes(vec![0x0031, 0x7001, 0x0045]);
+        let mut bin = unknown_attr.encode_unknown_attributes().unwrap();
+        assert_eq!(bin, [0x00, 0x31, 0x70, 0x01, 0x00, 0x45]);
+        STUNAttributesContent::add_padding_to_attr_bin(&mut bin);
+        let mut cursor = Cursor::new(&bin[..]);
+        match STUNAttributesContent::decode_unknown_attributes(&mut cursor, 6) {
+            Ok(attr) => {
+                assert_eq!(attr, unknown_attr);
+                assert_eq!(cursor.position(), 8);
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+                panic!("Unexpected error...");
+            }
+        }
+        return;
+    }
+}
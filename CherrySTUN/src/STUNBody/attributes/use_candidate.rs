@@ -0,0 +1,63 @@
+/*
+ * The controlling agent MUST include the USE-CANDIDATE attribute in
+ * order to nominate a candidate pair.  The controlled agent MUST NOT
+ * include the USE-CANDIDATE attribute in a Binding request.
+ *
+ * The USE-CANDIDATE attribute indicates that the candidate pair
+ * resulting from this check will be used for transmission of data.  The
+ * attribute has no content (the Length field of the attribute is zero);
+ * it serves as a flag.  It has an attribute value of 0x0025.
+ * (RFC 8445 sections 7.1.2 and 16.1)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+
+impl STUNAttributesContent {
+    pub fn new_use_candidate() -> Self {
+        Self::UseCandidate
+    }
+
+    pub fn encode_use_candidate(&self) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::UseCandidate => return Ok(Vec::new()),
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for UseCandidate on non UseCandidate type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    //Whatever a sender put in the value is skipped
+    pub fn decode_use_candidate(
+        cursor: &mut Cursor<&[u8]>,
+        length: u16,
+    ) -> Result<Self, STUNError> {
+        match Self::read_padded_attr_bin(cursor, length) {
+            Ok(_) => return Ok(Self::UseCandidate),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_use_candidate_encode_decode() {
+        let use_candidate = STUNAttributesContent::new_use_candidate();
+        assert!(use_candidate.encode_use_candidate().unwrap().is_empty());
+        let bin: [u8; 0] = [];
+        let mut cursor = Cursor::new(&bin[..]);
+        assert_eq!(
+            STUNAttributesContent::decode_use_candidate(&mut cursor, 0).unwrap(),
+            use_candidate
+        );
+    }
+}
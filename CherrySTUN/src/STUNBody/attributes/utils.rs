@@ -1,5 +1,6 @@
 use super::attributes::STUNAttributesContent;
 use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::{Cursor, Read};
 use stringprep::saslprep;
 
 //Used in a lot of user inputted fields that show up in the protocol
@@ -34,4 +35,27 @@ impl STUNAttributesContent {
             attr_bin.push(0 as u8); //Adding padding, can be random
         }
     }
+
+    ///Reads a `length` long attribute value along with its padding, returns the value without
+    ///the padding
+    pub fn read_padded_attr_bin(
+        cursor: &mut Cursor<&[u8]>,
+        length: u16,
+    ) -> Result<Vec<u8>, STUNError> {
+        let padded_length = crate::STUNBody::body::STUNBody::padded_len_calculator(length);
+        let mut attr_with_padding = vec![0; padded_length as usize];
+        match cursor.read_exact(attr_with_padding.as_mut_slice()) {
+            Ok(_) => {}
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::ReadError,
+                    message: "Error reading attribute value from bin rep. ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        attr_with_padding.truncate(length as usize);
+        return Ok(attr_with_padding);
+    }
 }
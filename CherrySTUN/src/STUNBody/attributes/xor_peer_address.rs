@@ -0,0 +1,52 @@
+/*
+ * The XOR-PEER-ADDRESS specifies the address and port of the peer as
+ * seen from the TURN server.  (For example, the peer's server-reflexive
+ * transport address if the peer is behind a NAT.)  It is encoded in the
+ * same way as the XOR-MAPPED-ADDRESS attribute.
+ * (RFC 8656 section 18.3)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+use std::net::SocketAddr;
+
+impl STUNAttributesContent {
+    pub fn new_xor_peer_address(address: SocketAddr) -> Self {
+        Self::XORPeerAddress { address }
+    }
+
+    pub fn encode_xor_peer_address(&self, transaction_id: [u8; 12]) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::XORPeerAddress { address } => {
+                return Self::new_xor_mapped_address(*address)
+                    .encode_xor_mapped_address(transaction_id);
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message: "Called encode function for XORPeerAddress on non XORPeerAddress type"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_xor_peer_address(
+        cursor: &mut Cursor<&[u8]>,
+        transaction_id: [u8; 12],
+    ) -> Result<Self, STUNError> {
+        match Self::decode_xor_mapped_address(cursor, transaction_id) {
+            Ok(Self::XORMappedAddress { address }) => return Ok(Self::XORPeerAddress { address }),
+            Ok(_) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::InternalError,
+                    message: "XOR mapped address decode returned a different attribute".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+/*
+ * The XOR-RELAYED-ADDRESS attribute is present in Allocate responses.
+ * It specifies the address and port that the server allocated to the
+ * client.  It is encoded in the same way as the XOR-MAPPED-ADDRESS
+ * attribute.
+ * (RFC 8656 section 18.5)
+ */
+
+use super::attributes::STUNAttributesContent;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::io::Cursor;
+use std::net::SocketAddr;
+
+impl STUNAttributesContent {
+    pub fn new_xor_relayed_address(address: SocketAddr) -> Self {
+        Self::XORRelayedAddress { address }
+    }
+
+    pub fn encode_xor_relayed_address(
+        &self,
+        transaction_id: [u8; 12],
+    ) -> Result<Vec<u8>, STUNError> {
+        match self {
+            Self::XORRelayedAddress { address } => {
+                return Self::new_xor_mapped_address(*address)
+                    .encode_xor_mapped_address(transaction_id);
+            }
+            _ => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::AttributeTypeMismatch,
+                    message:
+                        "Called encode function for XORRelayedAddress on non XORRelayedAddress type"
+                            .to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn decode_xor_relayed_address(
+        cursor: &mut Cursor<&[u8]>,
+        transaction_id: [u8; 12],
+    ) -> Result<Self, STUNError> {
+        match Self::decode_xor_mapped_address(cursor, transaction_id) {
+            Ok(Self::XORMappedAddress { address }) => {
+                return Ok(Self::XORRelayedAddress { address })
+            }
+            Ok(_) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNDecode,
+                    error_type: STUNErrorType::InternalError,
+                    message: "XOR mapped address decode returned a different attribute".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
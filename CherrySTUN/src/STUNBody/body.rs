@@ -51,6 +51,9 @@
 use crate::STUNBody::attributes::attributes::STUNAttributeType;
 use crate::STUNBody::attributes::attributes::STUNAttributesContent;
 use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use crate::STUNHeader::header::{
+    STUN_HEADER_ENDING_POSITION, STUN_HEADER_TRANSACTION_ID_START_POSITION,
+};
 use byteorder::{NetworkEndian, WriteBytesExt};
 use std::io::{Cursor, Read, Write};
 
@@ -66,6 +69,9 @@ pub struct STUNAttributes {
 #[derive(Debug, Clone)]
 pub struct STUNBody {
     pub attributes: Vec<STUNAttributes>,
+    //Comprehension-required attribute types that the decoder did not understand, filled only by
+    //the decode function. Servers answer these with a 420.
+    pub unknown_attributes: Vec<u16>,
 }
 
 impl STUNBody {
@@ -75,6 +81,7 @@ impl STUNBody {
     pub fn new() -> Self {
         STUNBody {
             attributes: Vec::new(),
+            unknown_attributes: Vec::new(),
         }
     }
     pub fn add_new_attribute(
@@ -91,6 +98,23 @@ impl STUNBody {
         });
     }
 
+    ///Transaction ID of the header at the start of `message_bin`, used by the XOR address
+    ///attributes
+    pub fn transaction_id_from_bin(message_bin: &[u8]) -> Result<[u8; 12], STUNError> {
+        let start = STUN_HEADER_TRANSACTION_ID_START_POSITION as usize;
+        let end = STUN_HEADER_ENDING_POSITION as usize;
+        if message_bin.len() < end {
+            return Err(STUNError {
+                step: STUNStep::STUNUtils,
+                error_type: STUNErrorType::ReadError,
+                message: "Header with transaction id is missing".to_string(),
+            });
+        }
+        let mut transaction_id = [0; 12];
+        transaction_id.copy_from_slice(&message_bin[start..end]);
+        return Ok(transaction_id);
+    }
+
     ///To be called from encode flows/driver
     pub fn write_attribute_header_to_body_encode(
         content_body: &[u8],
@@ -125,6 +149,35 @@ impl STUNBody {
         return Ok(());
     }
 
+    ///Writes header, value and padding of an attribute whose encoder returns non padded bin
+    pub fn write_padded_attribute_to_body_encode(
+        mut content_body: Vec<u8>,
+        write_cursor: &mut Cursor<&mut Vec<u8>>,
+        attribute_type: STUNAttributeType,
+    ) -> Result<(), STUNError> {
+        //header carries the true content length, so it is written before padding
+        match Self::write_attribute_header_to_body_encode(
+            &content_body,
+            write_cursor,
+            attribute_type,
+        ) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        };
+        STUNAttributesContent::add_padding_to_attr_bin(&mut content_body);
+        match write_cursor.write_all(content_body.as_slice()) {
+            Ok(_) => {}
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNEncode,
+                    error_type: STUNErrorType::WriteError,
+                    message: e.to_string() + ". Error writing encoded attribute to cursor.",
+                })
+            }
+        }
+        return Ok(());
+    }
+
     pub fn write_current_message_length_to_header(
         write_cursor: &mut std::io::Cursor<&mut Vec<u8>>,
     ) -> Result<(), STUNError> {
@@ -195,7 +248,8 @@ impl STUNBody {
         length_delta: u16,
     ) -> Result<(), STUNError> {
         let current_pos = write_cursor.position();
-        let current_len = current_pos + 1;
+        //Everything before the current position except the header
+        let current_len = current_pos - 20;
         write_cursor.set_position(2);
         let len_byte_rep: [u8; 2] = (current_len as u16 + length_delta as u16).to_be_bytes();
         match write_cursor.write_all(&len_byte_rep) {
@@ -5,7 +5,7 @@ use crate::STUNBody::attributes::attributes::STUNAttributeType;
 use crate::STUNBody::attributes::attributes::STUNAttributesContent;
 use crate::STUNBody::attributes::attributes::STUNAuthType;
 use crate::STUNBody::body::STUNBody;
-use crate::STUNContext::context::STUNContext;
+use crate::STUNContext::context::{STUNContext, STUNReceivedIntegrity};
 use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
 use crate::STUNSerde::decode::STUNDecode;
 use byteorder::{NetworkEndian, ReadBytesExt};
@@ -22,6 +22,9 @@ impl STUNDecode for STUNBody {
     ) -> Result<STUNBody, STUNError> {
         //All the way till the end will be attrs
         let mut new_body = STUNBody::new();
+        //RFC 8489 section 14.5, attributes after MESSAGE-INTEGRITY are not covered by it and
+        //are ignored, but for FINGERPRINT
+        let mut after_integrity = false;
         loop {
             let attribute_type = match cursor.read_u16::<NetworkEndian>() {
                 Ok(bin) => bin,
@@ -55,6 +58,11 @@ impl STUNDecode for STUNBody {
                 }
             };
 
+            if after_integrity && attribute_type != STUNAttributeType::Fingerprint as u16 {
+                cursor.set_position(cursor.position() + Self::padded_len_calculator(length) as u64);
+                continue;
+            }
+
             match num::FromPrimitive::from_u16(attribute_type) {
                 Some(STUNAttributeType::MappedAddress) => {
                     let attr_content = match STUNAttributesContent::decode_mapped_address(cursor) {
@@ -130,6 +138,7 @@ impl STUNDecode for STUNBody {
                         Ok(hmac) => hmac,
                         Err(e) => return Err(e),
                     };
+                    after_integrity = true;
                     let current_position = cursor.position();
 
                     //To prepare the message for computing MI, we need to copy and make all needed
@@ -165,6 +174,18 @@ impl STUNDecode for STUNBody {
 
                     let message_bin_copy = mod_cursor.get_ref().as_slice();
 
+                    //Key is not known yet, leave the validation to the caller
+                    match decode_context {
+                        Some(con) if con.defer_integrity_check => {
+                            con.received_integrity = Some(STUNReceivedIntegrity {
+                                hmac: message_integrity_hmac,
+                                message: message_bin_copy.to_vec(),
+                            });
+                            continue;
+                        }
+                        _ => {}
+                    }
+
                     //Creating clone and checking if context exists in decode
                     let context = match decode_context {
                         Some(con) => con.clone(),
@@ -177,9 +198,14 @@ impl STUNDecode for STUNBody {
                         }),
                     };
 
+                    //Long-term credentials always come with a realm, short-term never do
+                    let auth_type = match context.realm {
+                        Some(_) => STUNAuthType::LongTerm,
+                        None => STUNAuthType::ShortTerm,
+                    };
                     match STUNAttributesContent::compute_message_integrity(
                         &STUNAttributesContent::MessageIntegrity {
-                            authType: STUNAuthType::LongTerm,
+                            authType: auth_type,
                         },
                         &Some(&context),
                         message_bin_copy,
@@ -221,7 +247,284 @@ impl STUNDecode for STUNBody {
                         }
                     }
                 }
+                Some(STUNAttributeType::Software) => {
+                    let attr_content = match STUNAttributesContent::decode_software(cursor, length)
+                    {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::Software, length);
+                }
+                Some(STUNAttributeType::ErrorCode) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_error_code(cursor, length) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::ErrorCode, length);
+                }
+                Some(STUNAttributeType::UnknownAttributes) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_unknown_attributes(cursor, length) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::UnknownAttributes,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::ResponseOrigin) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_response_origin(cursor) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::ResponseOrigin,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::ChangeRequest) => {
+                    let attr_content = match STUNAttributesContent::decode_change_request(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::ChangeRequest,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::ResponsePort) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_response_port(cursor, length) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::ResponsePort,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::Padding) => {
+                    let attr_content = match STUNAttributesContent::decode_padding(cursor, length)
+                    {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::Padding, length);
+                }
+                Some(STUNAttributeType::XORRelayedAddress) => {
+                    let transaction_id = match Self::transaction_id_from_bin(cursor.get_ref()) {
+                        Ok(transaction_id) => transaction_id,
+                        Err(e) => return Err(e),
+                    };
+                    let attr_content = match STUNAttributesContent::decode_xor_relayed_address(
+                        cursor,
+                        transaction_id,
+                    ) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::XORRelayedAddress,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::XORPeerAddress) => {
+                    let transaction_id = match Self::transaction_id_from_bin(cursor.get_ref()) {
+                        Ok(transaction_id) => transaction_id,
+                        Err(e) => return Err(e),
+                    };
+                    let attr_content = match STUNAttributesContent::decode_xor_peer_address(
+                        cursor,
+                        transaction_id,
+                    ) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::XORPeerAddress,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::Data) => {
+                    let attr_content = match STUNAttributesContent::decode_data(cursor, length) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::Data, length);
+                }
+                Some(STUNAttributeType::ChannelNumber) => {
+                    let attr_content = match STUNAttributesContent::decode_channel_number(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::ChannelNumber,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::ConnectionId) => {
+                    let attr_content = match STUNAttributesContent::decode_connection_id(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::ConnectionId,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::EvenPort) => {
+                    let attr_content = match STUNAttributesContent::decode_even_port(cursor, length) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::EvenPort, length);
+                }
+                Some(STUNAttributeType::DontFragment) => {
+                    let attr_content = match STUNAttributesContent::decode_dont_fragment(cursor, length) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::DontFragment, length);
+                }
+                Some(STUNAttributeType::ReservationToken) => {
+                    let attr_content = match STUNAttributesContent::decode_reservation_token(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::ReservationToken, length);
+                }
+                Some(STUNAttributeType::RequestedAddressFamily) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_requested_address_family(cursor) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::RequestedAddressFamily,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::AdditionalAddressFamily) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_additional_address_family(cursor) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::AdditionalAddressFamily,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::AddressErrorCode) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_address_error_code(cursor, length) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::AddressErrorCode,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::Priority) => {
+                    let attr_content = match STUNAttributesContent::decode_priority(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::Priority, length);
+                }
+                Some(STUNAttributeType::UseCandidate) => {
+                    let attr_content = match STUNAttributesContent::decode_use_candidate(cursor, length) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::UseCandidate, length);
+                }
+                Some(STUNAttributeType::IceControlled) => {
+                    let attr_content = match STUNAttributesContent::decode_ice_controlled(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::IceControlled, length);
+                }
+                Some(STUNAttributeType::IceControlling) => {
+                    let attr_content = match STUNAttributesContent::decode_ice_controlling(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::IceControlling, length);
+                }
+                Some(STUNAttributeType::Lifetime) => {
+                    let attr_content = match STUNAttributesContent::decode_lifetime(cursor) {
+                        Ok(content) => content,
+                        Err(e) => return Err(e),
+                    };
+                    new_body.add_new_attribute(attr_content, STUNAttributeType::Lifetime, length);
+                }
+                Some(STUNAttributeType::RequestedTransport) => {
+                    let attr_content =
+                        match STUNAttributesContent::decode_requested_transport(cursor) {
+                            Ok(content) => content,
+                            Err(e) => return Err(e),
+                        };
+                    new_body.add_new_attribute(
+                        attr_content,
+                        STUNAttributeType::RequestedTransport,
+                        length,
+                    );
+                }
+                Some(STUNAttributeType::Fingerprint) => {
+                    let attribute_start = cursor.position() - 4;
+                    if attribute_start < 20 {
+                        return Err(STUNError {
+                            step: STUNStep::STUNDecode,
+                            error_type: STUNErrorType::InvalidMessageBinLength,
+                            message: "Fingerprint needs the whole message to be validated"
+                                .to_string(),
+                        });
+                    }
+                    let received_fingerprint =
+                        match STUNAttributesContent::decode_fingerprint(cursor) {
+                            Ok(fingerprint) => fingerprint,
+                            Err(e) => return Err(e),
+                        };
+                    //Message up to the fingerprint, with the length in header covering it
+                    let mut message_bin_copy = cursor.get_ref()[..attribute_start as usize].to_vec();
+                    let pseudo_length = (attribute_start - 20 + 8) as u16;
+                    message_bin_copy[2..4].copy_from_slice(&pseudo_length.to_be_bytes());
+                    if STUNAttributesContent::compute_fingerprint(&message_bin_copy)
+                        != received_fingerprint
+                    {
+                        return Err(STUNError {
+                            step: STUNStep::STUNDecode,
+                            error_type: STUNErrorType::FingerprintMismatch,
+                            message: "Fingerprint mismatch during decode.".to_string(),
+                        });
+                    }
+                    new_body.add_new_attribute(
+                        STUNAttributesContent::Fingerprint,
+                        STUNAttributeType::Fingerprint,
+                        length,
+                    );
+                }
                 _ => {
+                    if STUNAttributeType::is_comprehension_required(attribute_type) {
+                        new_body.unknown_attributes.push(attribute_type);
+                    }
                     cursor.set_position(cursor.position() + Self::padded_len_calculator(length) as u64);
                     continue;
                     // return Err(STUNError {
@@ -336,6 +639,73 @@ mod test {
         };
     }
 
+    #[test]
+    fn stun_body_decode_short_term_fingerprint_test() {
+        //Sample request of RFC 5769 section 2.1, short-term MESSAGE-INTEGRITY and FINGERPRINT
+        let mut message_bin = vec![0x00, 0x01, 0x00, 0x58, 0x21, 0x12, 0xa4, 0x42];
+        message_bin.extend_from_slice(&[
+            0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+        ]);
+        message_bin.extend_from_slice(&STUN_REQUEST_BODY_BIN);
+        let mut context = STUNContext::new();
+        context.password = Some("VOkJxbRl1RmTxUk/WvJxBt".to_string());
+
+        let mut cursor = roll_cursor_on_fixture(&message_bin);
+        cursor.set_position(STUN_HEADER_ENDING_POSITION as u64);
+        let body = STUNBody::decode(&mut cursor, &mut Some(&mut context)).unwrap();
+        assert_eq!(
+            body.attributes.last().unwrap().value,
+            STUNAttributesContent::Fingerprint
+        );
+
+        //Wrong password
+        context.password = Some("VOkJxbRl1RmTxUk/WvJxBu".to_string());
+        let mut cursor = roll_cursor_on_fixture(&message_bin);
+        cursor.set_position(STUN_HEADER_ENDING_POSITION as u64);
+        let error = STUNBody::decode(&mut cursor, &mut Some(&mut context)).unwrap_err();
+        assert_eq!(error.error_type, STUNErrorType::MessageIntegrityMismatch);
+
+        //Corrupted fingerprint
+        let last = message_bin.len() - 1;
+        message_bin[last] ^= 0x01;
+        let mut cursor = roll_cursor_on_fixture(&message_bin);
+        cursor.set_position(STUN_HEADER_ENDING_POSITION as u64);
+        context.password = Some("VOkJxbRl1RmTxUk/WvJxBt".to_string());
+        let error = STUNBody::decode(&mut cursor, &mut Some(&mut context)).unwrap_err();
+        assert_eq!(error.error_type, STUNErrorType::FingerprintMismatch);
+    }
+
+    #[test]
+    fn stun_body_decode_ignores_attributes_after_integrity_test() {
+        //Sample request of RFC 5769 section 2.1 without its FINGERPRINT, and a USERNAME
+        //added after MESSAGE-INTEGRITY
+        let mut message_bin = vec![0x00, 0x01, 0x00, 0x60, 0x21, 0x12, 0xa4, 0x42];
+        message_bin.extend_from_slice(&[
+            0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+        ]);
+        message_bin.extend_from_slice(&STUN_REQUEST_BODY_BIN[..STUN_REQUEST_BODY_BIN.len() - 8]);
+        message_bin.extend_from_slice(&[0x00, 0x06, 0x00, 0x09]);
+        message_bin.extend_from_slice(b"evil:user\0\0\0");
+
+        for defer_integrity_check in [false, true] {
+            let mut context = STUNContext::new();
+            context.password = Some("VOkJxbRl1RmTxUk/WvJxBt".to_string());
+            context.defer_integrity_check = defer_integrity_check;
+            let mut cursor = roll_cursor_on_fixture(&message_bin);
+            cursor.set_position(STUN_HEADER_ENDING_POSITION as u64);
+            let body = STUNBody::decode(&mut cursor, &mut Some(&mut context)).unwrap();
+            let usernames: Vec<&STUNAttributesContent> = body
+                .attributes
+                .iter()
+                .filter(|x| x.attribute_type == STUNAttributeType::Username)
+                .map(|x| &x.value)
+                .collect();
+            assert_eq!(usernames.len(), 1);
+            assert_ne!(context.username, Some("evil:user".to_string()));
+            assert!(body.unknown_attributes.is_empty());
+        }
+    }
+
     #[ignore]
     #[test]
     fn stun_body_decode_failure_test() -> Result<(), String> {
@@ -213,6 +213,366 @@ impl STUNEncode for STUNBody {
                         Err(e) => return Err(e),
                     }
                 }
+                STUNAttributesContent::Software { .. } => {
+                    match STUNAttributesContent::encode_software(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::Software,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ErrorCode { .. } => {
+                    match STUNAttributesContent::encode_error_code(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ErrorCode,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::UnknownAttributes { .. } => {
+                    match STUNAttributesContent::encode_unknown_attributes(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::UnknownAttributes,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::OtherAddress { .. } => {
+                    match STUNAttributesContent::encode_other_address(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::OtherAddress,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ResponseOrigin { .. } => {
+                    match STUNAttributesContent::encode_other_address(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ResponseOrigin,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ChangeRequest { .. } => {
+                    match STUNAttributesContent::encode_change_request(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ChangeRequest,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ResponsePort { .. } => {
+                    match STUNAttributesContent::encode_response_port(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ResponsePort,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::Padding { .. } => {
+                    match STUNAttributesContent::encode_padding(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::Padding,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::Fingerprint => {
+                    //Same as MessageIntegrity, length in header has to include the attribute
+                    match Self::add_pseudo_message_length_to_header(write_cursor, 8 as u16) {
+                        Ok(()) => {}
+                        Err(e) => return Err(e),
+                    }
+                    let fingerprint = STUNAttributesContent::compute_fingerprint(
+                        write_cursor.get_ref().as_slice(),
+                    );
+                    match Self::write_padded_attribute_to_body_encode(
+                        fingerprint.to_be_bytes().to_vec(),
+                        write_cursor,
+                        STUNAttributeType::Fingerprint,
+                    ) {
+                        Ok(_) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::XORRelayedAddress { .. } => {
+                    let transaction_id =
+                        match Self::transaction_id_from_bin(write_cursor.get_ref().as_slice()) {
+                            Ok(transaction_id) => transaction_id,
+                            Err(e) => return Err(e),
+                        };
+                    match STUNAttributesContent::encode_xor_relayed_address(
+                        &attribute.value,
+                        transaction_id,
+                    ) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::XORRelayedAddress,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::XORPeerAddress { .. } => {
+                    let transaction_id =
+                        match Self::transaction_id_from_bin(write_cursor.get_ref().as_slice()) {
+                            Ok(transaction_id) => transaction_id,
+                            Err(e) => return Err(e),
+                        };
+                    match STUNAttributesContent::encode_xor_peer_address(
+                        &attribute.value,
+                        transaction_id,
+                    ) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::XORPeerAddress,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::Data { .. } => {
+                    match STUNAttributesContent::encode_data(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::Data,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ChannelNumber { .. } => {
+                    match STUNAttributesContent::encode_channel_number(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ChannelNumber,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ConnectionId { .. } => {
+                    match STUNAttributesContent::encode_connection_id(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ConnectionId,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::EvenPort { .. } => {
+                    match STUNAttributesContent::encode_even_port(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::EvenPort,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::DontFragment => {
+                    match STUNAttributesContent::encode_dont_fragment(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::DontFragment,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::ReservationToken { .. } => {
+                    match STUNAttributesContent::encode_reservation_token(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::ReservationToken,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::RequestedAddressFamily { .. } => {
+                    match STUNAttributesContent::encode_requested_address_family(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::RequestedAddressFamily,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::AdditionalAddressFamily { .. } => {
+                    match STUNAttributesContent::encode_additional_address_family(&attribute.value)
+                    {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::AdditionalAddressFamily,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::AddressErrorCode { .. } => {
+                    match STUNAttributesContent::encode_address_error_code(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::AddressErrorCode,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::Priority { .. } => {
+                    match STUNAttributesContent::encode_priority(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::Priority,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::UseCandidate => {
+                    match STUNAttributesContent::encode_use_candidate(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::UseCandidate,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::IceControlled { .. } => {
+                    match STUNAttributesContent::encode_ice_controlled(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::IceControlled,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::IceControlling { .. } => {
+                    match STUNAttributesContent::encode_ice_controlling(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::IceControlling,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::Lifetime { .. } => {
+                    match STUNAttributesContent::encode_lifetime(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::Lifetime,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+                STUNAttributesContent::RequestedTransport { .. } => {
+                    match STUNAttributesContent::encode_requested_transport(&attribute.value) {
+                        Ok(bin) => match Self::write_padded_attribute_to_body_encode(
+                            bin,
+                            write_cursor,
+                            STUNAttributeType::RequestedTransport,
+                        ) {
+                            Ok(_) => {}
+                            Err(e) => return Err(e),
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
                 _ => {
                     continue;
                     // return Err(STUNError {
@@ -323,6 +683,52 @@ mod test {
         return Ok(());
     }
 
+    #[test]
+    fn stun_body_encode_short_term_fingerprint_test() {
+        let mut test_bin = STUN_TEST_HEADER.to_vec();
+        let mut write_test_cursor = roll_cursor_on_fixture(&mut test_bin);
+        let mut test_body = STUNBody::new();
+        test_body.add_new_attribute(
+            STUNAttributesContent::XORMappedAddress {
+                address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 32853),
+            },
+            STUNAttributeType::XORMappedAddress,
+            0,
+        );
+        test_body.add_new_attribute(
+            STUNAttributesContent::MessageIntegrity {
+                authType: STUNAuthType::ShortTerm,
+            },
+            STUNAttributeType::MessageIntegrity,
+            0,
+        );
+        test_body.add_new_attribute(
+            STUNAttributesContent::Fingerprint,
+            STUNAttributeType::Fingerprint,
+            0,
+        );
+        let mut encode_context = STUNContext::new();
+        encode_context.password = Some("VOkJxbRl1RmTxUk/WvJxBt".to_string());
+        test_body
+            .encode(&mut write_test_cursor, &Some(&encode_context))
+            .unwrap();
+        //12 for the address, 24 for integrity and 8 for fingerprint
+        assert_eq!(test_bin.len(), 20 + 12 + 24 + 8);
+        assert_eq!(test_bin[2..4], [0x00, 0x2c]);
+        assert_eq!(test_bin[56..60], [0x80, 0x28, 0x00, 0x04]);
+
+        let mut decode_context = STUNContext::new();
+        decode_context.password = encode_context.password.clone();
+        let mut read_cursor = Cursor::new(test_bin.as_slice());
+        read_cursor.set_position(20);
+        let decoded = crate::STUNSerde::decode::STUNDecode::decode(
+            &mut read_cursor,
+            &mut Some(&mut decode_context),
+        );
+        let decoded: STUNBody = decoded.unwrap();
+        assert_eq!(decoded.attributes.len(), 2);
+    }
+
     #[test]
     fn stun_body_encode_failure_test() -> Result<(), String> {
         //this test is without having the encoded header, which should cause the encode to fail
@@ -2,13 +2,15 @@
 use crate::STUNContext::context::STUNContext;
 use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
 use crate::STUNSerde::{decode::STUNDecode, encode::STUNEncode};
-use crate::STUN::stun::{STUNNatMappingType, STUN};
+use crate::STUN::stun::{STUNAddressFamily, STUNNatFilteringType, STUNNatMappingType, STUN};
 use log::{debug, error, info, warn};
 use rand::Rng;
 use std::io::Cursor;
 use std::net::{SocketAddr, UdpSocket};
 use std::time::Duration; // 0.8.5
 
+pub const TEST_STUN_SERVER: &str = "stunserver2025.stunprotocol.org:3478";
+
 /// Options for querying STUN server
 pub struct StunClient {
     /// "End-to-end" timeout for the operation.
@@ -32,31 +34,109 @@ impl StunClient {
         }
     }
 
-    /// Use hard coded STUN server `stun.l.google.com:19302`.
+    /// Use hard coded STUN server `stunserver2025.stunprotocol.org:3478` over IPv4.
     ///
     /// Not for actual use, for tests, prototypes and demos.
     pub fn with_test_stun_server() -> Self {
-        use std::net::ToSocketAddrs;
-        let stun_server = "stunserver2025.stunprotocol.org:3478"
-            .to_socket_addrs()
-            .unwrap()
-            .filter(|x| x.is_ipv4())
-            .next()
-            .unwrap();
-        StunClient::new(stun_server)
+        return Self::with_test_stun_server_for_family(STUNAddressFamily::IPv4).unwrap();
     }
 
+    /// Same as `with_test_stun_server`, but for the requested address family.
+    /// Returns `None` if the test server does not resolve for that family.
+    pub fn with_test_stun_server_for_family(family: STUNAddressFamily) -> Option<Self> {
+        return Self::client_with_addr_for_family(TEST_STUN_SERVER.to_string(), family);
+    }
+
+    /// Resolves `addr` and prefers an IPv4 server address, falling back to IPv6 if the host
+    /// only has AAAA records.
     pub fn client_with_addr(addr: String) -> Self {
-        use std::net::ToSocketAddrs;
-        let stun_server = addr
-            .to_socket_addrs()
-            .unwrap()
-            .filter(|x| x.is_ipv4())
-            .next()
-            .unwrap();
+        let resolved = Self::resolve_stun_server(addr).unwrap();
+        let stun_server = match resolved.iter().find(|x| x.is_ipv4()) {
+            Some(v4) => *v4,
+            None => resolved[0],
+        };
         StunClient::new(stun_server)
     }
 
+    /// Resolves `addr` and returns a client for the first server address of `family`
+    pub fn client_with_addr_for_family(addr: String, family: STUNAddressFamily) -> Option<Self> {
+        let resolved = match Self::resolve_stun_server(addr) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("{:?}", e);
+                return None;
+            }
+        };
+        return resolved
+            .into_iter()
+            .find(|x| STUNAddressFamily::of(x) == family)
+            .map(StunClient::new);
+    }
+
+    /// One client per address family the server resolves to, IPv4 first.
+    pub fn dual_stack_clients(addr: String) -> Vec<Self> {
+        let mut clients = Vec::new();
+        for family in [STUNAddressFamily::IPv4, STUNAddressFamily::IPv6] {
+            match Self::client_with_addr_for_family(addr.clone(), family) {
+                Some(client) => clients.push(client),
+                None => {
+                    debug!("{} does not resolve for {:?}", addr, family);
+                }
+            }
+        }
+        return clients;
+    }
+
+    /// Resolves both A and AAAA records of a `host:port` string
+    pub fn resolve_stun_server(addr: String) -> Result<Vec<SocketAddr>, STUNError> {
+        use std::net::ToSocketAddrs;
+        let resolved: Vec<SocketAddr> = match addr.to_socket_addrs() {
+            Ok(x) => x.collect(),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::AddressResolutionError,
+                    message: "Error resolving STUN server address ".to_string()
+                        + addr.as_str()
+                        + ": "
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        if resolved.is_empty() {
+            return Err(STUNError {
+                step: STUNStep::STUNNetwork,
+                error_type: STUNErrorType::AddressResolutionError,
+                message: "STUN server address resolved to nothing: ".to_string() + addr.as_str(),
+            });
+        }
+        return Ok(resolved);
+    }
+
+    /// Binds a UDP socket on the wildcard address of the same family as the STUN server.
+    /// A v4 socket cannot reach a v6 server (and the other way around), so the socket family
+    /// always follows the server.
+    pub fn bind_local_socket(&self, src_port: u32) -> Result<UdpSocket, STUNError> {
+        let local_addr =
+            STUNAddressFamily::of(&self.stun_server).unspecified_address(src_port as u16);
+        match UdpSocket::bind(local_addr) {
+            Ok(udp) => {
+                info!("client addr : {:?}", local_addr);
+                return Ok(udp);
+            }
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::SocketBindError,
+                    message: "Error binding local socket on ".to_string()
+                        + local_addr.to_string().as_str()
+                        + ": "
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+
     /// Set `timeout` field, builder pattern.
     pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
         self.timeout = timeout;
@@ -78,12 +158,35 @@ impl StunClient {
 
 impl StunClient {
     pub fn send_request(
+        &self,
+        udp: &UdpSocket,
+        stun_message: STUN,
+        context: STUNContext,
+    ) -> Result<STUN, STUNError> {
+        return self.send_request_inner(udp, stun_message, context, false);
+    }
+
+    /// Same as `send_request`, but takes the response from whichever address it comes from.
+    /// Needed for CHANGE-REQUEST, where the server answers from its alternate address and/or
+    /// port.
+    pub fn send_request_accepting_any_source(
+        &self,
+        udp: &UdpSocket,
+        stun_message: STUN,
+        context: STUNContext,
+    ) -> Result<STUN, STUNError> {
+        return self.send_request_inner(udp, stun_message, context, true);
+    }
+
+    fn send_request_inner(
         &self,
         udp: &UdpSocket,
         stun_message: STUN,
         mut context: STUNContext,
+        accept_any_source: bool,
     ) -> Result<STUN, STUNError> {
         let stun_server = self.stun_server;
+        let transaction_id = stun_message.header.transaction_id;
 
         let mut encoded_stun_msg = Vec::new();
         let mut write_encoded_stun_msg = Cursor::new(&mut encoded_stun_msg);
@@ -195,7 +298,7 @@ impl StunClient {
             let buf = &buf[0..len];
 
             debug!("Received reply from {:?} {:?}", addr, buf);
-            if addr != stun_server {
+            if !accept_any_source && addr != stun_server {
                 continue;
             }
 
@@ -212,6 +315,11 @@ impl StunClient {
                         })
                     }
                 };
+            //Late responses to an earlier request on the same socket
+            if response_stun_msg.header.transaction_id != transaction_id {
+                debug!("Ignoring response for another transaction from {:?}", addr);
+                continue;
+            }
 
             match udp.set_read_timeout(old_read_timeout) {
                 Ok(_) => {}
@@ -276,9 +384,45 @@ impl StunClient {
 //    CHANGE-REQUEST, SOURCE-ADDRESS, and REFLECTED-FROM attributes.
 
 impl StunClient {
+    /// Runs the mapping test against the hard coded IPv4 test server
     pub fn test_nat_mapping_type(
         verbose: bool,
         port: Option<u32>,
+    ) -> Result<STUNNatMappingType, STUNError> {
+        let client = self::StunClient::with_test_stun_server();
+        return client.nat_mapping_type(verbose, port);
+    }
+
+    /// Runs the mapping test once per address family the test server resolves to
+    pub fn test_nat_mapping_type_dual_stack(
+        verbose: bool,
+        port: Option<u32>,
+    ) -> Vec<(STUNAddressFamily, Result<STUNNatMappingType, STUNError>)> {
+        return Self::nat_mapping_type_dual_stack(TEST_STUN_SERVER.to_string(), verbose, port);
+    }
+
+    /// Runs the mapping test once per address family `stun_server` resolves to.
+    /// NATs for v4 and v6 are usually different boxes (or no NAT at all for v6), so the results
+    /// are reported per family.
+    pub fn nat_mapping_type_dual_stack(
+        stun_server: String,
+        verbose: bool,
+        port: Option<u32>,
+    ) -> Vec<(STUNAddressFamily, Result<STUNNatMappingType, STUNError>)> {
+        let mut results = Vec::new();
+        for client in Self::dual_stack_clients(stun_server) {
+            let family = STUNAddressFamily::of(&client.stun_server);
+            results.push((family, client.nat_mapping_type(verbose, port)));
+        }
+        return results;
+    }
+
+    /// Mapping test (RFC 5780 4.3) against this client's server. The server has to support
+    /// OTHER-ADDRESS.
+    pub fn nat_mapping_type(
+        &self,
+        verbose: bool,
+        port: Option<u32>,
     ) -> Result<STUNNatMappingType, STUNError> {
         let encode_ctx = crate::STUNContext::context::STUNContext::new();
         let stun_msg = crate::STUN::stun::STUN::new_default(
@@ -287,29 +431,28 @@ impl StunClient {
             None,
         );
 
-        let client = self::StunClient::with_test_stun_server();
-        let dst_port1 = client.stun_server.port().to_string();
+        let dst_port1 = self.stun_server.port();
         let src_port = match port {
             Some(s) => s,
             None => rand::thread_rng().gen_range(16834..32768),
         };
-        let local_addr: SocketAddr = format!("{}:{}", "0.0.0.0", src_port).parse().unwrap();
-        let udp = UdpSocket::bind(local_addr).unwrap();
-        info!("client addr : {:?}", local_addr);
+        let udp = match self.bind_local_socket(src_port) {
+            Ok(udp) => udp,
+            Err(e) => return Err(e),
+        };
 
-        let mut other_addr: SocketAddr =
-            "0.0.0.0:0".parse().expect("Error initializing other_Addr");
+        let mut other_addr: Option<SocketAddr> = None;
         let mut client_addr1 = "".to_string();
         //Assuming every connection is behind a NAT
-        //test II:
-        debug!("first call : {:?}", client.stun_server.to_string());
-        match client.send_request(&udp, stun_msg.clone(), encode_ctx.clone()) {
+        //test I:
+        debug!("first call : {:?}", self.stun_server.to_string());
+        match self.send_request(&udp, stun_msg.clone(), encode_ctx.clone()) {
             Ok(res) => {
                 for i in res.body.attributes.iter() {
                     match i.value {
                         crate::stunAttributes::STUNAttributesContent::OtherAddress { address } => {
                             debug!("other address: {}", address.to_string());
-                            other_addr = address;
+                            other_addr = Some(address);
                         }
                         crate::stunAttributes::STUNAttributesContent::XORMappedAddress {
                             address,
@@ -324,9 +467,21 @@ impl StunClient {
             }
             Err(e) => return Err(e),
         }
-        let server_addr2 = format!("{}:{}", other_addr.ip().to_string(), dst_port1);
+        let other_addr = match other_addr {
+            Some(addr) => addr,
+            None => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::DidNotFindExpectedAttribute,
+                    message: "Server did not return OTHER-ADDRESS, cannot test NAT mapping"
+                        .to_string(),
+                })
+            }
+        };
+        //test II:
+        let server_addr2 = SocketAddr::new(other_addr.ip(), dst_port1);
         debug!("second call : {:?}", server_addr2);
-        let client2 = self::StunClient::client_with_addr(server_addr2);
+        let client2 = self::StunClient::new(server_addr2);
         let mut client_addr2 = "".to_string();
         match client2.send_request(&udp, stun_msg.clone(), encode_ctx.clone()) {
             Ok(res) => {
@@ -350,17 +505,13 @@ impl StunClient {
             return Ok(STUNNatMappingType::EndpointIndependent);
         }
         //test III:
-        let server_addr3 = format!(
-            "{}:{}",
-            other_addr.ip().to_string(),
-            other_addr.port().to_string()
-        );
+        let server_addr3 = other_addr;
 
         if verbose {
             info!("third call : {:?}", server_addr3);
         }
 
-        let client3 = self::StunClient::client_with_addr(server_addr3);
+        let client3 = self::StunClient::new(server_addr3);
         let mut client_addr3 = "".to_string();
         match client3.send_request(&udp, stun_msg, encode_ctx) {
             Ok(res) => {
@@ -389,56 +540,104 @@ impl StunClient {
         return Ok(STUNNatMappingType::PortDependant);
     }
 
-    /// Get external (server-reflexive transport address) IP address and port of specified UDP socket
-    pub fn get_server_reflexive_address(src_port: u32) -> Result<SocketAddr, STUNError> {
+    /// Runs the filtering test against the hard coded IPv4 test server
+    pub fn test_nat_filtering_type(port: Option<u32>) -> Result<STUNNatFilteringType, STUNError> {
+        let client = self::StunClient::with_test_stun_server();
+        return client.nat_filtering_type(port);
+    }
+
+    /// Filtering test (RFC 5780 4.4) against this client's server. The server has to support
+    /// OTHER-ADDRESS and CHANGE-REQUEST. A missing response is only detected by `timeout`, so
+    /// lowering it speeds up the test for filtering NATs.
+    pub fn nat_filtering_type(&self, port: Option<u32>) -> Result<STUNNatFilteringType, STUNError> {
         let encode_ctx = crate::STUNContext::context::STUNContext::new();
-        let stun_msg = crate::STUN::stun::STUN::new_default(
+        let src_port = match port {
+            Some(s) => s,
+            None => rand::thread_rng().gen_range(16834..32768),
+        };
+        let udp = match self.bind_local_socket(src_port) {
+            Ok(udp) => udp,
+            Err(e) => return Err(e),
+        };
+
+        //test I:
+        let stun_msg = STUN::new_default(
             crate::stunHeader::STUNMessageClass::Request,
             crate::stunHeader::STUNMessageMethod::Binding,
             None,
         );
-
-        let local_addr: SocketAddr = format!("{}:{}", "0.0.0.0", src_port).parse().unwrap();
-        let udp = UdpSocket::bind(local_addr).unwrap();
-        info!("client addr : {:?}", local_addr);
-        let client = self::StunClient::with_test_stun_server();
-
-        info!(
-            "making stun server call : {:?}",
-            client.stun_server.to_string()
-        );
-        match client.send_request(&udp, stun_msg.clone(), encode_ctx.clone()) {
+        match self.send_request(&udp, stun_msg, encode_ctx.clone()) {
             Ok(res) => {
-                for i in res.body.attributes.iter() {
-                    match i.value {
-                        crate::stunAttributes::STUNAttributesContent::XORMappedAddress {
-                            address,
-                        } => {
-                            return Ok(address);
-                        }
-                        crate::stunAttributes::STUNAttributesContent::MappedAddress { address } => {
-                            return Ok(address);
-                        }
-                        _ => {
-                            continue;
-                        }
-                    }
+                let other_addr = res.body.attributes.iter().find(|x| {
+                    matches!(
+                        x.value,
+                        crate::stunAttributes::STUNAttributesContent::OtherAddress { .. }
+                    )
+                });
+                if other_addr.is_none() {
+                    return Err(STUNError {
+                        step: STUNStep::STUNNetwork,
+                        error_type: STUNErrorType::DidNotFindExpectedAttribute,
+                        message: "Server did not return OTHER-ADDRESS, cannot test NAT filtering"
+                            .to_string(),
+                    });
                 }
             }
             Err(e) => return Err(e),
-        };
-        return Err(STUNError {
-            step: STUNStep::STUNNetwork,
-            error_type: STUNErrorType::DidNotFindExpectedAttribute,
-            message: "Did not find XOR mapped or mapped address from server response".to_string(),
-        });
+        }
+
+        //test II:
+        match self.send_request_accepting_any_source(
+            &udp,
+            Self::change_request_message(true, true),
+            encode_ctx.clone(),
+        ) {
+            Ok(_) => {
+                info!("Endpoint-Independent filtering!");
+                return Ok(STUNNatFilteringType::EndpointIndependentFiltering);
+            }
+            Err(e) if e.error_type == STUNErrorType::NetworkTimeoutError => {}
+            Err(e) => return Err(e),
+        }
+
+        //test III:
+        match self.send_request_accepting_any_source(
+            &udp,
+            Self::change_request_message(false, true),
+            encode_ctx,
+        ) {
+            Ok(_) => {
+                info!("~ Address-Dependent filtering");
+                return Ok(STUNNatFilteringType::AddressDependantFiltering);
+            }
+            Err(e) if e.error_type == STUNErrorType::NetworkTimeoutError => {
+                info!(":( Address and Port-Dependent filtering");
+                return Ok(STUNNatFilteringType::AddressAndPortDependantFiltering);
+            }
+            Err(e) => return Err(e),
+        }
     }
 
-    /// Get external (server-reflexive transport address) IP address and port of specified UDP socket with stun server of your choice
-    pub fn get_server_reflexive_address_custom_stun_server(
-        src_port: u32,
-        stun_server: String,
-    ) -> Result<SocketAddr, STUNError> {
+    fn change_request_message(change_ip: bool, change_port: bool) -> STUN {
+        let mut stun_msg = STUN::new_default(
+            crate::stunHeader::STUNMessageClass::Request,
+            crate::stunHeader::STUNMessageMethod::Binding,
+            None,
+        );
+        stun_msg.body.add_new_attribute(
+            crate::stunAttributes::STUNAttributesContent::new_change_request(
+                change_ip,
+                change_port,
+            ),
+            crate::stunAttributes::STUNAttributeType::ChangeRequest,
+            0,
+        );
+        return stun_msg;
+    }
+
+    /// Sends a Binding request from `udp` to this client's server and returns the
+    /// server-reflexive address of that socket
+    pub fn query_server_reflexive_address(&self, udp: &UdpSocket) -> Result<SocketAddr, STUNError> {
         let encode_ctx = crate::STUNContext::context::STUNContext::new();
         let stun_msg = crate::STUN::stun::STUN::new_default(
             crate::stunHeader::STUNMessageClass::Request,
@@ -446,16 +645,11 @@ impl StunClient {
             None,
         );
 
-        let local_addr: SocketAddr = format!("{}:{}", "0.0.0.0", src_port).parse().unwrap();
-        let udp = UdpSocket::bind(local_addr).unwrap();
-        info!("client addr : {:?}", local_addr);
-        let client = self::StunClient::client_with_addr(stun_server);
-
         info!(
             "making stun server call : {:?}",
-            client.stun_server.to_string()
+            self.stun_server.to_string()
         );
-        match client.send_request(&udp, stun_msg.clone(), encode_ctx.clone()) {
+        match self.send_request(udp, stun_msg, encode_ctx) {
             Ok(res) => {
                 for i in res.body.attributes.iter() {
                     match i.value {
@@ -482,6 +676,47 @@ impl StunClient {
         });
     }
 
+    /// Get external (server-reflexive transport address) IP address and port of specified UDP socket
+    pub fn get_server_reflexive_address(src_port: u32) -> Result<SocketAddr, STUNError> {
+        let client = self::StunClient::with_test_stun_server();
+        let udp = match client.bind_local_socket(src_port) {
+            Ok(udp) => udp,
+            Err(e) => return Err(e),
+        };
+        return client.query_server_reflexive_address(&udp);
+    }
+
+    /// Get external (server-reflexive transport address) IP address and port of specified UDP socket with stun server of your choice
+    pub fn get_server_reflexive_address_custom_stun_server(
+        src_port: u32,
+        stun_server: String,
+    ) -> Result<SocketAddr, STUNError> {
+        let client = self::StunClient::client_with_addr(stun_server);
+        let udp = match client.bind_local_socket(src_port) {
+            Ok(udp) => udp,
+            Err(e) => return Err(e),
+        };
+        return client.query_server_reflexive_address(&udp);
+    }
+
+    /// Server-reflexive address for every family `stun_server` resolves to. Each family uses its
+    /// own socket bound on `src_port`.
+    pub fn get_server_reflexive_address_dual_stack(
+        src_port: u32,
+        stun_server: String,
+    ) -> Vec<(STUNAddressFamily, Result<SocketAddr, STUNError>)> {
+        let mut results = Vec::new();
+        for client in Self::dual_stack_clients(stun_server) {
+            let family = STUNAddressFamily::of(&client.stun_server);
+            let result = match client.bind_local_socket(src_port) {
+                Ok(udp) => client.query_server_reflexive_address(&udp),
+                Err(e) => Err(e),
+            };
+            results.push((family, result));
+        }
+        return results;
+    }
+
     ///Should be called after ICE exchange.
     pub fn NATHolePunching(src_port: u32, dst_address: SocketAddr) -> Result<(), STUNError> {
         //Its reccomened to test NAT types for the potential port we are going to use.
@@ -515,3 +750,45 @@ impl StunClient {
         return Ok(());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_picks_server_of_requested_family() {
+        let v6_client = StunClient::client_with_addr_for_family(
+            "[::1]:3478".to_string(),
+            STUNAddressFamily::IPv6,
+        );
+        match v6_client {
+            Some(client) => assert!(client.stun_server.is_ipv6()),
+            None => panic!("Expected a v6 client for a v6 literal"),
+        }
+        let v4_client = StunClient::client_with_addr_for_family(
+            "[::1]:3478".to_string(),
+            STUNAddressFamily::IPv4,
+        );
+        assert!(v4_client.is_none());
+
+        //v6 only hosts no longer panic, they fall back to v6
+        let fallback = StunClient::client_with_addr("[::1]:3478".to_string());
+        assert!(fallback.stun_server.is_ipv6());
+        let preferred = StunClient::client_with_addr("127.0.0.1:3478".to_string());
+        assert!(preferred.stun_server.is_ipv4());
+
+        let clients = StunClient::dual_stack_clients("127.0.0.1:3478".to_string());
+        assert_eq!(clients.len(), 1);
+    }
+
+    #[test]
+    fn local_socket_follows_server_family() {
+        let v6_client = StunClient::new("[::1]:3478".parse().unwrap());
+        let udp = v6_client.bind_local_socket(0).unwrap();
+        assert!(udp.local_addr().unwrap().is_ipv6());
+
+        let v4_client = StunClient::new("127.0.0.1:3478".parse().unwrap());
+        let udp = v4_client.bind_local_socket(0).unwrap();
+        assert!(udp.local_addr().unwrap().is_ipv4());
+    }
+}
@@ -0,0 +1,511 @@
+//Consent freshness (RFC 7675): once media flows, the peer is asked with authenticated Binding
+//requests every few seconds whether it still wants our packets. Without a valid response for
+//30 seconds consent is lost and the application has to stop sending.
+//
+//Consent checks are ICE connectivity checks (RFC 7675 section 5.1), so they carry PRIORITY and
+//ICE-CONTROLLING or ICE-CONTROLLED like any other (RFC 8445 section 7.2.2), ICE agents reject
+//checks without them.
+//
+//Responses arrive on the socket carrying media, which the application reads, so it has to hand
+//STUN packets from the peer to `StunConsentHandle::on_message`.
+use super::client::StunClient;
+use crate::STUNBody::attributes::attributes::{STUNAttributesContent, STUNAuthType};
+use crate::STUNContext::context::STUNContext;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use crate::STUNHeader::header::{STUNMessageClass, STUNMessageMethod};
+use crate::STUNSerde::{decode::STUNDecode, encode::STUNEncode};
+use crate::STUN::stun::STUN;
+use log::{debug, error, info, warn};
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//RFC 7675 section 5.1: checks every 5 seconds on average, randomised to 0.8 to 1.2 times that,
+//consent expires 30 seconds after the last response
+pub const STUN_CONSENT_DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+pub const STUN_CONSENT_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const STUN_CONSENT_JITTER: f64 = 0.2;
+//PRIORITY of a peer reflexive candidate of component 1 with the recommended type preference
+//(110) and the highest local preference (RFC 8445 section 5.1.2.1)
+pub const STUN_CONSENT_DEFAULT_PRIORITY: u32 = 1862270975;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StunConsentEvent {
+    /// No valid response within the consent timeout, sending to the peer has to stop
+    ConsentLost,
+}
+
+/// Options for monitoring consent of one peer. The peer is `client.stun_server`.
+pub struct StunConsentFreshness {
+    pub client: StunClient,
+    /// Short-term USERNAME, "remote ufrag:local ufrag" in ICE
+    pub username: String,
+    /// Short-term password of the peer
+    pub password: String,
+    /// PRIORITY of the checks, the one of the local candidate of the selected pair with the
+    /// peer reflexive type preference
+    pub priority: u32,
+    /// ICE role of the agent, sent as ICE-CONTROLLING or ICE-CONTROLLED with `tie_breaker`
+    pub controlling: bool,
+    pub tie_breaker: u64,
+    /// Average time between two checks
+    pub interval: Duration,
+    /// Consent is lost when no response was received for this long
+    pub consent_timeout: Duration,
+}
+
+/// Returned by `StunConsentFreshness::start`. Stops the checks when stopped or dropped.
+pub struct StunConsentHandle {
+    shared: Arc<ConsentShared>,
+    events: Receiver<StunConsentEvent>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct ConsentShared {
+    state: Mutex<ConsentState>,
+    wakeup: Condvar,
+    socket: Arc<UdpSocket>,
+    remote: SocketAddr,
+    username: String,
+    password: String,
+    priority: u32,
+    controlling: bool,
+    tie_breaker: u64,
+    software: Option<&'static str>,
+    interval: Duration,
+    consent_timeout: Duration,
+}
+
+struct ConsentState {
+    last_response: Instant,
+    //Checks that can still be answered, with the time they were sent
+    outstanding: HashMap<[u8; 12], Instant>,
+    consent: bool,
+    shutdown: bool,
+}
+
+impl StunConsentFreshness {
+    /// A constructor with default intervals
+    pub fn new(client: StunClient, username: String, password: String) -> Self {
+        StunConsentFreshness {
+            client,
+            username,
+            password,
+            priority: STUN_CONSENT_DEFAULT_PRIORITY,
+            controlling: false,
+            tie_breaker: rand::thread_rng().gen(),
+            interval: STUN_CONSENT_DEFAULT_INTERVAL,
+            consent_timeout: STUN_CONSENT_DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set `priority` field, builder pattern.
+    pub fn set_priority(&mut self, priority: u32) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set `controlling` field, builder pattern.
+    pub fn set_controlling(&mut self, controlling: bool) -> &mut Self {
+        self.controlling = controlling;
+        self
+    }
+
+    /// Set `tie_breaker` field, builder pattern.
+    pub fn set_tie_breaker(&mut self, tie_breaker: u64) -> &mut Self {
+        self.tie_breaker = tie_breaker;
+        self
+    }
+
+    /// Set `interval` field, builder pattern.
+    pub fn set_interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set `consent_timeout` field, builder pattern.
+    pub fn set_consent_timeout(&mut self, consent_timeout: Duration) -> &mut Self {
+        self.consent_timeout = consent_timeout;
+        self
+    }
+
+    /// Starts checking the peer over `socket`. Consent is considered fresh at this point, as
+    /// it follows a successful connectivity check.
+    pub fn start(&self, socket: Arc<UdpSocket>) -> Result<StunConsentHandle, STUNError> {
+        if self.interval.is_zero() || self.consent_timeout.is_zero() {
+            return Err(STUNError {
+                step: STUNStep::STUNConsent,
+                error_type: STUNErrorType::InvalidConfiguration,
+                message: "Consent interval and timeout must be larger than zero".to_string(),
+            });
+        }
+        let (event_sender, events) = channel();
+        let shared = Arc::new(ConsentShared {
+            state: Mutex::new(ConsentState {
+                last_response: Instant::now(),
+                outstanding: HashMap::new(),
+                consent: true,
+                shutdown: false,
+            }),
+            wakeup: Condvar::new(),
+            socket,
+            remote: self.client.stun_server,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            priority: self.priority,
+            controlling: self.controlling,
+            tie_breaker: self.tie_breaker,
+            software: self.client.software,
+            interval: self.interval,
+            consent_timeout: self.consent_timeout,
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || thread_shared.run(event_sender));
+        return Ok(StunConsentHandle {
+            shared,
+            events,
+            thread: Some(thread),
+        });
+    }
+}
+
+impl StunConsentHandle {
+    /// Feeds a packet received on the monitored socket. True if it was a valid response to
+    /// one of our checks, which refreshes consent.
+    pub fn on_message(&self, message_bin: &[u8], source: SocketAddr) -> bool {
+        //Responses have to come from where the checks were sent
+        if source != self.shared.remote {
+            return false;
+        }
+        let transaction_id = match self.shared.authenticated_response(message_bin) {
+            Some(transaction_id) => transaction_id,
+            None => return false,
+        };
+        let mut state = self.shared.lock_state();
+        if !state.consent || state.outstanding.remove(&transaction_id).is_none() {
+            return false;
+        }
+        state.last_response = Instant::now();
+        self.shared.wakeup.notify_all();
+        return true;
+    }
+
+    /// False once consent was lost, never becomes true again
+    pub fn has_consent(&self) -> bool {
+        return self.shared.lock_state().consent;
+    }
+
+    /// Time since the last valid response (or since start)
+    pub fn since_last_response(&self) -> Duration {
+        return self.shared.lock_state().last_response.elapsed();
+    }
+
+    /// Receives `StunConsentEvent`s
+    pub fn events(&self) -> &Receiver<StunConsentEvent> {
+        return &self.events;
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown_and_join();
+    }
+
+    fn shutdown_and_join(&mut self) {
+        self.shared.lock_state().shutdown = true;
+        self.shared.wakeup.notify_all();
+        match self.thread.take() {
+            Some(thread) => {
+                if thread.join().is_err() {
+                    error!("Consent freshness thread panicked");
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+impl Drop for StunConsentHandle {
+    fn drop(&mut self) {
+        self.shutdown_and_join();
+    }
+}
+
+impl ConsentShared {
+    fn lock_state(&self) -> MutexGuard<'_, ConsentState> {
+        match self.state.lock() {
+            Ok(state) => return state,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn next_wait(&self) -> Duration {
+        let factor =
+            rand::thread_rng().gen_range((1.0 - STUN_CONSENT_JITTER)..(1.0 + STUN_CONSENT_JITTER));
+        return self.interval.mul_f64(factor);
+    }
+
+    fn run(&self, event_sender: Sender<StunConsentEvent>) {
+        let mut next_check = Instant::now() + self.next_wait();
+        let mut state = self.lock_state();
+        while !state.shutdown {
+            let now = Instant::now();
+            let expiry = state.last_response + self.consent_timeout;
+            if now >= expiry {
+                state.consent = false;
+                state.outstanding.clear();
+                info!("Consent of {:?} lost", self.remote);
+                //Nobody listening is fine, has_consent tells the same
+                let _ = event_sender.send(StunConsentEvent::ConsentLost);
+                return;
+            }
+            if now >= next_check {
+                let consent_timeout = self.consent_timeout;
+                state
+                    .outstanding
+                    .retain(|_, sent| now.saturating_duration_since(*sent) < consent_timeout);
+                match self.send_check() {
+                    Ok(transaction_id) => {
+                        state.outstanding.insert(transaction_id, now);
+                    }
+                    Err(e) => warn!("Error sending consent check to {:?}: {:?}", self.remote, e),
+                }
+                next_check = now + self.next_wait();
+            }
+            let wait = next_check.min(expiry).saturating_duration_since(now);
+            state = match self.wakeup.wait_timeout(state, wait) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    fn short_term_context(&self) -> STUNContext {
+        let mut context = STUNContext::new();
+        context.username = Some(self.username.clone());
+        context.password = Some(self.password.clone());
+        return context;
+    }
+
+    //Every check is a new transaction, they are not retransmitted
+    fn send_check(&self) -> Result<[u8; 12], STUNError> {
+        let mut check =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        let role = match self.controlling {
+            true => STUNAttributesContent::IceControlling {
+                tie_breaker: self.tie_breaker,
+            },
+            false => STUNAttributesContent::IceControlled {
+                tie_breaker: self.tie_breaker,
+            },
+        };
+        let mut attributes = vec![
+            STUNAttributesContent::new_username(self.username.clone()),
+            STUNAttributesContent::Priority {
+                priority: self.priority,
+            },
+            role,
+        ];
+        match self.software {
+            Some(software) => {
+                attributes.push(STUNAttributesContent::new_software(software.to_string()))
+            }
+            None => {}
+        }
+        attributes.push(STUNAttributesContent::MessageIntegrity {
+            authType: STUNAuthType::ShortTerm,
+        });
+        attributes.push(STUNAttributesContent::Fingerprint);
+        for attribute in attributes {
+            let attribute_type = attribute.attribute_type();
+            check.body.add_new_attribute(attribute, attribute_type, 0);
+        }
+
+        let mut check_bin = Vec::new();
+        match check.encode(
+            &mut Cursor::new(&mut check_bin),
+            &Some(&self.short_term_context()),
+        ) {
+            Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        match self.socket.send_to(&check_bin, self.remote) {
+            Ok(_) => {
+                debug!("Sent consent check to {:?}", self.remote);
+                return Ok(check.header.transaction_id);
+            }
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::ErrorSendingMessageToServer,
+                    message: "Error sending consent check: ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+
+    //Transaction ID of a Binding success response carrying a valid short-term
+    //MESSAGE-INTEGRITY, None for anything else
+    fn authenticated_response(&self, message_bin: &[u8]) -> Option<[u8; 12]> {
+        let mut context = STUNContext::new();
+        context.defer_integrity_check = true;
+        let response = match STUN::decode(&mut Cursor::new(message_bin), &mut Some(&mut context)) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!(
+                    "Ignoring undecodable packet from {:?}: {:?}",
+                    self.remote, e
+                );
+                return None;
+            }
+        };
+        if response.header.message_class != STUNMessageClass::ResponseSuccess
+            || response.header.message_method != STUNMessageMethod::Binding
+        {
+            return None;
+        }
+        let key = match STUNAttributesContent::short_term_key(self.password.clone()) {
+            Ok(key) => key,
+            Err(_) => return None,
+        };
+        match context.received_integrity {
+            Some(integrity) if integrity.verify(&key) => {
+                return Some(response.header.transaction_id)
+            }
+            _ => {
+                debug!("Ignoring unauthenticated response from {:?}", self.remote);
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::STUNBody::attributes::attributes::STUNErrorCode;
+
+    //Answers consent checks like an ICE agent with password "peer-password" would
+    fn answer_check(peer: &UdpSocket, tamper: bool) -> Option<(Vec<u8>, SocketAddr)> {
+        let mut buf = [0; 512];
+        let (len, source) = match peer.recv_from(&mut buf) {
+            Ok(x) => x,
+            Err(_) => return None,
+        };
+        let mut context = STUNContext::new();
+        context.password = Some("peer-password".to_string());
+        let request = STUN::decode(&mut Cursor::new(&buf[..len]), &mut Some(&mut context)).unwrap();
+        assert_eq!(context.username, Some("peer:local".to_string()));
+        //ICE agents answer 400 to checks without PRIORITY and the role of the agent
+        let is_ice_check = request.body.attributes.iter().any(|x| {
+            x.value
+                == STUNAttributesContent::Priority {
+                    priority: STUN_CONSENT_DEFAULT_PRIORITY,
+                }
+        }) && request
+            .body
+            .attributes
+            .iter()
+            .any(|x| x.value == STUNAttributesContent::IceControlling { tie_breaker: 7 });
+        let (message_class, first_attribute) = match is_ice_check {
+            true => (
+                STUNMessageClass::ResponseSuccess,
+                STUNAttributesContent::XORMappedAddress { address: source },
+            ),
+            false => (
+                STUNMessageClass::ResponseError,
+                STUNAttributesContent::new_error_code(STUNErrorCode::BadRequest),
+            ),
+        };
+
+        let mut response = STUN::new_default(
+            message_class,
+            STUNMessageMethod::Binding,
+            Some(request.header.transaction_id),
+        );
+        for attribute in [
+            first_attribute,
+            STUNAttributesContent::MessageIntegrity {
+                authType: STUNAuthType::ShortTerm,
+            },
+            STUNAttributesContent::Fingerprint,
+        ] {
+            let attribute_type = attribute.attribute_type();
+            response
+                .body
+                .add_new_attribute(attribute, attribute_type, 0);
+        }
+        if tamper {
+            context.password = Some("wrong-password".to_string());
+        }
+        let mut response_bin = Vec::new();
+        response
+            .encode(&mut Cursor::new(&mut response_bin), &Some(&context))
+            .unwrap();
+        return Some((response_bin, peer.local_addr().unwrap()));
+    }
+
+    fn monitor(peer: &UdpSocket) -> StunConsentFreshness {
+        let mut consent = StunConsentFreshness::new(
+            StunClient::new(peer.local_addr().unwrap()),
+            "peer:local".to_string(),
+            "peer-password".to_string(),
+        );
+        consent
+            .set_controlling(true)
+            .set_tie_breaker(7)
+            .set_interval(Duration::from_millis(30))
+            .set_consent_timeout(Duration::from_millis(300));
+        return consent;
+    }
+
+    #[test]
+    fn test_consent_refreshed_by_responses() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let handle = monitor(&peer)
+            .start(Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap()))
+            .unwrap();
+
+        //Well past the consent timeout while answering every check
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(600) {
+            let (response, source) = answer_check(&peer, false).unwrap();
+            assert!(handle.on_message(&response, source));
+            //The same response twice, or from elsewhere, is not accepted
+            assert!(!handle.on_message(&response, source));
+        }
+        assert!(handle.has_consent());
+        assert!(handle.events().try_recv().is_err());
+
+        let (response, _) = answer_check(&peer, false).unwrap();
+        assert!(!handle.on_message(&response, "127.0.0.1:9".parse().unwrap()));
+        let (response, source) = answer_check(&peer, true).unwrap();
+        assert!(!handle.on_message(&response, source));
+        handle.stop();
+    }
+
+    #[test]
+    fn test_consent_lost_without_responses() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let handle = monitor(&peer)
+            .start(Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap()))
+            .unwrap();
+        assert_eq!(
+            handle.events().recv_timeout(Duration::from_secs(2)),
+            Ok(StunConsentEvent::ConsentLost)
+        );
+        assert!(!handle.has_consent());
+
+        //Late responses don't bring consent back
+        peer.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let (response, source) = answer_check(&peer, false).unwrap();
+        assert!(!handle.on_message(&response, source));
+        assert!(!handle.has_consent());
+    }
+}
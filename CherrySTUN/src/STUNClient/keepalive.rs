@@ -0,0 +1,294 @@
+//Keeps NAT bindings open by sending Binding Indications (RFC 8489 section 6.3), which need no
+//response, on every registered flow. Intervals are randomised so that flows opened together
+//don't keep sending together.
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use crate::STUNHeader::header::{STUNMessageClass, STUNMessageMethod};
+use crate::STUNSerde::encode::STUNEncode;
+use crate::STUN::stun::STUN;
+use log::{debug, error, warn};
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//RFC 8445 section 11 asks for at least one packet every 15 seconds
+pub const STUN_KEEPALIVE_DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+pub const STUN_KEEPALIVE_DEFAULT_JITTER: f64 = 0.2;
+
+pub type StunKeepaliveFlowId = u64;
+
+/// Options for sending keepalives
+pub struct StunKeepalive {
+    /// Average time between two indications on a flow
+    pub interval: Duration,
+    /// Each wait is picked uniformly in `interval * [1 - jitter, 1 + jitter]`, 0 to 1
+    pub jitter: f64,
+}
+
+/// Returned by `StunKeepalive::start`, flows are registered on it. Stops the keepalive thread
+/// when stopped or dropped.
+pub struct StunKeepaliveHandle {
+    shared: Arc<KeepaliveShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct KeepaliveShared {
+    state: Mutex<KeepaliveState>,
+    wakeup: Condvar,
+    interval: Duration,
+    jitter: f64,
+}
+
+struct KeepaliveState {
+    flows: HashMap<StunKeepaliveFlowId, KeepaliveFlow>,
+    next_id: StunKeepaliveFlowId,
+    shutdown: bool,
+}
+
+struct KeepaliveFlow {
+    socket: Arc<UdpSocket>,
+    destination: SocketAddr,
+    next_send: Instant,
+}
+
+impl StunKeepalive {
+    /// A constructor with default parameters
+    pub fn new() -> Self {
+        StunKeepalive {
+            interval: STUN_KEEPALIVE_DEFAULT_INTERVAL,
+            jitter: STUN_KEEPALIVE_DEFAULT_JITTER,
+        }
+    }
+
+    /// Set `interval` field, builder pattern.
+    pub fn set_interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set `jitter` field, builder pattern. Clamped to 0 to 1.
+    pub fn set_jitter(&mut self, jitter: f64) -> &mut Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Starts the thread sending indications, flows are added to the returned handle
+    pub fn start(&self) -> Result<StunKeepaliveHandle, STUNError> {
+        if self.interval.is_zero() {
+            return Err(STUNError {
+                step: STUNStep::STUNKeepalive,
+                error_type: STUNErrorType::InvalidConfiguration,
+                message: "Keepalive interval must be larger than zero".to_string(),
+            });
+        }
+        let shared = Arc::new(KeepaliveShared {
+            state: Mutex::new(KeepaliveState {
+                flows: HashMap::new(),
+                next_id: 0,
+                shutdown: false,
+            }),
+            wakeup: Condvar::new(),
+            interval: self.interval,
+            jitter: self.jitter,
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || thread_shared.run());
+        return Ok(StunKeepaliveHandle {
+            shared,
+            thread: Some(thread),
+        });
+    }
+}
+
+impl StunKeepaliveHandle {
+    /// Starts sending indications from `socket` to `destination`. The first one goes out after
+    /// one (randomised) interval, as the flow was just used.
+    pub fn register(&self, socket: Arc<UdpSocket>, destination: SocketAddr) -> StunKeepaliveFlowId {
+        let mut state = self.shared.lock_state();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.flows.insert(
+            id,
+            KeepaliveFlow {
+                socket,
+                destination,
+                next_send: Instant::now() + self.shared.next_wait(),
+            },
+        );
+        self.shared.wakeup.notify_all();
+        return id;
+    }
+
+    /// Stops sending on a flow, false if it was not registered
+    pub fn unregister(&self, id: StunKeepaliveFlowId) -> bool {
+        let mut state = self.shared.lock_state();
+        let removed = state.flows.remove(&id).is_some();
+        self.shared.wakeup.notify_all();
+        return removed;
+    }
+
+    pub fn flow_count(&self) -> usize {
+        return self.shared.lock_state().flows.len();
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown_and_join();
+    }
+
+    fn shutdown_and_join(&mut self) {
+        self.shared.lock_state().shutdown = true;
+        self.shared.wakeup.notify_all();
+        match self.thread.take() {
+            Some(thread) => {
+                if thread.join().is_err() {
+                    error!("Keepalive thread panicked");
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+impl Drop for StunKeepaliveHandle {
+    fn drop(&mut self) {
+        self.shutdown_and_join();
+    }
+}
+
+impl KeepaliveShared {
+    fn lock_state(&self) -> MutexGuard<'_, KeepaliveState> {
+        match self.state.lock() {
+            Ok(state) => return state,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn next_wait(&self) -> Duration {
+        if self.jitter == 0.0 {
+            return self.interval;
+        }
+        let factor = rand::thread_rng().gen_range((1.0 - self.jitter)..(1.0 + self.jitter));
+        return self.interval.mul_f64(factor);
+    }
+
+    fn run(&self) {
+        let mut state = self.lock_state();
+        while !state.shutdown {
+            let now = Instant::now();
+            let mut earliest: Option<Instant> = None;
+            for flow in state.flows.values_mut() {
+                if flow.next_send <= now {
+                    let indication = match Self::encode_indication() {
+                        Ok(bin) => bin,
+                        Err(e) => {
+                            error!("Keepalive stopped: {:?}", e);
+                            return;
+                        }
+                    };
+                    //Indications are fire and forget, a failed send is retried next interval
+                    match flow.socket.send_to(&indication, flow.destination) {
+                        Ok(_) => debug!("Sent keepalive to {:?}", flow.destination),
+                        Err(e) => warn!("Error sending keepalive to {:?}: {}", flow.destination, e),
+                    }
+                    flow.next_send = now + self.next_wait();
+                }
+                earliest = match earliest {
+                    Some(instant) if instant <= flow.next_send => Some(instant),
+                    _ => Some(flow.next_send),
+                };
+            }
+            state = match earliest {
+                Some(instant) => {
+                    match self
+                        .wakeup
+                        .wait_timeout(state, instant.saturating_duration_since(now))
+                    {
+                        Ok((state, _)) => state,
+                        Err(poisoned) => poisoned.into_inner().0,
+                    }
+                }
+                None => match self.wakeup.wait(state) {
+                    Ok(state) => state,
+                    Err(poisoned) => poisoned.into_inner(),
+                },
+            };
+        }
+    }
+
+    //Every indication gets a fresh transaction ID
+    fn encode_indication() -> Result<Vec<u8>, STUNError> {
+        let indication = STUN::new_default(
+            STUNMessageClass::Indication,
+            STUNMessageMethod::Binding,
+            None,
+        );
+        let mut indication_bin = Vec::new();
+        match indication.encode(&mut Cursor::new(&mut indication_bin), &None) {
+            Ok(()) => return Ok(indication_bin),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::STUNSerde::decode::STUNDecode;
+
+    fn receive_indication(udp: &UdpSocket) -> Option<STUN> {
+        let mut buf = [0; 512];
+        match udp.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                return Some(STUN::decode(&mut Cursor::new(&buf[..len]), &mut None).unwrap())
+            }
+            Err(_) => return None,
+        }
+    }
+
+    #[test]
+    fn test_keepalive_flows() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+
+        let handle = StunKeepalive::new()
+            .set_interval(Duration::from_millis(40))
+            .set_jitter(0.5)
+            .start()
+            .unwrap();
+        let id = handle.register(socket.clone(), peer.local_addr().unwrap());
+        assert_eq!(handle.flow_count(), 1);
+        for _ in 0..3 {
+            let indication = receive_indication(&peer).unwrap();
+            assert_eq!(
+                indication.header.message_class,
+                STUNMessageClass::Indication
+            );
+            assert_eq!(indication.header.message_method, STUNMessageMethod::Binding);
+        }
+
+        assert!(handle.unregister(id));
+        assert!(!handle.unregister(id));
+        //Drain what was in flight
+        std::thread::sleep(Duration::from_millis(100));
+        peer.set_nonblocking(true).unwrap();
+        while receive_indication(&peer).is_some() {}
+        peer.set_nonblocking(false).unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(receive_indication(&peer).is_none());
+        handle.stop();
+    }
+
+    #[test]
+    fn test_zero_interval_is_rejected() {
+        assert!(StunKeepalive::new()
+            .set_interval(Duration::ZERO)
+            .start()
+            .is_err());
+    }
+}
@@ -1 +1,3 @@
 pub mod client;
+pub mod keepalive;
+pub mod consent;
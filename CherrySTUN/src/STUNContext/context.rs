@@ -9,6 +9,20 @@ pub struct STUNContext {
     pub password: Option<String>, //Needs to be provided
     pub nonce: Option<String>,    //Will be filled by decode if provided
     pub realm: Option<String>,    //Will be filled bt decode if provided
+    //When provided, used as is as the HMAC key instead of deriving it from
+    //username/realm/password. Servers usually only store the key.
+    pub integrity_key: Option<Vec<u8>>,
+    //Decode stores MESSAGE-INTEGRITY in `received_integrity` instead of validating it, for
+    //servers that have to look up the key from the decoded username first
+    pub defer_integrity_check: bool,
+    pub received_integrity: Option<STUNReceivedIntegrity>, //Filled by decode when deferring
+}
+
+//MESSAGE-INTEGRITY of a decoded message along with the bytes it was computed over
+#[derive(Clone, Debug)]
+pub struct STUNReceivedIntegrity {
+    pub hmac: Vec<u8>,
+    pub message: Vec<u8>,
 }
 
 //This context allows/makes our serde library to be a little bit smarter
@@ -24,6 +38,16 @@ impl STUNContext {
             password: None,
             nonce: None,
             realm: None,
+            integrity_key: None,
+            defer_integrity_check: false,
+            received_integrity: None,
         };
     }
 }
+
+impl STUNReceivedIntegrity {
+    pub fn verify(&self, key: &[u8]) -> bool {
+        let hmac = hmac_sha1::hmac_sha1(key, self.message.as_slice());
+        return crate::utils::two_vector_are_identical(hmac.to_vec(), self.hmac.clone());
+    }
+}
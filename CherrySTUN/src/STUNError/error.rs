@@ -7,6 +7,11 @@ pub enum STUNStep {
     STUNDecode,
     STUNUtils,
     STUNNetwork,
+    STUNServer,
+    STUNKeepalive,
+    STUNConsent,
+    TURNClient,
+    TURNServer,
 }
 
 #[derive(Debug, PartialEq)]
@@ -27,12 +32,21 @@ pub enum STUNErrorType {
     SASLPrepError,
     InvalidMessageBinLength,
     MessageIntegrityMismatch,
+    FingerprintMismatch,
     ErrorSendingMessageToServer, 
     ErrorReceivingFromServer,
     NetworkTimeoutError,
     ErrorSettingNetworkTimeout,
     UnsupportedNATType,
     DidNotFindExpectedAttribute,
+    AddressResolutionError,
+    SocketBindError,
+    ErrorSendingMessageToClient,
+    ErrorReceivingFromClient,
+    InvalidConfiguration,
+    ErrorResponse(u16), //Error code of a STUN/TURN error response
+    ChannelNotBound,
+    ChannelNumbersExhausted,
 }
 
 #[derive(Debug)]
@@ -59,6 +59,17 @@ pub enum STUNMessageClass {
 #[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
 pub enum STUNMessageMethod {
     Binding = 0b0000_0000_0000_0001,
+    //TURN (RFC 8656), small enough to not be split by the class bits
+    Allocate = 0b0000_0000_0000_0011,
+    Refresh = 0b0000_0000_0000_0100,
+    Send = 0b0000_0000_0000_0110,
+    Data = 0b0000_0000_0000_0111,
+    CreatePermission = 0b0000_0000_0000_1000,
+    ChannelBind = 0b0000_0000_0000_1001,
+    //TURN over TCP (RFC 6062)
+    Connect = 0b0000_0000_0000_1010,
+    ConnectionBind = 0b0000_0000_0000_1011,
+    ConnectionAttempt = 0b0000_0000_0000_1100,
 }
 
 /*
@@ -0,0 +1,355 @@
+//Long-term credential mechanism on the server side (RFC 8489 section 9.2).
+//Nonces are stateless: they carry their issue time and an HMAC over it and the client IP, so
+//the server can validate them without remembering what it handed out.
+//
+//Credentials are either fixed (`StaticCredentials`) or time-limited ones handed out by a
+//signaling server that shares a secret with us (`TurnRestCredentials`).
+use crate::STUNBody::attributes::attributes::STUNAttributesContent;
+use base64::Engine;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//RFC 8489 recommends nonces to stay valid for a few minutes
+pub const STUN_DEFAULT_NONCE_LIFETIME: Duration = Duration::from_secs(600);
+const STUN_NONCE_SECRET_LENGTH: usize = 20;
+//8 hex chars of timestamp followed by the first 10 bytes of HMAC-SHA1 in hex. Kept short as
+//it ends up in every 401, which has to stay small for the amplification limit.
+const STUN_NONCE_TIMESTAMP_CHARS: usize = 8;
+const STUN_NONCE_HMAC_BYTES: usize = 10;
+
+/// Looks up the long-term key of a user, asked by the server for every authenticated request
+pub trait CredentialProvider: Send + Sync {
+    /// MD5(username ":" realm ":" SASLprep(password)) of `username`, `None` for unknown users.
+    /// `STUNAttributesContent::long_term_key` computes it from a password.
+    fn get_key(&self, username: &str, realm: &str) -> Option<Vec<u8>>;
+
+    /// Every key `username` may have used, tried in order by the server. Providers with more
+    /// than one password per user, like during a secret rotation, override it.
+    fn get_keys(&self, username: &str, realm: &str) -> Vec<Vec<u8>> {
+        match self.get_key(username, realm) {
+            Some(key) => return vec![key],
+            None => return Vec::new(),
+        }
+    }
+}
+
+/// In memory username to password store
+pub struct StaticCredentials {
+    passwords: HashMap<String, String>,
+}
+
+/// Time-limited credentials of the "TURN REST API" scheme
+/// (draft-uberti-behave-turn-rest): the username is `expiry:userid` with `expiry` in seconds
+/// since the UNIX epoch, the password is base64(HMAC-SHA1(secret, username)). Nothing is stored
+/// per user, any username signed with one of the secrets is accepted until it expires.
+pub struct TurnRestCredentials {
+    //Newest first, credentials are issued with the first one
+    secrets: RwLock<Vec<String>>,
+}
+
+/// Realm, credentials and nonces used by a server with long-term authentication
+pub struct StunLongTermAuth {
+    pub realm: String,
+    pub credential_provider: Arc<dyn CredentialProvider>,
+    pub nonces: StunNonces,
+}
+
+/// Issues and validates stateless nonces
+pub struct StunNonces {
+    secret: Vec<u8>,
+    /// Nonces older than this are answered with 438 (Stale Nonce)
+    pub lifetime: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StunNonceStatus {
+    Valid,
+    Stale,
+    Invalid, //Not issued by us, or for another client
+}
+
+impl StaticCredentials {
+    pub fn new() -> Self {
+        StaticCredentials {
+            passwords: HashMap::new(),
+        }
+    }
+
+    /// Add a user, builder pattern.
+    pub fn add_user(&mut self, username: &str, password: &str) -> &mut Self {
+        self.passwords
+            .insert(username.to_string(), password.to_string());
+        self
+    }
+}
+
+impl CredentialProvider for StaticCredentials {
+    fn get_key(&self, username: &str, realm: &str) -> Option<Vec<u8>> {
+        let password = match self.passwords.get(username) {
+            Some(password) => password,
+            None => return None,
+        };
+        match STUNAttributesContent::long_term_key(
+            username.to_string(),
+            realm.to_string(),
+            password.to_string(),
+        ) {
+            Ok(key) => return Some(key),
+            Err(e) => {
+                log::warn!("Error computing key of {:?}: {:?}", username, e);
+                return None;
+            }
+        }
+    }
+}
+
+impl TurnRestCredentials {
+    pub fn new(secret: &str) -> Self {
+        TurnRestCredentials {
+            secrets: RwLock::new(vec![secret.to_string()]),
+        }
+    }
+
+    /// Starts issuing credentials with `secret`. The older secrets stay valid until removed,
+    /// so that credentials handed out before the rotation keep working.
+    pub fn add_secret(&self, secret: &str) {
+        match self.secrets.write() {
+            Ok(mut secrets) => secrets.insert(0, secret.to_string()),
+            Err(poisoned) => poisoned.into_inner().insert(0, secret.to_string()),
+        }
+    }
+
+    /// Stops accepting credentials signed with `secret`
+    pub fn remove_secret(&self, secret: &str) {
+        match self.secrets.write() {
+            Ok(mut secrets) => secrets.retain(|x| x != secret),
+            Err(poisoned) => poisoned.into_inner().retain(|x| x != secret),
+        }
+    }
+
+    /// Username and password for `userid` valid for `ttl`, what a signaling server hands out
+    pub fn issue(&self, userid: &str, ttl: Duration) -> (String, String) {
+        let username = format!("{}:{}", StunNonces::now() + ttl.as_secs(), userid);
+        let password = match self.read_secrets().first() {
+            Some(secret) => Self::password(secret, &username),
+            None => String::new(),
+        };
+        return (username, password);
+    }
+
+    /// base64(HMAC-SHA1(secret, username))
+    pub fn password(secret: &str, username: &str) -> String {
+        let hmac = hmac_sha1::hmac_sha1(secret.as_bytes(), username.as_bytes());
+        return base64::engine::general_purpose::STANDARD.encode(hmac);
+    }
+
+    fn read_secrets(&self) -> std::sync::RwLockReadGuard<'_, Vec<String>> {
+        match self.secrets.read() {
+            Ok(secrets) => return secrets,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    //The expiry is what comes before the first colon, usernames without a userid are only an
+    //expiry
+    fn is_expired(username: &str) -> bool {
+        let expiry = match username.split_once(':') {
+            Some((expiry, _)) => expiry,
+            None => username,
+        };
+        match expiry.parse::<u64>() {
+            Ok(expiry) => return expiry <= StunNonces::now(),
+            Err(_) => return true,
+        }
+    }
+}
+
+impl CredentialProvider for TurnRestCredentials {
+    fn get_key(&self, username: &str, realm: &str) -> Option<Vec<u8>> {
+        return self.get_keys(username, realm).into_iter().next();
+    }
+
+    fn get_keys(&self, username: &str, realm: &str) -> Vec<Vec<u8>> {
+        if Self::is_expired(username) {
+            log::debug!("Expired or malformed REST username {:?}", username);
+            return Vec::new();
+        }
+        let mut keys = Vec::new();
+        for secret in self.read_secrets().iter() {
+            match STUNAttributesContent::long_term_key(
+                username.to_string(),
+                realm.to_string(),
+                Self::password(secret, username),
+            ) {
+                Ok(key) => keys.push(key),
+                Err(e) => log::warn!("Error computing key of {:?}: {:?}", username, e),
+            }
+        }
+        return keys;
+    }
+}
+
+impl StunLongTermAuth {
+    /// A constructor with default nonce lifetime and a random nonce secret
+    pub fn new(realm: String, credential_provider: Arc<dyn CredentialProvider>) -> Self {
+        StunLongTermAuth {
+            realm,
+            credential_provider,
+            nonces: StunNonces::new(STUN_DEFAULT_NONCE_LIFETIME),
+        }
+    }
+}
+
+impl StunNonces {
+    /// Nonces signed with a random secret, only valid for this instance
+    pub fn new(lifetime: Duration) -> Self {
+        let mut secret = vec![0; STUN_NONCE_SECRET_LENGTH];
+        rand::thread_rng().fill(secret.as_mut_slice());
+        return Self::with_secret(secret, lifetime);
+    }
+
+    /// Nonces signed with `secret`, so that several servers can share them
+    pub fn with_secret(secret: Vec<u8>, lifetime: Duration) -> Self {
+        StunNonces { secret, lifetime }
+    }
+
+    pub fn issue(&self, client: IpAddr) -> String {
+        return self.issue_at(Self::now(), client);
+    }
+
+    pub fn check(&self, nonce: &str, client: IpAddr) -> StunNonceStatus {
+        if nonce.len() <= STUN_NONCE_TIMESTAMP_CHARS
+            || !nonce.is_char_boundary(STUN_NONCE_TIMESTAMP_CHARS)
+        {
+            return StunNonceStatus::Invalid;
+        }
+        let issued_at = match u64::from_str_radix(&nonce[..STUN_NONCE_TIMESTAMP_CHARS], 16) {
+            Ok(timestamp) => timestamp,
+            Err(_) => return StunNonceStatus::Invalid,
+        };
+        let expected = self.issue_at(issued_at, client);
+        if !crate::utils::two_vector_are_identical(expected.into_bytes(), nonce.as_bytes().to_vec())
+        {
+            return StunNonceStatus::Invalid;
+        }
+        if Self::now().saturating_sub(issued_at) > self.lifetime.as_secs() {
+            return StunNonceStatus::Stale;
+        }
+        return StunNonceStatus::Valid;
+    }
+
+    fn issue_at(&self, issued_at: u64, client: IpAddr) -> String {
+        let timestamp = format!("{:08x}", issued_at);
+        let mut signed = timestamp.clone().into_bytes();
+        match client {
+            IpAddr::V4(ip) => signed.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => signed.extend_from_slice(&ip.octets()),
+        }
+        let hmac = hmac_sha1::hmac_sha1(self.secret.as_slice(), signed.as_slice());
+        let mut nonce = timestamp;
+        for byte in hmac[..STUN_NONCE_HMAC_BYTES].iter() {
+            nonce.push_str(&format!("{:02x}", byte));
+        }
+        return nonce;
+    }
+
+    fn now() -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => return duration.as_secs(),
+            Err(_) => return 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_long_term_key() {
+        //Example from RFC 8489 section 9.2.2
+        let key = STUNAttributesContent::long_term_key(
+            "user".to_string(),
+            "realm".to_string(),
+            "pass".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            key,
+            [
+                0x84, 0x93, 0xfb, 0xc5, 0x3b, 0xa5, 0x82, 0xfb, 0x4c, 0x04, 0x4c, 0x45, 0x6b, 0xdc,
+                0x40, 0xeb
+            ]
+        );
+        let mut credentials = StaticCredentials::new();
+        credentials.add_user("user", "pass");
+        assert_eq!(credentials.get_key("user", "realm"), Some(key));
+        assert_eq!(credentials.get_key("nobody", "realm"), None);
+    }
+
+    #[test]
+    fn test_stateless_nonces() {
+        let nonces = StunNonces::new(Duration::from_secs(60));
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let nonce = nonces.issue(client);
+        assert_eq!(nonce.len(), 28);
+        assert_eq!(nonces.check(&nonce, client), StunNonceStatus::Valid);
+        assert_eq!(
+            nonces.check(&nonce, "192.0.2.2".parse().unwrap()),
+            StunNonceStatus::Invalid
+        );
+        assert_eq!(
+            StunNonces::new(Duration::from_secs(60)).check(&nonce, client),
+            StunNonceStatus::Invalid
+        );
+        assert_eq!(nonces.check("garbage", client), StunNonceStatus::Invalid);
+
+        let old_nonce = nonces.issue_at(StunNonces::now() - 120, client);
+        assert_eq!(nonces.check(&old_nonce, client), StunNonceStatus::Stale);
+    }
+
+    #[test]
+    fn test_turn_rest_credentials() {
+        //Same as `echo -n "1700000000:alice" | openssl dgst -sha1 -hmac secret -binary | base64`
+        assert_eq!(
+            TurnRestCredentials::password("secret", "1700000000:alice"),
+            "d8soP47RbdIKLDUOpnJPVQyq5Ts="
+        );
+
+        let credentials = TurnRestCredentials::new("old secret");
+        let (old_username, old_password) = credentials.issue("alice", Duration::from_secs(60));
+        assert!(old_username.ends_with(":alice"));
+        let old_key = STUNAttributesContent::long_term_key(
+            old_username.clone(),
+            "realm".to_string(),
+            old_password,
+        )
+        .unwrap();
+        assert_eq!(
+            credentials.get_keys(&old_username, "realm"),
+            vec![old_key.clone()]
+        );
+
+        //Both secrets are accepted during the rotation, the new one first
+        credentials.add_secret("new secret");
+        let (username, password) = credentials.issue("alice", Duration::from_secs(60));
+        let key =
+            STUNAttributesContent::long_term_key(username.clone(), "realm".to_string(), password)
+                .unwrap();
+        assert_eq!(credentials.get_keys(&username, "realm")[0], key);
+        assert!(credentials
+            .get_keys(&old_username, "realm")
+            .contains(&old_key));
+        credentials.remove_secret("old secret");
+        assert!(!credentials
+            .get_keys(&old_username, "realm")
+            .contains(&old_key));
+
+        let expired = format!("{}:alice", StunNonces::now() - 1);
+        assert!(credentials.get_keys(&expired, "realm").is_empty());
+        assert!(credentials.get_keys("alice", "realm").is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//Abuse protection for public servers: per source IP token buckets and counters of what got
+//dropped and why.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+//At this many tracked IPs, buckets that refilled completely are forgotten, then the least
+//recently used ones down to half of it, so that a flood of new sources pays for one scan
+//every `STUN_RATE_LIMITER_MAX_TRACKED_IPS / 2` of them and not for one each
+const STUN_RATE_LIMITER_MAX_TRACKED_IPS: usize = 65536;
+
+/// Requests allowed per source IP: `burst` at once, refilled at `requests_per_second`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StunRateLimit {
+    pub requests_per_second: f64,
+    pub burst: f64,
+}
+
+/// Token bucket per source IP
+pub struct StunRateLimiter {
+    pub limit: StunRateLimit,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Counters of a running server, shared with `StunServerHandle`
+#[derive(Debug, Default)]
+pub struct StunServerStats {
+    /// Datagrams handed to the server
+    pub received: AtomicU64,
+    /// Datagrams that are not well formed STUN requests, dropped before decoding
+    pub dropped_invalid: AtomicU64,
+    /// Requests dropped by the per IP rate limit
+    pub rate_limited: AtomicU64,
+    /// Responses to unauthenticated requests not sent as they were too large
+    pub amplification_limited: AtomicU64,
+    /// Responses produced
+    pub responses: AtomicU64,
+}
+
+/// Plain copy of `StunServerStats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StunServerStatsSnapshot {
+    pub received: u64,
+    pub dropped_invalid: u64,
+    pub rate_limited: u64,
+    pub amplification_limited: u64,
+    pub responses: u64,
+}
+
+impl StunRateLimit {
+    pub fn new(requests_per_second: f64, burst: f64) -> Self {
+        StunRateLimit {
+            requests_per_second,
+            burst,
+        }
+    }
+}
+
+impl StunRateLimiter {
+    pub fn new(limit: StunRateLimit) -> Self {
+        StunRateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of `ip`, false when it is empty
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        return self.allow_at(ip, Instant::now());
+    }
+
+    fn allow_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let limit = self.limit;
+        if buckets.len() >= STUN_RATE_LIMITER_MAX_TRACKED_IPS && !buckets.contains_key(&ip) {
+            Self::evict(&mut buckets, &limit, now);
+        }
+        let bucket = buckets.entry(ip).or_insert(TokenBucket {
+            tokens: limit.burst,
+            last_refill: now,
+        });
+        bucket.refill(&limit, now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        return true;
+    }
+
+    fn evict(buckets: &mut HashMap<IpAddr, TokenBucket>, limit: &StunRateLimit, now: Instant) {
+        buckets.retain(|_, bucket| {
+            bucket.refill(limit, now);
+            return bucket.tokens < limit.burst;
+        });
+        let keep = STUN_RATE_LIMITER_MAX_TRACKED_IPS / 2;
+        if buckets.len() <= keep {
+            return;
+        }
+        //Buckets of sources not seen lately refilled the most, they are the cheapest to forget.
+        //Exactly the fullest ones go, ties included, so limited sources stay limited.
+        let mut tokens: Vec<(IpAddr, f64)> =
+            buckets.iter().map(|(ip, x)| (*ip, x.tokens)).collect();
+        tokens.sort_by(|a, b| a.1.total_cmp(&b.1));
+        for (ip, _) in &tokens[keep..] {
+            buckets.remove(ip);
+        }
+    }
+}
+
+impl TokenBucket {
+    fn refill(&mut self, limit: &StunRateLimit, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(limit.burst);
+        self.last_refill = now;
+    }
+}
+
+impl StunServerStats {
+    pub fn snapshot(&self) -> StunServerStatsSnapshot {
+        return StunServerStatsSnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            dropped_invalid: self.dropped_invalid.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            amplification_limited: self.amplification_limited.load(Ordering::Relaxed),
+            responses: self.responses.load(Ordering::Relaxed),
+        };
+    }
+
+    pub(crate) fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket_per_ip() {
+        let limiter = StunRateLimiter::new(StunRateLimit::new(2.0, 3.0));
+        let now = Instant::now();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..3 {
+            assert!(limiter.allow_at(ip, now));
+        }
+        assert!(!limiter.allow_at(ip, now));
+        //Other clients have their own bucket
+        assert!(limiter.allow_at("192.0.2.2".parse().unwrap(), now));
+        //Two tokens per second
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.allow_at(ip, later));
+        assert!(!limiter.allow_at(ip, later));
+        //Never more than the burst
+        let much_later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.allow_at(ip, much_later));
+        }
+        assert!(!limiter.allow_at(ip, much_later));
+    }
+
+    #[test]
+    fn test_eviction_of_tracked_ips() {
+        let limiter = StunRateLimiter::new(StunRateLimit::new(0.001, 3.0));
+        let now = Instant::now();
+        let ip = |i: usize| IpAddr::from(std::net::Ipv6Addr::from(0x2001_0db8 << 96 | i as u128));
+        //Every other source sent twice, its bucket is the emptier one
+        for i in 0..STUN_RATE_LIMITER_MAX_TRACKED_IPS {
+            assert!(limiter.allow_at(ip(i), now));
+            if i % 2 == 0 {
+                assert!(limiter.allow_at(ip(i), now));
+            }
+        }
+        assert!(limiter.allow_at(ip(STUN_RATE_LIMITER_MAX_TRACKED_IPS), now));
+        assert_eq!(
+            limiter.buckets.lock().unwrap().len(),
+            STUN_RATE_LIMITER_MAX_TRACKED_IPS / 2 + 1
+        );
+        //Still limited
+        assert!(limiter.allow_at(ip(0), now));
+        assert!(!limiter.allow_at(ip(0), now));
+        //A new source does not scan again until the map is full again
+        assert!(limiter.allow_at(ip(STUN_RATE_LIMITER_MAX_TRACKED_IPS + 1), now));
+        assert_eq!(
+            limiter.buckets.lock().unwrap().len(),
+            STUN_RATE_LIMITER_MAX_TRACKED_IPS / 2 + 2
+        );
+    }
+
+    #[test]
+    fn test_eviction_with_drained_buckets() {
+        //No refill, every source drained its single token at once
+        let limiter = StunRateLimiter::new(StunRateLimit::new(0.0, 1.0));
+        let now = Instant::now();
+        let ip = |i: usize| IpAddr::from(std::net::Ipv6Addr::from(0x2001_0db8 << 96 | i as u128));
+        for i in 0..STUN_RATE_LIMITER_MAX_TRACKED_IPS {
+            assert!(limiter.allow_at(ip(i), now));
+        }
+        assert!(limiter.allow_at(ip(STUN_RATE_LIMITER_MAX_TRACKED_IPS), now));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), STUN_RATE_LIMITER_MAX_TRACKED_IPS / 2 + 1);
+        //Those kept are still limited
+        let limited: Vec<IpAddr> = buckets.keys().copied().take(10).collect();
+        drop(buckets);
+        for kept in limited {
+            assert!(!limiter.allow_at(kept, now));
+        }
+    }
+}
@@ -0,0 +1,1353 @@
+//Binding server (RFC 8489 section 6.3). Answers Binding requests with the reflexive transport
+//address of the client, so tests and self-hosted setups don't have to rely on public servers.
+//Optionally runs as a RFC 5780 behaviour-discovery server on two IPs and two ports.
+use super::auth::{StunLongTermAuth, StunNonceStatus};
+use super::limits::{StunRateLimit, StunRateLimiter, StunServerStats};
+use crate::STUNBody::attributes::attributes::{
+    STUNAttributeType, STUNAttributesContent, STUNAuthType, STUNErrorCode,
+};
+use crate::STUNBody::body::STUNBody;
+use crate::STUNContext::context::STUNContext;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use crate::STUNHeader::header::{
+    STUNHeader, STUNMessageClass, STUNMessageMethod, STUN_5389_MAGIC_NUMBER_U32,
+};
+use crate::STUNSerde::{decode::STUNDecode, encode::STUNEncode};
+use crate::STUN::stun::STUN;
+use log::{debug, error, info, warn};
+use std::io::Cursor;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const STUN_SERVER_DEFAULT_SOFTWARE: &str = "CherrySTUN";
+//A Binding response to the smallest possible request (20 bytes) still fits, as does a 401
+//without SOFTWARE (ERROR-CODE, a NONCE and a REALM of up to 24 bytes, 100 bytes in all)
+pub const STUN_SERVER_DEFAULT_MAX_AMPLIFICATION: usize = 5;
+//PADDING (RFC 5780) requests are meant to be fragmented, so take any UDP datagram
+const STUN_SERVER_RECEIVE_BUFFER_SIZE: usize = 65536;
+//Attempts at finding a port free on both IPs when binding behaviour-discovery sockets
+const STUN_SERVER_PORT_PAIR_BIND_ATTEMPTS: usize = 16;
+//How often the spawned server loop wakes up to check for shutdown
+const STUN_SERVER_SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options for serving STUN Binding requests
+pub struct StunServer {
+    /// `SOFTWARE` attribute value in responses
+    pub software: Option<&'static str>,
+    /// Addresses of a behaviour-discovery server (RFC 5780). When `None`, OTHER-ADDRESS is not
+    /// sent and CHANGE-REQUEST, RESPONSE-PORT and PADDING are answered as unknown attributes.
+    pub behaviour_addresses: Option<StunBehaviourAddresses>,
+    /// When set, every request has to be authenticated with long-term credentials
+    pub long_term_auth: Option<StunLongTermAuth>,
+    /// Responses to unauthenticated requests are at most this many times larger than the
+    /// request. `None` turns the limit off.
+    pub max_amplification: Option<usize>,
+    /// Per source IP limit, `None` for unlimited
+    pub rate_limiter: Option<StunRateLimiter>,
+    pub stats: Arc<StunServerStats>,
+}
+
+/// The two IPs and two ports a behaviour-discovery server answers on, making up its four
+/// transport addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StunBehaviourAddresses {
+    pub primary_ip: IpAddr,
+    pub alternate_ip: IpAddr,
+    pub primary_port: u16,
+    pub alternate_port: u16,
+}
+
+/// A response and where it has to go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StunServerReply {
+    pub response: Vec<u8>,
+    /// Local address to send from, `None` for the socket the request arrived on
+    pub from: Option<SocketAddr>,
+    pub to: SocketAddr,
+}
+
+/// Returned by `StunServer::spawn`, stops the server threads when stopped or dropped
+pub struct StunServerHandle {
+    /// Address the (primary) server socket is bound to
+    pub local_addr: SocketAddr,
+    /// All four addresses when spawned with `spawn_behaviour_discovery`
+    pub behaviour_addresses: Option<StunBehaviourAddresses>,
+    pub stats: Arc<StunServerStats>,
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl StunBehaviourAddresses {
+    pub fn new(primary_ip: IpAddr, alternate_ip: IpAddr) -> Self {
+        StunBehaviourAddresses {
+            primary_ip,
+            alternate_ip,
+            primary_port: 0,
+            alternate_port: 0,
+        }
+    }
+
+    pub fn primary_address(&self) -> SocketAddr {
+        return SocketAddr::new(self.primary_ip, self.primary_port);
+    }
+
+    /// primary IP and port first, alternate IP and port last
+    pub fn all(&self) -> [SocketAddr; 4] {
+        return [
+            SocketAddr::new(self.primary_ip, self.primary_port),
+            SocketAddr::new(self.primary_ip, self.alternate_port),
+            SocketAddr::new(self.alternate_ip, self.primary_port),
+            SocketAddr::new(self.alternate_ip, self.alternate_port),
+        ];
+    }
+
+    pub fn contains(&self, local_addr: SocketAddr) -> bool {
+        return self.all().contains(&local_addr);
+    }
+
+    /// Address a response to a request received on `local_addr` is sent from, for the given
+    /// CHANGE-REQUEST flags
+    pub fn changed_address(
+        &self,
+        local_addr: SocketAddr,
+        change_ip: bool,
+        change_port: bool,
+    ) -> SocketAddr {
+        let mut ip = local_addr.ip();
+        let mut port = local_addr.port();
+        if change_ip {
+            ip = if ip == self.primary_ip {
+                self.alternate_ip
+            } else {
+                self.primary_ip
+            };
+        }
+        if change_port {
+            port = if port == self.primary_port {
+                self.alternate_port
+            } else {
+                self.primary_port
+            };
+        }
+        return SocketAddr::new(ip, port);
+    }
+
+    /// OTHER-ADDRESS for requests received on `local_addr`, differs in both IP and port
+    pub fn other_address(&self, local_addr: SocketAddr) -> SocketAddr {
+        return self.changed_address(local_addr, true, true);
+    }
+}
+
+impl StunServerHandle {
+    pub fn stop(mut self) {
+        self.shutdown_and_join();
+    }
+
+    fn shutdown_and_join(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("STUN server thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for StunServerHandle {
+    fn drop(&mut self) {
+        self.shutdown_and_join();
+    }
+}
+
+impl StunServer {
+    /// A constructor with default parameters
+    pub fn new() -> Self {
+        StunServer {
+            software: Some(STUN_SERVER_DEFAULT_SOFTWARE),
+            behaviour_addresses: None,
+            long_term_auth: None,
+            max_amplification: Some(STUN_SERVER_DEFAULT_MAX_AMPLIFICATION),
+            rate_limiter: None,
+            stats: Arc::new(StunServerStats::default()),
+        }
+    }
+
+    /// Set `software` field, builder pattern.
+    pub fn set_software(&mut self, software: Option<&'static str>) -> &mut Self {
+        self.software = software;
+        self
+    }
+
+    /// Set `behaviour_addresses` field, builder pattern. Only needed when running the sockets
+    /// yourself, `spawn_behaviour_discovery` sets it.
+    pub fn set_behaviour_addresses(
+        &mut self,
+        behaviour_addresses: Option<StunBehaviourAddresses>,
+    ) -> &mut Self {
+        self.behaviour_addresses = behaviour_addresses;
+        self
+    }
+
+    /// Set `long_term_auth` field, builder pattern.
+    pub fn set_long_term_auth(&mut self, long_term_auth: Option<StunLongTermAuth>) -> &mut Self {
+        self.long_term_auth = long_term_auth;
+        self
+    }
+
+    /// Set `max_amplification` field, builder pattern.
+    pub fn set_max_amplification(&mut self, max_amplification: Option<usize>) -> &mut Self {
+        self.max_amplification = max_amplification;
+        self
+    }
+
+    /// Set the per source IP rate limit, builder pattern.
+    pub fn set_rate_limit(&mut self, rate_limit: Option<StunRateLimit>) -> &mut Self {
+        self.rate_limiter = rate_limit.map(StunRateLimiter::new);
+        self
+    }
+}
+
+impl StunServer {
+    /// Processes one datagram received from `source` and returns the encoded response.
+    /// `None` means nothing should be sent back (not STUN, not a request, ...).
+    pub fn handle_message(&self, message_bin: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+        return self
+            .handle_message_on(message_bin, source, None)
+            .map(|reply| reply.response);
+    }
+
+    /// Same as `handle_message`, for a datagram received on `local_addr`. The reply says where
+    /// the response goes, which only differs from the request with the behaviour-discovery
+    /// attributes (CHANGE-REQUEST and RESPONSE-PORT).
+    pub fn handle_message_on(
+        &self,
+        message_bin: &[u8],
+        source: SocketAddr,
+        local_addr: Option<SocketAddr>,
+    ) -> Option<StunServerReply> {
+        StunServerStats::increment(&self.stats.received);
+        //Anything that is not STUN gets dropped before we spend anything on it
+        if !Self::is_well_formed_message(message_bin) {
+            debug!("Dropping invalid message from {:?}", source);
+            StunServerStats::increment(&self.stats.dropped_invalid);
+            return None;
+        }
+        //Class bits C1 and C0, both zero for requests. Indications (keepalives) need no
+        //response and responses are not ours to answer.
+        if message_bin[0] & 0x01 != 0 || message_bin[1] & 0x10 != 0 {
+            debug!("Ignoring non request message from {:?}", source);
+            return None;
+        }
+        match &self.rate_limiter {
+            Some(rate_limiter) if !rate_limiter.allow(source.ip()) => {
+                debug!("Rate limiting {:?}", source);
+                StunServerStats::increment(&self.stats.rate_limited);
+                return None;
+            }
+            _ => {}
+        }
+        let reply = self.process_request(message_bin, source, local_addr);
+        if reply.is_some() {
+            StunServerStats::increment(&self.stats.responses);
+        }
+        return reply;
+    }
+
+    //Checks of RFC 8489 section 6.3 that need no decoding: header bits, magic cookie and length
+    pub(crate) fn is_well_formed_message(message_bin: &[u8]) -> bool {
+        if message_bin.len() < 20 || message_bin.len() % 4 != 0 {
+            return false;
+        }
+        let message_type = u16::from_be_bytes([message_bin[0], message_bin[1]]);
+        let message_length = u16::from_be_bytes([message_bin[2], message_bin[3]]);
+        return message_type & 0xC000 == 0
+            && message_bin[4..8] == STUN_5389_MAGIC_NUMBER_U32.to_be_bytes()
+            && message_length as usize == message_bin.len() - 20;
+    }
+
+    fn process_request(
+        &self,
+        message_bin: &[u8],
+        source: SocketAddr,
+        local_addr: Option<SocketAddr>,
+    ) -> Option<StunServerReply> {
+        let request_len = message_bin.len();
+        let mut cursor = Cursor::new(message_bin);
+        let header = match STUNHeader::decode(&mut cursor, &mut None) {
+            Ok(header) => header,
+            Err(e) => {
+                //Without a valid header there is no transaction to answer
+                debug!("Dropping non STUN message from {:?}: {}", source, e);
+                return None;
+            }
+        };
+
+        let mut decode_context = STUNContext::new();
+        decode_context.defer_integrity_check = true;
+        let body = match STUNBody::decode(&mut cursor, &mut Some(&mut decode_context)) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Malformed request from {:?}: {}", source, e);
+                return self.reply_to(
+                    source,
+                    self.error_response(&header, STUNErrorCode::BadRequest),
+                    &None,
+                    request_len,
+                );
+            }
+        };
+
+        //Responses to authenticated requests are signed with the same key
+        let integrity_key = match &self.long_term_auth {
+            Some(auth) => match self.authenticate(auth, &header, &decode_context, source) {
+                Ok(key) => Some(key),
+                Err(response) => return self.reply_to(source, response, &None, request_len),
+            },
+            None => None,
+        };
+
+        //Behaviour-discovery attributes are only understood when there are addresses to
+        //change to
+        let behaviour_addresses = match (self.behaviour_addresses, local_addr) {
+            (Some(addresses), Some(local_addr)) if addresses.contains(local_addr) => {
+                Some(addresses)
+            }
+            _ => None,
+        };
+        let mut unknown_attributes = body.unknown_attributes.clone();
+        let mut change_request = None;
+        let mut response_port = None;
+        let mut padding = None;
+        for attribute in body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::ChangeRequest {
+                    change_ip,
+                    change_port,
+                } => change_request = Some((change_ip, change_port)),
+                STUNAttributesContent::ResponsePort { port } => response_port = Some(port),
+                STUNAttributesContent::Padding { length } => padding = Some(length),
+                _ => continue,
+            }
+            if behaviour_addresses.is_none() {
+                unknown_attributes.push(attribute.attribute_type as u16);
+            }
+        }
+
+        if !unknown_attributes.is_empty() {
+            let mut response = self.error_response(&header, STUNErrorCode::UnknownAttribute);
+            response.body.add_new_attribute(
+                STUNAttributesContent::new_unknown_attributes(unknown_attributes),
+                STUNAttributeType::UnknownAttributes,
+                0,
+            );
+            return self.reply_to(source, response, &integrity_key, request_len);
+        }
+
+        if response_port.is_some() && padding.is_some() {
+            //RFC 5780 7.5, such requests must not be processed
+            return self.reply_to(
+                source,
+                self.error_response(&header, STUNErrorCode::BadRequest),
+                &integrity_key,
+                request_len,
+            );
+        }
+
+        match header.message_method {
+            STUNMessageMethod::Binding => {
+                let mut response = self.binding_success_response(&header, source);
+                let behaviour_addresses = match behaviour_addresses {
+                    Some(addresses) => addresses,
+                    None => return self.reply_to(source, response, &integrity_key, request_len),
+                };
+                //Guarded by `behaviour_addresses` above
+                let local_addr = local_addr.unwrap();
+                let (change_ip, change_port) = change_request.unwrap_or((false, false));
+                let from = behaviour_addresses.changed_address(local_addr, change_ip, change_port);
+                let to = match response_port {
+                    Some(port) => SocketAddr::new(source.ip(), port),
+                    None => source,
+                };
+                response.body.add_new_attribute(
+                    STUNAttributesContent::new_other_address(
+                        behaviour_addresses.other_address(local_addr),
+                    ),
+                    STUNAttributeType::OtherAddress,
+                    0,
+                );
+                response.body.add_new_attribute(
+                    STUNAttributesContent::new_response_origin(from),
+                    STUNAttributeType::ResponseOrigin,
+                    0,
+                );
+                match padding {
+                    Some(length) => response.body.add_new_attribute(
+                        STUNAttributesContent::new_padding(length),
+                        STUNAttributeType::Padding,
+                        0,
+                    ),
+                    None => {}
+                }
+                return match self.sign_and_encode(response, &integrity_key, request_len) {
+                    Some(response) => Some(StunServerReply {
+                        response,
+                        from: Some(from),
+                        to,
+                    }),
+                    None => None,
+                };
+            }
+            //TURN methods are for a TURN server
+            _ => {
+                return self.reply_to(
+                    source,
+                    self.error_response(&header, STUNErrorCode::BadRequest),
+                    &integrity_key,
+                    request_len,
+                );
+            }
+        }
+    }
+
+    //Long-term credential checks of RFC 8489 section 9.2.4. Returns the key of the user, or
+    //the error response to send.
+    pub(crate) fn authenticate(
+        &self,
+        auth: &StunLongTermAuth,
+        header: &STUNHeader,
+        decode_context: &STUNContext,
+        source: SocketAddr,
+    ) -> Result<Vec<u8>, STUN> {
+        let received_integrity = match &decode_context.received_integrity {
+            Some(integrity) => integrity,
+            None => return Err(self.challenge(auth, header, STUNErrorCode::Unauthorized, source)),
+        };
+        let (username, nonce) = match (
+            &decode_context.username,
+            &decode_context.realm,
+            &decode_context.nonce,
+        ) {
+            (Some(username), Some(_), Some(nonce)) => (username, nonce),
+            _ => return Err(self.error_response(header, STUNErrorCode::BadRequest)),
+        };
+        match auth.nonces.check(nonce, source.ip()) {
+            StunNonceStatus::Valid => {}
+            StunNonceStatus::Stale | StunNonceStatus::Invalid => {
+                debug!("Stale nonce from {:?}", source);
+                return Err(self.challenge(auth, header, STUNErrorCode::StaleNonce, source));
+            }
+        }
+        let keys = auth.credential_provider.get_keys(username, &auth.realm);
+        if keys.is_empty() {
+            debug!("Unknown user {:?} from {:?}", username, source);
+            return Err(self.challenge(auth, header, STUNErrorCode::Unauthorized, source));
+        }
+        match keys.into_iter().find(|key| received_integrity.verify(key)) {
+            Some(key) => return Ok(key),
+            None => {
+                debug!(
+                    "MESSAGE-INTEGRITY mismatch for {:?} from {:?}",
+                    username, source
+                );
+                return Err(self.challenge(auth, header, STUNErrorCode::Unauthorized, source));
+            }
+        }
+    }
+
+    //401 and 438 responses carry the realm and a fresh nonce to retry with
+    fn challenge(
+        &self,
+        auth: &StunLongTermAuth,
+        header: &STUNHeader,
+        code: STUNErrorCode,
+        source: SocketAddr,
+    ) -> STUN {
+        let mut response = self.error_response(header, code);
+        response.body.add_new_attribute(
+            STUNAttributesContent::new_realm(auth.realm.clone()),
+            STUNAttributeType::Realm,
+            0,
+        );
+        response.body.add_new_attribute(
+            STUNAttributesContent::new_nonce(Some(auth.nonces.issue(source.ip()))),
+            STUNAttributeType::Nonce,
+            0,
+        );
+        return response;
+    }
+
+    fn reply_to(
+        &self,
+        source: SocketAddr,
+        response: STUN,
+        integrity_key: &Option<Vec<u8>>,
+        request_len: usize,
+    ) -> Option<StunServerReply> {
+        return match self.sign_and_encode(response, integrity_key, request_len) {
+            Some(response) => Some(StunServerReply {
+                response,
+                from: None,
+                to: source,
+            }),
+            None => None,
+        };
+    }
+
+    fn binding_success_response(&self, request_header: &STUNHeader, source: SocketAddr) -> STUN {
+        let mut response = STUN::new_default(
+            STUNMessageClass::ResponseSuccess,
+            request_header.message_method,
+            Some(request_header.transaction_id),
+        );
+        response.body.add_new_attribute(
+            STUNAttributesContent::new_xor_mapped_address(source),
+            STUNAttributeType::XORMappedAddress,
+            0,
+        );
+        //Still sent for RFC 3489 clients
+        response.body.add_new_attribute(
+            STUNAttributesContent::new_mapped_address(source),
+            STUNAttributeType::MappedAddress,
+            0,
+        );
+        self.add_software(&mut response);
+        return response;
+    }
+
+    pub(crate) fn error_response(&self, request_header: &STUNHeader, code: STUNErrorCode) -> STUN {
+        let mut response = STUN::new_default(
+            STUNMessageClass::ResponseError,
+            request_header.message_method,
+            Some(request_header.transaction_id),
+        );
+        response.body.add_new_attribute(
+            STUNAttributesContent::new_error_code(code),
+            STUNAttributeType::ErrorCode,
+            0,
+        );
+        self.add_software(&mut response);
+        return response;
+    }
+
+    pub(crate) fn add_software(&self, response: &mut STUN) {
+        match self.software {
+            Some(software) => {
+                response.body.add_new_attribute(
+                    STUNAttributesContent::new_software(software.to_string()),
+                    STUNAttributeType::Software,
+                    0,
+                );
+            }
+            None => {}
+        }
+    }
+
+    //Adds MESSAGE-INTEGRITY when the request was authenticated. Responses to unauthenticated
+    //requests are kept within `max_amplification` times the request, by leaving out optional
+    //attributes or not answering at all. 401 and 438 challenges go the same way, without
+    //SOFTWARE they fit when the realm is short enough.
+    pub(crate) fn sign_and_encode(
+        &self,
+        mut response: STUN,
+        integrity_key: &Option<Vec<u8>>,
+        request_len: usize,
+    ) -> Option<Vec<u8>> {
+        match integrity_key {
+            Some(key) => {
+                response.body.add_new_attribute(
+                    STUNAttributesContent::MessageIntegrity {
+                        authType: STUNAuthType::LongTerm,
+                    },
+                    STUNAttributeType::MessageIntegrity,
+                    0,
+                );
+                let mut encode_context = STUNContext::new();
+                encode_context.integrity_key = Some(key.clone());
+                return self.encode_response(response, &Some(&encode_context));
+            }
+            None => {}
+        }
+        let max_response_len = match self.max_amplification {
+            Some(factor) => request_len * factor,
+            None => return self.encode_response(response, &None),
+        };
+        let response_bin = match self.encode_response(response.clone(), &None) {
+            Some(bin) => bin,
+            None => return None,
+        };
+        if response_bin.len() <= max_response_len {
+            return Some(response_bin);
+        }
+        response.body.attributes.retain(|x| {
+            !matches!(
+                x.attribute_type,
+                STUNAttributeType::Software
+                    | STUNAttributeType::MappedAddress
+                    | STUNAttributeType::ResponseOrigin
+            )
+        });
+        match self.encode_response(response, &None) {
+            Some(bin) if bin.len() <= max_response_len => return Some(bin),
+            Some(_) => {
+                debug!("Response too large for a {} byte request", request_len);
+                StunServerStats::increment(&self.stats.amplification_limited);
+                return None;
+            }
+            None => return None,
+        }
+    }
+
+    fn encode_response(
+        &self,
+        response: STUN,
+        encode_context: &Option<&STUNContext>,
+    ) -> Option<Vec<u8>> {
+        let mut response_bin = Vec::new();
+        let mut write_cursor = Cursor::new(&mut response_bin);
+        match response.encode(&mut write_cursor, encode_context) {
+            Ok(()) => return Some(response_bin),
+            Err(e) => {
+                error!("Error encoding response: {:?}", e);
+                return None;
+            }
+        }
+    }
+}
+
+impl StunServer {
+    /// Serves requests arriving on `udp` forever, blocking the calling thread
+    pub fn run(&self, udp: &UdpSocket) -> Result<(), STUNError> {
+        let shutdown = AtomicBool::new(false);
+        return self.serve_until(std::slice::from_ref(udp), 0, &shutdown);
+    }
+
+    /// Serves requests arriving on `udp` forever. Behaviour-discovery needs all four sockets,
+    /// see `spawn_behaviour_discovery`.
+    pub async fn run_async(&self, udp: &tokio::net::UdpSocket) -> Result<(), STUNError> {
+        let mut buf = vec![0; STUN_SERVER_RECEIVE_BUFFER_SIZE];
+        loop {
+            let (len, source) = match udp.recv_from(&mut buf).await {
+                Ok(x) => x,
+                Err(e) => {
+                    return Err(STUNError {
+                        step: STUNStep::STUNServer,
+                        error_type: STUNErrorType::ErrorReceivingFromClient,
+                        message: "Error receiving request: ".to_string() + e.to_string().as_str(),
+                    })
+                }
+            };
+            match self.handle_message(&buf[..len], source) {
+                Some(response_bin) => match udp.send_to(&response_bin, source).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        //One unreachable client should not take the server down
+                        warn!("Error sending response to {:?}: {}", source, e);
+                    }
+                },
+                None => {}
+            }
+        }
+    }
+
+    /// Binds `bind_addr` and serves requests from a background thread until the returned
+    /// handle is stopped. Bind to port 0 and read `local_addr` for tests.
+    pub fn spawn(self, bind_addr: SocketAddr) -> Result<StunServerHandle, STUNError> {
+        let udp = match Self::bind_server_socket(bind_addr) {
+            Ok(udp) => udp,
+            Err(e) => return Err(e),
+        };
+        let local_addr = match Self::server_socket_addr(&udp) {
+            Ok(addr) => addr,
+            Err(e) => return Err(e),
+        };
+        info!("STUN server listening on {:?}", local_addr);
+        return Ok(Self::spawn_threads(
+            Arc::new(self),
+            vec![udp],
+            local_addr,
+            None,
+        ));
+    }
+
+    /// Binds the four transport addresses of a behaviour-discovery server (RFC 5780) and
+    /// serves them from background threads. Ports left at 0 are picked so that they are free
+    /// on both IPs. On a single host, loopback aliases (127.0.0.1 and 127.0.0.2) are enough.
+    pub fn spawn_behaviour_discovery(
+        mut self,
+        addresses: StunBehaviourAddresses,
+    ) -> Result<StunServerHandle, STUNError> {
+        let (primary_primary, alternate_primary) = match Self::bind_port_pair(
+            addresses.primary_ip,
+            addresses.alternate_ip,
+            addresses.primary_port,
+        ) {
+            Ok(pair) => pair,
+            Err(e) => return Err(e),
+        };
+        let (primary_alternate, alternate_alternate) = match Self::bind_port_pair(
+            addresses.primary_ip,
+            addresses.alternate_ip,
+            addresses.alternate_port,
+        ) {
+            Ok(pair) => pair,
+            Err(e) => return Err(e),
+        };
+        let mut bound_addresses = addresses;
+        match (
+            Self::server_socket_addr(&primary_primary),
+            Self::server_socket_addr(&primary_alternate),
+        ) {
+            (Ok(primary), Ok(alternate)) => {
+                bound_addresses.primary_port = primary.port();
+                bound_addresses.alternate_port = alternate.port();
+            }
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        }
+        info!(
+            "STUN behaviour-discovery server listening on {:?}",
+            bound_addresses.all()
+        );
+        self.behaviour_addresses = Some(bound_addresses);
+        //Same order as `StunBehaviourAddresses::all`
+        let sockets = vec![
+            primary_primary,
+            primary_alternate,
+            alternate_primary,
+            alternate_alternate,
+        ];
+        return Ok(Self::spawn_threads(
+            Arc::new(self),
+            sockets,
+            bound_addresses.primary_address(),
+            Some(bound_addresses),
+        ));
+    }
+
+    fn spawn_threads(
+        server: Arc<StunServer>,
+        sockets: Vec<UdpSocket>,
+        local_addr: SocketAddr,
+        behaviour_addresses: Option<StunBehaviourAddresses>,
+    ) -> StunServerHandle {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let sockets = Arc::new(sockets);
+        let mut threads = Vec::new();
+        for index in 0..sockets.len() {
+            let thread_server = server.clone();
+            let thread_sockets = sockets.clone();
+            let thread_shutdown = shutdown.clone();
+            threads.push(std::thread::spawn(move || {
+                match thread_server.serve_until(&thread_sockets, index, &thread_shutdown) {
+                    Ok(()) => {}
+                    Err(e) => error!("STUN server stopped: {:?}", e),
+                }
+            }));
+        }
+        return StunServerHandle {
+            local_addr,
+            behaviour_addresses,
+            stats: server.stats.clone(),
+            shutdown,
+            threads,
+        };
+    }
+
+    //Binds `ip_a:port` and `ip_b:port`. With port 0 an ephemeral port free on both IPs is
+    //searched for.
+    fn bind_port_pair(
+        ip_a: IpAddr,
+        ip_b: IpAddr,
+        port: u16,
+    ) -> Result<(UdpSocket, UdpSocket), STUNError> {
+        let mut last_error = None;
+        for _ in 0..STUN_SERVER_PORT_PAIR_BIND_ATTEMPTS {
+            let udp_a = match Self::bind_server_socket(SocketAddr::new(ip_a, port)) {
+                Ok(udp) => udp,
+                Err(e) => return Err(e),
+            };
+            let bound_port = match Self::server_socket_addr(&udp_a) {
+                Ok(addr) => addr.port(),
+                Err(e) => return Err(e),
+            };
+            match Self::bind_server_socket(SocketAddr::new(ip_b, bound_port)) {
+                Ok(udp_b) => return Ok((udp_a, udp_b)),
+                Err(e) => {
+                    if port != 0 {
+                        return Err(e);
+                    }
+                    debug!("Port {} taken on {:?}, retrying", bound_port, ip_b);
+                    last_error = Some(e);
+                }
+            }
+        }
+        return Err(last_error.unwrap());
+    }
+
+    pub(crate) fn bind_server_socket(bind_addr: SocketAddr) -> Result<UdpSocket, STUNError> {
+        let udp = match UdpSocket::bind(bind_addr) {
+            Ok(udp) => udp,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNServer,
+                    error_type: STUNErrorType::SocketBindError,
+                    message: "Error binding server socket: ".to_string() + e.to_string().as_str(),
+                })
+            }
+        };
+        match udp.set_read_timeout(Some(STUN_SERVER_SHUTDOWN_POLL_INTERVAL)) {
+            Ok(_) => {}
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNServer,
+                    error_type: STUNErrorType::ErrorSettingNetworkTimeout,
+                    message: "Error setting server socket timeout: ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+        return Ok(udp);
+    }
+
+    pub(crate) fn server_socket_addr(udp: &UdpSocket) -> Result<SocketAddr, STUNError> {
+        match udp.local_addr() {
+            Ok(addr) => return Ok(addr),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNServer,
+                    error_type: STUNErrorType::SocketBindError,
+                    message: "Error reading server socket address: ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+
+    //Serves `sockets[index]`, the other sockets are used to send CHANGE-REQUEST responses
+    fn serve_until(
+        &self,
+        sockets: &[UdpSocket],
+        index: usize,
+        shutdown: &AtomicBool,
+    ) -> Result<(), STUNError> {
+        let local_addrs: Vec<Option<SocketAddr>> =
+            sockets.iter().map(|x| x.local_addr().ok()).collect();
+        let udp = &sockets[index];
+        let mut buf = vec![0; STUN_SERVER_RECEIVE_BUFFER_SIZE];
+        while !shutdown.load(Ordering::SeqCst) {
+            let (len, source) = match udp.recv_from(&mut buf) {
+                Ok(x) => x,
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(STUNError {
+                        step: STUNStep::STUNServer,
+                        error_type: STUNErrorType::ErrorReceivingFromClient,
+                        message: "Error receiving request: ".to_string() + e.to_string().as_str(),
+                    })
+                }
+            };
+            let reply = match self.handle_message_on(&buf[..len], source, local_addrs[index]) {
+                Some(reply) => reply,
+                None => continue,
+            };
+            let send_udp = match reply.from {
+                Some(from) => match local_addrs.iter().position(|x| *x == Some(from)) {
+                    Some(position) => &sockets[position],
+                    None => {
+                        warn!("No socket bound to {:?}, not answering {:?}", from, source);
+                        continue;
+                    }
+                },
+                None => udp,
+            };
+            match send_udp.send_to(&reply.response, reply.to) {
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Error sending response to {:?}: {}", reply.to, e);
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::STUNClient::client::StunClient;
+
+    fn decode_response(response_bin: &[u8]) -> STUN {
+        let mut cursor = Cursor::new(response_bin);
+        return STUN::decode(&mut cursor, &mut None).unwrap();
+    }
+
+    fn error_code_of(response: &STUN) -> Option<u16> {
+        for attribute in response.body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::ErrorCode { code, .. } => return Some(code),
+                _ => continue,
+            }
+        }
+        return None;
+    }
+
+    #[test]
+    fn test_binding_request_over_loopback() {
+        let handle = StunServer::new()
+            .spawn("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        let client = StunClient::new(handle.local_addr);
+        let udp = client.bind_local_socket(0).unwrap();
+        let mapped = client.query_server_reflexive_address(&udp).unwrap();
+        assert_eq!(mapped.port(), udp.local_addr().unwrap().port());
+        assert_eq!(mapped.ip().to_string(), "127.0.0.1");
+        handle.stop();
+    }
+
+    #[test]
+    fn test_binding_success_attributes() {
+        let mut server = StunServer::new();
+        server.set_max_amplification(None);
+        let source: SocketAddr = "[2001:db8::1]:32853".parse().unwrap();
+        let request =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        let mut request_bin = Vec::new();
+        request
+            .encode(&mut Cursor::new(&mut request_bin), &None)
+            .unwrap();
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(
+            response.header.message_class,
+            STUNMessageClass::ResponseSuccess
+        );
+        assert_eq!(
+            response.header.transaction_id,
+            request.header.transaction_id
+        );
+        let values: Vec<STUNAttributesContent> = response
+            .body
+            .attributes
+            .iter()
+            .map(|x| x.value.clone())
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                STUNAttributesContent::XORMappedAddress { address: source },
+                STUNAttributesContent::MappedAddress { address: source },
+                STUNAttributesContent::Software {
+                    software: STUN_SERVER_DEFAULT_SOFTWARE.to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_and_unknown_attribute_requests() {
+        let server = StunServer::new();
+        let source: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let mut request_bin = vec![
+            0x00, 0x01, 0x00, 0x08, //Binding request, 8 byte body
+            0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87,
+            0xdf, 0xae, 0x00, 0x31, 0x00, 0x04, //unknown comprehension-required attribute
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(
+            response.header.message_class,
+            STUNMessageClass::ResponseError
+        );
+        assert_eq!(error_code_of(&response), Some(420));
+        assert!(response.body.attributes.iter().any(|x| x.value
+            == STUNAttributesContent::UnknownAttributes {
+                attributes: vec![0x0031]
+            }));
+
+        //Comprehension-optional attributes are ignored
+        request_bin[20] = 0x80;
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(
+            response.header.message_class,
+            STUNMessageClass::ResponseSuccess
+        );
+
+        //XOR-MAPPED-ADDRESS with an invalid family
+        request_bin[20..24].copy_from_slice(&[0x00, 0x20, 0x00, 0x04]);
+        request_bin[24..28].copy_from_slice(&[0x00, 0x07, 0x00, 0x00]);
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(error_code_of(&response), Some(400));
+
+        //Length in header does not match the datagram, dropped without decoding
+        assert!(server.handle_message(&request_bin[..24], source).is_none());
+
+        //Not STUN at all
+        assert!(server
+            .handle_message(&[0xde, 0xad, 0xbe, 0xef], source)
+            .is_none());
+
+        //Indications and responses are not answered, but they are not invalid either
+        request_bin[1] = 0x11;
+        assert!(server.handle_message(&request_bin, source).is_none());
+        request_bin[0] = 0x01;
+        request_bin[1] = 0x01;
+        assert!(server.handle_message(&request_bin, source).is_none());
+        assert_eq!(server.stats.snapshot().dropped_invalid, 2);
+    }
+
+    fn encode_request(request: &STUN) -> Vec<u8> {
+        let mut request_bin = Vec::new();
+        request
+            .encode(&mut Cursor::new(&mut request_bin), &None)
+            .unwrap();
+        return request_bin;
+    }
+
+    fn binding_request_with(attributes: Vec<STUNAttributesContent>) -> STUN {
+        let mut request =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        for attribute in attributes {
+            let attribute_type = attribute.attribute_type();
+            request.body.add_new_attribute(attribute, attribute_type, 0);
+        }
+        return request;
+    }
+
+    #[test]
+    fn test_behaviour_discovery_attributes() {
+        let addresses = StunBehaviourAddresses {
+            primary_ip: "192.0.2.1".parse().unwrap(),
+            alternate_ip: "192.0.2.2".parse().unwrap(),
+            primary_port: 3478,
+            alternate_port: 3479,
+        };
+        let mut server = StunServer::new();
+        server.set_behaviour_addresses(Some(addresses));
+        let source: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        let primary = addresses.primary_address();
+
+        let request =
+            binding_request_with(vec![STUNAttributesContent::new_change_request(true, true)]);
+        let reply = server
+            .handle_message_on(&encode_request(&request), source, Some(primary))
+            .unwrap();
+        let alternate: SocketAddr = "192.0.2.2:3479".parse().unwrap();
+        assert_eq!(reply.from, Some(alternate));
+        assert_eq!(reply.to, source);
+        let response = decode_response(&reply.response);
+        assert!(response
+            .body
+            .attributes
+            .iter()
+            .any(|x| x.value == STUNAttributesContent::OtherAddress { address: alternate }));
+        assert!(response
+            .body
+            .attributes
+            .iter()
+            .any(|x| x.value == STUNAttributesContent::ResponseOrigin { address: alternate }));
+
+        //Change port only, received on the alternate IP
+        let request =
+            binding_request_with(vec![STUNAttributesContent::new_change_request(false, true)]);
+        let reply = server
+            .handle_message_on(
+                &encode_request(&request),
+                source,
+                Some("192.0.2.2:3478".parse().unwrap()),
+            )
+            .unwrap();
+        assert_eq!(reply.from, Some("192.0.2.2:3479".parse().unwrap()));
+
+        let request = binding_request_with(vec![STUNAttributesContent::new_response_port(50000)]);
+        let reply = server
+            .handle_message_on(&encode_request(&request), source, Some(primary))
+            .unwrap();
+        assert_eq!(reply.from, Some(primary));
+        assert_eq!(reply.to, "198.51.100.7:50000".parse().unwrap());
+
+        let request = binding_request_with(vec![STUNAttributesContent::new_padding(1400)]);
+        let reply = server
+            .handle_message_on(&encode_request(&request), source, Some(primary))
+            .unwrap();
+        let response = decode_response(&reply.response);
+        assert!(response
+            .body
+            .attributes
+            .iter()
+            .any(|x| x.value == STUNAttributesContent::Padding { length: 1400 }));
+        assert!(reply.response.len() > 1400);
+
+        let request = binding_request_with(vec![
+            STUNAttributesContent::new_response_port(50000),
+            STUNAttributesContent::new_padding(8),
+        ]);
+        let reply = server
+            .handle_message_on(&encode_request(&request), source, Some(primary))
+            .unwrap();
+        assert_eq!(reply.to, source);
+        assert_eq!(error_code_of(&decode_response(&reply.response)), Some(400));
+
+        //A plain server does not understand CHANGE-REQUEST
+        let request =
+            binding_request_with(vec![STUNAttributesContent::new_change_request(true, false)]);
+        let response = decode_response(
+            &StunServer::new()
+                .handle_message(&encode_request(&request), source)
+                .unwrap(),
+        );
+        assert_eq!(error_code_of(&response), Some(420));
+        assert!(response.body.attributes.iter().any(|x| x.value
+            == STUNAttributesContent::UnknownAttributes {
+                attributes: vec![0x0003]
+            }));
+    }
+
+    #[test]
+    fn test_behaviour_discovery_over_loopback() {
+        let handle = StunServer::new()
+            .spawn_behaviour_discovery(StunBehaviourAddresses::new(
+                "127.0.0.1".parse().unwrap(),
+                "127.0.0.2".parse().unwrap(),
+            ))
+            .unwrap();
+        let addresses = handle.behaviour_addresses.unwrap();
+        assert_ne!(addresses.primary_port, addresses.alternate_port);
+        assert_eq!(handle.local_addr, addresses.primary_address());
+
+        let mut client = StunClient::new(handle.local_addr);
+        client.set_timeout(Duration::from_secs(2));
+        assert_eq!(
+            client.nat_mapping_type(false, Some(0)).unwrap(),
+            crate::STUN::stun::STUNNatMappingType::EndpointIndependent
+        );
+        assert_eq!(
+            client.nat_filtering_type(Some(0)).unwrap(),
+            crate::STUN::stun::STUNNatFilteringType::EndpointIndependentFiltering
+        );
+
+        //RESPONSE-PORT sends the response to another socket of the client
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let request = binding_request_with(vec![STUNAttributesContent::new_response_port(
+            receiver.local_addr().unwrap().port(),
+        )]);
+        udp.send_to(&encode_request(&request), handle.local_addr)
+            .unwrap();
+        let mut buf = [0; 512];
+        let (len, from) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(from, handle.local_addr);
+        let response = decode_response(&buf[..len]);
+        assert!(response.body.attributes.iter().any(|x| x.value
+            == STUNAttributesContent::XORMappedAddress {
+                address: udp.local_addr().unwrap()
+            }));
+        handle.stop();
+    }
+
+    fn attribute_value<'a>(
+        response: &'a STUN,
+        attribute_type: STUNAttributeType,
+    ) -> Option<&'a STUNAttributesContent> {
+        return response
+            .body
+            .attributes
+            .iter()
+            .find(|x| x.attribute_type == attribute_type)
+            .map(|x| &x.value);
+    }
+
+    fn authenticated_request(password: &str, nonce: &str) -> (Vec<u8>, STUNContext) {
+        let mut context = STUNContext::new();
+        context.username = Some("user".to_string());
+        context.realm = Some("example.org".to_string());
+        context.password = Some(password.to_string());
+        let request = binding_request_with(vec![
+            STUNAttributesContent::new_username("user".to_string()),
+            STUNAttributesContent::new_realm("example.org".to_string()),
+            STUNAttributesContent::new_nonce(Some(nonce.to_string())),
+            STUNAttributesContent::MessageIntegrity {
+                authType: STUNAuthType::LongTerm,
+            },
+        ]);
+        let mut request_bin = Vec::new();
+        request
+            .encode(&mut Cursor::new(&mut request_bin), &Some(&context))
+            .unwrap();
+        return (request_bin, context);
+    }
+
+    #[test]
+    fn test_long_term_authentication() {
+        let mut credentials = super::super::auth::StaticCredentials::new();
+        credentials.add_user("user", "secret");
+        let mut server = StunServer::new();
+        server.set_long_term_auth(Some(StunLongTermAuth::new(
+            "example.org".to_string(),
+            Arc::new(credentials),
+        )));
+        let source: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+
+        //Unauthenticated requests are challenged
+        let request = binding_request_with(vec![STUNAttributesContent::new_software(
+            "STUN test client".to_string(),
+        )]);
+        let response = decode_response(
+            &server
+                .handle_message(&encode_request(&request), source)
+                .unwrap(),
+        );
+        assert_eq!(error_code_of(&response), Some(401));
+        assert_eq!(
+            attribute_value(&response, STUNAttributeType::Realm),
+            Some(&STUNAttributesContent::Realm {
+                realm: Some("example.org".to_string())
+            })
+        );
+        let nonce = match attribute_value(&response, STUNAttributeType::Nonce) {
+            Some(STUNAttributesContent::Nonce { nonce: Some(nonce) }) => nonce.clone(),
+            _ => panic!("401 without NONCE"),
+        };
+
+        //Retry with credentials, the response is signed with the same key
+        let (request_bin, mut context) = authenticated_request("secret", &nonce);
+        let response_bin = server.handle_message(&request_bin, source).unwrap();
+        let response =
+            STUN::decode(&mut Cursor::new(&response_bin[..]), &mut Some(&mut context)).unwrap();
+        assert_eq!(
+            response.header.message_class,
+            STUNMessageClass::ResponseSuccess
+        );
+        let mut wrong_context = context.clone();
+        wrong_context.password = Some("wrong".to_string());
+        assert!(STUN::decode(
+            &mut Cursor::new(&response_bin[..]),
+            &mut Some(&mut wrong_context)
+        )
+        .is_err());
+
+        let (request_bin, _) = authenticated_request("wrong", &nonce);
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(error_code_of(&response), Some(401));
+
+        //Nonces are bound to the client address and signed by the server
+        let (request_bin, _) = authenticated_request("secret", &nonce);
+        let response = decode_response(
+            &server
+                .handle_message(&request_bin, "192.0.2.2:40000".parse().unwrap())
+                .unwrap(),
+        );
+        assert_eq!(error_code_of(&response), Some(438));
+        assert!(attribute_value(&response, STUNAttributeType::Nonce).is_some());
+        let (request_bin, _) = authenticated_request("secret", "0000000000000000deadbeef");
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(error_code_of(&response), Some(438));
+    }
+
+    #[test]
+    fn test_expired_nonce() {
+        let mut credentials = super::super::auth::StaticCredentials::new();
+        credentials.add_user("user", "secret");
+        let mut auth = StunLongTermAuth::new("example.org".to_string(), Arc::new(credentials));
+        auth.nonces.lifetime = Duration::from_secs(0);
+        let mut server = StunServer::new();
+        server.set_long_term_auth(Some(auth));
+        let source: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let nonce = server
+            .long_term_auth
+            .as_ref()
+            .unwrap()
+            .nonces
+            .issue(source.ip());
+        std::thread::sleep(Duration::from_millis(1100));
+        let (request_bin, _) = authenticated_request("secret", &nonce);
+        let response = decode_response(&server.handle_message(&request_bin, source).unwrap());
+        assert_eq!(error_code_of(&response), Some(438));
+    }
+
+    #[test]
+    fn test_rate_limit_and_amplification() {
+        let mut server = StunServer::new();
+        server.set_rate_limit(Some(StunRateLimit::new(0.001, 2.0)));
+        server.software = Some("CherrySTUN with a long SOFTWARE value");
+        let request_bin = encode_request(&binding_request_with(vec![]));
+        let source: SocketAddr = "[2001:db8::1]:32853".parse().unwrap();
+
+        //Optional attributes are left out to stay within 5 times the 20 byte request
+        let max_response_len = STUN_SERVER_DEFAULT_MAX_AMPLIFICATION * request_bin.len();
+        let response_bin = server.handle_message(&request_bin, source).unwrap();
+        assert!(response_bin.len() <= max_response_len);
+        let response = decode_response(&response_bin);
+        assert!(attribute_value(&response, STUNAttributeType::XORMappedAddress).is_some());
+        assert!(attribute_value(&response, STUNAttributeType::Software).is_none());
+
+        //A 401 fits without SOFTWARE, the client needs the realm and nonce to authenticate
+        let auth_server = |realm: &str| {
+            let mut credentials = super::super::auth::StaticCredentials::new();
+            credentials.add_user("user", "secret");
+            let mut auth_server = StunServer::new();
+            auth_server.set_long_term_auth(Some(StunLongTermAuth::new(
+                realm.to_string(),
+                Arc::new(credentials),
+            )));
+            return auth_server;
+        };
+        let short_realm_server = auth_server("example.org");
+        let challenge_bin = short_realm_server
+            .handle_message(&request_bin, source)
+            .unwrap();
+        assert!(challenge_bin.len() <= max_response_len);
+        let challenge = decode_response(&challenge_bin);
+        assert_eq!(error_code_of(&challenge), Some(401));
+        assert!(attribute_value(&challenge, STUNAttributeType::Software).is_none());
+        assert!(attribute_value(&challenge, STUNAttributeType::Nonce).is_some());
+        //Not when the realm is too long
+        let long_realm_server = auth_server("realm-of-an-example-organization.org");
+        assert!(long_realm_server
+            .handle_message(&request_bin, source)
+            .is_none());
+        assert_eq!(long_realm_server.stats.snapshot().amplification_limited, 1);
+
+        assert!(server.handle_message(&request_bin, source).is_some());
+        assert!(server.handle_message(&request_bin, source).is_none());
+        assert!(server
+            .handle_message(&request_bin, "[2001:db8::2]:32853".parse().unwrap())
+            .is_some());
+        let stats = server.stats.snapshot();
+        assert_eq!(stats.received, 4);
+        assert_eq!(stats.rate_limited, 1);
+        assert_eq!(stats.responses, 3);
+    }
+
+    #[tokio::test]
+    async fn test_binding_request_async() {
+        let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = udp.local_addr().unwrap();
+        let server = Arc::new(StunServer::new());
+        tokio::spawn(async move { server.run_async(&udp).await });
+
+        let client_udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let request =
+            STUN::new_default(STUNMessageClass::Request, STUNMessageMethod::Binding, None);
+        let mut request_bin = Vec::new();
+        request
+            .encode(&mut Cursor::new(&mut request_bin), &None)
+            .unwrap();
+        client_udp.send_to(&request_bin, server_addr).await.unwrap();
+        let mut buf = [0; 512];
+        let (len, _) = client_udp.recv_from(&mut buf).await.unwrap();
+        let response = decode_response(&buf[..len]);
+        assert!(response.body.attributes.iter().any(|x| x.value
+            == STUNAttributesContent::XORMappedAddress {
+                address: client_udp.local_addr().unwrap()
+            }));
+    }
+}
@@ -0,0 +1,1814 @@
+//TURN client (RFC 8656) over UDP or TCP. An allocation gives a relayed transport address on
+//the server that peers can reach even when the NAT in front of us is symmetric.
+//
+//Once allocated, the socket belongs to the allocation: a reader thread hands responses to the
+//pending transactions and data from peers to the allocation, a refresh thread keeps the
+//allocation and its permissions alive until it is released.
+//
+//Peers can also be bound to a channel (RFC 8656 section 12), data to and from them then travels
+//in ChannelData messages with a 4 byte header instead of Send/Data indications.
+//
+//Over TCP, the relayed address can be a TCP one (RFC 6062). Connections to and from peers are
+//then made with Connect requests and ConnectionAttempt indications, and each one is handed out
+//as a separate data connection to the server.
+use crate::STUNBody::attributes::attributes::{
+    STUNAttributesContent, STUNAuthType, STUNErrorCode, TURN_TRANSPORT_TCP, TURN_TRANSPORT_UDP,
+};
+use crate::STUNClient::client::StunClient;
+use crate::STUNContext::context::STUNContext;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use crate::STUNHeader::header::{STUNMessageClass, STUNMessageMethod};
+use crate::STUNSerde::{decode::STUNDecode, encode::STUNEncode};
+use crate::STUN::channel_data::{ChannelData, TURN_CHANNEL_NUMBER_MAX, TURN_CHANNEL_NUMBER_MIN};
+use crate::STUN::classifier::{classify_packet, STUNPacketType};
+use crate::STUN::stream::{read_frame_exact, StunStreamReader};
+use crate::STUN::stun::STUN;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//RFC 8656 section 3.2, servers grant 10 minutes when nothing else is asked for
+pub const TURN_DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+//Allocations are refreshed this long before they expire, or half way for short lifetimes
+const TURN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+//RFC 8656 section 9, permissions last 5 minutes and are refreshed a minute before that
+pub const TURN_PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+const TURN_PERMISSION_REFRESH_INTERVAL: Duration = Duration::from_secs(240);
+//RFC 8656 section 12, channel bindings last 10 minutes and are refreshed a minute before that
+pub const TURN_CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+const TURN_CHANNEL_REFRESH_INTERVAL: Duration = Duration::from_secs(540);
+//A channel the server would not bind is asked for again after this long
+const TURN_CHANNEL_BIND_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+//How often the reader thread checks for shutdown
+const TURN_READ_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Options for allocating on a TURN server. The server is `client.stun_server`, `client`
+/// also gives the timeouts and SOFTWARE of every request.
+pub struct TurnClient {
+    pub client: StunClient,
+    /// Long-term credentials on the TURN server
+    pub username: String,
+    pub password: String,
+    /// Lifetime asked for in Allocate and Refresh, the server decides what it grants
+    pub requested_lifetime: Option<Duration>,
+    /// Transport of the relayed address, `TURN_TRANSPORT_TCP` needs `allocate_over_tcp`
+    pub requested_transport: u8,
+    /// Asks for an even relayed port, `Some(true)` also reserves the next port for a second
+    /// allocation, see `TurnAllocation::reservation_token`
+    pub even_port: Option<bool>,
+    /// Token of a port reserved by an earlier allocation, to allocate it
+    pub reservation_token: Option<u64>,
+    /// Asks the server to set the DF bit on what it relays to peers over UDP
+    pub dont_fragment: bool,
+    /// Family of the relayed address, `TURN_ADDRESS_FAMILY_IPV4` when `None`
+    pub requested_address_family: Option<u8>,
+    /// `TURN_ADDRESS_FAMILY_IPV6` asks for an IPv6 relayed address as well as the IPv4 one,
+    /// see `TurnAllocation::additional_relayed_address`
+    pub additional_address_family: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnAllocationEvent {
+    /// The allocation was refreshed, the server granted `lifetime`
+    Refreshed { lifetime: Duration },
+    /// Refreshing failed until the allocation expired, the relayed address is gone
+    Expired,
+}
+
+/// An allocation on a TURN server, kept alive until released or dropped
+pub struct TurnAllocation {
+    session: Arc<TurnSession>,
+    state: Arc<AllocationState>,
+    events: Mutex<Receiver<TurnAllocationEvent>>,
+    //Data from peers, taken by `TurnRelayedSocket`
+    pub(crate) inbound: Mutex<Receiver<(Vec<u8>, SocketAddr)>>,
+    //CONNECTION-ID and peer of ConnectionAttempt indications, taken by `accept`
+    connection_attempts: Mutex<Receiver<(u32, SocketAddr)>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+//Request/response layer shared by everything using the allocation
+pub(crate) struct TurnSession {
+    pub(crate) transport: TurnTransport,
+    pub(crate) server: SocketAddr,
+    credentials: Mutex<TurnCredentials>,
+    transactions: Mutex<HashMap<[u8; 12], Sender<Vec<u8>>>>,
+    timeout: Duration,
+    retry_interval: Duration,
+    software: Option<&'static str>,
+    inbound: Mutex<Sender<(Vec<u8>, SocketAddr)>>,
+    connection_attempts: Mutex<Sender<(u32, SocketAddr)>>,
+    //Bound channels, to find the peer of incoming ChannelData
+    channel_peers: Mutex<HashMap<u16, SocketAddr>>,
+    pub(crate) shutdown: AtomicBool,
+}
+
+pub(crate) enum TurnTransport {
+    Udp(UdpSocket),
+    //Writes of several threads must not interleave, the reader thread reads from a clone
+    Tcp(Mutex<TcpStream>),
+}
+
+struct TurnCredentials {
+    username: String,
+    password: String,
+    //Learnt from the first 401
+    realm: Option<String>,
+    nonce: Option<String>,
+}
+
+struct AllocationState {
+    relayed_address: SocketAddr,
+    additional_relayed_address: Option<SocketAddr>,
+    mapped_address: Option<SocketAddr>,
+    reservation_token: Option<u64>,
+    requested_lifetime: Option<Duration>,
+    timers: Mutex<AllocationTimers>,
+    wakeup: Condvar,
+}
+
+struct AllocationTimers {
+    granted: Duration,
+    expires_at: Instant,
+    //Permissions are per peer IP, the port does not matter
+    permissions: HashMap<IpAddr, TurnPermission>,
+    //Channels are per peer transport address
+    channels: HashMap<SocketAddr, TurnChannel>,
+    next_channel_number: u16,
+    released: bool,
+}
+
+struct TurnPermission {
+    installed_at: Instant,
+    next_refresh: Instant,
+}
+
+struct TurnChannel {
+    channel_number: u16,
+    //None until the server accepted the first ChannelBind
+    bound_at: Option<Instant>,
+    next_refresh: Instant,
+}
+
+impl TurnClient {
+    pub fn new(client: StunClient, username: String, password: String) -> Self {
+        TurnClient {
+            client,
+            username,
+            password,
+            requested_lifetime: None,
+            requested_transport: TURN_TRANSPORT_UDP,
+            even_port: None,
+            reservation_token: None,
+            dont_fragment: false,
+            requested_address_family: None,
+            additional_address_family: None,
+        }
+    }
+
+    /// Set `requested_lifetime` field, builder pattern.
+    pub fn set_requested_lifetime(&mut self, requested_lifetime: Option<Duration>) -> &mut Self {
+        self.requested_lifetime = requested_lifetime;
+        self
+    }
+
+    /// Set `requested_transport` field, builder pattern.
+    pub fn set_requested_transport(&mut self, requested_transport: u8) -> &mut Self {
+        self.requested_transport = requested_transport;
+        self
+    }
+
+    /// Set `even_port` field, builder pattern.
+    pub fn set_even_port(&mut self, even_port: Option<bool>) -> &mut Self {
+        self.even_port = even_port;
+        self
+    }
+
+    /// Set `reservation_token` field, builder pattern.
+    pub fn set_reservation_token(&mut self, reservation_token: Option<u64>) -> &mut Self {
+        self.reservation_token = reservation_token;
+        self
+    }
+
+    /// Set `dont_fragment` field, builder pattern.
+    pub fn set_dont_fragment(&mut self, dont_fragment: bool) -> &mut Self {
+        self.dont_fragment = dont_fragment;
+        self
+    }
+
+    /// Set `requested_address_family` field, builder pattern.
+    pub fn set_requested_address_family(
+        &mut self,
+        requested_address_family: Option<u8>,
+    ) -> &mut Self {
+        self.requested_address_family = requested_address_family;
+        self
+    }
+
+    /// Set `additional_address_family` field, builder pattern.
+    pub fn set_additional_address_family(
+        &mut self,
+        additional_address_family: Option<u8>,
+    ) -> &mut Self {
+        self.additional_address_family = additional_address_family;
+        self
+    }
+
+    /// Allocates a UDP relayed address, answering the authentication challenge of the server.
+    /// `socket` is used for everything related to the allocation from now on.
+    pub fn allocate(&self, socket: UdpSocket) -> Result<TurnAllocation, STUNError> {
+        //RFC 6062 section 5.1, TCP relays are only given to clients connected over TCP
+        if self.requested_transport == TURN_TRANSPORT_TCP {
+            return Err(STUNError {
+                step: STUNStep::TURNClient,
+                error_type: STUNErrorType::InvalidConfiguration,
+                message: "TCP relayed addresses need a TCP connection to the server".to_string(),
+            });
+        }
+        match socket.set_read_timeout(Some(TURN_READ_POLL_INTERVAL)) {
+            Ok(()) => {}
+            Err(e) => return Err(Self::read_timeout_error(e)),
+        }
+        return self.allocate_on(TurnTransport::Udp(socket));
+    }
+
+    /// Allocates over a TCP connection to the server, for networks where UDP is blocked or to
+    /// get a TCP relayed address. The allocation lives as long as `stream`.
+    pub fn allocate_over_tcp(&self, stream: TcpStream) -> Result<TurnAllocation, STUNError> {
+        match stream.set_read_timeout(Some(TURN_READ_POLL_INTERVAL)) {
+            Ok(()) => {}
+            Err(e) => return Err(Self::read_timeout_error(e)),
+        }
+        return self.allocate_on(TurnTransport::Tcp(Mutex::new(stream)));
+    }
+
+    fn read_timeout_error(error: std::io::Error) -> STUNError {
+        return STUNError {
+            step: STUNStep::STUNNetwork,
+            error_type: STUNErrorType::ErrorSettingNetworkTimeout,
+            message: "Error setting read timeout of TURN socket: ".to_string()
+                + error.to_string().as_str(),
+        };
+    }
+
+    fn allocate_on(&self, transport: TurnTransport) -> Result<TurnAllocation, STUNError> {
+        let (inbound_sender, inbound) = channel();
+        let (connection_attempt_sender, connection_attempts) = channel();
+        let session = Arc::new(TurnSession {
+            transport,
+            server: self.client.stun_server,
+            credentials: Mutex::new(TurnCredentials {
+                username: self.username.clone(),
+                password: self.password.clone(),
+                realm: None,
+                nonce: None,
+            }),
+            transactions: Mutex::new(HashMap::new()),
+            timeout: self.client.timeout,
+            retry_interval: self.client.retry_interval,
+            software: self.client.software,
+            inbound: Mutex::new(inbound_sender),
+            connection_attempts: Mutex::new(connection_attempt_sender),
+            channel_peers: Mutex::new(HashMap::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let reader_session = session.clone();
+        let reader = std::thread::spawn(move || reader_session.read_loop());
+
+        let mut attributes = vec![STUNAttributesContent::new_requested_transport(
+            self.requested_transport,
+        )];
+        match self.requested_lifetime {
+            Some(lifetime) => attributes.push(STUNAttributesContent::new_lifetime(
+                lifetime.as_secs() as u32,
+            )),
+            None => {}
+        }
+        match self.even_port {
+            Some(reserve) => attributes.push(STUNAttributesContent::new_even_port(reserve)),
+            None => {}
+        }
+        match self.reservation_token {
+            Some(token) => attributes.push(STUNAttributesContent::new_reservation_token(token)),
+            None => {}
+        }
+        if self.dont_fragment {
+            attributes.push(STUNAttributesContent::new_dont_fragment());
+        }
+        match self.requested_address_family {
+            Some(family) => {
+                attributes.push(STUNAttributesContent::new_requested_address_family(family))
+            }
+            None => {}
+        }
+        match self.additional_address_family {
+            Some(family) => {
+                attributes.push(STUNAttributesContent::new_additional_address_family(family))
+            }
+            None => {}
+        }
+        let response = match session.request_success(STUNMessageMethod::Allocate, attributes) {
+            Ok(response) => response,
+            Err(e) => {
+                session.stop_reader(reader);
+                return Err(e);
+            }
+        };
+
+        //The requested family comes first, the additional one second
+        let mut relayed_addresses = Vec::new();
+        let mut mapped_address = None;
+        let mut reservation_token = None;
+        let mut granted = TURN_DEFAULT_LIFETIME;
+        for attribute in response.body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::XORRelayedAddress { address } => {
+                    relayed_addresses.push(address)
+                }
+                STUNAttributesContent::XORMappedAddress { address } => {
+                    mapped_address = Some(address)
+                }
+                STUNAttributesContent::Lifetime { lifetime } => {
+                    granted = Duration::from_secs(lifetime as u64)
+                }
+                STUNAttributesContent::ReservationToken { token } => {
+                    reservation_token = Some(token)
+                }
+                STUNAttributesContent::AddressErrorCode {
+                    family,
+                    code,
+                    ref reason,
+                } => warn!(
+                    "Server did not allocate family {:#x}: {} {}",
+                    family, code, reason
+                ),
+                _ => {}
+            }
+        }
+        let relayed_address = match relayed_addresses.first() {
+            Some(address) => *address,
+            None => {
+                session.stop_reader(reader);
+                return Err(STUNError {
+                    step: STUNStep::TURNClient,
+                    error_type: STUNErrorType::DidNotFindExpectedAttribute,
+                    message: "Allocate response without XOR-RELAYED-ADDRESS".to_string(),
+                });
+            }
+        };
+        let additional_relayed_address = relayed_addresses.get(1).copied();
+        info!(
+            "Allocated {:?} (additional {:?}) on {:?} for {:?}",
+            relayed_address, additional_relayed_address, session.server, granted
+        );
+
+        let state = Arc::new(AllocationState {
+            relayed_address,
+            additional_relayed_address,
+            mapped_address,
+            reservation_token,
+            requested_lifetime: self.requested_lifetime,
+            timers: Mutex::new(AllocationTimers {
+                granted,
+                expires_at: Instant::now() + granted,
+                permissions: HashMap::new(),
+                channels: HashMap::new(),
+                next_channel_number: TURN_CHANNEL_NUMBER_MIN,
+                released: false,
+            }),
+            wakeup: Condvar::new(),
+        });
+        let (event_sender, events) = channel();
+        let refresh_session = session.clone();
+        let refresh_state = state.clone();
+        let refresher =
+            std::thread::spawn(move || refresh_state.refresh_loop(&refresh_session, event_sender));
+        return Ok(TurnAllocation {
+            session,
+            state,
+            events: Mutex::new(events),
+            inbound: Mutex::new(inbound),
+            connection_attempts: Mutex::new(connection_attempts),
+            threads: vec![reader, refresher],
+        });
+    }
+}
+
+impl TurnAllocation {
+    /// Address on the TURN server that peers send to
+    pub fn relayed_address(&self) -> SocketAddr {
+        return self.state.relayed_address;
+    }
+
+    /// IPv6 relayed address when asked for with `additional_address_family` and granted.
+    /// Peers of its family are relayed through it, the allocation is otherwise the same.
+    pub fn additional_relayed_address(&self) -> Option<SocketAddr> {
+        return self.state.additional_relayed_address;
+    }
+
+    /// TURN server the allocation is on
+    pub fn server_address(&self) -> SocketAddr {
+        return self.session.server;
+    }
+
+    /// Our server reflexive address, as seen by the TURN server
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        return self.state.mapped_address;
+    }
+
+    /// Token of the port the server reserved after the relayed one, when asked for with
+    /// `even_port`. Another `TurnClient` allocates it with `set_reservation_token`.
+    pub fn reservation_token(&self) -> Option<u64> {
+        return self.state.reservation_token;
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, STUNError> {
+        let local_addr = match &self.session.transport {
+            TurnTransport::Udp(socket) => socket.local_addr(),
+            TurnTransport::Tcp(stream) => self.session.lock_stream(stream).local_addr(),
+        };
+        match local_addr {
+            Ok(address) => return Ok(address),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::SocketBindError,
+                    message: "Error reading local address: ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+
+    /// Lifetime granted by the last Allocate or Refresh
+    pub fn lifetime(&self) -> Duration {
+        return self.state.lock_timers().granted;
+    }
+
+    /// Time until the allocation expires if it is not refreshed
+    pub fn expires_in(&self) -> Duration {
+        return self
+            .state
+            .lock_timers()
+            .expires_at
+            .saturating_duration_since(Instant::now());
+    }
+
+    /// Receives `TurnAllocationEvent`s
+    pub fn events(&self) -> MutexGuard<'_, Receiver<TurnAllocationEvent>> {
+        match self.events.lock() {
+            Ok(events) => return events,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    /// Refreshes now instead of waiting for the refresh thread, returns the granted lifetime
+    pub fn refresh(&self) -> Result<Duration, STUNError> {
+        return self.state.refresh(&self.session);
+    }
+
+    /// Installs (or refreshes) permissions for `peers`, after which the server relays their
+    /// packets to us. They are refreshed until the allocation is released.
+    pub fn create_permission(&self, peers: &[IpAddr]) -> Result<(), STUNError> {
+        return self.state.create_permission(&self.session, peers);
+    }
+
+    /// True if a permission for `peer` is installed and not about to expire
+    pub fn has_permission(&self, peer: IpAddr) -> bool {
+        match self.state.lock_timers().permissions.get(&peer) {
+            Some(permission) => {
+                return permission.installed_at.elapsed() < TURN_PERMISSION_LIFETIME
+            }
+            None => return false,
+        }
+    }
+
+    /// Relays `data` to `peer` in a Send indication. Needs a permission for the peer.
+    pub fn send_indication(&self, data: &[u8], peer: SocketAddr) -> Result<(), STUNError> {
+        let mut indication =
+            STUN::new_default(STUNMessageClass::Indication, STUNMessageMethod::Send, None);
+        for attribute in [
+            STUNAttributesContent::new_xor_peer_address(peer),
+            STUNAttributesContent::new_data(data.to_vec()),
+        ] {
+            let attribute_type = attribute.attribute_type();
+            indication
+                .body
+                .add_new_attribute(attribute, attribute_type, 0);
+        }
+        let mut indication_bin = Vec::new();
+        match indication.encode(&mut Cursor::new(&mut indication_bin), &None) {
+            Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        return self.session.send_to_server(&indication_bin);
+    }
+
+    /// Binds `peer` to a channel, or refreshes its binding, and returns the channel number. The
+    /// binding also installs a permission for the peer and is refreshed until the allocation is
+    /// released.
+    pub fn bind_channel(&self, peer: SocketAddr) -> Result<u16, STUNError> {
+        return self.state.bind_channel(&self.session, peer);
+    }
+
+    /// Asks the refresh thread to bind `peer` to a channel, for callers that can't wait for the
+    /// server. Does nothing if the peer already has a channel.
+    pub fn request_channel_bind(&self, peer: SocketAddr) {
+        let mut timers = self.state.lock_timers();
+        if timers.channels.contains_key(&peer) {
+            return;
+        }
+        match AllocationState::new_channel(&mut timers, peer, Instant::now()) {
+            Ok(_) => self.state.wakeup.notify_all(),
+            Err(e) => debug!("Not binding a channel to {:?}: {:?}", peer, e),
+        }
+    }
+
+    /// Channel number of `peer` if its channel is bound and not expired
+    pub fn channel_number(&self, peer: SocketAddr) -> Option<u16> {
+        match self.state.lock_timers().channels.get(&peer) {
+            Some(TurnChannel {
+                channel_number,
+                bound_at: Some(bound_at),
+                ..
+            }) if bound_at.elapsed() < TURN_CHANNEL_LIFETIME => return Some(*channel_number),
+            _ => return None,
+        }
+    }
+
+    /// Relays `data` to `peer` in a ChannelData message. Needs a bound channel for the peer.
+    pub fn send_channel_data(&self, data: &[u8], peer: SocketAddr) -> Result<(), STUNError> {
+        let channel_number = match self.channel_number(peer) {
+            Some(channel_number) => channel_number,
+            None => {
+                return Err(STUNError {
+                    step: STUNStep::TURNClient,
+                    error_type: STUNErrorType::ChannelNotBound,
+                    message: format!("No channel bound to {:?}", peer),
+                })
+            }
+        };
+        //Padding is only required on stream transports
+        match ChannelData::new(channel_number, data.to_vec()).encode(self.session.is_stream()) {
+            Ok(message_bin) => return self.session.send_to_server(&message_bin),
+            Err(e) => return Err(e),
+        }
+    }
+
+    /// Opens a TCP connection to `peer` from the relayed address (RFC 6062 section 4.3),
+    /// creating a permission for the peer first if there is none. Needs a TCP allocation.
+    pub fn connect(&self, peer: SocketAddr) -> Result<TcpStream, STUNError> {
+        if !self.has_permission(peer.ip()) {
+            match self.create_permission(&[peer.ip()]) {
+                Ok(()) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let response = match self.session.request_success(
+            STUNMessageMethod::Connect,
+            vec![STUNAttributesContent::new_xor_peer_address(peer)],
+        ) {
+            Ok(response) => response,
+            Err(e) => return Err(e),
+        };
+        let connection_id = response.body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::ConnectionId { connection_id } => Some(connection_id),
+            _ => None,
+        });
+        match connection_id {
+            Some(connection_id) => return self.session.bind_connection(connection_id),
+            None => {
+                return Err(STUNError {
+                    step: STUNStep::TURNClient,
+                    error_type: STUNErrorType::DidNotFindExpectedAttribute,
+                    message: "Connect response without CONNECTION-ID".to_string(),
+                })
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for a peer to connect to the relayed address (RFC 6062 section
+    /// 4.4) and returns the connection. Only peers with a permission can connect.
+    pub fn accept(&self, timeout: Duration) -> Result<(TcpStream, SocketAddr), STUNError> {
+        let received = match self.connection_attempts.lock() {
+            Ok(connection_attempts) => connection_attempts.recv_timeout(timeout),
+            Err(poisoned) => poisoned.into_inner().recv_timeout(timeout),
+        };
+        let (connection_id, peer) = match received {
+            Ok(connection_attempt) => connection_attempt,
+            Err(_) => {
+                return Err(STUNError {
+                    step: STUNStep::TURNClient,
+                    error_type: STUNErrorType::NetworkTimeoutError,
+                    message: "No peer connected before the timeout".to_string(),
+                })
+            }
+        };
+        match self.session.bind_connection(connection_id) {
+            Ok(stream) => return Ok((stream, peer)),
+            Err(e) => return Err(e),
+        }
+    }
+
+    /// Deletes the allocation on the server (Refresh with a zero lifetime) and stops using the
+    /// socket
+    pub fn release(mut self) -> Result<(), STUNError> {
+        self.state.lock_timers().released = true;
+        self.state.wakeup.notify_all();
+        let result = match self.session.request_success(
+            STUNMessageMethod::Refresh,
+            vec![STUNAttributesContent::new_lifetime(0)],
+        ) {
+            Ok(_) => Ok(()),
+            //Already gone on the server
+            Err(STUNError {
+                error_type: STUNErrorType::ErrorResponse(437),
+                ..
+            }) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.shutdown_and_join();
+        return result;
+    }
+
+    fn shutdown_and_join(&mut self) {
+        self.state.lock_timers().released = true;
+        self.state.wakeup.notify_all();
+        self.session.shutdown.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("TURN allocation thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for TurnAllocation {
+    fn drop(&mut self) {
+        self.shutdown_and_join();
+    }
+}
+
+impl AllocationState {
+    fn lock_timers(&self) -> MutexGuard<'_, AllocationTimers> {
+        match self.timers.lock() {
+            Ok(timers) => return timers,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn create_permission(&self, session: &TurnSession, peers: &[IpAddr]) -> Result<(), STUNError> {
+        let mut attributes = Vec::new();
+        for peer in peers {
+            //Only the IP is looked at by the server
+            attributes.push(STUNAttributesContent::new_xor_peer_address(
+                SocketAddr::new(*peer, 0),
+            ));
+        }
+        match session.request_success(STUNMessageMethod::CreatePermission, attributes) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        let now = Instant::now();
+        let mut timers = self.lock_timers();
+        for peer in peers {
+            timers.permissions.insert(
+                *peer,
+                TurnPermission {
+                    installed_at: now,
+                    next_refresh: now + TURN_PERMISSION_REFRESH_INTERVAL,
+                },
+            );
+        }
+        self.wakeup.notify_all();
+        return Ok(());
+    }
+
+    //Refreshes permissions that are due together, the ones that could not be refreshed before
+    //they expired are forgotten
+    fn refresh_permissions(&self, session: &TurnSession, due: Vec<IpAddr>) {
+        match self.create_permission(session, &due) {
+            Ok(()) => debug!("Refreshed permissions for {:?}", due),
+            Err(e) => {
+                warn!("Error refreshing permissions for {:?}: {:?}", due, e);
+                let now = Instant::now();
+                let mut timers = self.lock_timers();
+                for peer in due.iter() {
+                    let expired = match timers.permissions.get_mut(peer) {
+                        Some(permission) => {
+                            permission.next_refresh = now + session.retry_interval;
+                            now.saturating_duration_since(permission.installed_at)
+                                >= TURN_PERMISSION_LIFETIME
+                        }
+                        None => false,
+                    };
+                    if expired {
+                        timers.permissions.remove(peer);
+                    }
+                }
+            }
+        }
+    }
+
+    //Gives `peer` the next unused channel number, numbers are not reused within an allocation.
+    //The refresh thread binds it at `bind_at`.
+    fn new_channel(
+        timers: &mut AllocationTimers,
+        peer: SocketAddr,
+        bind_at: Instant,
+    ) -> Result<u16, STUNError> {
+        if timers.next_channel_number > TURN_CHANNEL_NUMBER_MAX {
+            return Err(STUNError {
+                step: STUNStep::TURNClient,
+                error_type: STUNErrorType::ChannelNumbersExhausted,
+                message: "Every channel number of the allocation is taken".to_string(),
+            });
+        }
+        let channel_number = timers.next_channel_number;
+        timers.next_channel_number += 1;
+        timers.channels.insert(
+            peer,
+            TurnChannel {
+                channel_number,
+                bound_at: None,
+                next_refresh: bind_at,
+            },
+        );
+        return Ok(channel_number);
+    }
+
+    fn bind_channel(&self, session: &TurnSession, peer: SocketAddr) -> Result<u16, STUNError> {
+        let channel_number = {
+            let mut timers = self.lock_timers();
+            match timers.channels.get(&peer) {
+                Some(channel) => channel.channel_number,
+                //Bound right here, the refresh thread only retries if that fails
+                None => match Self::new_channel(
+                    &mut timers,
+                    peer,
+                    Instant::now() + TURN_CHANNEL_BIND_RETRY_INTERVAL,
+                ) {
+                    Ok(channel_number) => channel_number,
+                    Err(e) => return Err(e),
+                },
+            }
+        };
+        let result = session.request_success(
+            STUNMessageMethod::ChannelBind,
+            vec![
+                STUNAttributesContent::new_channel_number(channel_number),
+                STUNAttributesContent::new_xor_peer_address(peer),
+            ],
+        );
+        let now = Instant::now();
+        let mut timers = self.lock_timers();
+        match result {
+            Ok(_) => {}
+            Err(e) => {
+                //Bindings that could not be refreshed before they expired are forgotten, the
+                //channel is bound again with the same number later
+                let mut expired = false;
+                match timers.channels.get_mut(&peer) {
+                    Some(channel) => match channel.bound_at {
+                        Some(bound_at) => {
+                            channel.next_refresh = now + session.retry_interval;
+                            if now.saturating_duration_since(bound_at) >= TURN_CHANNEL_LIFETIME {
+                                channel.bound_at = None;
+                                channel.next_refresh = now + TURN_CHANNEL_BIND_RETRY_INTERVAL;
+                                expired = true;
+                            }
+                        }
+                        None => channel.next_refresh = now + TURN_CHANNEL_BIND_RETRY_INTERVAL,
+                    },
+                    None => {}
+                }
+                if expired {
+                    session.lock_channel_peers().remove(&channel_number);
+                }
+                return Err(e);
+            }
+        }
+        timers.channels.insert(
+            peer,
+            TurnChannel {
+                channel_number,
+                bound_at: Some(now),
+                next_refresh: now + TURN_CHANNEL_REFRESH_INTERVAL,
+            },
+        );
+        //RFC 8656 section 12.2, a ChannelBind installs or refreshes the permission of the peer
+        timers.permissions.insert(
+            peer.ip(),
+            TurnPermission {
+                installed_at: now,
+                next_refresh: now + TURN_PERMISSION_REFRESH_INTERVAL,
+            },
+        );
+        session.lock_channel_peers().insert(channel_number, peer);
+        self.wakeup.notify_all();
+        return Ok(channel_number);
+    }
+
+    fn refresh(&self, session: &TurnSession) -> Result<Duration, STUNError> {
+        let mut attributes = Vec::new();
+        match self.requested_lifetime {
+            Some(lifetime) => attributes.push(STUNAttributesContent::new_lifetime(
+                lifetime.as_secs() as u32,
+            )),
+            None => {}
+        }
+        let response = match session.request_success(STUNMessageMethod::Refresh, attributes) {
+            Ok(response) => response,
+            Err(e) => return Err(e),
+        };
+        let mut granted = TURN_DEFAULT_LIFETIME;
+        for attribute in response.body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::Lifetime { lifetime } => {
+                    granted = Duration::from_secs(lifetime as u64)
+                }
+                _ => {}
+            }
+        }
+        let mut timers = self.lock_timers();
+        timers.granted = granted;
+        timers.expires_at = Instant::now() + granted;
+        self.wakeup.notify_all();
+        return Ok(granted);
+    }
+
+    fn refresh_loop(&self, session: &TurnSession, event_sender: Sender<TurnAllocationEvent>) {
+        let mut timers = self.lock_timers();
+        let mut refresh_at = Self::refresh_time(&timers);
+        loop {
+            if timers.released {
+                return;
+            }
+            let now = Instant::now();
+            if now >= timers.expires_at {
+                warn!("Allocation {:?} expired", self.relayed_address);
+                let _ = event_sender.send(TurnAllocationEvent::Expired);
+                return;
+            }
+            if now >= refresh_at {
+                //The lock is not held while waiting for the server
+                drop(timers);
+                let result = self.refresh(session);
+                timers = self.lock_timers();
+                match result {
+                    Ok(granted) => {
+                        debug!("Refreshed {:?} for {:?}", self.relayed_address, granted);
+                        let _ =
+                            event_sender.send(TurnAllocationEvent::Refreshed { lifetime: granted });
+                        refresh_at = Self::refresh_time(&timers);
+                    }
+                    //The server does not know the allocation anymore
+                    Err(STUNError {
+                        error_type: STUNErrorType::ErrorResponse(437),
+                        ..
+                    }) => {
+                        warn!(
+                            "Allocation {:?} is gone on the server",
+                            self.relayed_address
+                        );
+                        let _ = event_sender.send(TurnAllocationEvent::Expired);
+                        return;
+                    }
+                    Err(e) => {
+                        warn!("Error refreshing {:?}: {:?}", self.relayed_address, e);
+                        refresh_at = Instant::now() + session.retry_interval;
+                    }
+                }
+                continue;
+            }
+            let due: Vec<IpAddr> = timers
+                .permissions
+                .iter()
+                .filter(|(_, permission)| permission.next_refresh <= now)
+                .map(|(peer, _)| *peer)
+                .collect();
+            if !due.is_empty() {
+                drop(timers);
+                self.refresh_permissions(session, due);
+                timers = self.lock_timers();
+                continue;
+            }
+            let due_channel = timers
+                .channels
+                .iter()
+                .find(|(_, channel)| channel.next_refresh <= now)
+                .map(|(peer, _)| *peer);
+            match due_channel {
+                Some(peer) => {
+                    drop(timers);
+                    match self.bind_channel(session, peer) {
+                        Ok(channel_number) => {
+                            debug!("Bound channel {:#06x} to {:?}", channel_number, peer)
+                        }
+                        Err(e) => warn!("Error binding a channel to {:?}: {:?}", peer, e),
+                    }
+                    timers = self.lock_timers();
+                    continue;
+                }
+                None => {}
+            }
+            let mut wake_at = refresh_at.min(timers.expires_at);
+            for permission in timers.permissions.values() {
+                wake_at = wake_at.min(permission.next_refresh);
+            }
+            for channel in timers.channels.values() {
+                wake_at = wake_at.min(channel.next_refresh);
+            }
+            timers = match self
+                .wakeup
+                .wait_timeout(timers, wake_at.saturating_duration_since(now))
+            {
+                Ok((timers, _)) => timers,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    fn refresh_time(timers: &AllocationTimers) -> Instant {
+        let before_expiry = TURN_REFRESH_MARGIN.min(timers.granted / 2);
+        return timers.expires_at - before_expiry;
+    }
+}
+
+impl TurnSession {
+    fn lock_credentials(&self) -> MutexGuard<'_, TurnCredentials> {
+        match self.credentials.lock() {
+            Ok(credentials) => return credentials,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn lock_transactions(&self) -> MutexGuard<'_, HashMap<[u8; 12], Sender<Vec<u8>>>> {
+        match self.transactions.lock() {
+            Ok(transactions) => return transactions,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn lock_channel_peers(&self) -> MutexGuard<'_, HashMap<u16, SocketAddr>> {
+        match self.channel_peers.lock() {
+            Ok(channel_peers) => return channel_peers,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn lock_stream<'a>(&self, stream: &'a Mutex<TcpStream>) -> MutexGuard<'a, TcpStream> {
+        match stream.lock() {
+            Ok(stream) => return stream,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    //Retransmissions and ChannelData padding depend on it
+    fn is_stream(&self) -> bool {
+        return matches!(self.transport, TurnTransport::Tcp(_));
+    }
+
+    fn stop_reader(&self, reader: JoinHandle<()>) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if reader.join().is_err() {
+            error!("TURN reader thread panicked");
+        }
+    }
+
+    fn read_loop(&self) {
+        let socket = match &self.transport {
+            TurnTransport::Udp(socket) => socket,
+            TurnTransport::Tcp(stream) => {
+                //Reads time out like the UDP socket does, writes go through the mutex
+                let reader_stream = self.lock_stream(stream).try_clone();
+                match reader_stream {
+                    Ok(reader_stream) => self.read_stream_loop(reader_stream),
+                    Err(e) => error!("Error cloning TURN connection: {}", e),
+                }
+                return;
+            }
+        };
+        let mut buf = vec![0; 65536];
+        while !self.shutdown.load(Ordering::Relaxed) {
+            let (len, source) = match socket.recv_from(&mut buf) {
+                Ok(x) => x,
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    warn!("Error receiving on TURN socket: {}", e);
+                    continue;
+                }
+            };
+            if source != self.server {
+                debug!("Ignoring {} bytes from {:?}", len, source);
+                continue;
+            }
+            self.handle_from_server(&buf[..len]);
+        }
+    }
+
+    fn read_stream_loop(&self, mut stream: TcpStream) {
+        let mut reader = StunStreamReader::new();
+        while !self.shutdown.load(Ordering::Relaxed) {
+            match reader.read_frame(&mut stream) {
+                Ok(Some(message_bin)) => self.handle_from_server(&message_bin),
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("TURN connection to {:?} closed: {}", self.server, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_from_server(&self, message_bin: &[u8]) {
+        match classify_packet(message_bin) {
+            STUNPacketType::Stun => {
+                if !self.dispatch_response(message_bin, self.server) {
+                    self.deliver_indication(message_bin);
+                }
+            }
+            STUNPacketType::ChannelData => self.deliver_channel_data(message_bin),
+            packet_type => debug!("Ignoring {:?} packet from the TURN server", packet_type),
+        }
+    }
+
+    //Data indications carry what peers sent to the relayed address, ConnectionAttempt
+    //indications the peers connecting to a TCP one
+    fn deliver_indication(&self, message_bin: &[u8]) {
+        let indication = match STUN::decode(&mut Cursor::new(message_bin), &mut None) {
+            Ok(indication) => indication,
+            Err(e) => {
+                debug!("Ignoring undecodable message from the TURN server: {:?}", e);
+                return;
+            }
+        };
+        match (
+            indication.header.message_class,
+            indication.header.message_method,
+        ) {
+            (STUNMessageClass::Indication, STUNMessageMethod::Data) => {}
+            (STUNMessageClass::Indication, STUNMessageMethod::ConnectionAttempt) => {
+                self.deliver_connection_attempt(indication);
+                return;
+            }
+            _ => {
+                debug!("Ignoring unexpected {:?}", indication.header);
+                return;
+            }
+        }
+        let mut peer = None;
+        let mut data = None;
+        for attribute in indication.body.attributes {
+            match attribute.value {
+                STUNAttributesContent::XORPeerAddress { address } => peer = Some(address),
+                STUNAttributesContent::Data { data: bin } => data = Some(bin),
+                _ => {}
+            }
+        }
+        match (data, peer) {
+            (Some(data), Some(peer)) => {
+                let inbound = match self.inbound.lock() {
+                    Ok(inbound) => inbound,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                //Nobody reading anymore is fine, the data is dropped
+                let _ = inbound.send((data, peer));
+            }
+            _ => debug!("Ignoring Data indication without XOR-PEER-ADDRESS or DATA"),
+        }
+    }
+
+    fn deliver_connection_attempt(&self, indication: STUN) {
+        let mut connection_id = None;
+        let mut peer = None;
+        for attribute in indication.body.attributes {
+            match attribute.value {
+                STUNAttributesContent::ConnectionId { connection_id: id } => {
+                    connection_id = Some(id)
+                }
+                STUNAttributesContent::XORPeerAddress { address } => peer = Some(address),
+                _ => {}
+            }
+        }
+        match (connection_id, peer) {
+            (Some(connection_id), Some(peer)) => {
+                let connection_attempts = match self.connection_attempts.lock() {
+                    Ok(connection_attempts) => connection_attempts,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                let _ = connection_attempts.send((connection_id, peer));
+            }
+            _ => debug!("Ignoring ConnectionAttempt without CONNECTION-ID or XOR-PEER-ADDRESS"),
+        }
+    }
+
+    fn deliver_channel_data(&self, message_bin: &[u8]) {
+        let message = match ChannelData::decode(message_bin) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring undecodable ChannelData: {:?}", e);
+                return;
+            }
+        };
+        let peer = match self.lock_channel_peers().get(&message.channel_number) {
+            Some(peer) => *peer,
+            None => {
+                debug!(
+                    "Ignoring ChannelData on unbound channel {:#06x}",
+                    message.channel_number
+                );
+                return;
+            }
+        };
+        let inbound = match self.inbound.lock() {
+            Ok(inbound) => inbound,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let _ = inbound.send((message.data, peer));
+    }
+
+    pub(crate) fn send_to_server(&self, message_bin: &[u8]) -> Result<(), STUNError> {
+        let sent = match &self.transport {
+            TurnTransport::Udp(socket) => socket.send_to(message_bin, self.server).map(|_| ()),
+            TurnTransport::Tcp(stream) => self.lock_stream(stream).write_all(message_bin),
+        };
+        match sent {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::ErrorSendingMessageToServer,
+                    message: "Error sending to TURN server: ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+    }
+
+    //Hands a response to the transaction waiting for it, false if nobody is
+    pub(crate) fn dispatch_response(&self, message_bin: &[u8], source: SocketAddr) -> bool {
+        if source != self.server || classify_packet(message_bin) != STUNPacketType::Stun {
+            return false;
+        }
+        let mut transaction_id = [0; 12];
+        transaction_id.copy_from_slice(&message_bin[8..20]);
+        match self.lock_transactions().get(&transaction_id) {
+            Some(sender) => return sender.send(message_bin.to_vec()).is_ok(),
+            None => return false,
+        }
+    }
+
+    //Sends a request and turns error responses into `STUNErrorType::ErrorResponse`
+    pub(crate) fn request_success(
+        &self,
+        method: STUNMessageMethod,
+        attributes: Vec<STUNAttributesContent>,
+    ) -> Result<STUN, STUNError> {
+        match self.request(method, attributes) {
+            Ok(response) => return Self::success_of(method, response),
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn success_of(method: STUNMessageMethod, response: STUN) -> Result<STUN, STUNError> {
+        if response.header.message_class == STUNMessageClass::ResponseSuccess {
+            return Ok(response);
+        }
+        let (code, reason) = match error_code_of(&response) {
+            Some(error) => error,
+            None => (0, String::new()),
+        };
+        return Err(STUNError {
+            step: STUNStep::TURNClient,
+            error_type: STUNErrorType::ErrorResponse(code),
+            message: format!("{:?} failed: {} {}", method, code, reason),
+        });
+    }
+
+    //Long-term authenticated request. The first one is sent without credentials to learn the
+    //realm and nonce from the 401, a stale nonce (438) is replaced and the request sent again.
+    pub(crate) fn request(
+        &self,
+        method: STUNMessageMethod,
+        attributes: Vec<STUNAttributesContent>,
+    ) -> Result<STUN, STUNError> {
+        return self.request_with(method, attributes, |request, context| {
+            self.transact(request, context)
+        });
+    }
+
+    //`request` with the request/response exchange done by `transact`
+    fn request_with(
+        &self,
+        method: STUNMessageMethod,
+        attributes: Vec<STUNAttributesContent>,
+        mut transact: impl FnMut(STUN, STUNContext) -> Result<STUN, STUNError>,
+    ) -> Result<STUN, STUNError> {
+        let mut challenged = false;
+        loop {
+            let (request, context) = self.authenticated_request(method, &attributes);
+            let authenticated = context.realm.is_some();
+            let response = match transact(request, context) {
+                Ok(response) => response,
+                Err(e) => return Err(e),
+            };
+            let code = match error_code_of(&response) {
+                Some((code, _)) => code,
+                None => return Ok(response),
+            };
+            let retry = (code == STUNErrorCode::Unauthorized as u16 && !authenticated)
+                || code == STUNErrorCode::StaleNonce as u16;
+            if !retry || challenged {
+                return Ok(response);
+            }
+            challenged = true;
+            let mut credentials = self.lock_credentials();
+            for attribute in response.body.attributes.iter() {
+                match &attribute.value {
+                    STUNAttributesContent::Realm { realm } => credentials.realm = realm.clone(),
+                    STUNAttributesContent::Nonce { nonce } => credentials.nonce = nonce.clone(),
+                    _ => {}
+                }
+            }
+            if credentials.realm.is_none() || credentials.nonce.is_none() {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn authenticated_request(
+        &self,
+        method: STUNMessageMethod,
+        attributes: &[STUNAttributesContent],
+    ) -> (STUN, STUNContext) {
+        let credentials = self.lock_credentials();
+        let mut context = STUNContext::new();
+        context.username = Some(credentials.username.clone());
+        context.password = Some(credentials.password.clone());
+        context.realm = credentials.realm.clone();
+        context.nonce = credentials.nonce.clone();
+
+        let mut request = STUN::new_default(STUNMessageClass::Request, method, None);
+        let mut all_attributes = attributes.to_vec();
+        match self.software {
+            Some(software) => {
+                all_attributes.push(STUNAttributesContent::new_software(software.to_string()))
+            }
+            None => {}
+        }
+        match (&credentials.realm, &credentials.nonce) {
+            (Some(realm), Some(nonce)) => {
+                all_attributes.push(STUNAttributesContent::new_username(
+                    credentials.username.clone(),
+                ));
+                all_attributes.push(STUNAttributesContent::new_realm(realm.clone()));
+                all_attributes.push(STUNAttributesContent::new_nonce(Some(nonce.clone())));
+                all_attributes.push(STUNAttributesContent::MessageIntegrity {
+                    authType: STUNAuthType::LongTerm,
+                });
+            }
+            _ => {}
+        }
+        for attribute in all_attributes {
+            let attribute_type = attribute.attribute_type();
+            request.body.add_new_attribute(attribute, attribute_type, 0);
+        }
+        return (request, context);
+    }
+
+    //Sends `request` until a response arrives or the timeout passes. Responses to
+    //authenticated requests have to carry valid MESSAGE-INTEGRITY, except for the errors sent
+    //before the credentials are checked.
+    fn transact(&self, request: STUN, context: STUNContext) -> Result<STUN, STUNError> {
+        let transaction_id = request.header.transaction_id;
+        let (request_bin, key) = match Self::encode_request(request, &context) {
+            Ok(encoded) => encoded,
+            Err(e) => return Err(e),
+        };
+
+        let (sender, receiver) = channel();
+        self.lock_transactions().insert(transaction_id, sender);
+        let result = self.send_until_response(&request_bin, &receiver, &key);
+        self.lock_transactions().remove(&transaction_id);
+        return result;
+    }
+
+    //The request and the key its response has to be signed with
+    fn encode_request(
+        request: STUN,
+        context: &STUNContext,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>), STUNError> {
+        let mut request_bin = Vec::new();
+        match request.encode(&mut Cursor::new(&mut request_bin), &Some(context)) {
+            Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        match (&context.realm, &context.username, &context.password) {
+            (Some(realm), Some(username), Some(password)) => {
+                match STUNAttributesContent::long_term_key(
+                    username.clone(),
+                    realm.clone(),
+                    password.clone(),
+                ) {
+                    Ok(key) => return Ok((request_bin, Some(key))),
+                    Err(e) => return Err(e),
+                }
+            }
+            _ => return Ok((request_bin, None)),
+        }
+    }
+
+    //RFC 6062 section 4.4, a new connection to the server joined to the peer connection
+    //`connection_id` by a ConnectionBind. Once bound, it only carries data to and from the peer.
+    fn bind_connection(&self, connection_id: u32) -> Result<TcpStream, STUNError> {
+        let mut stream = match TcpStream::connect_timeout(&self.server, self.timeout) {
+            Ok(stream) => stream,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::ErrorSendingMessageToServer,
+                    message: "Error opening TURN data connection: ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        match stream.set_read_timeout(Some(self.timeout)) {
+            Ok(()) => {}
+            Err(e) => return Err(TurnClient::read_timeout_error(e)),
+        }
+        let response = match self.request_with(
+            STUNMessageMethod::ConnectionBind,
+            vec![STUNAttributesContent::new_connection_id(connection_id)],
+            |request, context| Self::transact_on_stream(&mut stream, request, context),
+        ) {
+            Ok(response) => response,
+            Err(e) => return Err(e),
+        };
+        match Self::success_of(STUNMessageMethod::ConnectionBind, response) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        match stream.set_read_timeout(None) {
+            Ok(()) => return Ok(stream),
+            Err(e) => return Err(TurnClient::read_timeout_error(e)),
+        }
+    }
+
+    //One request on a connection of its own, read back without going past the response
+    fn transact_on_stream(
+        stream: &mut TcpStream,
+        request: STUN,
+        context: STUNContext,
+    ) -> Result<STUN, STUNError> {
+        let (request_bin, key) = match Self::encode_request(request, &context) {
+            Ok(encoded) => encoded,
+            Err(e) => return Err(e),
+        };
+        match stream.write_all(&request_bin) {
+            Ok(()) => {}
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::ErrorSendingMessageToServer,
+                    message: "Error sending to TURN server: ".to_string() + e.to_string().as_str(),
+                })
+            }
+        }
+        let response_bin = match read_frame_exact(stream) {
+            Ok(response_bin) => response_bin,
+            Err(e) => {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::ErrorReceivingFromServer,
+                    message: "Error receiving from TURN server: ".to_string()
+                        + e.to_string().as_str(),
+                })
+            }
+        };
+        match Self::verified_response(&response_bin, &key) {
+            Some(response) => return Ok(response),
+            None => {
+                return Err(STUNError {
+                    step: STUNStep::TURNClient,
+                    error_type: STUNErrorType::MessageIntegrityMismatch,
+                    message: "Invalid ConnectionBind response".to_string(),
+                })
+            }
+        }
+    }
+
+    fn send_until_response(
+        &self,
+        request_bin: &[u8],
+        receiver: &Receiver<Vec<u8>>,
+        key: &Option<Vec<u8>>,
+    ) -> Result<STUN, STUNError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(STUNError {
+                    step: STUNStep::STUNNetwork,
+                    error_type: STUNErrorType::NetworkTimeoutError,
+                    message: "Network timed out waiting for TURN response".to_string(),
+                });
+            }
+            match self.send_to_server(request_bin) {
+                Ok(()) => {}
+                Err(e) => return Err(e),
+            }
+            //Streams are reliable, requests are only sent once (RFC 8489 section 6.2.2)
+            let retransmit_at = match self.is_stream() {
+                true => deadline,
+                false => (now + self.retry_interval).min(deadline),
+            };
+            loop {
+                let response_bin = match receiver
+                    .recv_timeout(retransmit_at.saturating_duration_since(Instant::now()))
+                {
+                    Ok(bin) => bin,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(STUNError {
+                            step: STUNStep::TURNClient,
+                            error_type: STUNErrorType::InternalError,
+                            message: "Transaction was dropped".to_string(),
+                        })
+                    }
+                };
+                match Self::verified_response(&response_bin, key) {
+                    Some(response) => return Ok(response),
+                    None => continue,
+                }
+            }
+        }
+    }
+
+    fn verified_response(response_bin: &[u8], key: &Option<Vec<u8>>) -> Option<STUN> {
+        let mut context = STUNContext::new();
+        context.defer_integrity_check = true;
+        let response = match STUN::decode(&mut Cursor::new(response_bin), &mut Some(&mut context)) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Ignoring undecodable TURN response: {:?}", e);
+                return None;
+            }
+        };
+        let key = match key {
+            Some(key) => key,
+            None => return Some(response),
+        };
+        //RFC 8489 section 9.2.5, these come before the server could check the credentials
+        match error_code_of(&response) {
+            Some((code, _))
+                if code == STUNErrorCode::BadRequest as u16
+                    || code == STUNErrorCode::Unauthorized as u16
+                    || code == STUNErrorCode::UnknownAttribute as u16
+                    || code == STUNErrorCode::StaleNonce as u16 =>
+            {
+                return Some(response)
+            }
+            _ => {}
+        }
+        match context.received_integrity {
+            Some(integrity) if integrity.verify(key) => return Some(response),
+            _ => {
+                warn!("Ignoring TURN response without valid MESSAGE-INTEGRITY");
+                return None;
+            }
+        }
+    }
+}
+
+fn error_code_of(response: &STUN) -> Option<(u16, String)> {
+    if response.header.message_class != STUNMessageClass::ResponseError {
+        return None;
+    }
+    for attribute in response.body.attributes.iter() {
+        match &attribute.value {
+            STUNAttributesContent::ErrorCode { code, reason } => {
+                return Some((*code, reason.clone()))
+            }
+            _ => continue,
+        }
+    }
+    return Some((0, String::new()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::relay::TurnRelayedSocket;
+    use super::*;
+    use crate::STUNBody::attributes::attributes::STUNAttributeType;
+    use std::sync::atomic::AtomicUsize;
+
+    const TEST_REALM: &str = "example.org";
+    const TEST_NONCE: &str = "test-nonce";
+
+    //Just enough of a TURN server to allocate, refresh and release. Peers echo whatever is
+    //sent to them, on the channel it was sent on.
+    struct FakeTurnServer {
+        address: SocketAddr,
+        refreshes: Arc<AtomicUsize>,
+        permissions: Arc<AtomicUsize>,
+        channel_binds: Arc<AtomicUsize>,
+        channel_data: Arc<AtomicUsize>,
+        released: Arc<AtomicBool>,
+    }
+
+    fn response_to(
+        request: &STUN,
+        class: STUNMessageClass,
+        attributes: Vec<STUNAttributesContent>,
+    ) -> STUN {
+        let mut response = STUN::new_default(
+            class,
+            request.header.message_method,
+            Some(request.header.transaction_id),
+        );
+        for attribute in attributes {
+            let attribute_type = attribute.attribute_type();
+            response
+                .body
+                .add_new_attribute(attribute, attribute_type, 0);
+        }
+        return response;
+    }
+
+    fn spawn_fake_turn_server(lifetime: u32) -> FakeTurnServer {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let permissions = Arc::new(AtomicUsize::new(0));
+        let channel_binds = Arc::new(AtomicUsize::new(0));
+        let channel_data = Arc::new(AtomicUsize::new(0));
+        let released = Arc::new(AtomicBool::new(false));
+        let thread_refreshes = refreshes.clone();
+        let thread_permissions = permissions.clone();
+        let thread_channel_binds = channel_binds.clone();
+        let thread_channel_data = channel_data.clone();
+        let thread_released = released.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 1500];
+            loop {
+                let (len, source) = match socket.recv_from(&mut buf) {
+                    Ok(x) => x,
+                    Err(_) => return,
+                };
+                if classify_packet(&buf[..len]) == STUNPacketType::ChannelData {
+                    thread_channel_data.fetch_add(1, Ordering::Relaxed);
+                    let _ = socket.send_to(&buf[..len], source);
+                    continue;
+                }
+                let mut context = STUNContext::new();
+                context.password = Some("secret".to_string());
+                let request =
+                    match STUN::decode(&mut Cursor::new(&buf[..len]), &mut Some(&mut context)) {
+                        Ok(request) => request,
+                        Err(_) => continue,
+                    };
+                if request.header.message_class == STUNMessageClass::Indication {
+                    let mut data_indication = STUN::new_default(
+                        STUNMessageClass::Indication,
+                        STUNMessageMethod::Data,
+                        None,
+                    );
+                    for attribute in request.body.attributes {
+                        data_indication.body.add_new_attribute(
+                            attribute.value,
+                            attribute.attribute_type,
+                            0,
+                        );
+                    }
+                    let mut indication_bin = Vec::new();
+                    data_indication
+                        .encode(&mut Cursor::new(&mut indication_bin), &None)
+                        .unwrap();
+                    let _ = socket.send_to(&indication_bin, source);
+                    continue;
+                }
+                let authenticated = request
+                    .body
+                    .attributes
+                    .iter()
+                    .any(|x| x.attribute_type == STUNAttributeType::Nonce);
+                let response = if !authenticated {
+                    response_to(
+                        &request,
+                        STUNMessageClass::ResponseError,
+                        vec![
+                            STUNAttributesContent::new_error_code(STUNErrorCode::Unauthorized),
+                            STUNAttributesContent::new_realm(TEST_REALM.to_string()),
+                            STUNAttributesContent::new_nonce(Some(TEST_NONCE.to_string())),
+                        ],
+                    )
+                } else {
+                    let mut attributes = Vec::new();
+                    match request.header.message_method {
+                        STUNMessageMethod::Allocate => {
+                            attributes.push(STUNAttributesContent::new_xor_relayed_address(
+                                "192.0.2.15:50000".parse().unwrap(),
+                            ));
+                            attributes.push(STUNAttributesContent::new_xor_mapped_address(source));
+                            attributes.push(STUNAttributesContent::new_lifetime(lifetime));
+                        }
+                        STUNMessageMethod::CreatePermission => {
+                            thread_permissions.fetch_add(1, Ordering::Relaxed);
+                        }
+                        STUNMessageMethod::ChannelBind => {
+                            thread_channel_binds.fetch_add(1, Ordering::Relaxed);
+                        }
+                        _ => {
+                            let requested =
+                                request.body.attributes.iter().find_map(|x| match x.value {
+                                    STUNAttributesContent::Lifetime { lifetime } => Some(lifetime),
+                                    _ => None,
+                                });
+                            if requested == Some(0) {
+                                thread_released.store(true, Ordering::Relaxed);
+                                attributes.push(STUNAttributesContent::new_lifetime(0));
+                            } else {
+                                thread_refreshes.fetch_add(1, Ordering::Relaxed);
+                                attributes.push(STUNAttributesContent::new_lifetime(lifetime));
+                            }
+                        }
+                    }
+                    attributes.push(STUNAttributesContent::MessageIntegrity {
+                        authType: STUNAuthType::LongTerm,
+                    });
+                    response_to(&request, STUNMessageClass::ResponseSuccess, attributes)
+                };
+                let mut response_bin = Vec::new();
+                response
+                    .encode(&mut Cursor::new(&mut response_bin), &Some(&context))
+                    .unwrap();
+                let _ = socket.send_to(&response_bin, source);
+            }
+        });
+        return FakeTurnServer {
+            address,
+            refreshes,
+            permissions,
+            channel_binds,
+            channel_data,
+            released,
+        };
+    }
+
+    fn turn_client(server: SocketAddr, password: &str) -> TurnClient {
+        let mut client = StunClient::new(server);
+        client
+            .set_timeout(Duration::from_secs(2))
+            .set_retry_interval(Duration::from_millis(200));
+        return TurnClient::new(client, "user".to_string(), password.to_string());
+    }
+
+    #[test]
+    fn test_allocate_refresh_release() {
+        let server = spawn_fake_turn_server(1);
+        let allocation = turn_client(server.address, "secret")
+            .allocate(UdpSocket::bind("127.0.0.1:0").unwrap())
+            .unwrap();
+        assert_eq!(
+            allocation.relayed_address(),
+            "192.0.2.15:50000".parse().unwrap()
+        );
+        assert_eq!(
+            allocation.mapped_address(),
+            Some(allocation.local_addr().unwrap())
+        );
+        assert_eq!(allocation.lifetime(), Duration::from_secs(1));
+
+        //Refreshed half way through the one second lifetime
+        assert_eq!(
+            allocation.events().recv_timeout(Duration::from_secs(3)),
+            Ok(TurnAllocationEvent::Refreshed {
+                lifetime: Duration::from_secs(1)
+            })
+        );
+        assert!(server.refreshes.load(Ordering::Relaxed) >= 1);
+        allocation.release().unwrap();
+        assert!(server.released.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_allocate_with_wrong_password() {
+        let server = spawn_fake_turn_server(600);
+        //The server drops requests it cannot verify, the client gives up after its timeout
+        match turn_client(server.address, "not-the-secret")
+            .allocate(UdpSocket::bind("127.0.0.1:0").unwrap())
+        {
+            Ok(_) => panic!("Allocated with the wrong password"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::NetworkTimeoutError),
+        }
+    }
+
+    #[test]
+    fn test_bind_channel() {
+        let server = spawn_fake_turn_server(600);
+        let allocation = turn_client(server.address, "secret")
+            .allocate(UdpSocket::bind("127.0.0.1:0").unwrap())
+            .unwrap();
+        let peer: SocketAddr = "192.0.2.40:6000".parse().unwrap();
+        let other_peer: SocketAddr = "192.0.2.40:6001".parse().unwrap();
+        match allocation.send_channel_data(b"too early", peer) {
+            Ok(()) => panic!("Sent on a channel that is not bound"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ChannelNotBound),
+        }
+
+        assert_eq!(allocation.bind_channel(peer).unwrap(), 0x4000);
+        //Channels are per transport address, refreshing keeps the number
+        assert_eq!(allocation.bind_channel(other_peer).unwrap(), 0x4001);
+        assert_eq!(allocation.bind_channel(peer).unwrap(), 0x4000);
+        assert_eq!(server.channel_binds.load(Ordering::Relaxed), 3);
+        //Binding installed the permission without a CreatePermission
+        assert!(allocation.has_permission(peer.ip()));
+        assert_eq!(server.permissions.load(Ordering::Relaxed), 0);
+
+        allocation.send_channel_data(b"hello", other_peer).unwrap();
+        assert_eq!(
+            allocation
+                .inbound
+                .lock()
+                .unwrap()
+                .recv_timeout(Duration::from_secs(2)),
+            Ok((b"hello".to_vec(), other_peer))
+        );
+        allocation.release().unwrap();
+    }
+
+    #[test]
+    fn test_relayed_socket_send_and_receive() {
+        let server = spawn_fake_turn_server(600);
+        let socket = TurnRelayedSocket::new(
+            turn_client(server.address, "secret")
+                .allocate(UdpSocket::bind("127.0.0.1:0").unwrap())
+                .unwrap(),
+        );
+        assert_eq!(
+            socket.local_addr().unwrap(),
+            "192.0.2.15:50000".parse().unwrap()
+        );
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let peer: SocketAddr = "192.0.2.40:6000".parse().unwrap();
+        assert!(!socket.allocation().has_permission(peer.ip()));
+
+        let mut buf = [0; 64];
+        for datagram in [&b"hello peer"[..], &b"again"[..]] {
+            assert_eq!(socket.send_to(datagram, peer).unwrap(), datagram.len());
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], datagram);
+            assert_eq!(from, peer);
+        }
+        //The permission was installed once, on the first datagram
+        assert!(socket.allocation().has_permission(peer.ip()));
+        assert_eq!(server.permissions.load(Ordering::Relaxed), 1);
+
+        //The channel asked for on the first datagram takes over once bound
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while socket.allocation().channel_number(peer).is_none() {
+            assert!(Instant::now() < deadline, "Channel was never bound");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            socket.allocation().channel_number(peer),
+            Some(TURN_CHANNEL_NUMBER_MIN)
+        );
+        assert_eq!(server.channel_binds.load(Ordering::Relaxed), 1);
+        //"again" may already have gone over the channel
+        let channel_data = server.channel_data.load(Ordering::Relaxed);
+        socket.send_to(b"over the channel", peer).unwrap();
+        let (len, from) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..len], from), (&b"over the channel"[..], peer));
+        assert_eq!(
+            server.channel_data.load(Ordering::Relaxed),
+            channel_data + 1
+        );
+
+        //Truncated like UDP
+        socket.send_to(&[7; 32], peer).unwrap();
+        let mut small_buf = [0; 8];
+        assert_eq!(socket.recv_from(&mut small_buf).unwrap(), (8, peer));
+
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(
+            socket.recv_from(&mut buf).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        socket.release().unwrap();
+    }
+}
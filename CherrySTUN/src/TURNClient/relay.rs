@@ -0,0 +1,116 @@
+//A TURN allocation used like a UDP socket bound to the relayed address. Permissions are
+//installed on the first packet to a peer and datagrams travel in Send/Data indications until a
+//channel, asked for on that first packet, is bound to the peer. From then on they travel in
+//ChannelData messages.
+use super::client::TurnAllocation;
+use crate::STUNError::error::STUNError;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub struct TurnRelayedSocket {
+    allocation: TurnAllocation,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl TurnRelayedSocket {
+    pub fn new(allocation: TurnAllocation) -> Self {
+        TurnRelayedSocket {
+            allocation,
+            read_timeout: Mutex::new(None),
+        }
+    }
+
+    pub fn allocation(&self) -> &TurnAllocation {
+        return &self.allocation;
+    }
+
+    /// The relayed address, which is what peers see as our address
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        return Ok(self.allocation.relayed_address());
+    }
+
+    /// Sends `buf` to `peer` through the TURN server, creating a permission for the peer first
+    /// if there is none
+    pub fn send_to(&self, buf: &[u8], peer: SocketAddr) -> io::Result<usize> {
+        if self.allocation.channel_number(peer).is_some() {
+            match self.allocation.send_channel_data(buf, peer) {
+                Ok(()) => return Ok(buf.len()),
+                Err(e) => return Err(Self::to_io_error(e)),
+            }
+        }
+        self.allocation.request_channel_bind(peer);
+        if !self.allocation.has_permission(peer.ip()) {
+            match self.allocation.create_permission(&[peer.ip()]) {
+                Ok(()) => {}
+                Err(e) => return Err(Self::to_io_error(e)),
+            }
+        }
+        match self.allocation.send_indication(buf, peer) {
+            Ok(()) => return Ok(buf.len()),
+            Err(e) => return Err(Self::to_io_error(e)),
+        }
+    }
+
+    /// Receives a datagram relayed from a peer. Like `UdpSocket`, datagrams larger than `buf`
+    /// are truncated and a read timeout ends in `WouldBlock`.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let read_timeout = match self.read_timeout.lock() {
+            Ok(read_timeout) => *read_timeout,
+            Err(poisoned) => *poisoned.into_inner(),
+        };
+        let inbound = match self.allocation.inbound.lock() {
+            Ok(inbound) => inbound,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let received = match read_timeout {
+            Some(timeout) => inbound.recv_timeout(timeout),
+            None => inbound.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok((data, peer)) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok((len, peer));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "No relayed data before the read timeout",
+                ))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "The TURN allocation is closed",
+                ))
+            }
+        }
+    }
+
+    /// Same as `UdpSocket::set_read_timeout`, `None` blocks until data arrives
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Zero duration is not a valid timeout",
+            ));
+        }
+        match self.read_timeout.lock() {
+            Ok(mut read_timeout) => *read_timeout = timeout,
+            Err(poisoned) => *poisoned.into_inner() = timeout,
+        }
+        return Ok(());
+    }
+
+    /// Deletes the allocation on the server
+    pub fn release(self) -> Result<(), STUNError> {
+        return self.allocation.release();
+    }
+
+    fn to_io_error(error: STUNError) -> io::Error {
+        return io::Error::new(io::ErrorKind::Other, error.to_string());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod policy;
+pub mod quota;
+pub mod server;
+mod tcp;
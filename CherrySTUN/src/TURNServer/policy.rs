@@ -0,0 +1,264 @@
+//Which peers a TURN server relays to. Without it, anyone with credentials could use the relay
+//to reach hosts on the network of the server, like its loopback interface or private
+//addresses. Checked on CreatePermission and ChannelBind, refused peers get 403 (Forbidden).
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+//Loopback, link-local, RFC 1918 and their IPv6 counterparts (RFC 4193 unique local addresses),
+//"this network" (RFC 6890), carrier-grade NAT (RFC 6598) and multicast. NAT64 addresses are
+//checked as the IPv4 address they embed.
+const TURN_DEFAULT_DENIED_PEERS: [&str; 12] = [
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "0.0.0.0/8",
+    "100.64.0.0/10",
+    "224.0.0.0/4",
+    "::1/128",
+    "fe80::/10",
+    "fc00::/7",
+    "ff00::/8",
+];
+
+/// An IP prefix such as `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+/// Allow and deny lists of peer addresses. Peers in the allow list are relayed to even when in
+/// the deny list, so that single hosts can be opened in a denied range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnPeerPolicy {
+    pub allow: Vec<IpCidr>,
+    pub deny: Vec<IpCidr>,
+}
+
+impl IpCidr {
+    /// Fails when `prefix_len` is longer than the address
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Self, STUNError> {
+        let max_prefix_len = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(STUNError {
+                step: STUNStep::TURNServer,
+                error_type: STUNErrorType::InvalidConfiguration,
+                message: format!("Prefix length {} too long for {}", prefix_len, address),
+            });
+        }
+        return Ok(IpCidr {
+            address,
+            prefix_len,
+        });
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, Self::canonical(ip)) {
+            (IpAddr::V4(prefix), IpAddr::V4(ip)) => {
+                return Self::same_prefix(&prefix.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(prefix), IpAddr::V6(ip)) => {
+                return Self::same_prefix(&prefix.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => return false,
+        }
+    }
+
+    //IPv4-mapped IPv6 addresses reach the IPv4 host, so they are checked as IPv4. So are the
+    //addresses of the NAT64 well-known prefix 64:ff9b::/96 (RFC 6052), translated to the IPv4
+    //address in their last 32 bits.
+    fn canonical(ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V6(v6) => {
+                match v6.to_ipv4_mapped() {
+                    Some(v4) => return IpAddr::V4(v4),
+                    None => {}
+                }
+                let octets = v6.octets();
+                if octets[..12] == [0, 0x64, 0xff, 0x9b, 0, 0, 0, 0, 0, 0, 0, 0] {
+                    return IpAddr::V4(Ipv4Addr::new(
+                        octets[12], octets[13], octets[14], octets[15],
+                    ));
+                }
+                return ip;
+            }
+            IpAddr::V4(_) => return ip,
+        }
+    }
+
+    fn same_prefix(prefix: &[u8], ip: &[u8], prefix_len: u8) -> bool {
+        let full_bytes = (prefix_len / 8) as usize;
+        if prefix[..full_bytes] != ip[..full_bytes] {
+            return false;
+        }
+        let remaining_bits = prefix_len % 8;
+        if remaining_bits == 0 {
+            return true;
+        }
+        let mask = 0xffu8 << (8 - remaining_bits);
+        return prefix[full_bytes] & mask == ip[full_bytes] & mask;
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = STUNError;
+
+    /// `address/prefix_len`, or a single address
+    fn from_str(cidr: &str) -> Result<Self, Self::Err> {
+        let invalid = || STUNError {
+            step: STUNStep::TURNServer,
+            error_type: STUNErrorType::InvalidConfiguration,
+            message: format!("Invalid CIDR {:?}", cidr),
+        };
+        let (address, prefix_len) = match cidr.split_once('/') {
+            Some((address, prefix_len)) => match prefix_len.parse::<u8>() {
+                Ok(prefix_len) => (address, Some(prefix_len)),
+                Err(_) => return Err(invalid()),
+            },
+            None => (cidr, None),
+        };
+        let address = match address.parse::<IpAddr>() {
+            Ok(address) => address,
+            Err(_) => return Err(invalid()),
+        };
+        let prefix_len = match (prefix_len, address) {
+            (Some(prefix_len), _) => prefix_len,
+            (None, IpAddr::V4(_)) => 32,
+            (None, IpAddr::V6(_)) => 128,
+        };
+        return Self::new(address, prefix_len);
+    }
+}
+
+impl Default for TurnPeerPolicy {
+    /// Denies loopback, link-local and private addresses
+    fn default() -> Self {
+        let deny = TURN_DEFAULT_DENIED_PEERS
+            .iter()
+            .filter_map(|x| x.parse().ok())
+            .collect();
+        TurnPeerPolicy {
+            allow: Vec::new(),
+            deny,
+        }
+    }
+}
+
+impl TurnPeerPolicy {
+    /// The default policy, denying loopback, link-local and private addresses
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// A policy relaying to any unicast address
+    pub fn allow_all() -> Self {
+        TurnPeerPolicy {
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+
+    /// Add a prefix to the allow list, builder pattern.
+    pub fn add_allow(&mut self, cidr: IpCidr) -> &mut Self {
+        self.allow.push(cidr);
+        self
+    }
+
+    /// Add a prefix to the deny list, builder pattern.
+    pub fn add_deny(&mut self, cidr: IpCidr) -> &mut Self {
+        self.deny.push(cidr);
+        self
+    }
+
+    pub fn is_allowed(&self, peer: IpAddr) -> bool {
+        if self.allow.iter().any(|x| x.contains(peer)) {
+            return true;
+        }
+        //Unspecified and broadcast addresses are never meaningful peers
+        match IpCidr::canonical(peer) {
+            IpAddr::V4(ip) if ip == Ipv4Addr::UNSPECIFIED || ip == Ipv4Addr::BROADCAST => {
+                return false
+            }
+            IpAddr::V6(ip) if ip == Ipv6Addr::UNSPECIFIED => return false,
+            _ => {}
+        }
+        return !self.deny.iter().any(|x| x.contains(peer));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cidr() {
+        let cidr: IpCidr = "172.16.0.0/12".parse().unwrap();
+        assert!(cidr.contains("172.16.0.1".parse().unwrap()));
+        assert!(cidr.contains("172.31.255.255".parse().unwrap()));
+        assert!(!cidr.contains("172.32.0.1".parse().unwrap()));
+        assert!(!cidr.contains("::1".parse().unwrap()));
+        assert!(cidr.contains("::ffff:172.20.1.1".parse().unwrap()));
+
+        let host: IpCidr = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.prefix_len, 128);
+        assert!(host.contains("2001:db8::1".parse().unwrap()));
+        assert!(!host.contains("2001:db8::2".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<IpCidr>()
+            .unwrap()
+            .contains("198.51.100.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("10.0.0/8".parse::<IpCidr>().is_err());
+        assert!("10.0.0.0/".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let mut policy = TurnPeerPolicy::default();
+        for denied in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.5.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "0.0.0.0",
+            "0.1.2.3",
+            "100.64.0.1",
+            "100.127.255.254",
+            "224.0.0.251",
+            "239.255.255.250",
+            "ff02::1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!policy.is_allowed(denied.parse().unwrap()), "{}", denied);
+        }
+        for allowed in [
+            "198.51.100.1",
+            "172.32.0.1",
+            "100.128.0.1",
+            "2001:db8::1",
+            "64:ff9b::c633:6401",
+        ] {
+            assert!(policy.is_allowed(allowed.parse().unwrap()), "{}", allowed);
+        }
+
+        policy
+            .add_allow("192.168.1.10".parse().unwrap())
+            .add_deny("198.51.100.0/24".parse().unwrap());
+        assert!(policy.is_allowed("192.168.1.10".parse().unwrap()));
+        assert!(!policy.is_allowed("192.168.1.11".parse().unwrap()));
+        assert!(!policy.is_allowed("198.51.100.1".parse().unwrap()));
+        assert!(TurnPeerPolicy::allow_all().is_allowed("127.0.0.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,259 @@
+//Limits on what a TURN server hands out, per user and in total, and counters of what each
+//allocation relayed for billing and abuse review.
+//
+//Allocation counts are checked on Allocate: going over the limit of a user is answered with 486
+//(Allocation Quota Reached), over the total with 508 (Insufficient Capacity). Lifetimes cap how
+//long an allocation may live whatever the refreshes. Bandwidth is enforced with token buckets of
+//bytes per user and in total, datagrams over the budget are dropped and TCP connections slowed
+//down.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+//Buckets of users without an allocation are forgotten above this many
+const TURN_QUOTA_MAX_TRACKED_USERS: usize = 65536;
+
+/// Limits that apply to one user or to the whole server, `None` is unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TurnQuotaLimits {
+    pub allocations: Option<usize>,
+    /// Bytes relayed per second in both directions, with bursts of up to a second of it
+    pub bytes_per_second: Option<u64>,
+    /// How long an allocation may live, refreshes can't extend it past this
+    pub lifetime: Option<Duration>,
+}
+
+/// Per user and total limits of a TURN server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TurnQuota {
+    pub per_user: TurnQuotaLimits,
+    pub total: TurnQuotaLimits,
+}
+
+/// Counters of an allocation, only traffic between the client and its peers is counted
+#[derive(Debug, Default)]
+pub struct TurnAllocationCounters {
+    pub bytes_to_peers: AtomicU64,
+    pub packets_to_peers: AtomicU64,
+    pub bytes_from_peers: AtomicU64,
+    pub packets_from_peers: AtomicU64,
+    /// Datagrams dropped as the bandwidth quota was used up
+    pub dropped_over_quota: AtomicU64,
+}
+
+/// Plain copy of `TurnAllocationCounters`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TurnAllocationCountersSnapshot {
+    pub bytes_to_peers: u64,
+    pub packets_to_peers: u64,
+    pub bytes_from_peers: u64,
+    pub packets_from_peers: u64,
+    pub dropped_over_quota: u64,
+}
+
+/// What `TurnServerHandle::allocations` reports about a live allocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnAllocationInfo {
+    pub username: String,
+    pub client: SocketAddr,
+    /// `TURN_TRANSPORT_UDP` or `TURN_TRANSPORT_TCP`, between the client and the server
+    pub client_transport: u8,
+    pub relayed_address: SocketAddr,
+    /// Relayed address of the other family, for ADDITIONAL-ADDRESS-FAMILY
+    pub additional_relayed_address: Option<SocketAddr>,
+    /// Time since the Allocate
+    pub age: Duration,
+    pub counters: TurnAllocationCountersSnapshot,
+}
+
+//Byte budgets of the users and of the server
+pub(super) struct TurnBandwidth {
+    per_user: Mutex<HashMap<String, ByteBucket>>,
+    total: Mutex<Option<ByteBucket>>,
+}
+
+//Tokens may go negative: a packet passes when there is any budget left and the debt is paid
+//before the next one, so packets larger than the rate still get through
+struct ByteBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TurnQuotaLimits {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Set `allocations` field, builder pattern.
+    pub fn set_allocations(&mut self, allocations: Option<usize>) -> &mut Self {
+        self.allocations = allocations;
+        self
+    }
+
+    /// Set `bytes_per_second` field, builder pattern.
+    pub fn set_bytes_per_second(&mut self, bytes_per_second: Option<u64>) -> &mut Self {
+        self.bytes_per_second = bytes_per_second;
+        self
+    }
+
+    /// Set `lifetime` field, builder pattern.
+    pub fn set_lifetime(&mut self, lifetime: Option<Duration>) -> &mut Self {
+        self.lifetime = lifetime;
+        self
+    }
+}
+
+impl TurnQuota {
+    /// Longest an allocation may live, `None` when neither limit sets one
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        match (self.per_user.lifetime, self.total.lifetime) {
+            (Some(user), Some(total)) => return Some(user.min(total)),
+            (user, total) => return user.or(total),
+        }
+    }
+}
+
+impl TurnAllocationCounters {
+    pub fn snapshot(&self) -> TurnAllocationCountersSnapshot {
+        return TurnAllocationCountersSnapshot {
+            bytes_to_peers: self.bytes_to_peers.load(Ordering::Relaxed),
+            packets_to_peers: self.packets_to_peers.load(Ordering::Relaxed),
+            bytes_from_peers: self.bytes_from_peers.load(Ordering::Relaxed),
+            packets_from_peers: self.packets_from_peers.load(Ordering::Relaxed),
+            dropped_over_quota: self.dropped_over_quota.load(Ordering::Relaxed),
+        };
+    }
+
+    pub(super) fn count(bytes: &AtomicU64, packets: &AtomicU64, len: usize) {
+        bytes.fetch_add(len as u64, Ordering::Relaxed);
+        packets.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl TurnBandwidth {
+    pub(super) fn new() -> Self {
+        TurnBandwidth {
+            per_user: Mutex::new(HashMap::new()),
+            total: Mutex::new(None),
+        }
+    }
+
+    fn lock_per_user(&self) -> MutexGuard<'_, HashMap<String, ByteBucket>> {
+        match self.per_user.lock() {
+            Ok(per_user) => return per_user,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn lock_total(&self) -> MutexGuard<'_, Option<ByteBucket>> {
+        match self.total.lock() {
+            Ok(total) => return total,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    /// Takes `len` bytes from the budgets of `username` and of the server, false when either
+    /// is used up
+    pub(super) fn allow(&self, quota: &TurnQuota, username: &str, len: usize) -> bool {
+        return self.allow_at(quota, username, len, Instant::now());
+    }
+
+    fn allow_at(&self, quota: &TurnQuota, username: &str, len: usize, now: Instant) -> bool {
+        let mut per_user = self.lock_per_user();
+        let mut total = self.lock_total();
+        let user_bucket = match quota.per_user.bytes_per_second {
+            Some(rate) => {
+                if per_user.len() >= TURN_QUOTA_MAX_TRACKED_USERS
+                    && !per_user.contains_key(username)
+                {
+                    per_user.retain(|_, bucket| {
+                        bucket.refill(rate, now);
+                        return bucket.tokens < rate as f64;
+                    });
+                }
+                let bucket = per_user
+                    .entry(username.to_string())
+                    .or_insert_with(|| ByteBucket::new(rate, now));
+                bucket.refill(rate, now);
+                Some(bucket)
+            }
+            None => None,
+        };
+        let total_bucket = match quota.total.bytes_per_second {
+            Some(rate) => {
+                let bucket = total.get_or_insert_with(|| ByteBucket::new(rate, now));
+                bucket.refill(rate, now);
+                Some(bucket)
+            }
+            None => None,
+        };
+        //Nothing is taken unless both budgets allow it
+        if user_bucket.as_ref().is_some_and(|x| x.tokens <= 0.0)
+            || total_bucket.as_ref().is_some_and(|x| x.tokens <= 0.0)
+        {
+            return false;
+        }
+        for bucket in [user_bucket, total_bucket].into_iter().flatten() {
+            bucket.tokens -= len as f64;
+        }
+        return true;
+    }
+}
+
+impl ByteBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        ByteBucket {
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, rate: u64, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_per_user_and_total() {
+        let mut quota = TurnQuota::default();
+        quota.per_user.set_bytes_per_second(Some(1000));
+        quota.total.set_bytes_per_second(Some(1500));
+        let bandwidth = TurnBandwidth::new();
+        let now = Instant::now();
+
+        //A second of budget at once, the last packet goes into debt
+        assert!(bandwidth.allow_at(&quota, "alice", 600, now));
+        assert!(bandwidth.allow_at(&quota, "alice", 600, now));
+        assert!(!bandwidth.allow_at(&quota, "alice", 1, now));
+        //Others have their own budget, but share the total one
+        assert!(bandwidth.allow_at(&quota, "bob", 600, now));
+        assert!(!bandwidth.allow_at(&quota, "bob", 1, now));
+
+        //The debt of alice is paid after 200ms, the total one after 200ms as well
+        let later = now + Duration::from_millis(300);
+        assert!(bandwidth.allow_at(&quota, "alice", 100, later));
+        assert!(bandwidth.allow_at(&quota, "bob", 100, later));
+
+        assert!(TurnBandwidth::new().allow_at(&TurnQuota::default(), "alice", 1 << 30, now));
+    }
+
+    #[test]
+    fn test_max_lifetime() {
+        let mut quota = TurnQuota::default();
+        assert_eq!(quota.max_lifetime(), None);
+        quota.total.set_lifetime(Some(Duration::from_secs(3600)));
+        assert_eq!(quota.max_lifetime(), Some(Duration::from_secs(3600)));
+        quota.per_user.set_lifetime(Some(Duration::from_secs(600)));
+        assert_eq!(quota.max_lifetime(), Some(Duration::from_secs(600)));
+    }
+}
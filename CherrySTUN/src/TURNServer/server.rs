@@ -0,0 +1,2151 @@
+//TURN server (RFC 8656) with UDP relays. Every allocation gets its own relay socket, bound to
+//a port of `relay_ports`, and a thread forwarding what peers send to it back to the client.
+//
+//Allocate requests can ask for an even port and to reserve the next one (EVEN-PORT), the
+//reserved port is handed to whoever comes with its RESERVATION-TOKEN. DONT-FRAGMENT is honoured
+//where the OS lets the DF bit be set, elsewhere it is answered with a 420.
+//
+//With `additional_relay_ip` set, clients can pick the family of their relayed address
+//(REQUESTED-ADDRESS-FAMILY) or get one of each in the same allocation
+//(ADDITIONAL-ADDRESS-FAMILY), then peers of either family go through the relay of theirs.
+//
+//Clients reach the server over UDP or TCP on the same port. Over TCP they can also ask for TCP
+//relays (RFC 6062), see `tcp.rs`.
+//
+//Requests have to be authenticated with long-term credentials, the same `StunLongTermAuth` as
+//a STUN server can be used. Binding requests are answered without authentication by
+//`stun_server`, so the TURN server doubles as a STUN server for ICE.
+use super::policy::TurnPeerPolicy;
+use super::quota::{TurnAllocationCounters, TurnAllocationInfo, TurnBandwidth, TurnQuota};
+use super::tcp::TurnServerTcpRelayAddress;
+use crate::STUNBody::attributes::attributes::{
+    STUNAttributeType, STUNAttributesContent, STUNErrorCode, TURN_ADDRESS_FAMILY_IPV4,
+    TURN_ADDRESS_FAMILY_IPV6, TURN_TRANSPORT_TCP, TURN_TRANSPORT_UDP,
+};
+use crate::STUNBody::body::STUNBody;
+use crate::STUNContext::context::STUNContext;
+use crate::STUNError::error::{STUNError, STUNErrorType, STUNStep};
+use crate::STUNHeader::header::{STUNHeader, STUNMessageClass, STUNMessageMethod};
+use crate::STUNSerde::{decode::STUNDecode, encode::STUNEncode};
+use crate::STUNServer::auth::StunLongTermAuth;
+use crate::STUNServer::server::StunServer;
+use crate::STUN::channel_data::ChannelData;
+use crate::STUN::classifier::{classify_packet, STUNPacketType};
+use crate::STUN::stun::STUN;
+use log::{debug, error, info, warn};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//RFC 8656 section 3.2, allocations last 10 minutes unless asked otherwise, at most an hour
+pub const TURN_SERVER_DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+pub const TURN_SERVER_MAX_LIFETIME: Duration = Duration::from_secs(3600);
+//RFC 8656 section 9 and 12
+const TURN_SERVER_PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+const TURN_SERVER_CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+//Dynamic port range of RFC 6335, as recommended for relayed addresses
+pub const TURN_SERVER_DEFAULT_RELAY_PORTS: RangeInclusive<u16> = 49152..=65535;
+const TURN_SERVER_RECEIVE_BUFFER_SIZE: usize = 65536;
+//How often the server and relay threads wake up to check for shutdown
+pub(super) const TURN_SERVER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+//How often expired allocations are looked for
+const TURN_SERVER_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+//Attempts at finding a port free for both UDP and TCP
+const TURN_SERVER_PORT_PAIR_BIND_ATTEMPTS: usize = 16;
+//RFC 8656 section 7.2, reserved ports are kept about 30 seconds
+const TURN_SERVER_RESERVATION_LIFETIME: Duration = Duration::from_secs(30);
+//Each TCP connection, control or data, is served by its own thread
+pub const TURN_SERVER_DEFAULT_MAX_TCP_CONNECTIONS: usize = 1024;
+//Relay sockets can only get the DF bit set there
+const TURN_SERVER_DONT_FRAGMENT_SUPPORTED: bool =
+    cfg!(any(target_os = "linux", target_os = "android"));
+
+/// Options for serving TURN allocations
+pub struct TurnServer {
+    /// Answers Binding requests, its SOFTWARE and amplification limit also apply to TURN
+    /// responses
+    pub stun_server: StunServer,
+    pub long_term_auth: StunLongTermAuth,
+    /// IP relay sockets are bound to, advertised in XOR-RELAYED-ADDRESS so it can't be
+    /// unspecified
+    pub relay_ip: IpAddr,
+    /// IP of the other family, to relay over both IPv4 and IPv6. Allocations are IPv4 unless
+    /// asked otherwise, so it is the IPv6 one on dual-stack hosts.
+    pub additional_relay_ip: Option<IpAddr>,
+    pub relay_ports: RangeInclusive<u16>,
+    /// Granted when clients ask for less or nothing
+    pub default_lifetime: Duration,
+    /// Granted when clients ask for more
+    pub max_lifetime: Duration,
+    /// Limits on allocations and relayed traffic, per user and in total
+    pub quota: TurnQuota,
+    /// Peers that can be given permissions, private and loopback addresses are denied by
+    /// default
+    pub peer_policy: TurnPeerPolicy,
+    /// TCP connections served at once, more are closed as soon as accepted
+    pub max_tcp_connections: usize,
+    pub(super) bandwidth: Arc<TurnBandwidth>,
+    //Allocations by client 5-tuple. There is one server address, so the transport and the
+    //client address are enough.
+    allocations: Mutex<HashMap<TurnServerFiveTuple, Arc<TurnServerAllocation>>>,
+    //Sockets bound to the port after an even one, by RESERVATION-TOKEN
+    reservations: Mutex<HashMap<u64, TurnServerReservation>>,
+    //Relayed addresses of the TCP relay listeners, from binding until the listener is closed
+    pub(super) tcp_relay_addresses: Arc<Mutex<HashSet<SocketAddr>>>,
+    //TCP connections being served, up to `max_tcp_connections`
+    pub(super) tcp_connections: AtomicUsize,
+    //Relay and client connection threads, joined when the server stops
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    //CONNECTION-IDs are unique across allocations, ConnectionBind requests only carry the ID
+    pub(super) next_connection_id: Arc<AtomicU32>,
+}
+
+pub(super) type TurnServerFiveTuple = (u8, SocketAddr);
+
+/// Returned by `TurnServer::spawn`, stops the server and closes every allocation when stopped
+/// or dropped
+pub struct TurnServerHandle {
+    /// Address the server answers on, over both UDP and TCP
+    pub local_addr: SocketAddr,
+    server: Arc<TurnServer>,
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+//Where responses and relayed data for a client go
+#[derive(Clone)]
+pub(super) enum TurnServerClient {
+    Udp {
+        socket: Arc<UdpSocket>,
+        address: SocketAddr,
+    },
+    //Writes of several threads must not interleave
+    Tcp {
+        stream: Arc<Mutex<TcpStream>>,
+        address: SocketAddr,
+    },
+}
+
+pub(super) enum TurnServerRelay {
+    Udp(UdpSocket),
+    //RFC 6062, peers connect to it and data connections are made from its address
+    Tcp(TcpListener, TurnServerTcpRelayAddress),
+}
+
+pub(super) struct TurnServerAllocation {
+    pub(super) client: TurnServerClient,
+    pub(super) relayed_address: SocketAddr,
+    pub(super) relay: TurnServerRelay,
+    //Relayed address and socket of the other family, for ADDITIONAL-ADDRESS-FAMILY
+    additional_relay: Option<(SocketAddr, UdpSocket)>,
+    //Family that was asked for but not allocated, sent back in ADDRESS-ERROR-CODE
+    address_error: Option<(u8, STUNErrorCode)>,
+    //Requests on the allocation must come from the user that created it
+    pub(super) username: String,
+    //Retransmissions of the Allocate get the same answer instead of a 437
+    transaction_id: [u8; 12],
+    //Of the port reserved along with this one, sent in retransmitted answers too
+    reservation_token: Option<u64>,
+    created_at: Instant,
+    pub(super) counters: TurnAllocationCounters,
+    bindings: Mutex<TurnServerBindings>,
+    //Peer data connections of TCP allocations by CONNECTION-ID
+    pub(super) connections: Mutex<HashMap<u32, TurnServerPeerConnection>>,
+    pub(super) closed: AtomicBool,
+}
+
+struct TurnServerBindings {
+    expires_at: Instant,
+    //Expiry of the permission of each peer IP
+    permissions: HashMap<IpAddr, Instant>,
+    //Peer and expiry of each bound channel
+    channels: HashMap<u16, (SocketAddr, Instant)>,
+}
+
+struct TurnServerReservation {
+    socket: UdpSocket,
+    expires_at: Instant,
+}
+
+pub(super) struct TurnServerPeerConnection {
+    pub(super) peer: SocketAddr,
+    //Waiting for the ConnectionBind of the client. `None` while connecting to the peer and
+    //once bound.
+    pub(super) stream: Option<TcpStream>,
+    pub(super) since: Instant,
+}
+
+impl TurnServer {
+    /// A constructor with default parameters
+    pub fn new(relay_ip: IpAddr, long_term_auth: StunLongTermAuth) -> Self {
+        TurnServer {
+            stun_server: StunServer::new(),
+            long_term_auth,
+            relay_ip,
+            additional_relay_ip: None,
+            relay_ports: TURN_SERVER_DEFAULT_RELAY_PORTS,
+            default_lifetime: TURN_SERVER_DEFAULT_LIFETIME,
+            max_lifetime: TURN_SERVER_MAX_LIFETIME,
+            quota: TurnQuota::default(),
+            peer_policy: TurnPeerPolicy::default(),
+            max_tcp_connections: TURN_SERVER_DEFAULT_MAX_TCP_CONNECTIONS,
+            bandwidth: Arc::new(TurnBandwidth::new()),
+            allocations: Mutex::new(HashMap::new()),
+            reservations: Mutex::new(HashMap::new()),
+            tcp_relay_addresses: Arc::new(Mutex::new(HashSet::new())),
+            tcp_connections: AtomicUsize::new(0),
+            threads: Mutex::new(Vec::new()),
+            next_connection_id: Arc::new(AtomicU32::new(rand::thread_rng().gen())),
+        }
+    }
+
+    /// Set `additional_relay_ip` field, builder pattern.
+    pub fn set_additional_relay_ip(&mut self, additional_relay_ip: Option<IpAddr>) -> &mut Self {
+        self.additional_relay_ip = additional_relay_ip;
+        self
+    }
+
+    /// Set `relay_ports` field, builder pattern.
+    pub fn set_relay_ports(&mut self, relay_ports: RangeInclusive<u16>) -> &mut Self {
+        self.relay_ports = relay_ports;
+        self
+    }
+
+    /// Set `default_lifetime` field, builder pattern.
+    pub fn set_default_lifetime(&mut self, default_lifetime: Duration) -> &mut Self {
+        self.default_lifetime = default_lifetime;
+        self
+    }
+
+    /// Set `max_lifetime` field, builder pattern.
+    pub fn set_max_lifetime(&mut self, max_lifetime: Duration) -> &mut Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Set `quota` field, builder pattern.
+    pub fn set_quota(&mut self, quota: TurnQuota) -> &mut Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Set `peer_policy` field, builder pattern.
+    pub fn set_peer_policy(&mut self, peer_policy: TurnPeerPolicy) -> &mut Self {
+        self.peer_policy = peer_policy;
+        self
+    }
+
+    /// Set `max_tcp_connections` field, builder pattern.
+    pub fn set_max_tcp_connections(&mut self, max_tcp_connections: usize) -> &mut Self {
+        self.max_tcp_connections = max_tcp_connections;
+        self
+    }
+
+    /// Binds `bind_addr` for both UDP and TCP and serves clients from background threads until
+    /// the returned handle is stopped. Bind to port 0 and read `local_addr` for tests, a port
+    /// free for both is picked.
+    pub fn spawn(self, bind_addr: SocketAddr) -> Result<TurnServerHandle, STUNError> {
+        if self.relay_ports.is_empty() || self.relay_ip.is_unspecified() {
+            return Err(STUNError {
+                step: STUNStep::TURNServer,
+                error_type: STUNErrorType::InvalidConfiguration,
+                message: "TURN server needs a relay IP and at least one relay port".to_string(),
+            });
+        }
+        match self.additional_relay_ip {
+            Some(ip) if ip.is_unspecified() || ip.is_ipv4() == self.relay_ip.is_ipv4() => {
+                return Err(STUNError {
+                    step: STUNStep::TURNServer,
+                    error_type: STUNErrorType::InvalidConfiguration,
+                    message: "Additional relay IP must be of the other family than the relay IP"
+                        .to_string(),
+                });
+            }
+            _ => {}
+        }
+        let (udp, listener) = match Self::bind_server_sockets(bind_addr) {
+            Ok(sockets) => sockets,
+            Err(e) => return Err(e),
+        };
+        let local_addr = match StunServer::server_socket_addr(&udp) {
+            Ok(addr) => addr,
+            Err(e) => return Err(e),
+        };
+        info!("TURN server listening on {:?}", local_addr);
+        let server = Arc::new(self);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let udp_server = server.clone();
+        let udp_shutdown = shutdown.clone();
+        let udp_thread = std::thread::spawn(move || {
+            match udp_server.serve_until(&Arc::new(udp), &udp_shutdown) {
+                Ok(()) => {}
+                Err(e) => error!("TURN server stopped: {:?}", e),
+            }
+        });
+        let tcp_server = server.clone();
+        let tcp_shutdown = shutdown.clone();
+        let tcp_thread =
+            std::thread::spawn(move || Self::accept_until(&tcp_server, &listener, &tcp_shutdown));
+        return Ok(TurnServerHandle {
+            local_addr,
+            server,
+            shutdown,
+            threads: vec![udp_thread, tcp_thread],
+        });
+    }
+
+    //UDP socket and TCP listener on the same port. With port 0, an ephemeral port free for
+    //both is searched for.
+    fn bind_server_sockets(bind_addr: SocketAddr) -> Result<(UdpSocket, TcpListener), STUNError> {
+        let mut last_error = None;
+        for _ in 0..TURN_SERVER_PORT_PAIR_BIND_ATTEMPTS {
+            let udp = match StunServer::bind_server_socket(bind_addr) {
+                Ok(udp) => udp,
+                Err(e) => return Err(e),
+            };
+            let udp_addr = match StunServer::server_socket_addr(&udp) {
+                Ok(addr) => addr,
+                Err(e) => return Err(e),
+            };
+            let listener = match TcpListener::bind(udp_addr) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    let error = STUNError {
+                        step: STUNStep::TURNServer,
+                        error_type: STUNErrorType::SocketBindError,
+                        message: "Error binding TCP listener: ".to_string()
+                            + e.to_string().as_str(),
+                    };
+                    if bind_addr.port() != 0 {
+                        return Err(error);
+                    }
+                    debug!("TCP port {} taken, retrying", udp_addr.port());
+                    last_error = Some(error);
+                    continue;
+                }
+            };
+            //Polled, so that the accepting thread can notice shutdowns
+            match listener.set_nonblocking(true) {
+                Ok(()) => return Ok((udp, listener)),
+                Err(e) => {
+                    return Err(STUNError {
+                        step: STUNStep::TURNServer,
+                        error_type: STUNErrorType::SocketBindError,
+                        message: "Error setting TCP listener non blocking: ".to_string()
+                            + e.to_string().as_str(),
+                    })
+                }
+            }
+        }
+        return Err(last_error.unwrap());
+    }
+
+    pub(super) fn lock_allocations(
+        &self,
+    ) -> MutexGuard<'_, HashMap<TurnServerFiveTuple, Arc<TurnServerAllocation>>> {
+        match self.allocations.lock() {
+            Ok(allocations) => return allocations,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn lock_reservations(&self) -> MutexGuard<'_, HashMap<u64, TurnServerReservation>> {
+        match self.reservations.lock() {
+            Ok(reservations) => return reservations,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    pub(super) fn lock_threads(&self) -> MutexGuard<'_, Vec<JoinHandle<()>>> {
+        match self.threads.lock() {
+            Ok(threads) => return threads,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    fn serve_until(&self, udp: &Arc<UdpSocket>, shutdown: &AtomicBool) -> Result<(), STUNError> {
+        let mut buf = vec![0; TURN_SERVER_RECEIVE_BUFFER_SIZE];
+        let mut last_sweep = Instant::now();
+        while !shutdown.load(Ordering::SeqCst) {
+            if last_sweep.elapsed() >= TURN_SERVER_SWEEP_INTERVAL {
+                self.remove_expired_allocations();
+                last_sweep = Instant::now();
+            }
+            let (len, source) = match udp.recv_from(&mut buf) {
+                Ok(x) => x,
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(STUNError {
+                        step: STUNStep::TURNServer,
+                        error_type: STUNErrorType::ErrorReceivingFromClient,
+                        message: "Error receiving from client: ".to_string()
+                            + e.to_string().as_str(),
+                    })
+                }
+            };
+            let client = TurnServerClient::Udp {
+                socket: udp.clone(),
+                address: source,
+            };
+            match self.handle_message(&buf[..len], &client) {
+                Some(response_bin) => match client.send(&response_bin) {
+                    Ok(()) => {}
+                    Err(e) => warn!("Error sending response to {:?}: {}", source, e),
+                },
+                None => {}
+            }
+        }
+        return Ok(());
+    }
+
+    //Processes one message from a client, returns the response to send back if any
+    pub(super) fn handle_message(
+        &self,
+        message_bin: &[u8],
+        client: &TurnServerClient,
+    ) -> Option<Vec<u8>> {
+        let source = client.address();
+        match classify_packet(message_bin) {
+            STUNPacketType::ChannelData => {
+                self.relay_channel_data(message_bin, client);
+                return None;
+            }
+            STUNPacketType::Stun => {}
+            packet_type => {
+                debug!("Dropping {:?} packet from {:?}", packet_type, source);
+                return None;
+            }
+        }
+        if !StunServer::is_well_formed_message(message_bin) {
+            debug!("Dropping invalid message from {:?}", source);
+            return None;
+        }
+        let header = match STUNHeader::decode(&mut Cursor::new(message_bin), &mut None) {
+            Ok(header) => header,
+            Err(e) => {
+                debug!("Dropping non STUN message from {:?}: {}", source, e);
+                return None;
+            }
+        };
+        match (header.message_class, header.message_method) {
+            (STUNMessageClass::Request, STUNMessageMethod::Binding) => {
+                return self.stun_server.handle_message(message_bin, source)
+            }
+            (STUNMessageClass::Request, _) => {}
+            (STUNMessageClass::Indication, STUNMessageMethod::Send) => {
+                self.relay_send_indication(message_bin, client);
+                return None;
+            }
+            _ => {
+                debug!("Ignoring {:?} from {:?}", header, source);
+                return None;
+            }
+        }
+
+        let request_len = message_bin.len();
+        let (body, username, key) = match self.authenticated_request(message_bin, &header, source) {
+            Ok(request) => request,
+            Err(response) => return response,
+        };
+        let result = match header.message_method {
+            STUNMessageMethod::Allocate => self.allocate(&header, &body, client, &username),
+            STUNMessageMethod::Refresh => self.refresh(&body, client, &username),
+            STUNMessageMethod::CreatePermission => self.create_permission(&body, client, &username),
+            STUNMessageMethod::ChannelBind => self.bind_channel(&body, client, &username),
+            //Answered once the connection to the peer is made
+            STUNMessageMethod::Connect => {
+                match self.connect(&header, &body, client, &username, &key, request_len) {
+                    Ok(()) => return None,
+                    Err(code) => Err(code),
+                }
+            }
+            _ => Err(STUNErrorCode::BadRequest),
+        };
+        return self.encode_result(&header, result, &key, request_len);
+    }
+
+    //Decodes the body of a request and checks its credentials. Returns the body, the username
+    //and the key to sign the response with, or the response to send when the request can't go
+    //further.
+    pub(super) fn authenticated_request(
+        &self,
+        message_bin: &[u8],
+        header: &STUNHeader,
+        source: SocketAddr,
+    ) -> Result<(STUNBody, String, Vec<u8>), Option<Vec<u8>>> {
+        let request_len = message_bin.len();
+        let mut cursor = Cursor::new(message_bin);
+        cursor.set_position(20);
+        let mut decode_context = STUNContext::new();
+        decode_context.defer_integrity_check = true;
+        let mut body = match STUNBody::decode(&mut cursor, &mut Some(&mut decode_context)) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Malformed request from {:?}: {}", source, e);
+                return Err(self.stun_server.sign_and_encode(
+                    self.stun_server
+                        .error_response(header, STUNErrorCode::BadRequest),
+                    &None,
+                    request_len,
+                ));
+            }
+        };
+        let key = match self.stun_server.authenticate(
+            &self.long_term_auth,
+            header,
+            &decode_context,
+            source,
+        ) {
+            Ok(key) => key,
+            Err(response) => {
+                return Err(self
+                    .stun_server
+                    .sign_and_encode(response, &None, request_len))
+            }
+        };
+        //RFC 8656 section 7.2, servers that can't set DF treat it as unknown
+        if !TURN_SERVER_DONT_FRAGMENT_SUPPORTED
+            && header.message_method == STUNMessageMethod::Allocate
+            && body
+                .attributes
+                .iter()
+                .any(|x| x.value == STUNAttributesContent::DontFragment)
+        {
+            body.unknown_attributes
+                .push(STUNAttributeType::DontFragment as u16);
+        }
+        if !body.unknown_attributes.is_empty() {
+            let mut response = self
+                .stun_server
+                .error_response(header, STUNErrorCode::UnknownAttribute);
+            response.body.add_new_attribute(
+                STUNAttributesContent::new_unknown_attributes(body.unknown_attributes.clone()),
+                STUNAttributeType::UnknownAttributes,
+                0,
+            );
+            return Err(self
+                .stun_server
+                .sign_and_encode(response, &Some(key), request_len));
+        }
+        //Checked by `authenticate`
+        let username = decode_context.username.unwrap_or_default();
+        return Ok((body, username, key));
+    }
+
+    //Success response with the attributes, or the error response, signed with `key`
+    pub(super) fn encode_result(
+        &self,
+        header: &STUNHeader,
+        result: Result<Vec<STUNAttributesContent>, STUNErrorCode>,
+        key: &[u8],
+        request_len: usize,
+    ) -> Option<Vec<u8>> {
+        let response = match result {
+            Ok(attributes) => {
+                let mut response = STUN::new_default(
+                    STUNMessageClass::ResponseSuccess,
+                    header.message_method,
+                    Some(header.transaction_id),
+                );
+                for attribute in attributes {
+                    let attribute_type = attribute.attribute_type();
+                    response
+                        .body
+                        .add_new_attribute(attribute, attribute_type, 0);
+                }
+                self.stun_server.add_software(&mut response);
+                response
+            }
+            Err(code) => {
+                debug!("{:?} failed: {:?}", header.message_method, code);
+                self.stun_server.error_response(header, code)
+            }
+        };
+        return self
+            .stun_server
+            .sign_and_encode(response, &Some(key.to_vec()), request_len);
+    }
+
+    //The allocation of `client`, which only its owner can use
+    pub(super) fn allocation_of(
+        &self,
+        client: &TurnServerClient,
+        username: &str,
+    ) -> Result<Arc<TurnServerAllocation>, STUNErrorCode> {
+        match self.lock_allocations().get(&client.five_tuple()) {
+            Some(allocation) if allocation.username == username => return Ok(allocation.clone()),
+            Some(_) => return Err(STUNErrorCode::WrongCredentials),
+            None => return Err(STUNErrorCode::AllocationMismatch),
+        }
+    }
+
+    //RFC 8656 section 7.2, the requested lifetime capped by `max_lifetime` but never below
+    //`default_lifetime`. Allocations created at `created_at` never outlive the lifetime quota.
+    fn granted_lifetime(&self, requested: Option<u32>, created_at: Instant) -> Duration {
+        let granted = match requested {
+            Some(lifetime) => Duration::from_secs(lifetime as u64)
+                .min(self.max_lifetime)
+                .max(self.default_lifetime),
+            None => self.default_lifetime,
+        };
+        match self.quota.max_lifetime() {
+            Some(max_lifetime) => {
+                let left = (created_at + max_lifetime).saturating_duration_since(Instant::now());
+                return granted.min(left);
+            }
+            None => return granted,
+        }
+    }
+
+    //486 when the user has all the allocations it may have, 508 when the server has. Checked
+    //before binding the relay and again with the insert, under the same lock, as Allocates of
+    //TCP clients are handled concurrently.
+    fn check_allocation_quota(
+        &self,
+        allocations: &HashMap<TurnServerFiveTuple, Arc<TurnServerAllocation>>,
+        username: &str,
+    ) -> Result<(), STUNErrorCode> {
+        match self.quota.total.allocations {
+            Some(max) if allocations.len() >= max => {
+                return Err(STUNErrorCode::InsufficientCapacity)
+            }
+            _ => {}
+        }
+        match self.quota.per_user.allocations {
+            Some(max)
+                if allocations
+                    .values()
+                    .filter(|x| x.username == username)
+                    .count()
+                    >= max =>
+            {
+                return Err(STUNErrorCode::AllocationQuotaReached)
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    fn allocate(
+        &self,
+        header: &STUNHeader,
+        body: &STUNBody,
+        client: &TurnServerClient,
+        username: &str,
+    ) -> Result<Vec<STUNAttributesContent>, STUNErrorCode> {
+        match self.lock_allocations().get(&client.five_tuple()) {
+            Some(allocation) if allocation.transaction_id == header.transaction_id => {
+                return Ok(allocation.allocate_success_attributes())
+            }
+            Some(_) => return Err(STUNErrorCode::AllocationMismatch),
+            None => {}
+        }
+        match self.check_allocation_quota(&self.lock_allocations(), username) {
+            Ok(()) => {}
+            Err(code) => {
+                info!("Allocation quota reached for {:?}", username);
+                return Err(code);
+            }
+        }
+        let mut requested_transport = None;
+        let mut requested_lifetime = None;
+        let mut even_port = None;
+        let mut reservation_token = None;
+        let mut dont_fragment = false;
+        let mut requested_family = None;
+        let mut additional_family = None;
+        for attribute in body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::RequestedTransport { protocol } => {
+                    requested_transport = Some(protocol)
+                }
+                STUNAttributesContent::Lifetime { lifetime } => requested_lifetime = Some(lifetime),
+                STUNAttributesContent::EvenPort { reserve } => even_port = Some(reserve),
+                STUNAttributesContent::ReservationToken { token } => {
+                    reservation_token = Some(token)
+                }
+                STUNAttributesContent::DontFragment => dont_fragment = true,
+                STUNAttributesContent::RequestedAddressFamily { family } => {
+                    requested_family = Some(family)
+                }
+                STUNAttributesContent::AdditionalAddressFamily { family } => {
+                    additional_family = Some(family)
+                }
+                _ => {}
+            }
+        }
+        //RFC 8656 section 7.2, a reserved port already is what the earlier EVEN-PORT asked for,
+        //family included. Only IPv6 can be asked for in addition to IPv4.
+        if (even_port.is_some() || requested_family.is_some() || additional_family.is_some())
+            && reservation_token.is_some()
+        {
+            return Err(STUNErrorCode::BadRequest);
+        }
+        match (requested_family, additional_family) {
+            (_, None) | (None, Some(TURN_ADDRESS_FAMILY_IPV6)) => {}
+            _ => return Err(STUNErrorCode::BadRequest),
+        }
+        let relay_ip = match self.relay_ip_of(requested_family.unwrap_or(TURN_ADDRESS_FAMILY_IPV4))
+        {
+            Some(relay_ip) => relay_ip,
+            //Reserved ports are bound already
+            None if reservation_token.is_some() => self.relay_ip,
+            None => return Err(STUNErrorCode::AddressFamilyNotSupported),
+        };
+        let relay = match requested_transport {
+            Some(TURN_TRANSPORT_UDP) => self.bind_udp_relay(relay_ip, even_port, reservation_token),
+            //RFC 6062 section 5.1, TCP relays are only for clients connected over TCP, and
+            //have no port parity nor DF bit. They have a single relayed address here.
+            Some(TURN_TRANSPORT_TCP)
+                if client.is_stream()
+                    && even_port.is_none()
+                    && reservation_token.is_none()
+                    && !dont_fragment
+                    && additional_family.is_none() =>
+            {
+                match self.bind_relay_listener(relay_ip) {
+                    Some(relay) => Ok((relay, None)),
+                    None => Err(STUNErrorCode::InsufficientCapacity),
+                }
+            }
+            Some(TURN_TRANSPORT_TCP) => return Err(STUNErrorCode::BadRequest),
+            Some(_) => return Err(STUNErrorCode::UnsupportedTransportProtocol),
+            None => return Err(STUNErrorCode::BadRequest),
+        };
+        let (relay, reservation_token) = match relay {
+            Ok(relay) => relay,
+            Err(code) => return Err(code),
+        };
+        //Failing to get the additional family still allocates the requested one
+        let (additional_relay, address_error) = match additional_family {
+            Some(family) => match self.bind_additional_relay(family) {
+                Ok(additional_relay) => (Some(additional_relay), None),
+                Err(code) => (None, Some((family, code))),
+            },
+            None => (None, None),
+        };
+        if dont_fragment {
+            let relay_sockets = [
+                match &relay {
+                    TurnServerRelay::Udp(relay_socket) => Some(relay_socket),
+                    TurnServerRelay::Tcp(..) => None,
+                },
+                additional_relay
+                    .as_ref()
+                    .map(|(_, relay_socket)| relay_socket),
+            ];
+            for relay_socket in relay_sockets.into_iter().flatten() {
+                match set_dont_fragment(relay_socket) {
+                    Ok(()) => {}
+                    Err(e) => warn!("Error setting DF on relay socket: {}", e),
+                }
+            }
+        }
+        let relayed_address = match relay.local_addr() {
+            Ok(address) => address,
+            Err(e) => {
+                error!("Error reading relay socket address: {}", e);
+                return Err(STUNErrorCode::ServerError);
+            }
+        };
+        let created_at = Instant::now();
+        let allocation = Arc::new(TurnServerAllocation {
+            client: client.clone(),
+            relayed_address,
+            relay,
+            additional_relay,
+            address_error,
+            username: username.to_string(),
+            transaction_id: header.transaction_id,
+            reservation_token,
+            created_at,
+            counters: TurnAllocationCounters::default(),
+            bindings: Mutex::new(TurnServerBindings {
+                expires_at: created_at + self.granted_lifetime(requested_lifetime, created_at),
+                permissions: HashMap::new(),
+                channels: HashMap::new(),
+            }),
+            connections: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        });
+        let attributes = allocation.allocate_success_attributes();
+        {
+            let mut allocations = self.lock_allocations();
+            match self.check_allocation_quota(&allocations, username) {
+                Ok(()) => {}
+                Err(code) => {
+                    info!("Allocation quota reached for {:?}", username);
+                    return Err(code);
+                }
+            }
+            allocations.insert(client.five_tuple(), allocation.clone());
+        }
+        let relay_allocation = allocation.clone();
+        let relay_thread = match allocation.relay {
+            TurnServerRelay::Udp(_) => {
+                let quota = self.quota;
+                let bandwidth = self.bandwidth.clone();
+                std::thread::spawn(move || relay_allocation.relay_loop(false, &quota, &bandwidth))
+            }
+            TurnServerRelay::Tcp(..) => {
+                let connection_ids = self.next_connection_id.clone();
+                std::thread::spawn(move || relay_allocation.accept_peers_loop(&connection_ids))
+            }
+        };
+        self.lock_threads().push(relay_thread);
+        if allocation.additional_relay.is_some() {
+            let relay_allocation = allocation.clone();
+            let quota = self.quota;
+            let bandwidth = self.bandwidth.clone();
+            let relay_thread =
+                std::thread::spawn(move || relay_allocation.relay_loop(true, &quota, &bandwidth));
+            self.lock_threads().push(relay_thread);
+        }
+        info!(
+            "Allocated {:?} (additional {:?}) for {:?} ({:?})",
+            relayed_address,
+            allocation.additional_relayed_address(),
+            client.address(),
+            username
+        );
+        return Ok(attributes);
+    }
+
+    //Peers have to be of the family of the relayed address and allowed by `peer_policy`
+    pub(super) fn check_peer(
+        &self,
+        allocation: &TurnServerAllocation,
+        peer: IpAddr,
+    ) -> Result<(), STUNErrorCode> {
+        match allocation.check_peer_family(peer) {
+            Ok(()) => {}
+            Err(code) => return Err(code),
+        }
+        if !self.peer_policy.is_allowed(peer) {
+            info!("Refused peer {:?} for {:?}", peer, allocation.username);
+            return Err(STUNErrorCode::Forbidden);
+        }
+        return Ok(());
+    }
+
+    //Relay IP of a REQUESTED-ADDRESS-FAMILY family, `None` when the server has none
+    fn relay_ip_of(&self, family: u8) -> Option<IpAddr> {
+        let is_ipv4 = match family {
+            TURN_ADDRESS_FAMILY_IPV4 => true,
+            TURN_ADDRESS_FAMILY_IPV6 => false,
+            _ => return None,
+        };
+        return [Some(self.relay_ip), self.additional_relay_ip]
+            .into_iter()
+            .flatten()
+            .find(|x| x.is_ipv4() == is_ipv4);
+    }
+
+    //Tries the ports of `relay_ports` on `relay_ip` from a random one on, `None` when all are
+    //taken
+    pub(super) fn bind_relay<T>(
+        &self,
+        relay_ip: IpAddr,
+        bind: impl Fn(SocketAddr) -> std::io::Result<T>,
+    ) -> Option<T> {
+        let first = *self.relay_ports.start() as u32;
+        let count = *self.relay_ports.end() as u32 - first + 1;
+        let offset = rand::thread_rng().gen_range(0..count);
+        for index in 0..count {
+            let port = (first + (offset + index) % count) as u16;
+            match bind(SocketAddr::new(relay_ip, port)) {
+                Ok(relay) => return Some(relay),
+                Err(_) => continue,
+            }
+        }
+        warn!("No relay port left in {:?}", self.relay_ports);
+        return None;
+    }
+
+    //The reserved socket of `reservation_token`, or a new one on an even port when
+    //`even_port` is set. Reserving binds the next port as well and returns its token.
+    fn bind_udp_relay(
+        &self,
+        relay_ip: IpAddr,
+        even_port: Option<bool>,
+        reservation_token: Option<u64>,
+    ) -> Result<(TurnServerRelay, Option<u64>), STUNErrorCode> {
+        match reservation_token {
+            Some(token) => match self.lock_reservations().remove(&token) {
+                Some(reservation) => {
+                    return Ok((TurnServerRelay::Udp(reservation.socket), None));
+                }
+                None => {
+                    info!("No reservation for token {:#x}", token);
+                    return Err(STUNErrorCode::InsufficientCapacity);
+                }
+            },
+            None => {}
+        }
+        let relay = match even_port {
+            Some(true) => self.bind_relay(relay_ip, |address| self.bind_reserving_pair(address)),
+            Some(false) => self
+                .bind_relay(relay_ip, |address| match address.port().is_multiple_of(2) {
+                    true => Self::relay_udp_socket(address),
+                    false => Err(std::io::ErrorKind::AddrNotAvailable.into()),
+                })
+                .map(|relay_socket| (relay_socket, None)),
+            None => self
+                .bind_relay(relay_ip, Self::relay_udp_socket)
+                .map(|relay_socket| (relay_socket, None)),
+        };
+        let (relay_socket, reserved) = match relay {
+            Some(relay) => relay,
+            None => return Err(STUNErrorCode::InsufficientCapacity),
+        };
+        let token = match reserved {
+            Some(reserved) => {
+                let mut reservations = self.lock_reservations();
+                let mut token = rand::thread_rng().gen();
+                while reservations.contains_key(&token) {
+                    token = rand::thread_rng().gen();
+                }
+                reservations.insert(
+                    token,
+                    TurnServerReservation {
+                        socket: reserved,
+                        expires_at: Instant::now() + TURN_SERVER_RESERVATION_LIFETIME,
+                    },
+                );
+                Some(token)
+            }
+            None => None,
+        };
+        return Ok((TurnServerRelay::Udp(relay_socket), token));
+    }
+
+    //UDP relay of ADDITIONAL-ADDRESS-FAMILY, the error code goes in ADDRESS-ERROR-CODE
+    fn bind_additional_relay(&self, family: u8) -> Result<(SocketAddr, UdpSocket), STUNErrorCode> {
+        let relay_ip = match self.relay_ip_of(family) {
+            Some(relay_ip) => relay_ip,
+            None => return Err(STUNErrorCode::AddressFamilyNotSupported),
+        };
+        let relay_socket = match self.bind_relay(relay_ip, Self::relay_udp_socket) {
+            Some(relay_socket) => relay_socket,
+            None => return Err(STUNErrorCode::InsufficientCapacity),
+        };
+        match relay_socket.local_addr() {
+            Ok(address) => return Ok((address, relay_socket)),
+            Err(e) => {
+                error!("Error reading relay socket address: {}", e);
+                return Err(STUNErrorCode::ServerError);
+            }
+        }
+    }
+
+    //Sockets on an even port of `relay_ports` and on the port after it
+    fn bind_reserving_pair(
+        &self,
+        address: SocketAddr,
+    ) -> std::io::Result<(UdpSocket, Option<UdpSocket>)> {
+        if !address.port().is_multiple_of(2) || address.port() >= *self.relay_ports.end() {
+            return Err(std::io::ErrorKind::AddrNotAvailable.into());
+        }
+        let relay_socket = match Self::relay_udp_socket(address) {
+            Ok(relay_socket) => relay_socket,
+            Err(e) => return Err(e),
+        };
+        let next_address = SocketAddr::new(address.ip(), address.port() + 1);
+        match Self::relay_udp_socket(next_address) {
+            Ok(reserved) => return Ok((relay_socket, Some(reserved))),
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn relay_udp_socket(address: SocketAddr) -> std::io::Result<UdpSocket> {
+        let relay_socket = match UdpSocket::bind(address) {
+            Ok(relay_socket) => relay_socket,
+            Err(e) => return Err(e),
+        };
+        match relay_socket.set_read_timeout(Some(TURN_SERVER_POLL_INTERVAL)) {
+            Ok(()) => return Ok(relay_socket),
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn refresh(
+        &self,
+        body: &STUNBody,
+        client: &TurnServerClient,
+        username: &str,
+    ) -> Result<Vec<STUNAttributesContent>, STUNErrorCode> {
+        let allocation = match self.allocation_of(client, username) {
+            Ok(allocation) => allocation,
+            Err(code) => return Err(code),
+        };
+        let requested_lifetime = body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::Lifetime { lifetime } => Some(lifetime),
+            _ => None,
+        });
+        //RFC 8656 section 7.3, the family has to be one of the allocation
+        let requested_family = body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::RequestedAddressFamily { family } => Some(family),
+            _ => None,
+        });
+        match requested_family {
+            Some(family) if !allocation.has_family(family) => {
+                return Err(STUNErrorCode::PeerAddressFamilyMismatch)
+            }
+            _ => {}
+        }
+        if requested_lifetime == Some(0) {
+            info!("Released {:?}", allocation.relayed_address);
+            self.remove_allocation(&client.five_tuple());
+            return Ok(vec![STUNAttributesContent::new_lifetime(0)]);
+        }
+        let lifetime = self.granted_lifetime(requested_lifetime, allocation.created_at);
+        allocation.lock_bindings().expires_at = Instant::now() + lifetime;
+        return Ok(vec![STUNAttributesContent::new_lifetime(
+            lifetime.as_millis().div_ceil(1000) as u32,
+        )]);
+    }
+
+    fn create_permission(
+        &self,
+        body: &STUNBody,
+        client: &TurnServerClient,
+        username: &str,
+    ) -> Result<Vec<STUNAttributesContent>, STUNErrorCode> {
+        let allocation = match self.allocation_of(client, username) {
+            Ok(allocation) => allocation,
+            Err(code) => return Err(code),
+        };
+        let mut peers = Vec::new();
+        for attribute in body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::XORPeerAddress { address } => peers.push(address.ip()),
+                _ => {}
+            }
+        }
+        if peers.is_empty() {
+            return Err(STUNErrorCode::BadRequest);
+        }
+        //All or nothing, RFC 8656 section 9.2
+        for peer in peers.iter() {
+            match self.check_peer(&allocation, *peer) {
+                Ok(()) => {}
+                Err(code) => return Err(code),
+            }
+        }
+        let expires_at = Instant::now() + TURN_SERVER_PERMISSION_LIFETIME;
+        let mut bindings = allocation.lock_bindings();
+        for peer in peers {
+            bindings.permissions.insert(peer, expires_at);
+        }
+        return Ok(Vec::new());
+    }
+
+    fn bind_channel(
+        &self,
+        body: &STUNBody,
+        client: &TurnServerClient,
+        username: &str,
+    ) -> Result<Vec<STUNAttributesContent>, STUNErrorCode> {
+        let allocation = match self.allocation_of(client, username) {
+            Ok(allocation) => allocation,
+            Err(code) => return Err(code),
+        };
+        //RFC 6062 section 5.1, data of TCP allocations only flows over data connections
+        match allocation.relay {
+            TurnServerRelay::Udp(_) => {}
+            TurnServerRelay::Tcp(..) => return Err(STUNErrorCode::BadRequest),
+        }
+        let mut channel_number = None;
+        let mut peer = None;
+        for attribute in body.attributes.iter() {
+            match attribute.value {
+                STUNAttributesContent::ChannelNumber {
+                    channel_number: number,
+                } => channel_number = Some(number),
+                STUNAttributesContent::XORPeerAddress { address } => peer = Some(address),
+                _ => {}
+            }
+        }
+        let (channel_number, peer) = match (channel_number, peer) {
+            (Some(number), Some(peer)) if ChannelData::is_valid_channel_number(number) => {
+                (number, peer)
+            }
+            _ => return Err(STUNErrorCode::BadRequest),
+        };
+        match self.check_peer(&allocation, peer.ip()) {
+            Ok(()) => {}
+            Err(code) => return Err(code),
+        }
+        let now = Instant::now();
+        let mut bindings = allocation.lock_bindings();
+        //A channel stays with its peer, and a peer with its channel, until it expires
+        for (number, (bound_peer, expires_at)) in bindings.channels.iter() {
+            if *expires_at > now && (*number == channel_number) != (*bound_peer == peer) {
+                return Err(STUNErrorCode::BadRequest);
+            }
+        }
+        bindings
+            .channels
+            .insert(channel_number, (peer, now + TURN_SERVER_CHANNEL_LIFETIME));
+        bindings
+            .permissions
+            .insert(peer.ip(), now + TURN_SERVER_PERMISSION_LIFETIME);
+        return Ok(Vec::new());
+    }
+
+    //Send indications from clients, dropped when anything is missing or not permitted
+    fn relay_send_indication(&self, message_bin: &[u8], client: &TurnServerClient) {
+        let allocation = match self.lock_allocations().get(&client.five_tuple()) {
+            Some(allocation) => allocation.clone(),
+            None => return,
+        };
+        let indication = match STUN::decode(&mut Cursor::new(message_bin), &mut None) {
+            Ok(indication) => indication,
+            Err(e) => {
+                debug!(
+                    "Dropping undecodable indication from {:?}: {:?}",
+                    client.address(),
+                    e
+                );
+                return;
+            }
+        };
+        let mut peer = None;
+        let mut data = None;
+        for attribute in indication.body.attributes {
+            match attribute.value {
+                STUNAttributesContent::XORPeerAddress { address } => peer = Some(address),
+                STUNAttributesContent::Data { data: bin } => data = Some(bin),
+                _ => {}
+            }
+        }
+        match (peer, data) {
+            (Some(peer), Some(data)) => self.relay_to_peer(&allocation, &data, peer),
+            _ => debug!("Dropping Send indication without XOR-PEER-ADDRESS or DATA"),
+        }
+    }
+
+    fn relay_channel_data(&self, message_bin: &[u8], client: &TurnServerClient) {
+        let allocation = match self.lock_allocations().get(&client.five_tuple()) {
+            Some(allocation) => allocation.clone(),
+            None => return,
+        };
+        let message = match ChannelData::decode(message_bin) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!(
+                    "Dropping undecodable ChannelData from {:?}: {:?}",
+                    client.address(),
+                    e
+                );
+                return;
+            }
+        };
+        let peer = match allocation
+            .lock_bindings()
+            .channels
+            .get(&message.channel_number)
+        {
+            Some((peer, expires_at)) if *expires_at > Instant::now() => *peer,
+            _ => {
+                debug!(
+                    "Dropping ChannelData on unbound channel {:#06x}",
+                    message.channel_number
+                );
+                return;
+            }
+        };
+        self.relay_to_peer(&allocation, &message.data, peer);
+    }
+
+    fn relay_to_peer(&self, allocation: &TurnServerAllocation, data: &[u8], peer: SocketAddr) {
+        if !self
+            .bandwidth
+            .allow(&self.quota, &allocation.username, data.len())
+        {
+            debug!("Dropping datagram for {:?}, over quota", peer);
+            TurnServerAllocation::count_drop(&allocation.counters);
+            return;
+        }
+        allocation.send_to_peer(data, peer);
+    }
+
+    pub(super) fn remove_allocation(&self, five_tuple: &TurnServerFiveTuple) {
+        match self.lock_allocations().remove(five_tuple) {
+            Some(allocation) => allocation.closed.store(true, Ordering::Relaxed),
+            None => {}
+        }
+    }
+
+    fn remove_expired_allocations(&self) {
+        let now = Instant::now();
+        self.lock_allocations().retain(|_, allocation| {
+            if allocation.lock_bindings().expires_at > now {
+                allocation.remove_unbound_connections();
+                return true;
+            }
+            info!("Allocation {:?} expired", allocation.relayed_address);
+            allocation.closed.store(true, Ordering::Relaxed);
+            return false;
+        });
+        self.lock_reservations()
+            .retain(|_, reservation| reservation.expires_at > now);
+        //Threads of closed allocations and connections end on their own
+        self.lock_threads().retain(|x| !x.is_finished());
+    }
+
+    fn close_allocations(&self) {
+        self.lock_reservations().clear();
+        for (_, allocation) in self.lock_allocations().drain() {
+            allocation.closed.store(true, Ordering::Relaxed);
+        }
+        let threads: Vec<JoinHandle<()>> = self.lock_threads().drain(..).collect();
+        for thread in threads {
+            if thread.join().is_err() {
+                error!("TURN relay thread panicked");
+            }
+        }
+    }
+}
+
+//Sets the DF bit on what the socket sends, peers past a smaller MTU then get nothing instead of
+//fragments. The OS answers senders with ICMP fragmentation needed.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_dont_fragment(socket: &UdpSocket) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let (level, name, value) = match socket.local_addr() {
+        Ok(address) if address.is_ipv4() => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        ),
+        Ok(_) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+        Err(e) => return Err(e),
+    };
+    //SAFETY: the descriptor is owned by `socket` and `value` outlives the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_dont_fragment(_socket: &UdpSocket) -> std::io::Result<()> {
+    return Err(std::io::ErrorKind::Unsupported.into());
+}
+
+impl TurnServerHandle {
+    /// Number of live allocations
+    pub fn allocation_count(&self) -> usize {
+        return self.server.lock_allocations().len();
+    }
+
+    /// Live allocations with their counters
+    pub fn allocations(&self) -> Vec<TurnAllocationInfo> {
+        return self
+            .server
+            .lock_allocations()
+            .iter()
+            .map(|((transport, client), allocation)| TurnAllocationInfo {
+                username: allocation.username.clone(),
+                client: *client,
+                client_transport: *transport,
+                relayed_address: allocation.relayed_address,
+                additional_relayed_address: allocation.additional_relayed_address(),
+                age: allocation.created_at.elapsed(),
+                counters: allocation.counters.snapshot(),
+            })
+            .collect();
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown_and_join();
+    }
+
+    fn shutdown_and_join(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("TURN server thread panicked");
+            }
+        }
+        self.server.close_allocations();
+    }
+}
+
+impl Drop for TurnServerHandle {
+    fn drop(&mut self) {
+        self.shutdown_and_join();
+    }
+}
+
+impl TurnServerClient {
+    pub(super) fn address(&self) -> SocketAddr {
+        match self {
+            Self::Udp { address, .. } | Self::Tcp { address, .. } => return *address,
+        }
+    }
+
+    pub(super) fn five_tuple(&self) -> TurnServerFiveTuple {
+        match self {
+            Self::Udp { address, .. } => return (TURN_TRANSPORT_UDP, *address),
+            Self::Tcp { address, .. } => return (TURN_TRANSPORT_TCP, *address),
+        }
+    }
+
+    //ChannelData is padded on streams
+    pub(super) fn is_stream(&self) -> bool {
+        return matches!(self, Self::Tcp { .. });
+    }
+
+    pub(super) fn send(&self, message_bin: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Udp { socket, address } => match socket.send_to(message_bin, address) {
+                Ok(_) => return Ok(()),
+                Err(e) => return Err(e),
+            },
+            Self::Tcp { stream, .. } => {
+                let mut stream = match stream.lock() {
+                    Ok(stream) => stream,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                return stream.write_all(message_bin);
+            }
+        }
+    }
+}
+
+impl TurnServerRelay {
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Self::Udp(socket) => return socket.local_addr(),
+            Self::Tcp(_, relay_address) => return Ok(relay_address.address),
+        }
+    }
+}
+
+impl TurnServerAllocation {
+    fn lock_bindings(&self) -> MutexGuard<'_, TurnServerBindings> {
+        match self.bindings.lock() {
+            Ok(bindings) => return bindings,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    pub(super) fn lock_connections(
+        &self,
+    ) -> MutexGuard<'_, HashMap<u32, TurnServerPeerConnection>> {
+        match self.connections.lock() {
+            Ok(connections) => return connections,
+            Err(poisoned) => return poisoned.into_inner(),
+        }
+    }
+
+    //LIFETIME is what is left, rounded up so that a fresh allocation reports what was granted
+    fn allocate_success_attributes(&self) -> Vec<STUNAttributesContent> {
+        let lifetime = self
+            .lock_bindings()
+            .expires_at
+            .saturating_duration_since(Instant::now());
+        let mut attributes = vec![STUNAttributesContent::new_xor_relayed_address(
+            self.relayed_address,
+        )];
+        match self.additional_relayed_address() {
+            Some(address) => {
+                attributes.push(STUNAttributesContent::new_xor_relayed_address(address))
+            }
+            None => {}
+        }
+        match self.address_error {
+            Some((family, code)) => {
+                attributes.push(STUNAttributesContent::new_address_error_code(family, code))
+            }
+            None => {}
+        }
+        attributes.push(STUNAttributesContent::new_lifetime(
+            lifetime.as_millis().div_ceil(1000) as u32,
+        ));
+        attributes.push(STUNAttributesContent::new_xor_mapped_address(
+            self.client.address(),
+        ));
+        match self.reservation_token {
+            Some(token) => attributes.push(STUNAttributesContent::new_reservation_token(token)),
+            None => {}
+        }
+        return attributes;
+    }
+
+    pub(super) fn additional_relayed_address(&self) -> Option<SocketAddr> {
+        return self.additional_relay.as_ref().map(|(address, _)| *address);
+    }
+
+    fn has_family(&self, family: u8) -> bool {
+        let is_ipv4 = match family {
+            TURN_ADDRESS_FAMILY_IPV4 => true,
+            TURN_ADDRESS_FAMILY_IPV6 => false,
+            _ => return false,
+        };
+        return [
+            Some(self.relayed_address),
+            self.additional_relayed_address(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|x| x.is_ipv4() == is_ipv4);
+    }
+
+    //Peers can only be reached over the family of a relayed address
+    fn check_peer_family(&self, peer: IpAddr) -> Result<(), STUNErrorCode> {
+        if peer.is_ipv4() != self.relayed_address.is_ipv4()
+            && self
+                .additional_relayed_address()
+                .is_none_or(|x| peer.is_ipv4() != x.is_ipv4())
+        {
+            return Err(STUNErrorCode::PeerAddressFamilyMismatch);
+        }
+        return Ok(());
+    }
+
+    //UDP relay socket and relayed address, the additional ones or the requested ones
+    fn udp_relay(&self, additional: bool) -> Option<(&UdpSocket, SocketAddr)> {
+        match (additional, &self.relay, &self.additional_relay) {
+            (false, TurnServerRelay::Udp(relay_socket), _) => {
+                return Some((relay_socket, self.relayed_address))
+            }
+            (true, _, Some((address, relay_socket))) => return Some((relay_socket, *address)),
+            _ => return None,
+        }
+    }
+
+    pub(super) fn has_permission(&self, peer: IpAddr) -> bool {
+        match self.lock_bindings().permissions.get(&peer) {
+            Some(expires_at) => return *expires_at > Instant::now(),
+            None => return false,
+        }
+    }
+
+    fn channel_of(&self, peer: SocketAddr) -> Option<u16> {
+        let now = Instant::now();
+        for (number, (bound_peer, expires_at)) in self.lock_bindings().channels.iter() {
+            if *bound_peer == peer && *expires_at > now {
+                return Some(*number);
+            }
+        }
+        return None;
+    }
+
+    fn send_to_peer(&self, data: &[u8], peer: SocketAddr) {
+        //Sent from the relayed address of the family of the peer
+        let relay = [false, true]
+            .into_iter()
+            .filter_map(|x| self.udp_relay(x))
+            .find(|(_, address)| address.is_ipv4() == peer.is_ipv4());
+        let (relay_socket, relayed_address) = match relay {
+            Some(relay) => relay,
+            None => {
+                debug!(
+                    "Dropping datagram for {:?}, no UDP relay of its family",
+                    peer
+                );
+                return;
+            }
+        };
+        if !self.has_permission(peer.ip()) {
+            debug!("No permission for {:?} on {:?}", peer, relayed_address);
+            return;
+        }
+        match relay_socket.send_to(data, peer) {
+            Ok(_) => TurnAllocationCounters::count(
+                &self.counters.bytes_to_peers,
+                &self.counters.packets_to_peers,
+                data.len(),
+            ),
+            Err(e) => warn!("Error relaying to {:?}: {}", peer, e),
+        }
+    }
+
+    pub(super) fn count_drop(counters: &TurnAllocationCounters) {
+        counters.dropped_over_quota.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //Forwards what peers with a permission send to the relayed address, in ChannelData when
+    //the peer has a channel and in a Data indication otherwise
+    fn relay_loop(&self, additional: bool, quota: &TurnQuota, bandwidth: &TurnBandwidth) {
+        let (relay_socket, relayed_address) = match self.udp_relay(additional) {
+            Some(relay) => relay,
+            None => return,
+        };
+        let mut buf = vec![0; TURN_SERVER_RECEIVE_BUFFER_SIZE];
+        while !self.closed.load(Ordering::Relaxed) {
+            let (len, peer) = match relay_socket.recv_from(&mut buf) {
+                Ok(x) => x,
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Error receiving on {:?}: {}", relayed_address, e);
+                    continue;
+                }
+            };
+            if !self.has_permission(peer.ip()) {
+                debug!("Dropping {} bytes from {:?}, no permission", len, peer);
+                continue;
+            }
+            if !bandwidth.allow(quota, &self.username, len) {
+                debug!("Dropping {} bytes from {:?}, over quota", len, peer);
+                Self::count_drop(&self.counters);
+                continue;
+            }
+            let message_bin = match self.channel_of(peer) {
+                Some(channel_number) => ChannelData::new(channel_number, buf[..len].to_vec())
+                    .encode(self.client.is_stream()),
+                None => Self::encode_data_indication(&buf[..len], peer),
+            };
+            let message_bin = match message_bin {
+                Ok(bin) => bin,
+                Err(e) => {
+                    debug!("Dropping datagram from {:?}: {:?}", peer, e);
+                    continue;
+                }
+            };
+            match self.client.send(&message_bin) {
+                Ok(()) => TurnAllocationCounters::count(
+                    &self.counters.bytes_from_peers,
+                    &self.counters.packets_from_peers,
+                    len,
+                ),
+                Err(e) => warn!(
+                    "Error relaying to client {:?}: {}",
+                    self.client.address(),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn encode_data_indication(data: &[u8], peer: SocketAddr) -> Result<Vec<u8>, STUNError> {
+        let mut indication =
+            STUN::new_default(STUNMessageClass::Indication, STUNMessageMethod::Data, None);
+        for attribute in [
+            STUNAttributesContent::new_xor_peer_address(peer),
+            STUNAttributesContent::new_data(data.to_vec()),
+        ] {
+            let attribute_type = attribute.attribute_type();
+            indication
+                .body
+                .add_new_attribute(attribute, attribute_type, 0);
+        }
+        let mut indication_bin = Vec::new();
+        match indication.encode(&mut Cursor::new(&mut indication_bin), &None) {
+            Ok(()) => return Ok(indication_bin),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::STUNClient::client::StunClient;
+    use crate::STUNServer::auth::{StaticCredentials, TurnRestCredentials};
+    use crate::TURNClient::client::{TurnAllocation, TurnClient};
+    use crate::TURNClient::relay::TurnRelayedSocket;
+
+    fn turn_server() -> TurnServer {
+        let mut credentials = StaticCredentials::new();
+        credentials.add_user("user", "secret");
+        let mut server = TurnServer::new(
+            "127.0.0.1".parse().unwrap(),
+            StunLongTermAuth::new("example.org".to_string(), Arc::new(credentials)),
+        );
+        //Peers of the tests are on loopback, which is denied by default
+        let mut peer_policy = TurnPeerPolicy::default();
+        peer_policy.add_allow("127.0.0.0/8".parse().unwrap());
+        server.set_peer_policy(peer_policy);
+        return server;
+    }
+
+    fn allocate(server: SocketAddr, password: &str) -> Result<TurnAllocation, STUNError> {
+        let mut client = StunClient::new(server);
+        client
+            .set_timeout(Duration::from_secs(2))
+            .set_retry_interval(Duration::from_millis(200));
+        return TurnClient::new(client, "user".to_string(), password.to_string())
+            .allocate(UdpSocket::bind("127.0.0.1:0").unwrap());
+    }
+
+    fn allocate_over_tcp(server: SocketAddr, transport: u8) -> Result<TurnAllocation, STUNError> {
+        let mut client = StunClient::new(server);
+        client.set_timeout(Duration::from_secs(2));
+        let mut turn_client = TurnClient::new(client, "user".to_string(), "secret".to_string());
+        turn_client.set_requested_transport(transport);
+        return turn_client.allocate_over_tcp(TcpStream::connect(server).unwrap());
+    }
+
+    fn exchange(local: &mut TcpStream, remote: &mut TcpStream) {
+        use std::io::Read;
+        let mut buf = [0; 4];
+        local.write_all(b"ping").unwrap();
+        remote
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        remote.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        remote.write_all(b"pong").unwrap();
+        local
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        local.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    fn receive(udp: &UdpSocket) -> Option<(Vec<u8>, SocketAddr)> {
+        let mut buf = [0; 1500];
+        match udp.recv_from(&mut buf) {
+            Ok((len, source)) => return Some((buf[..len].to_vec(), source)),
+            Err(_) => return None,
+        }
+    }
+
+    #[test]
+    fn test_relay_between_client_and_peer() {
+        let handle = turn_server().spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let socket = TurnRelayedSocket::new(allocate(handle.local_addr, "secret").unwrap());
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let relayed_address = socket.local_addr().unwrap();
+        assert_eq!(relayed_address.ip().to_string(), "127.0.0.1");
+        assert!(TURN_SERVER_DEFAULT_RELAY_PORTS.contains(&relayed_address.port()));
+        assert_eq!(
+            socket.allocation().mapped_address(),
+            Some(socket.allocation().local_addr().unwrap())
+        );
+        assert_eq!(handle.allocation_count(), 1);
+
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let peer_address = peer.local_addr().unwrap();
+        let mut buf = [0; 64];
+        //First in a Send indication, then over the channel once it is bound
+        for datagram in [&b"hello peer"[..], &b"again"[..], &b"once more"[..]] {
+            socket.send_to(datagram, peer_address).unwrap();
+            assert_eq!(receive(&peer), Some((datagram.to_vec(), relayed_address)));
+            peer.send_to(datagram, relayed_address).unwrap();
+            assert_eq!(
+                socket.recv_from(&mut buf).unwrap(),
+                (datagram.len(), peer_address)
+            );
+            assert_eq!(&buf[..datagram.len()], datagram);
+        }
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while socket.allocation().channel_number(peer_address).is_none() {
+            assert!(Instant::now() < deadline, "Channel was never bound");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        socket.send_to(b"on the channel", peer_address).unwrap();
+        assert_eq!(
+            receive(&peer),
+            Some((b"on the channel".to_vec(), relayed_address))
+        );
+        peer.send_to(b"back on it", relayed_address).unwrap();
+        assert_eq!(socket.recv_from(&mut buf).unwrap(), (10, peer_address));
+
+        //Nothing from peers without a permission gets through
+        let stranger = UdpSocket::bind("127.0.0.2:0").unwrap();
+        stranger.send_to(b"let me in", relayed_address).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        assert_eq!(
+            socket.recv_from(&mut buf).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+
+        socket.release().unwrap();
+        assert_eq!(handle.allocation_count(), 0);
+        handle.stop();
+    }
+
+    #[test]
+    fn test_wrong_password_is_rejected() {
+        let handle = turn_server().spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        match allocate(handle.local_addr, "not-the-secret") {
+            Ok(_) => panic!("Allocated with the wrong password"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(401)),
+        }
+        assert_eq!(handle.allocation_count(), 0);
+    }
+
+    #[test]
+    fn test_denied_peers_are_forbidden() {
+        let mut server = turn_server();
+        server.set_peer_policy(TurnPeerPolicy::default());
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let allocation = allocate(handle.local_addr, "secret").unwrap();
+        for peer in ["127.0.0.1", "192.168.1.1"] {
+            match allocation.create_permission(&[peer.parse().unwrap()]) {
+                Ok(()) => panic!("Permission granted for {}", peer),
+                Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(403)),
+            }
+        }
+        match allocation.bind_channel("10.0.0.1:5000".parse().unwrap()) {
+            Ok(_) => panic!("Channel bound to a private address"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(403)),
+        }
+        //All or nothing
+        match allocation.create_permission(&[
+            "198.51.100.1".parse().unwrap(),
+            "169.254.169.254".parse().unwrap(),
+        ]) {
+            Ok(()) => panic!("Permission granted for a link-local address"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(403)),
+        }
+        allocation
+            .create_permission(&["198.51.100.1".parse().unwrap()])
+            .unwrap();
+        allocation.release().unwrap();
+    }
+
+    #[test]
+    fn test_rest_credentials() {
+        let credentials = Arc::new(TurnRestCredentials::new("shared secret"));
+        let server = TurnServer::new(
+            "127.0.0.1".parse().unwrap(),
+            StunLongTermAuth::new("example.org".to_string(), credentials.clone()),
+        );
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let allocate_as = |(username, password): (String, String)| {
+            let mut client = StunClient::new(handle.local_addr);
+            client.set_timeout(Duration::from_secs(2));
+            return TurnClient::new(client, username, password)
+                .allocate(UdpSocket::bind("127.0.0.1:0").unwrap());
+        };
+
+        let allocation = allocate_as(credentials.issue("alice", Duration::from_secs(60))).unwrap();
+        allocation.release().unwrap();
+        let username = "1:alice".to_string();
+        let password = TurnRestCredentials::password("shared secret", &username);
+        match allocate_as((username, password)) {
+            Ok(_) => panic!("Allocated with expired credentials"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(401)),
+        }
+    }
+
+    #[test]
+    fn test_allocation_and_bandwidth_quotas() {
+        let mut credentials = StaticCredentials::new();
+        credentials
+            .add_user("user", "secret")
+            .add_user("other", "secret");
+        let mut server = TurnServer::new(
+            "127.0.0.1".parse().unwrap(),
+            StunLongTermAuth::new("example.org".to_string(), Arc::new(credentials)),
+        );
+        let mut quota = TurnQuota::default();
+        quota
+            .per_user
+            .set_allocations(Some(1))
+            .set_bytes_per_second(Some(100));
+        quota
+            .total
+            .set_allocations(Some(2))
+            .set_lifetime(Some(Duration::from_secs(5)));
+        server
+            .set_quota(quota)
+            .set_peer_policy(TurnPeerPolicy::allow_all());
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let allocate_as = |username: &str| {
+            let mut client = StunClient::new(handle.local_addr);
+            client.set_timeout(Duration::from_secs(2));
+            return TurnClient::new(client, username.to_string(), "secret".to_string())
+                .allocate(UdpSocket::bind("127.0.0.1:0").unwrap());
+        };
+
+        let allocation = allocate_as("user").unwrap();
+        assert_eq!(allocation.lifetime(), Duration::from_secs(5));
+        match allocate_as("user") {
+            Ok(_) => panic!("Allocated over the quota of the user"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(486)),
+        }
+        let _other = allocate_as("other").unwrap();
+        match allocate_as("other") {
+            Ok(_) => panic!("Allocated over the quota of the server"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(508)),
+        }
+
+        //80 bytes go through, the next 80 into debt and the last ones are dropped
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        allocation
+            .create_permission(&[peer.local_addr().unwrap().ip()])
+            .unwrap();
+        for _ in 0..3 {
+            allocation
+                .send_indication(&[0; 80], peer.local_addr().unwrap())
+                .unwrap();
+        }
+        assert_eq!(receive(&peer).unwrap().0.len(), 80);
+        assert_eq!(receive(&peer).unwrap().0.len(), 80);
+        std::thread::sleep(Duration::from_millis(100));
+        let info = handle
+            .allocations()
+            .into_iter()
+            .find(|x| x.username == "user")
+            .unwrap();
+        assert_eq!(info.client, allocation.local_addr().unwrap());
+        assert_eq!(info.client_transport, TURN_TRANSPORT_UDP);
+        assert_eq!(info.relayed_address, allocation.relayed_address());
+        assert_eq!(info.counters.bytes_to_peers, 160);
+        assert_eq!(info.counters.packets_to_peers, 2);
+        assert_eq!(info.counters.dropped_over_quota, 1);
+        assert_eq!(info.counters.bytes_from_peers, 0);
+    }
+
+    #[test]
+    fn test_concurrent_allocations_keep_quota() {
+        let mut server = turn_server();
+        let mut quota = TurnQuota::default();
+        quota.total.set_allocations(Some(1));
+        server.set_quota(quota);
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let local_addr = handle.local_addr;
+
+        //Each TCP client has its own thread on the server
+        let threads: Vec<JoinHandle<Result<TurnAllocation, STUNError>>> = (0..8)
+            .map(|_| std::thread::spawn(move || allocate_over_tcp(local_addr, TURN_TRANSPORT_UDP)))
+            .collect();
+        let results: Vec<Result<TurnAllocation, STUNError>> =
+            threads.into_iter().map(|x| x.join().unwrap()).collect();
+        assert_eq!(results.iter().filter(|x| x.is_ok()).count(), 1);
+        for result in results.iter() {
+            match result {
+                Ok(_) => {}
+                Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(508)),
+            }
+        }
+        assert_eq!(handle.allocation_count(), 1);
+    }
+
+    #[test]
+    fn test_relay_port_exhaustion_and_expiry() {
+        //A single port, free when the server starts
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut server = turn_server();
+        server
+            .set_relay_ports(port..=port)
+            .set_default_lifetime(Duration::from_secs(1))
+            .set_max_lifetime(Duration::from_secs(1));
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let allocation = allocate(handle.local_addr, "secret").unwrap();
+        assert_eq!(allocation.relayed_address().port(), port);
+        assert_eq!(allocation.lifetime(), Duration::from_secs(1));
+        match allocate(handle.local_addr, "secret") {
+            Ok(_) => panic!("Allocated without a free relay port"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(508)),
+        }
+
+        //Dropped without releasing, the server lets it expire and reuses the port
+        drop(allocation);
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while handle.allocation_count() > 0 {
+            assert!(Instant::now() < deadline, "Allocation never expired");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        std::thread::sleep(2 * TURN_SERVER_POLL_INTERVAL);
+        let allocation = allocate(handle.local_addr, "secret").unwrap();
+        assert_eq!(allocation.relayed_address().port(), port);
+        allocation.release().unwrap();
+    }
+
+    #[test]
+    fn test_even_port_reservation_and_dont_fragment() {
+        let handle = turn_server().spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let turn_client = |configure: &dyn Fn(&mut TurnClient)| {
+            let mut client = StunClient::new(handle.local_addr);
+            client.set_timeout(Duration::from_secs(2));
+            let mut turn_client = TurnClient::new(client, "user".to_string(), "secret".to_string());
+            configure(&mut turn_client);
+            return turn_client.allocate(UdpSocket::bind("127.0.0.1:0").unwrap());
+        };
+
+        let even = turn_client(&|x| {
+            x.set_even_port(Some(true));
+        })
+        .unwrap();
+        assert_eq!(even.relayed_address().port() % 2, 0);
+        let token = even.reservation_token().unwrap();
+
+        //The token and EVEN-PORT don't go together
+        match turn_client(&|x| {
+            x.set_even_port(Some(false))
+                .set_reservation_token(Some(token));
+        }) {
+            Ok(_) => panic!("Allocated with both EVEN-PORT and RESERVATION-TOKEN"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(400)),
+        }
+        let odd = turn_client(&|x| {
+            x.set_reservation_token(Some(token));
+        })
+        .unwrap();
+        assert_eq!(
+            odd.relayed_address().port(),
+            even.relayed_address().port() + 1
+        );
+        assert_eq!(odd.reservation_token(), None);
+        //Reservations are used once
+        match turn_client(&|x| {
+            x.set_reservation_token(Some(token));
+        }) {
+            Ok(_) => panic!("Allocated a reservation twice"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(508)),
+        }
+
+        let dont_fragment = turn_client(&|x| {
+            x.set_even_port(Some(false)).set_dont_fragment(true);
+        });
+        if TURN_SERVER_DONT_FRAGMENT_SUPPORTED {
+            let dont_fragment = dont_fragment.unwrap();
+            assert_eq!(dont_fragment.relayed_address().port() % 2, 0);
+            assert_eq!(dont_fragment.reservation_token(), None);
+        } else {
+            assert_eq!(
+                dont_fragment.err().unwrap().error_type,
+                STUNErrorType::ErrorResponse(420)
+            );
+        }
+    }
+
+    #[test]
+    fn test_dual_stack_allocations() {
+        let mut server = turn_server();
+        server
+            .set_additional_relay_ip(Some("::1".parse().unwrap()))
+            .set_peer_policy(TurnPeerPolicy::allow_all());
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let turn_client = |requested: Option<u8>, additional: Option<u8>| {
+            let mut client = StunClient::new(handle.local_addr);
+            client.set_timeout(Duration::from_secs(2));
+            let mut turn_client = TurnClient::new(client, "user".to_string(), "secret".to_string());
+            turn_client
+                .set_requested_address_family(requested)
+                .set_additional_address_family(additional);
+            return turn_client.allocate(UdpSocket::bind("127.0.0.1:0").unwrap());
+        };
+
+        let ipv6 = turn_client(Some(TURN_ADDRESS_FAMILY_IPV6), None).unwrap();
+        assert!(ipv6.relayed_address().is_ipv6());
+        assert_eq!(ipv6.additional_relayed_address(), None);
+        match turn_client(
+            Some(TURN_ADDRESS_FAMILY_IPV4),
+            Some(TURN_ADDRESS_FAMILY_IPV6),
+        ) {
+            Ok(_) => panic!("Allocated with both address family attributes"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(400)),
+        }
+
+        //Peers of both families go through the relayed address of theirs
+        let socket =
+            TurnRelayedSocket::new(turn_client(None, Some(TURN_ADDRESS_FAMILY_IPV6)).unwrap());
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let relayed_addresses = [
+            socket.allocation().relayed_address(),
+            socket.allocation().additional_relayed_address().unwrap(),
+        ];
+        assert!(relayed_addresses[0].is_ipv4());
+        assert!(relayed_addresses[1].is_ipv6());
+        let mut buf = [0; 64];
+        for (peer_ip, relayed_address) in ["127.0.0.1:0", "[::1]:0"]
+            .into_iter()
+            .zip(relayed_addresses)
+        {
+            let peer = UdpSocket::bind(peer_ip).unwrap();
+            peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let peer_address = peer.local_addr().unwrap();
+            socket.send_to(b"hello peer", peer_address).unwrap();
+            assert_eq!(
+                receive(&peer),
+                Some((b"hello peer".to_vec(), relayed_address))
+            );
+            peer.send_to(b"hello", relayed_address).unwrap();
+            assert_eq!(socket.recv_from(&mut buf).unwrap(), (5, peer_address));
+        }
+        let info = handle
+            .allocations()
+            .into_iter()
+            .find(|x| x.relayed_address == relayed_addresses[0])
+            .unwrap();
+        assert_eq!(info.additional_relayed_address, Some(relayed_addresses[1]));
+    }
+
+    #[test]
+    fn test_unsupported_address_family() {
+        let handle = turn_server().spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut client = StunClient::new(handle.local_addr);
+        client.set_timeout(Duration::from_secs(2));
+        let mut turn_client = TurnClient::new(client, "user".to_string(), "secret".to_string());
+        turn_client.set_requested_address_family(Some(TURN_ADDRESS_FAMILY_IPV6));
+        match turn_client.allocate(UdpSocket::bind("127.0.0.1:0").unwrap()) {
+            Ok(_) => panic!("Allocated IPv6 without an IPv6 relay IP"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(440)),
+        }
+        //The IPv4 half of a dual allocation is still granted
+        turn_client
+            .set_requested_address_family(None)
+            .set_additional_address_family(Some(TURN_ADDRESS_FAMILY_IPV6));
+        let allocation = turn_client
+            .allocate(UdpSocket::bind("127.0.0.1:0").unwrap())
+            .unwrap();
+        assert!(allocation.relayed_address().is_ipv4());
+        assert_eq!(allocation.additional_relayed_address(), None);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_set_dont_fragment() {
+        use std::os::unix::io::AsRawFd;
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        set_dont_fragment(&socket).unwrap();
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(result, 0);
+        assert_eq!(value, libc::IP_PMTUDISC_DO);
+    }
+
+    #[test]
+    fn test_max_tcp_connections() {
+        let mut server = turn_server();
+        server.set_max_tcp_connections(1);
+        let handle = server.spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let allocation = allocate_over_tcp(handle.local_addr, TURN_TRANSPORT_UDP).unwrap();
+        //Closed by the server as soon as accepted
+        let mut extra = TcpStream::connect(handle.local_addr).unwrap();
+        extra
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(std::io::Read::read(&mut extra, &mut buf).unwrap(), 0);
+
+        drop(allocation);
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while handle.server.tcp_connections.load(Ordering::SeqCst) > 0 {
+            assert!(Instant::now() < deadline, "TCP connection never closed");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        allocate_over_tcp(handle.local_addr, TURN_TRANSPORT_UDP)
+            .unwrap()
+            .release()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tcp_relay_ports_are_not_shared() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut server = turn_server();
+        server.set_relay_ports(port..=port);
+        let relay_ip = server.relay_ip;
+        //Port reuse would let the second listener bind the same port
+        let relay = server.bind_relay_listener(relay_ip).unwrap();
+        assert_eq!(relay.local_addr().unwrap().port(), port);
+        assert!(server.bind_relay_listener(relay_ip).is_none());
+        drop(relay);
+        assert!(server.bind_relay_listener(relay_ip).is_some());
+    }
+
+    #[test]
+    fn test_tcp_relay_connect_and_accept() {
+        let handle = turn_server().spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+        let allocation = allocate_over_tcp(handle.local_addr, TURN_TRANSPORT_TCP).unwrap();
+        let relayed_address = allocation.relayed_address();
+        assert_eq!(relayed_address.ip().to_string(), "127.0.0.1");
+
+        //Outbound, the peer sees the connection coming from the relayed address
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut relayed = allocation.connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, from) = listener.accept().unwrap();
+        assert_eq!(from, relayed_address);
+        exchange(&mut relayed, &mut peer);
+        match allocation.connect(listener.local_addr().unwrap()) {
+            Ok(_) => panic!("Connected twice to the same peer"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::ErrorResponse(446)),
+        }
+
+        //Inbound, the peer has a permission since the outbound connection
+        let mut peer = TcpStream::connect(relayed_address).unwrap();
+        let (mut relayed, from) = allocation.accept(Duration::from_secs(2)).unwrap();
+        assert_eq!(from, peer.local_addr().unwrap());
+        exchange(&mut relayed, &mut peer);
+
+        //A UDP relay is still available over TCP, a TCP one is not over UDP
+        let udp_relayed = allocate_over_tcp(handle.local_addr, TURN_TRANSPORT_UDP).unwrap();
+        assert_eq!(handle.allocation_count(), 2);
+        let mut client = StunClient::new(handle.local_addr);
+        client.set_timeout(Duration::from_secs(2));
+        let mut turn_client = TurnClient::new(client, "user".to_string(), "secret".to_string());
+        turn_client.set_requested_transport(TURN_TRANSPORT_TCP);
+        match turn_client.allocate(UdpSocket::bind("127.0.0.1:0").unwrap()) {
+            Ok(_) => panic!("Allocated a TCP relay over UDP"),
+            Err(e) => assert_eq!(e.error_type, STUNErrorType::InvalidConfiguration),
+        }
+
+        //Closing the control connection deletes the allocation
+        drop(udp_relayed);
+        allocation.release().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while handle.allocation_count() > 0 {
+            assert!(
+                Instant::now() < deadline,
+                "Allocation outlived its connection"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
@@ -0,0 +1,623 @@
+//TURN over TCP and TCP relays (RFC 6062). Clients connect to the port of the server over TCP,
+//the connection acts as a 5-tuple like a UDP one does. On such a control connection, an
+//Allocate with REQUESTED-TRANSPORT TCP gets a TCP relay: a listener on the relayed address.
+//
+//Each peer connection, accepted on the listener or made with a Connect request, gets a
+//CONNECTION-ID. The client opens a new data connection to the server and sends a
+//ConnectionBind with the ID on it, after the response the data connection and the peer
+//connection are piped together.
+use super::quota::{TurnAllocationCounters, TurnBandwidth, TurnQuota};
+use super::server::{
+    TurnServer, TurnServerAllocation, TurnServerClient, TurnServerPeerConnection, TurnServerRelay,
+    TURN_SERVER_POLL_INTERVAL,
+};
+use crate::STUNBody::attributes::attributes::{STUNAttributesContent, STUNErrorCode};
+use crate::STUNBody::body::STUNBody;
+use crate::STUNError::error::STUNError;
+use crate::STUNHeader::header::{STUNHeader, STUNMessageClass, STUNMessageMethod};
+use crate::STUNSerde::{decode::STUNDecode, encode::STUNEncode};
+use crate::STUN::stream::StunStreamReader;
+use crate::STUN::stun::STUN;
+use log::{debug, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//RFC 6062 section 5.2 and 5.3, how long connecting to a peer and waiting for the
+//ConnectionBind of the client may take
+const TURN_SERVER_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+const TURN_SERVER_LISTEN_BACKLOG: i32 = 128;
+const TURN_SERVER_PIPE_BUFFER_SIZE: usize = 16384;
+//How long a connection over its bandwidth quota waits before trying again
+const TURN_SERVER_THROTTLE_INTERVAL: Duration = Duration::from_millis(10);
+
+//Gives the relayed address of a TCP relay back when the listener is closed
+pub(super) struct TurnServerTcpRelayAddress {
+    pub(super) address: SocketAddr,
+    taken: Arc<Mutex<HashSet<SocketAddr>>>,
+}
+
+impl Drop for TurnServerTcpRelayAddress {
+    fn drop(&mut self) {
+        match self.taken.lock() {
+            Ok(mut taken) => taken.remove(&self.address),
+            Err(poisoned) => poisoned.into_inner().remove(&self.address),
+        };
+    }
+}
+
+impl TurnServer {
+    //Accepts clients connecting over TCP, each served by its own thread. Past
+    //`max_tcp_connections`, connections are closed right away.
+    pub(super) fn accept_until(
+        server: &Arc<Self>,
+        listener: &TcpListener,
+        shutdown: &Arc<AtomicBool>,
+    ) {
+        while !shutdown.load(Ordering::SeqCst) {
+            let (stream, source) = match listener.accept() {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(TURN_SERVER_POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Error accepting TCP client: {}", e);
+                    std::thread::sleep(TURN_SERVER_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            if server.tcp_connections.load(Ordering::SeqCst) >= server.max_tcp_connections {
+                info!("Too many TCP connections, closing the one of {:?}", source);
+                continue;
+            }
+            debug!("TCP client connected from {:?}", source);
+            server.tcp_connections.fetch_add(1, Ordering::SeqCst);
+            let connection_server = server.clone();
+            let connection_shutdown = shutdown.clone();
+            let thread = std::thread::spawn(move || {
+                connection_server.serve_tcp_connection(stream, source, &connection_shutdown);
+                connection_server
+                    .tcp_connections
+                    .fetch_sub(1, Ordering::SeqCst);
+            });
+            server.lock_threads().push(thread);
+        }
+    }
+
+    //A control connection, unless its first message is a ConnectionBind which makes it a data
+    //connection
+    fn serve_tcp_connection(&self, stream: TcpStream, source: SocketAddr, shutdown: &AtomicBool) {
+        match stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(TURN_SERVER_POLL_INTERVAL)))
+        {
+            Ok(()) => {}
+            Err(e) => {
+                warn!("Error setting up TCP connection of {:?}: {}", source, e);
+                return;
+            }
+        }
+        let mut reader_stream = match stream.try_clone() {
+            Ok(reader_stream) => reader_stream,
+            Err(e) => {
+                warn!("Error cloning TCP connection of {:?}: {}", source, e);
+                return;
+            }
+        };
+        let client = TurnServerClient::Tcp {
+            stream: Arc::new(Mutex::new(stream)),
+            address: source,
+        };
+        let mut reader = StunStreamReader::new();
+        let mut first_message = true;
+        while !shutdown.load(Ordering::SeqCst) {
+            let message_bin = match reader.read_frame(&mut reader_stream) {
+                Ok(Some(message_bin)) => message_bin,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("TCP connection of {:?} closed: {}", source, e);
+                    break;
+                }
+            };
+            if first_message && Self::is_connection_bind(&message_bin) {
+                self.serve_data_connection(&message_bin, reader, reader_stream, &client, shutdown);
+                return;
+            }
+            first_message = false;
+            match self.handle_message(&message_bin, &client) {
+                Some(response_bin) => match client.send(&response_bin) {
+                    Ok(()) => {}
+                    Err(e) => {
+                        warn!("Error sending response to {:?}: {}", source, e);
+                        break;
+                    }
+                },
+                None => {}
+            }
+        }
+        //RFC 6062 section 4, the allocation goes with its control connection
+        self.remove_allocation(&client.five_tuple());
+    }
+
+    fn is_connection_bind(message_bin: &[u8]) -> bool {
+        match STUNHeader::decode(&mut Cursor::new(message_bin), &mut None) {
+            Ok(header) => {
+                return header.message_class == STUNMessageClass::Request
+                    && header.message_method == STUNMessageMethod::ConnectionBind
+            }
+            Err(_) => return false,
+        }
+    }
+
+    //Port reuse, needed to connect to peers from the relayed address, would let two listeners
+    //share a port and split the peer connections between them. Addresses are taken from
+    //before binding until the listener is closed, so that concurrent Allocates get different
+    //ones.
+    pub(super) fn bind_relay_listener(&self, relay_ip: IpAddr) -> Option<TurnServerRelay> {
+        let mut taken = match self.tcp_relay_addresses.lock() {
+            Ok(taken) => taken,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let (listener, address) = match self.bind_relay(relay_ip, |address| {
+            if taken.contains(&address) {
+                return Err(std::io::Error::from(std::io::ErrorKind::AddrInUse));
+            }
+            let socket = match Self::relay_tcp_socket(address) {
+                Ok(socket) => socket,
+                Err(e) => return Err(e),
+            };
+            let listener: TcpListener = match socket.listen(TURN_SERVER_LISTEN_BACKLOG) {
+                Ok(()) => socket.into(),
+                Err(e) => return Err(e),
+            };
+            match listener.set_nonblocking(true) {
+                Ok(()) => return Ok((listener, address)),
+                Err(e) => return Err(e),
+            }
+        }) {
+            Some(bound) => bound,
+            None => return None,
+        };
+        taken.insert(address);
+        return Some(TurnServerRelay::Tcp(
+            listener,
+            TurnServerTcpRelayAddress {
+                address,
+                taken: self.tcp_relay_addresses.clone(),
+            },
+        ));
+    }
+
+    //Connections to peers are made from the relayed address, which the listener is bound to
+    //as well
+    fn relay_tcp_socket(address: SocketAddr) -> std::io::Result<Socket> {
+        let socket = match Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        ) {
+            Ok(socket) => socket,
+            Err(e) => return Err(e),
+        };
+        match socket.set_reuse_address(true) {
+            Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        #[cfg(unix)]
+        match socket.set_reuse_port(true) {
+            Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        match socket.bind(&address.into()) {
+            Ok(()) => return Ok(socket),
+            Err(e) => return Err(e),
+        }
+    }
+
+    //RFC 6062 section 5.2. The response is sent once the peer answered, from another thread.
+    pub(super) fn connect(
+        &self,
+        header: &STUNHeader,
+        body: &STUNBody,
+        client: &TurnServerClient,
+        username: &str,
+        key: &[u8],
+        request_len: usize,
+    ) -> Result<(), STUNErrorCode> {
+        let allocation = match self.allocation_of(client, username) {
+            Ok(allocation) => allocation,
+            Err(code) => return Err(code),
+        };
+        match allocation.relay {
+            TurnServerRelay::Tcp(..) => {}
+            TurnServerRelay::Udp(_) => return Err(STUNErrorCode::BadRequest),
+        }
+        let peer = match body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::XORPeerAddress { address } => Some(address),
+            _ => None,
+        }) {
+            Some(peer) => peer,
+            None => return Err(STUNErrorCode::BadRequest),
+        };
+        match self.check_peer(&allocation, peer.ip()) {
+            Ok(()) => {}
+            Err(code) => return Err(code),
+        }
+        if !allocation.has_permission(peer.ip()) {
+            return Err(STUNErrorCode::Forbidden);
+        }
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut connections = allocation.lock_connections();
+            if connections.values().any(|x| x.peer == peer) {
+                return Err(STUNErrorCode::ConnectionAlreadyExists);
+            }
+            connections.insert(
+                connection_id,
+                TurnServerPeerConnection {
+                    peer,
+                    stream: None,
+                    since: Instant::now(),
+                },
+            );
+        }
+        let success_bin = self.encode_result(
+            header,
+            Ok(vec![STUNAttributesContent::new_connection_id(
+                connection_id,
+            )]),
+            key,
+            request_len,
+        );
+        let failure_bin = self.encode_result(
+            header,
+            Err(STUNErrorCode::ConnectionTimeoutOrFailure),
+            key,
+            request_len,
+        );
+        let thread = std::thread::spawn(move || {
+            let response_bin = match Self::connect_to_peer(allocation.relayed_address, peer) {
+                Ok(stream) => {
+                    debug!("Connected {:?} to {:?}", allocation.relayed_address, peer);
+                    match allocation.lock_connections().get_mut(&connection_id) {
+                        Some(connection) => {
+                            connection.stream = Some(stream);
+                            connection.since = Instant::now();
+                        }
+                        None => return,
+                    }
+                    success_bin
+                }
+                Err(e) => {
+                    debug!("Error connecting to {:?}: {}", peer, e);
+                    allocation.lock_connections().remove(&connection_id);
+                    failure_bin
+                }
+            };
+            match response_bin {
+                Some(response_bin) => match allocation.client.send(&response_bin) {
+                    Ok(()) => {}
+                    Err(e) => warn!("Error answering Connect: {}", e),
+                },
+                None => {}
+            }
+        });
+        self.lock_threads().push(thread);
+        return Ok(());
+    }
+
+    fn connect_to_peer(
+        relayed_address: SocketAddr,
+        peer: SocketAddr,
+    ) -> std::io::Result<TcpStream> {
+        let socket = match Self::relay_tcp_socket(relayed_address) {
+            Ok(socket) => socket,
+            Err(e) => return Err(e),
+        };
+        match socket.connect_timeout(&peer.into(), TURN_SERVER_CONNECTION_TIMEOUT) {
+            Ok(()) => return Ok(socket.into()),
+            Err(e) => return Err(e),
+        }
+    }
+
+    //RFC 6062 section 5.4, answers the ConnectionBind and pipes the connection to its peer
+    fn serve_data_connection(
+        &self,
+        message_bin: &[u8],
+        reader: StunStreamReader,
+        stream: TcpStream,
+        client: &TurnServerClient,
+        shutdown: &AtomicBool,
+    ) {
+        let header = match STUNHeader::decode(&mut Cursor::new(message_bin), &mut None) {
+            Ok(header) => header,
+            Err(_) => return,
+        };
+        let request_len = message_bin.len();
+        let (body, username, key) =
+            match self.authenticated_request(message_bin, &header, client.address()) {
+                Ok(request) => request,
+                Err(response) => {
+                    match response {
+                        Some(response_bin) => {
+                            let _ = client.send(&response_bin);
+                        }
+                        None => {}
+                    }
+                    return;
+                }
+            };
+        let connection_id = body.attributes.iter().find_map(|x| match x.value {
+            STUNAttributesContent::ConnectionId { connection_id } => Some(connection_id),
+            _ => None,
+        });
+        let peer_connection = match connection_id {
+            Some(connection_id) => self.take_peer_connection(connection_id, &username),
+            None => None,
+        };
+        let (allocation, peer_stream) = match peer_connection {
+            Some(peer_connection) => peer_connection,
+            None => {
+                match self.encode_result(&header, Err(STUNErrorCode::BadRequest), &key, request_len)
+                {
+                    Some(response_bin) => {
+                        let _ = client.send(&response_bin);
+                    }
+                    None => {}
+                }
+                return;
+            }
+        };
+        match self.encode_result(&header, Ok(Vec::new()), &key, request_len) {
+            Some(response_bin) => match client.send(&response_bin) {
+                Ok(()) => {}
+                Err(_) => return,
+            },
+            None => return,
+        }
+        //Only one ConnectionBind is answered on a data connection, anything after it is data
+        let remaining = reader.into_remaining();
+        self.pipe(&allocation, stream, peer_stream, remaining, shutdown);
+        match connection_id {
+            Some(connection_id) => {
+                allocation.lock_connections().remove(&connection_id);
+            }
+            None => {}
+        }
+    }
+
+    //The peer connection waiting for a ConnectionBind with `connection_id`, marked as bound
+    fn take_peer_connection(
+        &self,
+        connection_id: u32,
+        username: &str,
+    ) -> Option<(Arc<TurnServerAllocation>, TcpStream)> {
+        let allocations: Vec<Arc<TurnServerAllocation>> =
+            self.lock_allocations().values().cloned().collect();
+        for allocation in allocations {
+            let mut connections = allocation.lock_connections();
+            let stream = match connections.get_mut(&connection_id) {
+                Some(connection) => connection.stream.take(),
+                None => continue,
+            };
+            drop(connections);
+            //The ID is only good for the user of the allocation
+            if allocation.username != username {
+                debug!("ConnectionBind for {} from another user", connection_id);
+                return None;
+            }
+            match stream {
+                Some(stream) => return Some((allocation, stream)),
+                None => return None,
+            }
+        }
+        return None;
+    }
+
+    //Copies both ways until either side closes or the allocation goes away
+    fn pipe(
+        &self,
+        allocation: &Arc<TurnServerAllocation>,
+        client_stream: TcpStream,
+        mut peer_stream: TcpStream,
+        remaining: Vec<u8>,
+        shutdown: &AtomicBool,
+    ) {
+        if !remaining.is_empty() && peer_stream.write_all(&remaining).is_err() {
+            return;
+        }
+        let (peer_reader, client_writer) =
+            match (peer_stream.try_clone(), client_stream.try_clone()) {
+                (Ok(peer_reader), Ok(client_writer)) => (peer_reader, client_writer),
+                _ => return,
+            };
+        info!(
+            "Piping {:?} to {:?} through {:?}",
+            client_stream.peer_addr().ok(),
+            peer_stream.peer_addr().ok(),
+            allocation.relayed_address
+        );
+        let pipe_allocation = allocation.clone();
+        let quota = self.quota;
+        let bandwidth = self.bandwidth.clone();
+        let peer_to_client = std::thread::spawn(move || {
+            let pipe = TurnServerPipe {
+                allocation: &pipe_allocation,
+                quota: &quota,
+                bandwidth: &bandwidth,
+                to_peer: false,
+            };
+            pipe.copy_until_closed(peer_reader, client_writer, None)
+        });
+        let pipe = TurnServerPipe {
+            allocation,
+            quota: &self.quota,
+            bandwidth: &self.bandwidth,
+            to_peer: true,
+        };
+        pipe.copy_until_closed(client_stream, peer_stream, Some(shutdown));
+        let _ = peer_to_client.join();
+    }
+}
+
+//One direction of a data connection
+struct TurnServerPipe<'a> {
+    allocation: &'a TurnServerAllocation,
+    quota: &'a TurnQuota,
+    bandwidth: &'a TurnBandwidth,
+    to_peer: bool,
+}
+
+impl TurnServerPipe<'_> {
+    //Copies `from` into `to`, then shuts both down so that the other direction ends too
+    fn copy_until_closed(
+        &self,
+        mut from: TcpStream,
+        mut to: TcpStream,
+        shutdown: Option<&AtomicBool>,
+    ) {
+        match from
+            .set_nonblocking(false)
+            .and_then(|_| from.set_read_timeout(Some(TURN_SERVER_POLL_INTERVAL)))
+        {
+            Ok(()) => {}
+            Err(_) => return,
+        }
+        let mut buf = vec![0; TURN_SERVER_PIPE_BUFFER_SIZE];
+        while !self.is_closed(shutdown) {
+            let len = match from.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    continue;
+                }
+                Err(_) => break,
+            };
+            //Streams are slowed down rather than cut when over the bandwidth quota
+            while !self
+                .bandwidth
+                .allow(self.quota, &self.allocation.username, len)
+            {
+                if self.is_closed(shutdown) {
+                    break;
+                }
+                std::thread::sleep(TURN_SERVER_THROTTLE_INTERVAL);
+            }
+            match to.write_all(&buf[..len]) {
+                Ok(()) => {}
+                Err(_) => break,
+            }
+            let counters = &self.allocation.counters;
+            match self.to_peer {
+                true => TurnAllocationCounters::count(
+                    &counters.bytes_to_peers,
+                    &counters.packets_to_peers,
+                    len,
+                ),
+                false => TurnAllocationCounters::count(
+                    &counters.bytes_from_peers,
+                    &counters.packets_from_peers,
+                    len,
+                ),
+            }
+        }
+        let _ = from.shutdown(Shutdown::Both);
+        let _ = to.shutdown(Shutdown::Both);
+    }
+
+    fn is_closed(&self, shutdown: Option<&AtomicBool>) -> bool {
+        return self.allocation.closed.load(Ordering::Relaxed)
+            || shutdown.is_some_and(|x| x.load(Ordering::SeqCst));
+    }
+}
+
+impl TurnServerAllocation {
+    //RFC 6062 section 5.3, peers with a permission connecting to the relayed address are
+    //announced to the client with a ConnectionAttempt
+    pub(super) fn accept_peers_loop(&self, connection_ids: &AtomicU32) {
+        let listener = match &self.relay {
+            TurnServerRelay::Tcp(listener, _) => listener,
+            TurnServerRelay::Udp(_) => return,
+        };
+        while !self.closed.load(Ordering::Relaxed) {
+            let (stream, peer) = match listener.accept() {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(TURN_SERVER_POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Error accepting on {:?}: {}", self.relayed_address, e);
+                    std::thread::sleep(TURN_SERVER_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            if !self.has_permission(peer.ip()) {
+                debug!("Refusing connection from {:?}, no permission", peer);
+                continue;
+            }
+            let connection_id = connection_ids.fetch_add(1, Ordering::Relaxed);
+            self.lock_connections().insert(
+                connection_id,
+                TurnServerPeerConnection {
+                    peer,
+                    stream: Some(stream),
+                    since: Instant::now(),
+                },
+            );
+            let indication_bin = match Self::encode_connection_attempt(connection_id, peer) {
+                Ok(bin) => bin,
+                Err(e) => {
+                    warn!("Error encoding ConnectionAttempt: {:?}", e);
+                    continue;
+                }
+            };
+            match self.client.send(&indication_bin) {
+                Ok(()) => {}
+                Err(e) => warn!("Error sending ConnectionAttempt: {}", e),
+            }
+        }
+    }
+
+    fn encode_connection_attempt(
+        connection_id: u32,
+        peer: SocketAddr,
+    ) -> Result<Vec<u8>, STUNError> {
+        let mut indication = STUN::new_default(
+            STUNMessageClass::Indication,
+            STUNMessageMethod::ConnectionAttempt,
+            None,
+        );
+        for attribute in [
+            STUNAttributesContent::new_xor_peer_address(peer),
+            STUNAttributesContent::new_connection_id(connection_id),
+        ] {
+            let attribute_type = attribute.attribute_type();
+            indication
+                .body
+                .add_new_attribute(attribute, attribute_type, 0);
+        }
+        let mut indication_bin = Vec::new();
+        match indication.encode(&mut Cursor::new(&mut indication_bin), &None) {
+            Ok(()) => return Ok(indication_bin),
+            Err(e) => return Err(e),
+        }
+    }
+
+    //Peer connections the client never bound within the timeout are closed
+    pub(super) fn remove_unbound_connections(&self) {
+        let now = Instant::now();
+        self.lock_connections().retain(|_, connection| {
+            connection.stream.is_none()
+                || now.duration_since(connection.since) < TURN_SERVER_CONNECTION_TIMEOUT
+        });
+    }
+}
@@ -8,8 +8,14 @@ mod STUNSerde; //Interface for encode/decode for STUN
 mod TestFixtures;
 mod utils;
 mod STUNClient;
+mod STUNServer;
+mod TURNClient;
+mod TURNServer;
 
 pub use STUN::stun as stun;
+pub use STUN::classifier as stunClassifier;
+pub use STUN::channel_data as turnChannelData;
+pub use STUN::stream as stunStream;
 pub use STUNHeader::header as stunHeader;
 pub use STUNBody::body as stunBody;
 pub use STUNContext::context as stunContext;
@@ -17,6 +23,16 @@ pub use STUNSerde::encode as stunEncode;
 pub use STUNSerde::decode as stunDecode;
 pub use STUNBody::attributes::attributes as stunAttributes;
 pub use STUNClient::client as stunClient;
+pub use STUNClient::keepalive as stunKeepalive;
+pub use STUNClient::consent as stunConsent;
+pub use STUNServer::server as stunServer;
+pub use STUNServer::auth as stunServerAuth;
+pub use STUNServer::limits as stunServerLimits;
+pub use TURNClient::client as turnClient;
+pub use TURNClient::relay as turnRelay;
+pub use TURNServer::server as turnServer;
+pub use TURNServer::quota as turnQuota;
+pub use TURNServer::policy as turnPeerPolicy;
 
 #[macro_use]
 extern crate num_derive;
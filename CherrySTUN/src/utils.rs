@@ -1,12 +1,11 @@
+//Compares every byte instead of returning early, as it is used to compare HMACs
 pub fn two_vector_are_identical(v1: Vec<u8>, v2: Vec<u8>) -> bool {
+    if v1.len() != v2.len() {
+        return false;
+    }
+    let mut difference = 0;
     for (i, v) in v1.iter().enumerate() {
-        let second_value = match v2.get(i) {
-            Some(v) => v,
-            None => return false,
-        };
-        if v == second_value {
-            continue;
-        }
+        difference |= v ^ v2[i];
     }
-    return true;
+    return difference == 0;
 }